@@ -0,0 +1,339 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// The command matrix. Every case starts on a fresh connection speaking the
+// default protocol and only touches keys from ctx.key().
+
+use crate::{Context, Expect, Frame, check};
+
+/// A named check run against a listener.
+pub struct Case {
+    pub name: &'static str,
+    pub run: fn(&mut Context) -> Result<(), String>,
+}
+
+pub static CASES: &[Case] = &[
+    Case { name: "set and get", run: set_and_get },
+    Case { name: "get missing key", run: get_missing },
+    Case { name: "quoted values", run: quoted_values },
+    Case { name: "delete", run: delete },
+    Case { name: "setex", run: setex },
+    Case { name: "getorset", run: getorset },
+    Case { name: "ttl", run: ttl },
+    Case { name: "counters", run: counters },
+    Case { name: "bitmaps", run: bitmaps },
+    Case { name: "streams", run: streams },
+    Case { name: "bloom filters", run: bloom_filters },
+    Case { name: "cuckoo filters", run: cuckoo_filters },
+    Case { name: "top-k", run: top_k },
+    Case { name: "tags", run: tags },
+    Case { name: "locks", run: locks },
+    Case { name: "invalidate", run: invalidate },
+    Case { name: "malformed requests", run: malformed },
+    Case { name: "empty lines", run: empty_lines },
+    Case { name: "pipelining", run: pipelining },
+    Case { name: "batches", run: batches },
+    Case { name: "request ids", run: request_ids },
+    Case { name: "idempotency tokens", run: idempotency_tokens },
+    Case { name: "hello", run: hello },
+    Case { name: "typed framing", run: typed_framing },
+    Case { name: "time", run: time },
+];
+
+fn set_and_get(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("a");
+    ctx.expect(&format!("set({}, hello)", key), Expect::Line("OK"))?;
+    ctx.expect(&format!("get({})", key), Expect::Line("hello"))?;
+    ctx.expect(&format!("set({}, world)", key), Expect::Line("OK"))?;
+    ctx.expect(&format!("GET({})", key), Expect::Line("world"))?;
+    Ok(())
+}
+
+fn get_missing(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("missing");
+    ctx.expect(&format!("get({})", key), Expect::Line("NULL"))?;
+    Ok(())
+}
+
+fn quoted_values(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("quoted");
+    ctx.expect(&format!("set({}, \"a, b (c)\")", key), Expect::Line("OK"))?;
+    ctx.expect(&format!("get(\"{}\")", key), Expect::Line("a, b (c)"))?;
+    Ok(())
+}
+
+fn delete(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("doomed");
+    ctx.expect(&format!("set({}, v)", key), Expect::Line("OK"))?;
+    ctx.expect(&format!("delete({})", key), Expect::Line("1"))?;
+    ctx.expect(&format!("del({})", key), Expect::Line("0"))?;
+    ctx.expect(&format!("get({})", key), Expect::Line("NULL"))?;
+    Ok(())
+}
+
+fn setex(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("expiring");
+    ctx.expect(&format!("setex({}, 60, v)", key), Expect::Line("OK"))?;
+    ctx.expect(&format!("get({})", key), Expect::Line("v"))?;
+    ctx.expect(&format!("setex({}, 60)", key), Expect::Error("ERR_SYNTAX"))?;
+    Ok(())
+}
+
+fn getorset(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("lazy");
+    ctx.expect(&format!("getorset({}, first)", key), Expect::Line("first"))?;
+    ctx.expect(&format!("getorset({}, second)", key), Expect::Line("first"))?;
+    ctx.expect(&format!("get({})", key), Expect::Line("first"))?;
+    Ok(())
+}
+
+fn ttl(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("expiring");
+    let plain = ctx.key("plain");
+    ctx.expect(&format!("setex({}, 60, v)", key), Expect::Line("OK"))?;
+    ctx.expect(&format!("ttl({})", key), Expect::Line("60"))?;
+    ctx.expect(&format!("set({}, v)", plain), Expect::Line("OK"))?;
+    ctx.expect(&format!("ttl({})", plain), Expect::Line("-1"))?;
+    ctx.expect(&format!("expire({}, 100)", plain), Expect::Line("1"))?;
+    ctx.expect(&format!("ttl({})", plain), Expect::Line("100"))?;
+    ctx.expect(&format!("ttl({})", ctx.key("missing")), Expect::Line("NULL"))?;
+    ctx.expect(&format!("expire({}, 10)", ctx.key("missing")), Expect::Line("0"))?;
+    Ok(())
+}
+
+fn counters(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("counter");
+    let text = ctx.key("text");
+    ctx.expect(&format!("incr({})", key), Expect::Line("1"))?;
+    ctx.expect(&format!("incrby({}, 41)", key), Expect::Line("42"))?;
+    ctx.expect(&format!("decr({})", key), Expect::Line("41"))?;
+    ctx.expect(&format!("get({})", key), Expect::Line("41"))?;
+    ctx.expect(&format!("incrby({}, x)", key), Expect::Error("ERR_SYNTAX"))?;
+    ctx.expect(&format!("incrby({}, 9223372036854775807)", key), Expect::Error("ERR_WRONGTYPE"))?;
+    ctx.expect(&format!("set({}, abc)", text), Expect::Line("OK"))?;
+    ctx.expect(&format!("incr({})", text), Expect::Error("ERR_WRONGTYPE"))?;
+    Ok(())
+}
+
+fn bitmaps(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("bits");
+    ctx.expect(&format!("setbit({}, 7, 1)", key), Expect::Line("0"))?;
+    ctx.expect(&format!("setbit({}, 7, 1)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("getbit({}, 7)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("getbit({}, 6)", key), Expect::Line("0"))?;
+    ctx.expect(&format!("bitcount({})", key), Expect::Line("1"))?;
+    ctx.expect(&format!("bitcount({})", ctx.key("nobits")), Expect::Line("0"))?;
+    ctx.expect(&format!("setbit({}, 1, 2)", key), Expect::Error("ERR_SYNTAX"))?;
+    Ok(())
+}
+
+fn streams(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("stream");
+    ctx.expect(&format!("xadd({}, one)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("xadd({}, two)", key), Expect::Line("2"))?;
+    ctx.expect(
+        &format!("xrange({}, 0, 10)", key),
+        Expect::Line(r#"[{"id":1,"value":"one"},{"id":2,"value":"two"}]"#),
+    )?;
+    ctx.expect(&format!("xread({}, 1)", key), Expect::Line(r#"[{"id":2,"value":"two"}]"#))?;
+    ctx.expect(&format!("xread({}, 2)", key), Expect::Line("(empty)"))?;
+    ctx.expect(&format!("xrange({}, 0, 10)", ctx.key("nostream")), Expect::Line("(empty)"))?;
+    Ok(())
+}
+
+fn bloom_filters(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("bloom");
+    ctx.expect(&format!("bfadd({}, x)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("bfadd({}, x)", key), Expect::Line("0"))?;
+    ctx.expect(&format!("bfexists({}, x)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("bfexists({}, y)", key), Expect::Line("0"))?;
+    ctx.expect(&format!("bfexists({}, y)", ctx.key("nobloom")), Expect::Line("0"))?;
+    ctx.expect(&format!("incr({})", key), Expect::Error("ERR_WRONGTYPE"))?;
+    Ok(())
+}
+
+fn cuckoo_filters(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("cuckoo");
+    ctx.expect(&format!("cfadd({}, x)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("cfexists({}, x)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("cfdel({}, x)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("cfdel({}, x)", key), Expect::Line("0"))?;
+    ctx.expect(&format!("cfexists({}, x)", key), Expect::Line("0"))?;
+    Ok(())
+}
+
+fn top_k(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("topk");
+    ctx.expect(&format!("topk_add({}, a)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("topk_add({}, a)", key), Expect::Line("2"))?;
+    ctx.expect(&format!("topk_add({}, b)", key), Expect::Line("1"))?;
+    ctx.expect(&format!("topk_query({}, a)", key), Expect::Line("2"))?;
+    ctx.expect(&format!("topk_query({}, z)", key), Expect::Line("0"))?;
+    ctx.expect(&format!("topk_list({})", key), Expect::Line(r#"[{"count":2,"item":"a"},{"count":1,"item":"b"}]"#))?;
+    Ok(())
+}
+
+fn tags(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("tagged");
+    let tag = ctx.key("tag");
+    ctx.expect(&format!("set({}, v)", key), Expect::Line("OK"))?;
+    ctx.expect(&format!("tag({}, {})", key, tag), Expect::Line("1"))?;
+    ctx.expect(&format!("keysbytag({})", tag), Expect::Line(&key))?;
+    ctx.expect(&format!("deletebytag({})", tag), Expect::Line("1"))?;
+    ctx.expect(&format!("get({})", key), Expect::Line("NULL"))?;
+    ctx.expect(&format!("keysbytag({})", tag), Expect::Line("(empty)"))?;
+    Ok(())
+}
+
+fn locks(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("lock");
+    let token = ctx.send_volatile(&format!("lock({}, 60)", key))?;
+    let Frame::Line(token) = token else {
+        return Err(format!("lock(): expected a line, got {}", token));
+    };
+    if token.parse::<u64>().is_err() {
+        return Err(format!("lock(): expected a fencing token, got {:?}", token));
+    }
+    ctx.expect(&format!("lock({}, 60)", key), Expect::Line("NULL"))?;
+    ctx.expect(&format!("unlock({}, abc)", key), Expect::Error("ERR_SYNTAX"))?;
+    let unlock = format!("unlock({}, {})", key, token);
+    let reply = ctx.send_volatile(&unlock)?;
+    check(&unlock, &reply, Expect::Line("1"))?;
+    Ok(())
+}
+
+fn invalidate(ctx: &mut Context) -> Result<(), String> {
+    let first = ctx.key("first");
+    let second = ctx.key("second");
+    ctx.expect(&format!("set({}, 1)", first), Expect::Line("OK"))?;
+    ctx.expect(&format!("set({}, 2)", second), Expect::Line("OK"))?;
+    let namespace = ctx.namespace().to_string();
+    // Replies with the namespace's new generation.
+    ctx.expect(&format!("invalidate({})", namespace), Expect::Line("1"))?;
+    ctx.expect(&format!("get({})", first), Expect::Line("NULL"))?;
+    ctx.expect(&format!("get({})", second), Expect::Line("NULL"))?;
+    ctx.expect(&format!("invalidate({})", namespace), Expect::Line("2"))?;
+    ctx.expect(&format!("invalidate({}:x)", namespace), Expect::Error("ERR_SYNTAX"))?;
+    Ok(())
+}
+
+fn malformed(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("k");
+    ctx.expect("nosuchcommand(1)", Expect::Error("ERR_SYNTAX"))?;
+    ctx.expect(&format!("set({})", key), Expect::Error("ERR_SYNTAX"))?;
+    ctx.expect(&format!("get({}", key), Expect::Error("ERR_SYNTAX"))?;
+    ctx.expect("get(bad key)", Expect::Error("ERR_SYNTAX"))?;
+    ctx.expect("set", Expect::Error("ERR_SYNTAX"))?;
+    // The connection is still usable after errors.
+    ctx.expect(&format!("get({})", key), Expect::Line("NULL"))?;
+    Ok(())
+}
+
+fn empty_lines(ctx: &mut Context) -> Result<(), String> {
+    let request = format!("get({})", ctx.key("k"));
+    ctx.connection()
+        .write_raw(format!("\n  \n{}\n", request).as_bytes())
+        .map_err(|e| e.to_string())?;
+    let reply = ctx.connection().read_frame().map_err(|e| format!("{}: {}", request, e))?;
+    check(&request, &reply, Expect::Line("NULL"))?;
+    ctx.record(&request, reply);
+    Ok(())
+}
+
+fn pipelining(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("pipelined");
+    let requests = [
+        (format!("set({}, 1)", key), "OK"),
+        (format!("get({})", key), "1"),
+        (format!("delete({})", key), "1"),
+        (format!("get({})", key), "NULL"),
+    ];
+    let batch: String = requests.iter().map(|(request, _)| format!("{}\n", request)).collect();
+    ctx.connection().write_raw(batch.as_bytes()).map_err(|e| e.to_string())?;
+    for (request, expected) in &requests {
+        let reply = ctx.connection().read_frame().map_err(|e| format!("{}: {}", request, e))?;
+        check(request, &reply, Expect::Line(expected))?;
+        ctx.record(request, reply);
+    }
+    Ok(())
+}
+
+fn batches(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("batched");
+    expect_batch(ctx, &format!("set({}, v); get({}); get({})", key, key, ctx.key("none")), &["OK", "v", "NULL"])?;
+    expect_batch(ctx, &format!("set({}, \"a;b c\"); get({})", key, key), &["OK", "a;b c"])?;
+    Ok(())
+}
+
+// Under protocol 1 a batch is answered with one line per command.
+fn expect_batch(ctx: &mut Context, request: &str, expected: &[&str]) -> Result<(), String> {
+    ctx.connection().write_raw(format!("{}\n", request).as_bytes()).map_err(|e| e.to_string())?;
+    for expected in expected {
+        let reply = ctx.connection().read_frame().map_err(|e| format!("{}: {}", request, e))?;
+        check(request, &reply, Expect::Line(expected))?;
+        ctx.record(request, reply);
+    }
+    Ok(())
+}
+
+fn request_ids(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("k");
+    ctx.expect(&format!("#req-1 get({})", key), Expect::Line("NULL"))?;
+    let reply = ctx.expect("#req-2 nosuchcommand(1)", Expect::Error("ERR_SYNTAX"))?;
+    match reply {
+        Frame::Line(text) if text.ends_with(" (request req-2)") => Ok(()),
+        reply => Err(format!("#req-2 nosuchcommand(1): error does not name the request id: {}", reply)),
+    }
+}
+
+fn idempotency_tokens(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("stream");
+    let token = format!("tok-{}", ctx.namespace());
+    ctx.expect(&format!("!{} xadd({}, one)", token, key), Expect::Line("1"))?;
+    // The retry gets the remembered reply instead of appending again.
+    ctx.expect(&format!("!{} xadd({}, one)", token, key), Expect::Line("1"))?;
+    ctx.expect(&format!("xrange({}, 0, 10)", key), Expect::Line(r#"[{"id":1,"value":"one"}]"#))?;
+    Ok(())
+}
+
+fn hello(ctx: &mut Context) -> Result<(), String> {
+    ctx.expect("hello()", Expect::Line("1"))?;
+    ctx.expect("hello(3)", Expect::Error("ERR_NOPROTO"))?;
+    ctx.expect("hello(x)", Expect::Error("ERR_SYNTAX"))?;
+    ctx.expect("hello(1)", Expect::Line("OK"))?;
+    ctx.expect("hello()", Expect::Line("1"))?;
+    Ok(())
+}
+
+fn typed_framing(ctx: &mut Context) -> Result<(), String> {
+    let key = ctx.key("typed");
+    ctx.expect("hello(2)", Expect::Line("+OK"))?;
+    ctx.connection().set_typed(true);
+
+    ctx.expect("hello()", Expect::Frame(&Frame::Integer(2)))?;
+    ctx.expect(&format!("get({})", key), Expect::Frame(&Frame::Null))?;
+    ctx.expect(&format!("set({}, \"NULL\")", key), Expect::Frame(&Frame::Status("OK".to_string())))?;
+    // A stored "NULL" is told apart from a miss.
+    ctx.expect(&format!("get({})", key), Expect::Frame(&Frame::Bulk("NULL".to_string())))?;
+    ctx.expect(&format!("bitcount({})", ctx.key("nobits")), Expect::Frame(&Frame::Integer(0)))?;
+    ctx.expect(&format!("keysbytag({})", ctx.key("notag")), Expect::Frame(&Frame::Array(Vec::new())))?;
+    ctx.expect(
+        &format!("set({}, v); get({})", key, key),
+        Expect::Frame(&Frame::Array(vec![Frame::Status("OK".to_string()), Frame::Bulk("v".to_string())])),
+    )?;
+    ctx.expect("nosuchcommand(1)", Expect::Error("ERR_SYNTAX"))?;
+    Ok(())
+}
+
+fn time(ctx: &mut Context) -> Result<(), String> {
+    let reply = ctx.send_volatile("time()")?;
+    let Frame::Line(line) = &reply else {
+        return Err(format!("time(): expected a line, got {}", reply));
+    };
+    let fields: Vec<&str> = line.split(' ').collect();
+    if fields.len() != 3 || fields.iter().any(|field| field.parse::<u64>().is_err()) {
+        return Err(format!("time(): expected seconds, microseconds and uptime, got {:?}", line));
+    }
+    ctx.expect("time(1)", Expect::Error("ERR_SYNTAX"))?;
+    Ok(())
+}
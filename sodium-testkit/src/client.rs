@@ -0,0 +1,147 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// One reply as read off the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A reply under the default protocol, which is always one bare line.
+    Line(String),
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Null,
+    Bulk(String),
+    Array(Vec<Frame>),
+}
+
+impl Frame {
+    pub fn is_error(&self) -> bool {
+        match self {
+            Frame::Error(_) => true,
+            Frame::Line(line) => line.starts_with("ERR_"),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Line(line) => write!(f, "{:?}", line),
+            Frame::Status(text) => write!(f, "+{}", text),
+            Frame::Error(text) => write!(f, "-{}", text),
+            Frame::Integer(value) => write!(f, ":{}", value),
+            Frame::Null => write!(f, "_"),
+            Frame::Bulk(text) => write!(f, "${:?}", text),
+            Frame::Array(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// A blocking client connection that reads replies in whichever framing
+/// the connection has negotiated.
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    typed: bool,
+}
+
+impl Connection {
+    pub fn connect(address: &str, timeout: Duration) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+        let writer = stream.try_clone()?;
+        Ok(Self { reader: BufReader::new(stream), writer, typed: false })
+    }
+
+    /// Reads later replies with the typed framing of protocol 2. Call once
+    /// the server has accepted hello(2).
+    pub fn set_typed(&mut self, typed: bool) {
+        self.typed = typed;
+    }
+
+    /// Sends one request line and reads its reply.
+    pub fn request(&mut self, line: &str) -> io::Result<Frame> {
+        self.write_raw(format!("{}\n", line).as_bytes())?;
+        self.read_frame()
+    }
+
+    /// Writes bytes as they are, without adding a newline or reading.
+    pub fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.writer.flush()
+    }
+
+    pub fn read_frame(&mut self) -> io::Result<Frame> {
+        let line = self.read_line()?;
+        if !self.typed {
+            return Ok(Frame::Line(line));
+        }
+        self.parse_typed(line)
+    }
+
+    fn parse_typed(&mut self, line: String) -> io::Result<Frame> {
+        let Some(marker) = line.chars().next() else {
+            return Err(invalid("empty reply line"));
+        };
+        let rest = &line[marker.len_utf8()..];
+        match marker {
+            '+' => Ok(Frame::Status(rest.to_string())),
+            '-' => Ok(Frame::Error(rest.to_string())),
+            ':' => rest.parse().map(Frame::Integer).map_err(|_| invalid(&line)),
+            '_' if rest.is_empty() => Ok(Frame::Null),
+            '$' => {
+                let len: usize = rest.parse().map_err(|_| invalid(&line))?;
+                // The bytes are followed by the newline that ends this frame.
+                let mut bytes = vec![0; len + 1];
+                self.reader.read_exact(&mut bytes)?;
+                if bytes.pop() != Some(b'\n') {
+                    return Err(invalid("bulk string not followed by a newline"));
+                }
+                String::from_utf8(bytes).map(Frame::Bulk).map_err(|_| invalid("bulk string is not UTF-8"))
+            }
+            '*' => {
+                let count: usize = rest.parse().map_err(|_| invalid(&line))?;
+                let mut items = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let line = self.read_line()?;
+                    items.push(self.parse_typed(line)?);
+                }
+                Ok(Frame::Array(items))
+            }
+            _ => Err(invalid(&line)),
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        if !line.ends_with('\n') {
+            return Err(invalid("reply not terminated by a newline"));
+        }
+        line.pop();
+        Ok(line)
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed reply: {}", what))
+}
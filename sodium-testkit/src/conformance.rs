@@ -0,0 +1,64 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Runs the conformance suite against one listener, or against two and
+// reports every request they answered differently.
+
+use std::process::ExitCode;
+
+use sodium_testkit::{compare, Suite};
+
+const USAGE: &str = "Usage: sodium-conformance [--auth <token>] [--filter <name>] <address> [<address>]";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut token = None;
+    let mut filter = None;
+    let mut addresses = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--auth" => token = args.next(),
+            "--filter" => filter = args.next(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ => addresses.push(arg),
+        }
+    }
+    if addresses.is_empty() || addresses.len() > 2 {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+
+    let suite = |address: &str| {
+        let mut suite = Suite::new(address);
+        if let Some(token) = &token {
+            suite = suite.auth(token.clone());
+        }
+        if let Some(filter) = &filter {
+            suite = suite.filter(filter);
+        }
+        suite
+    };
+
+    let passed = match addresses.as_slice() {
+        [address] => {
+            let report = suite(address).run();
+            println!("{}", report);
+            report.passed()
+        }
+        [left, right] => {
+            let (left_report, right_report, divergences) = compare(&suite(left), &suite(right));
+            println!("{}\n\n{}\n", left_report, right_report);
+            for divergence in &divergences {
+                println!("DIFF {}", divergence);
+            }
+            println!("{} differing replies", divergences.len());
+            left_report.passed() && right_report.passed() && divergences.is_empty()
+        }
+        _ => unreachable!(),
+    };
+
+    if passed { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -0,0 +1,322 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+//! Protocol conformance harness for Sodium listeners.
+//!
+//! A [`Suite`] drives a running listener through the command matrix over
+//! plain TCP and checks every reply byte for byte, so any frontend (the
+//! tokio listener, the io_uring one, a proxy) can be held to the same wire
+//! behavior. [`compare`] runs the suite against two listeners and reports
+//! every request they answered differently.
+//!
+//! Each case uses its own connection and its own key namespace, so the
+//! suite can run against a server that already holds data.
+
+mod cases;
+mod client;
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use cases::{Case, CASES};
+pub use client::{Connection, Frame};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a case expects back for a request.
+#[derive(Debug, Clone, Copy)]
+pub enum Expect<'a> {
+    /// Exactly this line, under the default protocol.
+    Line(&'a str),
+    /// An error whose code is this one, e.g. "ERR_SYNTAX".
+    Error(&'a str),
+    /// Exactly this frame.
+    Frame(&'a Frame),
+}
+
+/// A request and the reply it got, kept so two listeners can be compared.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    pub request: String,
+    pub reply: Frame,
+    /// Replies expected to differ between servers, such as time().
+    pub volatile: bool,
+}
+
+/// The state handed to a case: a fresh connection and a namespace no other
+/// case writes to.
+pub struct Context {
+    connection: Connection,
+    namespace: String,
+    transcript: Vec<Exchange>,
+}
+
+impl Context {
+    /// A key inside this case's namespace.
+    pub fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.namespace, name)
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn connection(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+
+    /// Sends a request and returns its reply.
+    pub fn send(&mut self, request: &str) -> Result<Frame, String> {
+        self.exchange(request, false)
+    }
+
+    /// Sends a request whose reply legitimately differs from server to
+    /// server, so compare() leaves it out.
+    pub fn send_volatile(&mut self, request: &str) -> Result<Frame, String> {
+        self.exchange(request, true)
+    }
+
+    /// Sends a request and fails unless the reply is the expected one.
+    pub fn expect(&mut self, request: &str, expected: Expect) -> Result<Frame, String> {
+        let reply = self.send(request)?;
+        check(request, &reply, expected)?;
+        Ok(reply)
+    }
+
+    /// Records a reply read outside send(), e.g. after pipelining.
+    pub fn record(&mut self, request: &str, reply: Frame) {
+        self.transcript.push(Exchange { request: request.to_string(), reply, volatile: false });
+    }
+
+    fn exchange(&mut self, request: &str, volatile: bool) -> Result<Frame, String> {
+        let reply = self
+            .connection
+            .request(request)
+            .map_err(|e| format!("{}: {}", request, e))?;
+        self.transcript.push(Exchange { request: request.to_string(), reply: reply.clone(), volatile });
+        Ok(reply)
+    }
+}
+
+/// Fails unless `reply` matches `expected`.
+pub fn check(request: &str, reply: &Frame, expected: Expect) -> Result<(), String> {
+    let matched = match (expected, reply) {
+        (Expect::Line(line), Frame::Line(actual)) => line == actual,
+        (Expect::Error(code), Frame::Line(text) | Frame::Error(text)) => {
+            text.split(' ').next() == Some(code)
+        }
+        (Expect::Frame(frame), actual) => frame == actual,
+        _ => false,
+    };
+    if matched {
+        return Ok(());
+    }
+    let wanted = match expected {
+        Expect::Line(line) => format!("{:?}", line),
+        Expect::Error(code) => format!("an {} error", code),
+        Expect::Frame(frame) => frame.to_string(),
+    };
+    Err(format!("{}: expected {}, got {}", request, wanted, reply))
+}
+
+/// The outcome of one case.
+#[derive(Debug)]
+pub struct CaseResult {
+    pub name: &'static str,
+    pub outcome: Result<(), String>,
+    pub transcript: Vec<Exchange>,
+}
+
+/// The outcome of a suite run against one listener.
+#[derive(Debug)]
+pub struct Report {
+    pub address: String,
+    pub results: Vec<CaseResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.outcome.is_ok())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.results.iter().filter(|result| result.outcome.is_err())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "PASS {}", result.name)?,
+                Err(e) => writeln!(f, "FAIL {}: {}", result.name, e)?,
+            }
+        }
+        let failed = self.failures().count();
+        write!(f, "{}: {} passed, {} failed", self.address, self.results.len() - failed, failed)
+    }
+}
+
+/// Runs cases against one listener.
+pub struct Suite {
+    address: String,
+    token: Option<String>,
+    timeout: Duration,
+    cases: Vec<&'static Case>,
+}
+
+impl Suite {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            token: None,
+            timeout: DEFAULT_TIMEOUT,
+            cases: CASES.iter().collect(),
+        }
+    }
+
+    /// Authenticates every connection with this token first. It must grant
+    /// access to all namespaces.
+    pub fn auth(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Only runs the cases whose name contains `filter`.
+    pub fn filter(mut self, filter: &str) -> Self {
+        self.cases.retain(|case| case.name.contains(filter));
+        self
+    }
+
+    pub fn run(&self) -> Report {
+        // Namespaces unique to this run, so reruns against the same server
+        // never see each other's keys.
+        let run = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros();
+        let results = self
+            .cases
+            .iter()
+            .enumerate()
+            .map(|(index, case)| {
+                let namespace = format!("conformance{}x{}x{}", std::process::id(), run, index);
+                self.run_case(case, namespace)
+            })
+            .collect();
+        Report { address: self.address.clone(), results }
+    }
+
+    fn run_case(&self, case: &'static Case, namespace: String) -> CaseResult {
+        let connection = match Connection::connect(&self.address, self.timeout) {
+            Ok(connection) => connection,
+            Err(e) => {
+                return CaseResult {
+                    name: case.name,
+                    outcome: Err(format!("failed to connect to {}: {}", self.address, e)),
+                    transcript: Vec::new(),
+                };
+            }
+        };
+        let mut context = Context { connection, namespace, transcript: Vec::new() };
+
+        let outcome = match &self.token {
+            Some(token) => context
+                .connection
+                .request(&format!("auth({})", token))
+                .map_err(|e| format!("auth(...): {}", e))
+                .and_then(|reply| check("auth(...)", &reply, Expect::Line("OK"))),
+            None => Ok(()),
+        }
+        .and_then(|()| (case.run)(&mut context));
+
+        CaseResult { name: case.name, outcome, transcript: context.transcript }
+    }
+}
+
+/// A request two listeners answered differently.
+#[derive(Debug)]
+pub struct Divergence {
+    pub case: &'static str,
+    pub request: String,
+    pub left: Option<Frame>,
+    pub right: Option<Frame>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |frame: &Option<Frame>| frame.as_ref().map_or("(not sent)".to_string(), Frame::to_string);
+        write!(f, "{}: {}: {} vs {}", self.case, self.request, show(&self.left), show(&self.right))
+    }
+}
+
+/// Runs the suite against both listeners and lists every request whose reply
+/// differed. Namespaces are generated per run, so keys are compared by their
+/// name within the case's namespace.
+pub fn compare(left: &Suite, right: &Suite) -> (Report, Report, Vec<Divergence>) {
+    let left_report = left.run();
+    let right_report = right.run();
+    let mut divergences = Vec::new();
+
+    for (left_case, right_case) in left_report.results.iter().zip(&right_report.results) {
+        let left_transcript = normalized(&left_case.transcript);
+        let right_transcript = normalized(&right_case.transcript);
+        let len = left_transcript.len().max(right_transcript.len());
+        for index in 0..len {
+            let left_exchange = left_transcript.get(index);
+            let right_exchange = right_transcript.get(index);
+            if left_exchange.zip(right_exchange).is_some_and(|(l, r)| l.volatile || r.volatile) {
+                continue;
+            }
+            if left_exchange.map(|e| (&e.request, &e.reply)) != right_exchange.map(|e| (&e.request, &e.reply)) {
+                let request = left_exchange.or(right_exchange).map(|e| e.request.clone()).unwrap_or_default();
+                divergences.push(Divergence {
+                    case: left_case.name,
+                    request,
+                    left: left_exchange.map(|e| e.reply.clone()),
+                    right: right_exchange.map(|e| e.reply.clone()),
+                });
+            }
+        }
+    }
+    (left_report, right_report, divergences)
+}
+
+// Rewrites each run's generated namespace to a fixed one.
+fn normalized(transcript: &[Exchange]) -> Vec<Exchange> {
+    let rewrite = |text: &str| {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("conformance") {
+            out.push_str(&rest[..start]);
+            let tail = &rest[start + "conformance".len()..];
+            let end = tail.find(|c: char| !(c.is_ascii_digit() || c == 'x')).unwrap_or(tail.len());
+            out.push_str("ns");
+            rest = &tail[end..];
+        }
+        out.push_str(rest);
+        out
+    };
+    let rewrite_frame = |frame: &Frame| rewrite_frame(frame, &rewrite);
+    transcript
+        .iter()
+        .map(|exchange| Exchange {
+            request: rewrite(&exchange.request),
+            reply: rewrite_frame(&exchange.reply),
+            volatile: exchange.volatile,
+        })
+        .collect()
+}
+
+fn rewrite_frame(frame: &Frame, rewrite: &dyn Fn(&str) -> String) -> Frame {
+    match frame {
+        Frame::Line(text) => Frame::Line(rewrite(text)),
+        Frame::Status(text) => Frame::Status(rewrite(text)),
+        Frame::Error(text) => Frame::Error(rewrite(text)),
+        Frame::Bulk(text) => Frame::Bulk(rewrite(text)),
+        Frame::Array(items) => Frame::Array(items.iter().map(|item| rewrite_frame(item, rewrite)).collect()),
+        Frame::Integer(_) | Frame::Null => frame.clone(),
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+const USAGE: &str = "Usage: sodium-bench [options]
+  --address <host:port>    server to load (default 127.0.0.1:1123)
+  --connections <n>        concurrent connections (default 50)
+  --requests <n>           total requests across all connections (default 100000)
+  --keyspace <n>           distinct keys (default 10000)
+  --distribution <d>       uniform or zipf (default uniform)
+  --zipf-exponent <s>      skew of the zipf distribution (default 0.99)
+  --value-size <n|min-max> value length in bytes (default 64)
+  --read-ratio <r>         fraction of requests that are gets (default 0.8)
+  --pipeline <n>           requests in flight per connection (default 1)
+  --auth <token>           token sent with auth() on every connection
+  --seed <n>               seed for the workload generator";
+
+#[derive(Debug, Clone)]
+struct Workload {
+    address: String,
+    connections: usize,
+    requests: usize,
+    keyspace: usize,
+    zipf_exponent: Option<f64>,
+    value_size: (usize, usize),
+    read_ratio: f64,
+    pipeline: usize,
+    auth: Option<String>,
+    seed: Option<u64>,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:1123".to_string(),
+            connections: 50,
+            requests: 100_000,
+            keyspace: 10_000,
+            zipf_exponent: None,
+            value_size: (64, 64),
+            read_ratio: 0.8,
+            pipeline: 1,
+            auth: None,
+            seed: None,
+        }
+    }
+}
+
+impl Workload {
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut workload = Workload::default();
+        let mut distribution = "uniform".to_string();
+        let mut zipf_exponent = 0.99;
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value", flag));
+            match flag.as_str() {
+                "--address" => workload.address = value()?,
+                "--connections" => workload.connections = parse_number(flag, &value()?)?,
+                "--requests" => workload.requests = parse_number(flag, &value()?)?,
+                "--keyspace" => workload.keyspace = parse_number(flag, &value()?)?,
+                "--distribution" => distribution = value()?,
+                "--zipf-exponent" => zipf_exponent = parse_number(flag, &value()?)?,
+                "--value-size" => {
+                    let size = value()?;
+                    workload.value_size = match size.split_once('-') {
+                        Some((min, max)) => (parse_number(flag, min)?, parse_number(flag, max)?),
+                        None => {
+                            let size = parse_number(flag, &size)?;
+                            (size, size)
+                        }
+                    };
+                }
+                "--read-ratio" => workload.read_ratio = parse_number(flag, &value()?)?,
+                "--pipeline" => workload.pipeline = parse_number(flag, &value()?)?,
+                "--auth" => workload.auth = Some(value()?),
+                "--seed" => workload.seed = Some(parse_number(flag, &value()?)?),
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                other => return Err(format!("Unknown option: {}\n{}", other, USAGE)),
+            }
+        }
+
+        workload.zipf_exponent = match distribution.as_str() {
+            "uniform" => None,
+            "zipf" => Some(zipf_exponent),
+            other => return Err(format!("Unknown distribution: {}. Valid distributions are: uniform, zipf", other)),
+        };
+        if workload.connections == 0 || workload.keyspace == 0 || workload.pipeline == 0 {
+            return Err("--connections, --keyspace and --pipeline must be at least 1".to_string());
+        }
+        if workload.value_size.0 == 0 || workload.value_size.0 > workload.value_size.1 {
+            return Err("--value-size must be a positive size or an increasing min-max range".to_string());
+        }
+        if !(0.0..=1.0).contains(&workload.read_ratio) {
+            return Err("--read-ratio must be between 0 and 1".to_string());
+        }
+        Ok(workload)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+// Picks key indexes, either uniformly or following a zipf distribution where
+// key 0 is the hottest.
+struct KeyPicker {
+    // Cumulative probabilities per key, empty for a uniform pick.
+    cdf: Vec<f64>,
+    keyspace: usize,
+}
+
+impl KeyPicker {
+    fn new(keyspace: usize, zipf_exponent: Option<f64>) -> Self {
+        let Some(exponent) = zipf_exponent else {
+            return Self { cdf: Vec::new(), keyspace };
+        };
+
+        let weights: Vec<f64> = (1..=keyspace).map(|rank| 1.0 / (rank as f64).powf(exponent)).collect();
+        let total: f64 = weights.iter().sum();
+        let mut cumulative = 0.0;
+        let cdf = weights.iter()
+            .map(|weight| {
+                cumulative += weight / total;
+                cumulative
+            })
+            .collect();
+        Self { cdf, keyspace }
+    }
+
+    fn pick(&self, rng: &mut impl Rng) -> usize {
+        if self.cdf.is_empty() {
+            return rng.gen_range(0..self.keyspace);
+        }
+        let sample: f64 = rng.r#gen();
+        self.cdf.partition_point(|&p| p < sample).min(self.keyspace - 1)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ConnectionReport {
+    latencies_micros: Vec<u64>,
+    errors: u64,
+}
+
+async fn run_connection(
+    workload: Arc<Workload>,
+    picker: Arc<KeyPicker>,
+    requests: usize,
+    seed: u64,
+) -> std::io::Result<ConnectionReport> {
+    let stream = TcpStream::connect(&workload.address).await?;
+    stream.set_nodelay(true)?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    if let Some(token) = &workload.auth {
+        writer.write_all(format!("auth({})\n", token).as_bytes()).await?;
+        reader.read_line(&mut line).await?;
+        if !line.starts_with("OK") {
+            return Err(std::io::Error::other(format!("auth() failed: {}", line.trim())));
+        }
+    }
+
+    let mut report = ConnectionReport {
+        latencies_micros: Vec::with_capacity(requests),
+        errors: 0,
+    };
+    let mut remaining = requests;
+    let mut batch = String::new();
+    while remaining > 0 {
+        let depth = remaining.min(workload.pipeline);
+        batch.clear();
+        for _ in 0..depth {
+            let key = picker.pick(&mut rng);
+            if rng.r#gen::<f64>() < workload.read_ratio {
+                batch.push_str(&format!("get(bench:{})\n", key));
+            } else {
+                let size = rng.gen_range(workload.value_size.0..=workload.value_size.1);
+                batch.push_str(&format!("set(bench:{}, {})\n", key, "x".repeat(size)));
+            }
+        }
+
+        let sent = Instant::now();
+        writer.write_all(batch.as_bytes()).await?;
+        for _ in 0..depth {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "server closed the connection"));
+            }
+            report.latencies_micros.push(sent.elapsed().as_micros() as u64);
+            if line.starts_with("ERR") {
+                report.errors += 1;
+            }
+        }
+        remaining -= depth;
+    }
+
+    Ok(report)
+}
+
+fn percentile(sorted: &[u64], fraction: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len()) - 1;
+    Duration::from_micros(sorted[index])
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let workload = match Workload::from_args(&args) {
+        Ok(workload) => Arc::new(workload),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    let picker = Arc::new(KeyPicker::new(workload.keyspace, workload.zipf_exponent));
+    let base_seed = workload.seed.unwrap_or_else(rand::random);
+
+    println!(
+        "Benchmarking {} with {} connections, {} requests, pipeline {}, {:.0}% reads",
+        workload.address,
+        workload.connections,
+        workload.requests,
+        workload.pipeline,
+        workload.read_ratio * 100.0,
+    );
+
+    let started = Instant::now();
+    let mut handles = Vec::with_capacity(workload.connections);
+    for index in 0..workload.connections {
+        // The first connections pick up the remainder of an uneven split.
+        let share = workload.requests / workload.connections
+            + usize::from(index < workload.requests % workload.connections);
+        let seed = base_seed.wrapping_add(index as u64);
+        handles.push(tokio::spawn(run_connection(workload.clone(), picker.clone(), share, seed)));
+    }
+
+    let mut latencies = Vec::with_capacity(workload.requests);
+    let mut errors = 0;
+    let mut failed_connections = 0;
+    for handle in handles {
+        match handle.await {
+            Ok(Ok(report)) => {
+                latencies.extend(report.latencies_micros);
+                errors += report.errors;
+            }
+            Ok(Err(e)) => {
+                eprintln!("Connection failed: {}", e);
+                failed_connections += 1;
+            }
+            Err(e) => {
+                eprintln!("Connection task failed: {}", e);
+                failed_connections += 1;
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+
+    let completed = latencies.len();
+    println!("completed    {} requests in {:.2}s", completed, elapsed.as_secs_f64());
+    println!("throughput   {:.0} requests/s", completed as f64 / elapsed.as_secs_f64().max(f64::EPSILON));
+    println!("errors       {}", errors);
+    println!(
+        "latency      p50 {:?}  p90 {:?}  p99 {:?}  p99.9 {:?}  max {:?}",
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.90),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 0.999),
+        percentile(&latencies, 1.0),
+    );
+
+    if failed_connections > 0 {
+        std::process::exit(1);
+    }
+}
@@ -1,15 +1,38 @@
 // Copyright (c) 2025, TheByteSlayer, Sodium
 // A scalable and optimized Key Value Caching System, written in Rust.
 
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs;
 use std::io::{self, Write, BufRead, BufReader};
 use std::net::TcpStream;
+use std::process;
+
+const USAGE: &str = "Usage: sodium-cli [run <script> [--var name=value]... [--continue] \
+                     | diff <address> <address> [--auth <token>] [--ttl-slack <secs>]]";
+
+// Keys fetched per scan() call while listing a keyspace.
+const DIFF_SCAN_COUNT: usize = 1000;
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => repl(),
+        Some("run") => process::exit(run(&args[1..])),
+        Some("diff") => process::exit(diff(&args[1..])),
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn repl() {
     let stdin = io::stdin();
     loop {
         print!("sodium-cli> ");
         io::stdout().flush().unwrap();
-        
+
         let mut input = String::new();
         match stdin.lock().read_line(&mut input) {
             Ok(_) => {
@@ -17,17 +40,17 @@ fn main() {
                 if input.is_empty() {
                     continue;
                 }
-                
+
                 // Parse address and command
                 let parts: Vec<&str> = input.splitn(2, ' ').collect();
                 if parts.len() < 2 {
                     println!("Error: Usage: <address> <command>");
                     continue;
                 }
-                
+
                 let address = parts[0];
                 let command = parts[1];
-                
+
                 execute_command(address, command);
             }
             Err(e) => {
@@ -40,28 +63,16 @@ fn main() {
 
 fn execute_command(address: &str, command: &str) {
     match TcpStream::connect(address) {
-        Ok(mut stream) => {
-            if let Err(e) = stream.write_all(command.as_bytes()) {
-                println!("Failed to send command: {}", e);
-                return;
-            }
-
-            if let Err(e) = stream.write_all(b"\n") {
-                println!("Failed to send newline: {}", e);
-                return;
-            }
-
-            let mut reader = BufReader::new(&mut stream);
-            let mut response = String::new();
-            match reader.read_line(&mut response) {
-                Ok(_) => {
-                    let trimmed = response.trim();
-                    if !trimmed.is_empty() {
-                        println!("{}", trimmed);
+        Ok(stream) => {
+            let mut reader = BufReader::new(stream);
+            match send_command(&mut reader, command) {
+                Ok(response) => {
+                    if !response.is_empty() {
+                        println!("{}", response);
                     }
                 }
                 Err(e) => {
-                    println!("Failed to read response: {}", e);
+                    println!("Failed to send command: {}", e);
                 }
             }
         }
@@ -69,4 +80,277 @@ fn execute_command(address: &str, command: &str) {
             println!("Failed to connect to {}: {}", address, e);
         }
     }
-} 
\ No newline at end of file
+}
+
+fn send_command(reader: &mut BufReader<TcpStream>, command: &str) -> io::Result<String> {
+    let stream = reader.get_mut();
+    stream.write_all(command.as_bytes())?;
+    stream.write_all(b"\n")?;
+
+    let mut response = String::new();
+    if reader.read_line(&mut response)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    Ok(response.trim().to_string())
+}
+
+/// Runs a script of `<address> <command>` lines, the same form the prompt
+/// takes. `${name}` is replaced by the value given with `--var name=value`,
+/// blank lines and lines starting with `#` are skipped. Connections are kept
+/// per address for the whole run, so an auth() line covers the lines after
+/// it. Returns the process exit code.
+fn run(args: &[String]) -> i32 {
+    let mut script = None;
+    let mut vars = HashMap::new();
+    let mut keep_going = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--continue" => keep_going = true,
+            "--var" => {
+                let Some((name, value)) = args.next().and_then(|var| var.split_once('=')) else {
+                    eprintln!("Error: --var takes name=value");
+                    return 2;
+                };
+                vars.insert(name.to_string(), value.to_string());
+            }
+            _ if script.is_none() => script = Some(arg.clone()),
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+    let Some(script) = script else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let content = match fs::read_to_string(&script) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", script, e);
+            return 2;
+        }
+    };
+
+    // Every line is checked before any runs, so a typo late in a runbook
+    // never leaves it half applied.
+    let mut lines = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = match substitute(line, &vars) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}:{}: {}", script, index + 1, e);
+                return 2;
+            }
+        };
+        match line.split_once(' ') {
+            Some((address, command)) if !command.trim().is_empty() => {
+                lines.push((index + 1, address.to_string(), command.trim().to_string()));
+            }
+            _ => {
+                eprintln!("{}:{}: expected <address> <command>", script, index + 1);
+                return 2;
+            }
+        }
+    }
+
+    let mut connections: HashMap<String, BufReader<TcpStream>> = HashMap::new();
+    let mut failed = 0;
+    for (number, address, command) in lines {
+        let result = match connections.get_mut(&address) {
+            Some(reader) => send_command(reader, &command),
+            None => TcpStream::connect(&address).and_then(|stream| {
+                let reader = connections.entry(address.clone()).or_insert(BufReader::new(stream));
+                send_command(reader, &command)
+            }),
+        };
+
+        let error = match result {
+            Ok(response) => {
+                println!("{} {} => {}", address, command, response);
+                response.starts_with("ERR_").then_some(response)
+            }
+            Err(e) => {
+                // A broken connection is reopened by the next line for it.
+                connections.remove(&address);
+                Some(format!("failed to reach {}: {}", address, e))
+            }
+        };
+        if let Some(error) = error {
+            eprintln!("{}:{}: {}", script, number, error);
+            failed += 1;
+            if !keep_going {
+                return 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{} command(s) failed", failed);
+        return 1;
+    }
+    0
+}
+
+fn substitute(line: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start + 2..].find('}') else {
+            return Err("unterminated ${".to_string());
+        };
+        let name = &rest[start + 2..start + 2 + end];
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => return Err(format!("undefined variable {}, pass it with --var {}=...", name, name)),
+        }
+        rest = &rest[start + 3 + end..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Compares the keyspaces of two servers, such as a primary and its replica
+/// or the two ends of a migration: keys present on only one side, values
+/// that differ, and TTLs further apart than `--ttl-slack` seconds (default
+/// 1, since the two sides are read moments apart). Values are compared as
+/// get() returns them. Exits 0 when nothing differs, 1 when something does.
+fn diff(args: &[String]) -> i32 {
+    let mut addresses = Vec::new();
+    let mut token = None;
+    let mut ttl_slack = 1;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--auth" => match args.next() {
+                Some(value) => token = Some(value.clone()),
+                None => {
+                    eprintln!("Error: --auth takes a token");
+                    return 2;
+                }
+            },
+            "--ttl-slack" => match args.next().and_then(|secs| secs.parse::<i64>().ok()) {
+                Some(secs) if secs >= 0 => ttl_slack = secs,
+                _ => {
+                    eprintln!("Error: --ttl-slack takes a number of seconds");
+                    return 2;
+                }
+            },
+            _ => addresses.push(arg.clone()),
+        }
+    }
+    let [left, right] = addresses.as_slice() else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let mut sides = Vec::new();
+    for address in [left, right] {
+        let side = TcpStream::connect(address).and_then(|stream| {
+            let mut reader = BufReader::new(stream);
+            if let Some(token) = &token {
+                let response = send_command(&mut reader, &format!("auth({})", token))?;
+                if response.starts_with("ERR_") {
+                    return Err(io::Error::other(response));
+                }
+            }
+            let keys = scan_keys(&mut reader)?;
+            Ok((reader, keys))
+        });
+        match side {
+            Ok(side) => sides.push(side),
+            Err(e) => {
+                eprintln!("Failed to read keys from {}: {}", address, e);
+                return 2;
+            }
+        }
+    }
+    let (mut right_reader, right_keys) = sides.pop().unwrap();
+    let (mut left_reader, left_keys) = sides.pop().unwrap();
+
+    let mut differences = 0;
+    for key in left_keys.difference(&right_keys) {
+        println!("only on {}: {}", left, key);
+        differences += 1;
+    }
+    for key in right_keys.difference(&left_keys) {
+        println!("only on {}: {}", right, key);
+        differences += 1;
+    }
+
+    let common: Vec<&String> = left_keys.intersection(&right_keys).collect();
+    for key in &common {
+        match compare_key(&mut left_reader, &mut right_reader, key, ttl_slack) {
+            Ok(found) => {
+                for difference in &found {
+                    println!("{}: {}", difference, key);
+                }
+                differences += found.len();
+            }
+            Err(e) => {
+                eprintln!("Failed to compare {}: {}", key, e);
+                return 2;
+            }
+        }
+    }
+
+    println!(
+        "{} keys on {}, {} on {}, {} compared, {} difference(s)",
+        left_keys.len(), left, right_keys.len(), right, common.len(), differences
+    );
+    if differences > 0 { 1 } else { 0 }
+}
+
+// Every key, walked with scan() until the cursor returns to 0.
+fn scan_keys(reader: &mut BufReader<TcpStream>) -> io::Result<BTreeSet<String>> {
+    let mut keys = BTreeSet::new();
+    let mut cursor = "0".to_string();
+    loop {
+        let response = send_command(reader, &format!("scan({}, {})", cursor, DIFF_SCAN_COUNT))?;
+        if response.starts_with("ERR_") {
+            return Err(io::Error::other(response));
+        }
+        let mut parts = response.split(' ');
+        cursor = parts.next().unwrap_or("0").to_string();
+        keys.extend(parts.filter(|key| !key.is_empty()).map(str::to_string));
+        if cursor == "0" {
+            return Ok(keys);
+        }
+    }
+}
+
+// What differs for a key present on both sides.
+fn compare_key(
+    left: &mut BufReader<TcpStream>,
+    right: &mut BufReader<TcpStream>,
+    key: &str,
+    ttl_slack: i64,
+) -> io::Result<Vec<String>> {
+    let mut found = Vec::new();
+    let get = format!("get({})", key);
+    if send_command(left, &get)? != send_command(right, &get)? {
+        found.push("value differs".to_string());
+    }
+
+    let ttl = format!("ttl({})", key);
+    let (left_ttl, right_ttl) = (send_command(left, &ttl)?, send_command(right, &ttl)?);
+    let mismatch = match (left_ttl.parse::<i64>(), right_ttl.parse::<i64>()) {
+        (Ok(-1), Ok(-1)) => false,
+        (Ok(-1), Ok(_)) | (Ok(_), Ok(-1)) => true,
+        (Ok(a), Ok(b)) => (a - b).abs() > ttl_slack,
+        _ => left_ttl != right_ttl,
+    };
+    if mismatch {
+        found.push(format!("ttl differs ({} vs {})", left_ttl, right_ttl));
+    }
+    Ok(found)
+}
@@ -0,0 +1,763 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use crate::background::{Job, Throttled};
+use crate::checksum;
+use crate::encryption::{self, EncryptionError};
+use crate::configuration::SodiumConfig;
+use crate::core::{get_cache, key_namespace, Metadata, SetOptions};
+use crate::snapshot::{self, SnapshotError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AofError {
+    #[error("AOF IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("AOF encoding error: {0}")]
+    Encode(#[from] serde_json::Error),
+    #[error("AOF encryption error: {0}")]
+    Encrypt(EncryptionError),
+    #[error("Corrupt AOF record at line {line}: {reason}")]
+    Corrupt { line: u64, reason: String },
+    #[error("AOF sequence gap at line {line}: expected {expected}, found {found}")]
+    Gap { line: u64, expected: u64, found: u64 },
+    #[error("Unreadable AOF record at line {line}: {source}")]
+    Encryption { line: u64, source: EncryptionError },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy {
+    Always,
+    EverySec,
+    No,
+}
+
+impl FsyncPolicy {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.trim().to_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format!("Invalid fsync policy: {}. Valid policies are: always, everysec, no", input)),
+        }
+    }
+}
+
+/// A mutation as written to the log. Expiry is stored as an absolute time so
+/// replay does not extend it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AofRecord {
+    Set {
+        key: String,
+        value: String,
+        tags: Vec<String>,
+        metadata: Metadata,
+        // Microseconds since the epoch, 0 when the entry never expires.
+        expires_at: u64,
+        // Microseconds, 0 unless the TTL slides on access.
+        sliding_ttl: u64,
+    },
+    Delete { key: String },
+    Tag { key: String, tag: String },
+    // Microseconds since the epoch the key now expires at.
+    Expire { key: String, expires_at: u64 },
+    // The generation the namespace moved to, so replaying over a snapshot
+    // that already holds the bump does not bump it twice.
+    Invalidate {
+        namespace: String,
+        #[serde(default)]
+        generation: u64,
+    },
+    SetBit { key: String, offset: u64, bit: bool },
+    // The id the entry was given, so an append the snapshot already holds is
+    // skipped on replay.
+    StreamAdd {
+        key: String,
+        value: String,
+        #[serde(default)]
+        id: u64,
+        // The length the stream was trimmed to after the append, 0 for none.
+        #[serde(default)]
+        max_len: u64,
+    },
+    // The filter's dimensions, so replay creates it the same size even if
+    // the configured defaults changed since.
+    BloomAdd { key: String, item: String, capacity: u64, error_rate: f64 },
+    TopKAdd { key: String, item: String, k: usize },
+    CuckooAdd { key: String, item: String, capacity: u64 },
+    CuckooDelete { key: String, item: String },
+    // A set() kept invisible until `visible_at`, microseconds since the
+    // epoch. The TTL, in microseconds and 0 for none, starts once it is.
+    Schedule {
+        key: String,
+        value: String,
+        tags: Vec<String>,
+        metadata: Metadata,
+        ttl: u64,
+        sliding: bool,
+        ttl_jitter: Option<u8>,
+        visible_at: u64,
+    },
+}
+
+impl AofRecord {
+    // Namespace of the key the record changes, which picks its encryption key.
+    fn namespace(&self) -> Option<&str> {
+        match self {
+            AofRecord::Invalidate { namespace, .. } => Some(namespace),
+            AofRecord::Set { key, .. }
+            | AofRecord::Delete { key }
+            | AofRecord::Tag { key, .. }
+            | AofRecord::Expire { key, .. }
+            | AofRecord::SetBit { key, .. }
+            | AofRecord::StreamAdd { key, .. }
+            | AofRecord::BloomAdd { key, .. }
+            | AofRecord::TopKAdd { key, .. }
+            | AofRecord::CuckooAdd { key, .. }
+            | AofRecord::CuckooDelete { key, .. }
+            | AofRecord::Schedule { key, .. } => key_namespace(key),
+        }
+    }
+}
+
+/// What replaying the log restored.
+#[derive(Debug, Default, Clone)]
+pub struct AofReplay {
+    pub applied: u64,
+    // Records already covered by the snapshot.
+    pub skipped: u64,
+    pub last_seq: u64,
+    pub truncated_tail: bool,
+}
+
+/// What rewrite() dropped and kept.
+#[derive(Debug, Default, Clone)]
+pub struct AofRewrite {
+    pub dropped: u64,
+    pub kept: u64,
+}
+
+/// What repair() found and did.
+#[derive(Debug, Default, Clone)]
+pub struct AofRepair {
+    pub records: u64,
+    // Bytes of torn tail cut off, 0 when the log was intact.
+    pub truncated_bytes: u64,
+    // Where the cut bytes were saved.
+    pub saved_to: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AofEntry {
+    seq: u64,
+    #[serde(flatten)]
+    record: AofRecord,
+}
+
+struct AppendLog {
+    // Replaced by rewrite(); everyone writing or syncing takes it from here.
+    file: Arc<File>,
+    next_seq: u64,
+    // Encoded records waiting for the next group commit.
+    pending: Vec<u8>,
+}
+
+struct Aof {
+    path: String,
+    log: Mutex<AppendLog>,
+    policy: FsyncPolicy,
+    // Set when data reached the OS but has not been fsynced yet.
+    dirty: AtomicBool,
+    // Present when group commit is on; carries the last sequence number the
+    // committer has written (and synced, under "always").
+    committed: Option<watch::Sender<u64>>,
+    // Held by the group committer from taking a batch until it is written,
+    // so a rewrite never swaps the file out from under it.
+    committing: Mutex<()>,
+    // The log is fsynced outside the log lock: by flush(), and without
+    // group commit by "always" once the appender has let go of its locks,
+    // one fsync at a time, up to the sequence number in synced.
+    syncing: Mutex<()>,
+    synced: AtomicU64,
+}
+
+static AOF: OnceLock<Aof> = OnceLock::new();
+
+// Set once the log has been handed to another process, which appends to it
+// from then on.
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+/// Replays the records of an existing log after `after_seq`, the sequence
+/// number a loaded snapshot already covers, then opens it for appending.
+pub async fn initialize_aof(config: &SodiumConfig, after_seq: u64) -> Result<AofReplay, AofError> {
+    if !config.aof_enabled {
+        return Ok(AofReplay::default());
+    }
+
+    let mut report = if Path::new(&config.aof_path).exists() {
+        replay(&config.aof_path, after_seq).await?
+    } else {
+        AofReplay::default()
+    };
+    // Numbering continues past the snapshot even if the log lost its tail.
+    report.last_seq = report.last_seq.max(after_seq);
+    let last_seq = report.last_seq;
+
+    let file = OpenOptions::new().create(true).append(true).open(&config.aof_path)?;
+    let policy = FsyncPolicy::parse(&config.fsync).unwrap_or(FsyncPolicy::EverySec);
+    let window = Duration::from_micros(config.aof_group_commit_us);
+    let committed = (!window.is_zero()).then(|| watch::Sender::new(last_seq));
+    let group_commit = committed.is_some();
+
+    let _ = AOF.set(Aof {
+        path: config.aof_path.clone(),
+        log: Mutex::new(AppendLog { file: Arc::new(file), next_seq: last_seq + 1, pending: Vec::new() }),
+        policy,
+        dirty: AtomicBool::new(false),
+        committed,
+        committing: Mutex::new(()),
+        syncing: Mutex::new(()),
+        synced: AtomicU64::new(last_seq),
+    });
+
+    if let Some(aof) = AOF.get() {
+        if policy == FsyncPolicy::EverySec {
+            thread::spawn(move || fsync_every_second(aof));
+        }
+        if group_commit {
+            thread::spawn(move || commit_batches(aof, window));
+        }
+    }
+    Ok(report)
+}
+
+/// Sequence number of the last record appended, 0 when the log is off.
+pub fn last_seq() -> u64 {
+    AOF.get().map_or(0, |aof| aof.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next_seq - 1)
+}
+
+/// Appends a mutation. Callers hold the lock protecting the mutated state so
+/// the log order matches the order the changes were applied in; `record` is
+/// only built when the log is enabled. Under fsync = "always" the record is
+/// only written here; sync_appended() fsyncs it once those locks are released.
+pub fn append(record: impl FnOnce() -> AofRecord) {
+    let Some(aof) = AOF.get() else {
+        return;
+    };
+
+    let mut log = aof.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if SUSPENDED.load(Ordering::Relaxed) {
+        return;
+    }
+    let entry = AofEntry { seq: log.next_seq, record: record() };
+    let line = match encode(&entry) {
+        Ok(line) => line,
+        Err(e) => {
+            error!("Failed to encode AOF record, not appending it: {}", e);
+            return;
+        }
+    };
+
+    if aof.committed.is_some() {
+        log.pending.extend_from_slice(&line);
+        log.next_seq += 1;
+        return;
+    }
+
+    if let Err(e) = (&*log.file).write_all(&line) {
+        error!("Failed to append to AOF: {}", e);
+        return;
+    }
+    log.next_seq += 1;
+
+    if aof.policy == FsyncPolicy::EverySec {
+        aof.dirty.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Under fsync = "always" without group commit, fsyncs every record
+/// appended so far. Concurrent callers share one fsync: whoever waited on
+/// another's finds its records already covered. Returns at once otherwise.
+pub fn sync_appended() {
+    let Some(aof) = AOF.get() else {
+        return;
+    };
+    if aof.policy != FsyncPolicy::Always || aof.committed.is_some() {
+        return;
+    }
+
+    let (appended, file) = appended(aof);
+    if aof.synced.load(Ordering::Acquire) >= appended {
+        return;
+    }
+    let _syncing = aof.syncing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if aof.synced.load(Ordering::Acquire) >= appended {
+        return;
+    }
+    // As with group commit, a failed fsync is logged and the records count
+    // as synced, so callers are not held up indefinitely.
+    if let Err(e) = file.sync_data() {
+        error!("Failed to fsync AOF: {}", e);
+    }
+    aof.synced.fetch_max(appended, Ordering::Release);
+}
+
+/// Under fsync = "always", waits until every record appended so far is on
+/// disk, so a command is acknowledged only once its writes have been synced:
+/// by the group committer's next batch, or by fsyncing them here. Returns at
+/// once otherwise.
+pub async fn wait_for_commit() {
+    let Some(aof) = AOF.get() else {
+        return;
+    };
+    if aof.policy != FsyncPolicy::Always {
+        return;
+    }
+    let Some(committed) = &aof.committed else {
+        let _ = tokio::task::spawn_blocking(sync_appended).await;
+        return;
+    };
+
+    let appended = aof.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next_seq - 1;
+    let mut receiver = committed.subscribe();
+    let _ = receiver.wait_for(|seq| *seq >= appended).await;
+}
+
+/// Stops appending and waits until every record appended so far has reached
+/// the file, so another process can take the log over. Mutations made while
+/// suspended are not logged.
+#[cfg(unix)]
+pub async fn suspend() {
+    let Some(aof) = AOF.get() else {
+        return;
+    };
+
+    // Stored before taking the lock, so every append after the one read
+    // below sees it.
+    SUSPENDED.store(true, Ordering::Relaxed);
+    let appended = aof.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next_seq - 1;
+    if let Some(committed) = &aof.committed {
+        let mut receiver = committed.subscribe();
+        let _ = receiver.wait_for(|seq| *seq >= appended).await;
+    }
+}
+
+/// Waits until every record appended so far has been written, then fsyncs
+/// the log whatever the fsync policy, so a clean shutdown loses nothing
+/// still buffered by group commit or waiting on the once-a-second fsync.
+pub async fn flush() {
+    let Some(aof) = AOF.get() else {
+        return;
+    };
+
+    let (appended, file) = appended(aof);
+    if let Some(committed) = &aof.committed {
+        let mut receiver = committed.subscribe();
+        let _ = receiver.wait_for(|seq| *seq >= appended).await;
+    }
+    let synced = tokio::task::spawn_blocking(move || {
+        let _syncing = aof.syncing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        file.sync_data()
+    });
+    match synced.await {
+        Ok(Ok(())) => {
+            aof.synced.fetch_max(appended, Ordering::Release);
+        }
+        Ok(Err(e)) => error!("Failed to fsync AOF: {}", e),
+        Err(e) => error!("Failed to fsync AOF: {}", e),
+    }
+}
+
+/// Undoes suspend() when the other process did not take the log after all.
+#[cfg(unix)]
+pub fn resume() {
+    SUSPENDED.store(false, Ordering::Relaxed);
+}
+
+/// Compacts the log, dropping the records up to `after_seq`, which a full
+/// snapshot already holds. The records kept are re-encrypted with the
+/// current key, so keys only older lines were written with can be retired.
+/// They are copied to a new file at most `io_bytes_per_sec` (0 for no
+/// limit); appends only wait while the records logged during the copy are
+/// carried over and the new file takes the old one's place. Returns None
+/// when there is nothing to drop.
+pub fn rewrite(after_seq: u64, io_bytes_per_sec: u64) -> Result<Option<AofRewrite>, AofError> {
+    let Some(aof) = AOF.get() else {
+        return Ok(None);
+    };
+    if SUSPENDED.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    let mut reader = BufReader::new(File::open(&aof.path)?);
+    let temporary = format!("{}.rewrite", aof.path);
+    let _ = fs::remove_file(&temporary);
+    let file = OpenOptions::new().create(true).append(true).open(&temporary)?;
+    let mut writer = BufWriter::new(Throttled::new(file, io_bytes_per_sec));
+    let mut report = AofRewrite::default();
+    // Bytes of the old log copied or dropped so far.
+    let mut copied = 0u64;
+    let mut line_number = 0u64;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        // A line without its newline is still being written; it is carried
+        // over with the rest of the tail below.
+        if read == 0 || !line.ends_with(b"\n") {
+            break;
+        }
+        copied += read as u64;
+        line_number += 1;
+
+        let text = std::str::from_utf8(&line)
+            .map_err(|_| AofError::Corrupt { line: line_number, reason: "not valid UTF-8".to_string() })?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let entry = decode(text.trim_end_matches(['\n', '\r']), line_number)?;
+        if entry.seq <= after_seq {
+            report.dropped += 1;
+            continue;
+        }
+        if report.dropped == 0 {
+            break;
+        }
+        writer.write_all(&encode(&entry)?)?;
+        report.kept += 1;
+    }
+    if report.dropped == 0 {
+        drop(writer);
+        let _ = fs::remove_file(&temporary);
+        return Ok(None);
+    }
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?.into_inner();
+    // Synced before taking the locks, leaving only the tail to sync under them.
+    file.sync_data()?;
+
+    let _committing = aof.committing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut log = aof.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if SUSPENDED.load(Ordering::Relaxed) {
+        let _ = fs::remove_file(&temporary);
+        return Ok(None);
+    }
+    // Appended during the copy, so already under the current key.
+    let mut tail = Vec::new();
+    reader.seek(SeekFrom::Start(copied))?;
+    reader.read_to_end(&mut tail)?;
+    file.write_all(&tail)?;
+    file.sync_data()?;
+    fs::rename(&temporary, &aof.path)?;
+    log.file = Arc::new(file);
+    report.kept += tail.iter().filter(|byte| **byte == b'\n').count() as u64;
+    Ok(Some(report))
+}
+
+/// Background job rewriting the log every `aof_rewrite_interval_secs` down
+/// to the records the last full snapshot does not hold, or None when the
+/// log or snapshots are off: without a snapshot every record is still needed.
+pub fn rewrite_job(config: &SodiumConfig) -> Option<Job> {
+    if !config.aof_enabled || config.snapshot_interval_secs == 0 || config.aof_rewrite_interval_secs == 0 {
+        return None;
+    }
+
+    let snapshot_path = config.snapshot_path.clone();
+    let io_bytes_per_sec = config.background_io_bytes_per_sec;
+    Some(Job::new("AOF rewrite", Duration::from_secs(config.aof_rewrite_interval_secs), move || {
+        let covered = match snapshot::covered_seq(&snapshot_path) {
+            Ok(covered) => covered,
+            Err(SnapshotError::Io(e)) if e.kind() == ErrorKind::NotFound => return,
+            Err(e) => {
+                error!("Skipping AOF rewrite, the snapshot is unreadable: {}", e);
+                return;
+            }
+        };
+        match rewrite(covered, io_bytes_per_sec) {
+            Ok(Some(rewritten)) => info!("Rewrote AOF, dropping {} records and keeping {}", rewritten.dropped, rewritten.kept),
+            Ok(None) => {}
+            Err(e) => error!("Failed to rewrite AOF: {}", e),
+        }
+    }))
+}
+
+// The last sequence number appended and the file it went to.
+fn appended(aof: &Aof) -> (u64, Arc<File>) {
+    let log = aof.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    (log.next_seq - 1, log.file.clone())
+}
+
+// Every `window`, writes whatever was appended since the last batch with a
+// single write and, under "always", a single fsync. The file is written
+// outside the log lock so appenders only ever wait for a buffer push.
+fn commit_batches(aof: &Aof, window: Duration) {
+    let Some(committed) = &aof.committed else {
+        return;
+    };
+
+    loop {
+        thread::sleep(window);
+        let _committing = aof.committing.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (batch, last_seq, file) = {
+            let mut log = aof.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            (std::mem::take(&mut log.pending), log.next_seq - 1, log.file.clone())
+        };
+        if batch.is_empty() {
+            continue;
+        }
+
+        if let Err(e) = (&*file).write_all(&batch) {
+            error!("Failed to append to AOF: {}", e);
+        } else {
+            match aof.policy {
+                FsyncPolicy::Always => {
+                    if let Err(e) = file.sync_data() {
+                        error!("Failed to fsync AOF: {}", e);
+                    }
+                }
+                FsyncPolicy::EverySec => aof.dirty.store(true, Ordering::Relaxed),
+                FsyncPolicy::No => {}
+            }
+        }
+
+        // Waiters are released even after a failed write, matching the
+        // ungrouped path, which logs the error and carries on.
+        committed.send_replace(last_seq);
+    }
+}
+
+fn fsync_every_second(aof: &Aof) {
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        if aof.dirty.swap(false, Ordering::Relaxed)
+            && let Err(e) = appended(aof).1.sync_data() {
+            error!("Failed to fsync AOF: {}", e);
+        }
+    }
+}
+
+/// Reads a log without applying it, checking that every record decodes,
+/// matches its checksum and that sequence numbers are consecutive. Returns
+/// the number of records.
+pub fn verify(path: &str) -> Result<u64, AofError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = 0u64;
+    let mut last_seq = None;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = index as u64 + 1;
+        let entry = decode(&line, line_number)?;
+        if let Some(last_seq) = last_seq
+            && entry.seq != last_seq + 1 {
+            return Err(AofError::Gap { line: line_number, expected: last_seq + 1, found: entry.seq });
+        }
+        last_seq = Some(entry.seq);
+        records += 1;
+    }
+    Ok(records)
+}
+
+// Applies the records after `after_seq`. Those must carry consecutive
+// sequence numbers starting at `after_seq + 1`, or records were lost and
+// recovery stops. A torn final line from a crash mid-write is dropped;
+// anything else unreadable is an error.
+async fn replay(path: &str, after_seq: u64) -> Result<AofReplay, AofError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut lines = reader.lines().peekable();
+    let mut line_number = 0u64;
+    let mut report = AofReplay { last_seq: after_seq, ..AofReplay::default() };
+
+    while let Some(line) = lines.next() {
+        let line = line?;
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = match decode(&line, line_number) {
+            Ok(entry) => entry,
+            Err(e) if lines.peek().is_none() => {
+                warn!("Ignoring truncated AOF record: {}", e);
+                report.truncated_tail = true;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        if entry.seq <= after_seq {
+            report.skipped += 1;
+            continue;
+        }
+        if entry.seq != report.last_seq + 1 {
+            return Err(AofError::Gap { line: line_number, expected: report.last_seq + 1, found: entry.seq });
+        }
+
+        apply(entry.record).await;
+        report.last_seq = entry.seq;
+        report.applied += 1;
+    }
+
+    Ok(report)
+}
+
+/// Cuts off a torn tail: the stretch of unreadable lines a crash mid-write
+/// leaves at the end of the log. Damage followed by readable records is not
+/// a torn write; it is returned as an error and the log is left untouched,
+/// as is a whole line that fails to decrypt, which points at a missing or
+/// wrong key rather than a crash. The cut bytes are kept in `<path>.torn`. Run it only on the log of a
+/// stopped server.
+pub fn repair(path: &str) -> Result<AofRepair, AofError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut report = AofRepair::default();
+    let mut last_seq = None;
+    // Offset and error of the first unreadable line.
+    let mut torn: Option<(u64, AofError)> = None;
+    let mut offset = 0u64;
+    let mut line_number = 0u64;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        let start = offset;
+        offset += read as u64;
+        line_number += 1;
+
+        // A record is only whole once its newline is written.
+        let decoded = match std::str::from_utf8(&line) {
+            Ok(text) if text.trim().is_empty() => continue,
+            Ok(text) if text.ends_with('\n') => decode(text.trim_end_matches(['\n', '\r']), line_number),
+            Ok(_) => Err(AofError::Corrupt { line: line_number, reason: "missing newline".to_string() }),
+            Err(_) => Err(AofError::Corrupt { line: line_number, reason: "not valid UTF-8".to_string() }),
+        };
+        match decoded {
+            Ok(entry) => {
+                if let Some((_, error)) = torn {
+                    return Err(error);
+                }
+                if let Some(last_seq) = last_seq
+                    && entry.seq != last_seq + 1 {
+                    return Err(AofError::Gap { line: line_number, expected: last_seq + 1, found: entry.seq });
+                }
+                last_seq = Some(entry.seq);
+                report.records += 1;
+            }
+            Err(error @ AofError::Encryption { .. }) => return Err(error),
+            Err(error) => {
+                torn.get_or_insert((start, error));
+            }
+        }
+    }
+
+    if let Some((start, _)) = torn {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(start))?;
+        file.read_to_end(&mut tail)?;
+        let saved_to = format!("{}.torn", path);
+        fs::write(&saved_to, &tail)?;
+        file.set_len(start)?;
+        file.sync_all()?;
+        report.truncated_bytes = tail.len() as u64;
+        report.saved_to = Some(saved_to);
+    }
+    Ok(report)
+}
+
+fn encode(entry: &AofEntry) -> Result<Vec<u8>, AofError> {
+    let mut line = serde_json::to_vec(entry)?;
+    encryption::encrypt(&mut line, entry.record.namespace()).map_err(AofError::Encrypt)?;
+    checksum::seal(&mut line);
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn decode(line: &str, line_number: u64) -> Result<AofEntry, AofError> {
+    let corrupt = |reason: String| AofError::Corrupt { line: line_number, reason };
+    let document = checksum::unseal(line).map_err(corrupt)?;
+    let document = encryption::decrypt(document).map_err(|source| AofError::Encryption { line: line_number, source })?;
+    serde_json::from_str(&document).map_err(|e| corrupt(e.to_string()))
+}
+
+async fn apply(record: AofRecord) {
+    let cache = get_cache();
+    let result = match record {
+        AofRecord::Set { key, value, tags, metadata, expires_at, sliding_ttl } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64;
+            // Sliding entries get a fresh window since reads were not logged.
+            let ttl = match (sliding_ttl, expires_at) {
+                (0, 0) => None,
+                (0, expires_at) if expires_at <= now => {
+                    let _ = cache.delete(&key).await;
+                    return;
+                }
+                (0, expires_at) => Some(Duration::from_micros(expires_at - now)),
+                (sliding_ttl, _) => Some(Duration::from_micros(sliding_ttl)),
+            };
+            let options = SetOptions { tags, metadata, ttl, sliding: sliding_ttl != 0, ttl_jitter: Some(0), writer: None, visible_at: None };
+            cache.set(key, value, options).await
+        }
+        AofRecord::Delete { key } => cache.delete(&key).await.map(|_| ()),
+        AofRecord::Tag { key, tag } => cache.tag(&key, tag).await.map(|_| ()),
+        AofRecord::Expire { key, expires_at } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64;
+            if expires_at <= now {
+                cache.delete(&key).await.map(|_| ())
+            } else {
+                cache.expire(&key, Duration::from_micros(expires_at - now), Some(0)).await.map(|_| ())
+            }
+        }
+        AofRecord::Invalidate { namespace, generation: 0 } => cache.invalidate(&namespace).await.map(|_| ()),
+        AofRecord::Invalidate { namespace, generation } => {
+            cache.restore_generations(BTreeMap::from([(namespace, generation)]));
+            Ok(())
+        }
+        AofRecord::SetBit { key, offset, bit } => cache.set_bit(key, offset, bit).await.map(|_| ()),
+        AofRecord::StreamAdd { key, value, id, max_len } => {
+            match cache.stream_range(&key, id.max(1), u64::MAX).await {
+                Ok(existing) if id != 0 && !existing.is_empty() => Ok(()),
+                _ => cache.stream_add(key, value, max_len).await.map(|_| ()),
+            }
+        }
+        AofRecord::BloomAdd { key, item, capacity, error_rate } => {
+            cache.bloom_add(key, item, capacity, error_rate).await.map(|_| ())
+        }
+        AofRecord::TopKAdd { key, item, k } => cache.topk_add(key, item, k).await.map(|_| ()),
+        AofRecord::CuckooAdd { key, item, capacity } => cache.cuckoo_add(key, item, capacity).await,
+        AofRecord::CuckooDelete { key, item } => cache.cuckoo_delete(&key, &item).await.map(|_| ()),
+        // Stored at once when its time passed while the server was down.
+        AofRecord::Schedule { key, value, tags, metadata, ttl, sliding, ttl_jitter, visible_at } => {
+            let ttl = (ttl != 0).then(|| Duration::from_micros(ttl));
+            let options = SetOptions { tags, metadata, ttl, sliding, ttl_jitter, writer: None, visible_at: Some(visible_at) };
+            cache.set(key, value, options).await
+        }
+    };
+
+    if let Err(e) = result {
+        warn!("Skipping AOF record that no longer applies: {}", e);
+    }
+}
@@ -1,14 +1,31 @@
 // Copyright (c) 2025, TheByteSlayer, Sodium
 // A scalable and optimized Key Value Caching System, written in Rust.
 
-use crate::threading;
-use crate::core::CacheError;
+use crate::aof;
+use crate::backing::{self, BackingStoreError};
+use crate::chaos::{self, Fault};
+use crate::threading::{self, BusyError};
+use crate::configuration::SodiumConfig;
+use crate::idempotency::{self, Claim};
+use crate::metrics;
+use crate::interceptors::{self, RateBucket, Request};
+use crate::plugins::{self, PluginError};
+use crate::protocol::{self, Reply};
+use crate::core::{get_cache, key_namespace, CacheError, Metadata, ScanCursor, SetOptions, SortOrder, StreamEntry};
 use crate::search::SearchType;
+use crate::sketches;
+use crate::webhooks::ExpirationFeed;
+use std::io::IoSlice;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::AsyncWriteExt;
-use tracing::{info, error, warn};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::sync::Notify;
+use tracing::{info, info_span, error, warn, Instrument, Span};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ApiError {
@@ -24,13 +41,174 @@ pub enum ApiError {
 
 type ApiResult<T> = Result<T, ApiError>;
 
+/// Leads every error response, followed by a space and a human readable
+/// message, so clients can branch on the code instead of the text.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ErrorCode {
+    Syntax,
+    NoProto,
+    Auth,
+    NoPerm,
+    NotFound,
+    WrongType,
+    Busy,
+    Timeout,
+    Cancelled,
+    Backend,
+    OutOfMemory,
+    Full,
+    Internal,
+}
+
+impl ErrorCode {
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "ERR_SYNTAX",
+            ErrorCode::NoProto => "ERR_NOPROTO",
+            ErrorCode::Auth => "ERR_AUTH",
+            ErrorCode::NoPerm => "ERR_NOPERM",
+            ErrorCode::NotFound => "ERR_NOTFOUND",
+            ErrorCode::WrongType => "ERR_WRONGTYPE",
+            ErrorCode::Busy => "ERR_BUSY",
+            ErrorCode::Timeout => "ERR_TIMEOUT",
+            ErrorCode::Cancelled => "ERR_CANCELLED",
+            ErrorCode::Backend => "ERR_BACKEND",
+            ErrorCode::OutOfMemory => "ERR_OOM",
+            ErrorCode::Full => "ERR_FULL",
+            ErrorCode::Internal => "ERR_INTERNAL",
+        }
+    }
+
+    fn of(e: &(dyn std::error::Error + 'static)) -> Self {
+        if let Some(e) = e.downcast_ref::<CacheError>() {
+            return match e {
+                CacheError::KeyNotFound(_) => ErrorCode::NotFound,
+                CacheError::WrongType(_) => ErrorCode::WrongType,
+                CacheError::NotAnInteger(_) => ErrorCode::WrongType,
+                CacheError::OutOfMemory(_) => ErrorCode::OutOfMemory,
+                CacheError::FilterFull(_) => ErrorCode::Full,
+            };
+        }
+        if e.is::<BusyError>() {
+            return ErrorCode::Busy;
+        }
+        if let Some(e) = e.downcast_ref::<PluginError>() {
+            return match e {
+                PluginError::InvalidArguments(_) => ErrorCode::Syntax,
+                PluginError::Cache(e) => ErrorCode::of(e),
+                PluginError::Failed(_) => ErrorCode::Internal,
+            };
+        }
+        match e.downcast_ref::<BackingStoreError>() {
+            Some(BackingStoreError::Timeout) => ErrorCode::Timeout,
+            Some(_) => ErrorCode::Backend,
+            None => ErrorCode::Internal,
+        }
+    }
+}
+
+pub(crate) fn error_response(code: ErrorCode, message: impl std::fmt::Display) -> Reply {
+    Reply::Error(format!("{} {}", code.as_str(), message))
+}
+
+fn failure(e: &(dyn std::error::Error + 'static)) -> Reply {
+    error_response(ErrorCode::of(e), e)
+}
+
+const DEFAULT_BIGKEYS_COUNT: usize = 10;
+// Larger counts are clamped to this, so a report stays a readable size.
+const MAX_BIGKEYS_COUNT: usize = 1000;
+const DEFAULT_SCAN_COUNT: usize = 10;
+const MAX_SCAN_COUNT: usize = 1000;
+const MAX_METADATA_FIELDS: usize = 16;
+const MAX_BATCH_COMMANDS: usize = 128;
+const MAX_MGET_KEYS: usize = 1000;
+// Caps a single bitmap at 512MB.
+const MAX_BIT_OFFSET: u64 = (1 << 32) - 1;
+// How often a blocked xread re-checks whether its client went away.
+const STREAM_BLOCK_POLL: Duration = Duration::from_millis(250);
+
+/// Testing aids behind the debug_commands setting.
+#[derive(Debug, Clone)]
+pub enum DebugCommand {
+    Sleep(Duration),
+    Object { key: String },
+    // Backdates the key's last access by `age`.
+    SetAccessTime { key: String, age: Duration },
+}
+
 #[derive(Debug, Clone)]
 pub enum Command {
-    Set { key: String, value: String },
-    Get { key: String },
+    Set { key: String, value: String, options: SetOptions },
+    Get { key: String, early: Option<Duration> },
+    Setex { key: String, value: String, ttl: Duration, sliding: bool },
+    GetOrSet { key: String, value: String, ttl: Option<Duration> },
+    GetSet { key: String, value: String },
+    GetDel { key: String },
+    MGet { keys: Vec<String> },
+    MSet { pairs: Vec<(String, String)> },
+    // incr(), decr() and incrby() alike.
+    IncrBy { key: String, delta: i64 },
+    SetBit { key: String, offset: u64, bit: bool },
+    GetBit { key: String, offset: u64 },
+    BitCount { key: String },
+    // A max_len left unset takes stream_max_len.
+    Xadd { key: String, value: String, max_len: Option<u64> },
+    // Dimensions left unset take the bloom_ config defaults.
+    BfAdd { key: String, item: String, capacity: Option<u64>, error_rate: Option<f64> },
+    BfExists { key: String, item: String },
+    // A capacity left unset takes cuckoo_capacity.
+    CfAdd { key: String, item: String, capacity: Option<u64> },
+    CfExists { key: String, item: String },
+    CfDel { key: String, item: String },
+    // A size left unset takes topk_size.
+    TopKAdd { key: String, item: String, k: Option<usize> },
+    TopKQuery { key: String, item: String },
+    TopKList { key: String },
+    Xrange { key: String, start: u64, end: u64 },
+    Xread { key: String, after: u64, block: Option<Duration> },
+    Meta { key: String },
     Delete { key: String },
-    Keys,
-    Search { search_type: SearchType, queries: Vec<String> },
+    Undelete { key: String },
+    History { key: String },
+    GetVersion { key: String, n: usize },
+    Keys { sort: Option<SortOrder>, cursor: Option<String> },
+    Scan { cursor: ScanCursor, count: usize },
+    Search { search_type: SearchType, queries: Vec<String>, sort: Option<SortOrder>, cursor: Option<String> },
+    Tag { key: String, tag: String },
+    Expire { key: String, ttl: Duration },
+    Ttl { key: String },
+    KeysByTag { tag: String },
+    DeleteByTag { tag: String },
+    Invalidate { namespace: String },
+    Lock { key: String, ttl: Duration },
+    Unlock { key: String, token: u64 },
+    Auth { token: String },
+    Hello { version: Option<u8> },
+    // Pushes `expired <key>` to this connection for keys matching the
+    // pattern, as push frames, so only under hello(2).
+    OnExpire { pattern: String },
+    Time,
+    Debug(DebugCommand),
+    Stats,
+    // Totals for one namespace, given as "namespace:".
+    PrefixStats { prefix: String },
+    // Calls, errors and latency per command name, or per metrics key pattern.
+    BreakdownStats { by_key_pattern: bool },
+    // Depth and recent wait of each worker queue.
+    QueueStats,
+    // Totals behind used_memory.
+    Memory,
+    // Bytes one key holds against max_memory, asked for with
+    // memory(usage, <key>).
+    MemoryUsage { key: String },
+    MemoryDoctor,
+    BigKeys { count: usize },
+    // Starts rebuilding storage shards left mostly empty by deletes.
+    Defrag,
+    Shutdown,
+    // A command served by a registered plugin.
+    Plugin { name: String, args: Vec<String> },
 }
 
 impl Command {
@@ -42,7 +220,7 @@ impl Command {
 
         // Special case for 'keys' without parentheses
         if input.to_lowercase() == "keys" {
-            return Ok(Command::Keys);
+            return Ok(Command::Keys { sort: None, cursor: None });
         }
 
         // All other commands must use function syntax
@@ -53,6 +231,217 @@ impl Command {
         Self::parse_function_syntax(input)
     }
 
+    /// The single key a command operates on, if any. Kept exhaustive so new
+    /// commands have to be classified for namespace scoping.
+    fn key(&self) -> Option<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::Get { key, .. }
+            | Command::Setex { key, .. }
+            | Command::GetOrSet { key, .. }
+            | Command::GetSet { key, .. }
+            | Command::GetDel { key }
+            | Command::IncrBy { key, .. }
+            | Command::SetBit { key, .. }
+            | Command::GetBit { key, .. }
+            | Command::BitCount { key }
+            | Command::Xadd { key, .. }
+            | Command::BfAdd { key, .. }
+            | Command::BfExists { key, .. }
+            | Command::CfAdd { key, .. }
+            | Command::CfExists { key, .. }
+            | Command::CfDel { key, .. }
+            | Command::TopKAdd { key, .. }
+            | Command::TopKQuery { key, .. }
+            | Command::TopKList { key }
+            | Command::Xrange { key, .. }
+            | Command::Xread { key, .. }
+            | Command::Meta { key }
+            | Command::Delete { key }
+            | Command::Undelete { key }
+            | Command::History { key }
+            | Command::GetVersion { key, .. }
+            | Command::Tag { key, .. }
+            | Command::Expire { key, .. }
+            | Command::Ttl { key }
+            | Command::Lock { key, .. }
+            | Command::Unlock { key, .. }
+            | Command::PrefixStats { prefix: key }
+            | Command::MemoryUsage { key }
+            | Command::OnExpire { pattern: key }
+            | Command::Debug(DebugCommand::Object { key } | DebugCommand::SetAccessTime { key, .. }) => Some(key),
+            Command::MGet { .. }
+            | Command::MSet { .. }
+            | Command::Keys { .. }
+            | Command::Scan { .. }
+            | Command::Search { .. }
+            | Command::KeysByTag { .. }
+            | Command::DeleteByTag { .. }
+            | Command::Invalidate { .. }
+            | Command::Auth { .. }
+            | Command::Hello { .. }
+            | Command::Time
+            | Command::Debug(DebugCommand::Sleep(_))
+            | Command::Stats
+            | Command::BreakdownStats { .. }
+            | Command::QueueStats
+            | Command::Memory
+            | Command::MemoryDoctor
+            | Command::BigKeys { .. }
+            | Command::Defrag
+            | Command::Shutdown
+            | Command::Plugin { .. } => None,
+        }
+    }
+
+    /// Commands that change stored data, whose replies are remembered when
+    /// sent with an idempotency token. Kept exhaustive like key().
+    fn is_mutating(&self) -> bool {
+        match self {
+            Command::Set { .. }
+            | Command::Setex { .. }
+            | Command::GetOrSet { .. }
+            | Command::GetSet { .. }
+            | Command::GetDel { .. }
+            | Command::MSet { .. }
+            | Command::IncrBy { .. }
+            | Command::SetBit { .. }
+            | Command::Xadd { .. }
+            | Command::BfAdd { .. }
+            | Command::CfAdd { .. }
+            | Command::CfDel { .. }
+            | Command::TopKAdd { .. }
+            | Command::Delete { .. }
+            | Command::Undelete { .. }
+            | Command::Tag { .. }
+            | Command::Expire { .. }
+            | Command::DeleteByTag { .. }
+            | Command::Invalidate { .. }
+            | Command::Lock { .. }
+            | Command::Unlock { .. } => true,
+            Command::Get { .. }
+            | Command::MGet { .. }
+            | Command::GetBit { .. }
+            | Command::BitCount { .. }
+            | Command::BfExists { .. }
+            | Command::CfExists { .. }
+            | Command::TopKQuery { .. }
+            | Command::TopKList { .. }
+            | Command::Xrange { .. }
+            | Command::Xread { .. }
+            | Command::Meta { .. }
+            | Command::History { .. }
+            | Command::GetVersion { .. }
+            | Command::Ttl { .. }
+            | Command::Keys { .. }
+            | Command::Scan { .. }
+            | Command::Search { .. }
+            | Command::KeysByTag { .. }
+            | Command::Auth { .. }
+            | Command::Hello { .. }
+            | Command::OnExpire { .. }
+            | Command::Time
+            | Command::Debug(_)
+            | Command::Stats
+            | Command::PrefixStats { .. }
+            | Command::BreakdownStats { .. }
+            | Command::QueueStats
+            | Command::Memory
+            | Command::MemoryUsage { .. }
+            | Command::MemoryDoctor
+            | Command::BigKeys { .. }
+            | Command::Defrag
+            | Command::Shutdown => false,
+            Command::Plugin { name, .. } => plugins::find(name).is_some_and(|plugin| plugin.is_mutating()),
+        }
+    }
+
+    /// Reads that walk many keys, abandoned when their client disconnects
+    /// rather than finished for nobody.
+    fn is_bulk_read(&self) -> bool {
+        matches!(
+            self,
+            Command::Keys { .. }
+                | Command::Scan { .. }
+                | Command::Search { .. }
+                | Command::KeysByTag { .. }
+                | Command::MemoryDoctor
+                | Command::BigKeys { .. }
+        )
+    }
+
+    /// The function name the command was sent as, for metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Set { .. } => "set",
+            Command::Get { .. } => "get",
+            Command::Setex { .. } => "setex",
+            Command::GetOrSet { .. } => "getorset",
+            Command::GetSet { .. } => "getset",
+            Command::GetDel { .. } => "getdel",
+            Command::MGet { .. } => "mget",
+            Command::MSet { .. } => "mset",
+            Command::IncrBy { .. } => "incrby",
+            Command::SetBit { .. } => "setbit",
+            Command::GetBit { .. } => "getbit",
+            Command::BitCount { .. } => "bitcount",
+            Command::Xadd { .. } => "xadd",
+            Command::BfAdd { .. } => "bfadd",
+            Command::BfExists { .. } => "bfexists",
+            Command::CfAdd { .. } => "cfadd",
+            Command::CfExists { .. } => "cfexists",
+            Command::CfDel { .. } => "cfdel",
+            Command::TopKAdd { .. } => "topk_add",
+            Command::TopKQuery { .. } => "topk_query",
+            Command::TopKList { .. } => "topk_list",
+            Command::Xrange { .. } => "xrange",
+            Command::Xread { .. } => "xread",
+            Command::Meta { .. } => "meta",
+            Command::Delete { .. } => "delete",
+            Command::Undelete { .. } => "undelete",
+            Command::History { .. } => "history",
+            Command::GetVersion { .. } => "getversion",
+            Command::Keys { .. } => "keys",
+            Command::Scan { .. } => "scan",
+            Command::Search { .. } => "search",
+            Command::Tag { .. } => "tag",
+            Command::Expire { .. } => "expire",
+            Command::Ttl { .. } => "ttl",
+            Command::KeysByTag { .. } => "keysbytag",
+            Command::DeleteByTag { .. } => "deletebytag",
+            Command::Invalidate { .. } => "invalidate",
+            Command::Lock { .. } => "lock",
+            Command::Unlock { .. } => "unlock",
+            Command::Auth { .. } => "auth",
+            Command::Hello { .. } => "hello",
+            Command::OnExpire { .. } => "onexpire",
+            Command::Time => "time",
+            Command::Debug(_) => "debug",
+            Command::Stats | Command::PrefixStats { .. } | Command::BreakdownStats { .. } | Command::QueueStats => "stats",
+            Command::Memory | Command::MemoryUsage { .. } | Command::MemoryDoctor => "memory",
+            Command::BigKeys { .. } => "bigkeys",
+            Command::Defrag => "defrag",
+            Command::Shutdown => "shutdown",
+            Command::Plugin { name, .. } => plugins::find(name).map_or("plugin", |plugin| plugin.name()),
+        }
+    }
+
+    /// Management commands, which only the admin listener accepts once an
+    /// admin port is configured.
+    pub(crate) fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Command::Stats
+                | Command::BreakdownStats { .. }
+                | Command::QueueStats
+                | Command::Memory
+                | Command::MemoryDoctor
+                | Command::BigKeys { .. }
+                | Command::Defrag
+                | Command::Shutdown
+        )
+    }
+
     fn is_function_syntax(input: &str) -> bool {
         input.contains('(') && input.ends_with(')')
     }
@@ -67,41 +456,653 @@ impl Command {
         
         match function_name.to_lowercase().as_str() {
             "set" => {
-                let (key, value) = Self::parse_function_args(args_str, 2)?;
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() < 2 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 2 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                let value = Self::unquote_string(&args[1]);
                 Self::validate_key(&key)?;
-                Ok(Command::Set { key, value })
+                // A bare third argument is a TTL in seconds, ahead of any
+                // name(value) options.
+                let (ttl, rest) = match args.get(2) {
+                    Some(ttl) if !ttl.contains('(') => (Some(Self::parse_ttl(&Self::unquote_string(ttl))?), &args[3..]),
+                    _ => (None, &args[2..]),
+                };
+                let mut options = Self::parse_set_options(rest)?;
+                options.ttl = ttl;
+                Ok(Command::Set { key, value, options })
             }
             "get" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.is_empty() || args.len() > 2 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 1 or 2 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                Self::validate_key(&key)?;
+                // early(ms) opts into probabilistic early expiration, where
+                // ms is roughly how long the caller takes to recompute the value.
+                let early = match args.get(1) {
+                    Some(option) => {
+                        let (name, value) = Self::parse_option(option)?;
+                        if name != "early" {
+                            return Err(ApiError::InvalidCommand(format!(
+                                "Unknown get option: {}. Supported options: early",
+                                name
+                            )));
+                        }
+                        Some(Self::parse_millis("early", &value)?)
+                    }
+                    None => None,
+                };
+                Ok(Command::Get { key, early })
+            }
+            "setex" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 3 && args.len() != 4 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 3 or 4 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                Self::validate_key(&key)?;
+                let ttl = Self::parse_ttl(&Self::unquote_string(&args[1]))?;
+                let value = Self::unquote_string(&args[2]);
+                let sliding = match args.get(3) {
+                    Some(option) => match Self::parse_option(option)? {
+                        (name, value) if name == "sliding" && value.trim().is_empty() => true,
+                        (name, _) => return Err(ApiError::InvalidCommand(format!(
+                            "Unknown setex option: {}. Supported options: sliding()",
+                            name
+                        ))),
+                    },
+                    None => false,
+                };
+                Ok(Command::Setex { key, value, ttl, sliding })
+            }
+            "getorset" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 2 && args.len() != 3 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 2 or 3 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                let value = Self::unquote_string(&args[1]);
+                Self::validate_key(&key)?;
+                let ttl = match args.get(2) {
+                    Some(ttl) => Some(Self::parse_ttl(&Self::unquote_string(ttl))?),
+                    None => None,
+                };
+                Ok(Command::GetOrSet { key, value, ttl })
+            }
+            "getset" => {
+                let (key, value) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                Ok(Command::GetSet { key, value })
+            }
+            "getdel" => {
+                let key = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&key)?;
+                Ok(Command::GetDel { key })
+            }
+            "mget" => {
+                let keys = Self::parse_array_argument("mget", args_str)?;
+                if keys.len() > MAX_MGET_KEYS {
+                    return Err(ApiError::InvalidCommand(format!("mget() takes at most {} keys", MAX_MGET_KEYS)));
+                }
+                for key in &keys {
+                    Self::validate_key(key)?;
+                }
+                Ok(Command::MGet { keys })
+            }
+            "mset" => {
+                let items = Self::parse_array_argument("mset", args_str)?;
+                if items.len() % 2 != 0 {
+                    return Err(ApiError::InvalidCommand("mset() takes [key, value, ...] pairs".to_string()));
+                }
+                let mut pairs = Vec::with_capacity(items.len() / 2);
+                let mut items = items.into_iter();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    Self::validate_key(&key)?;
+                    pairs.push((key, value));
+                }
+                Ok(Command::MSet { pairs })
+            }
+            "incr" | "decr" => {
+                let key = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&key)?;
+                let delta = if function_name.eq_ignore_ascii_case("incr") { 1 } else { -1 };
+                Ok(Command::IncrBy { key, delta })
+            }
+            "incrby" => {
+                let (key, delta) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                let delta = delta.trim().parse::<i64>()
+                    .map_err(|_| ApiError::InvalidCommand("incrby() takes a 64-bit integer".to_string()))?;
+                Ok(Command::IncrBy { key, delta })
+            }
+            "setbit" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 3 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 3 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                Self::validate_key(&key)?;
+                let offset = Self::parse_bit_offset(&Self::unquote_string(&args[1]))?;
+                let bit = match Self::unquote_string(&args[2]).as_str() {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(ApiError::InvalidCommand("Bit value must be 0 or 1".to_string())),
+                };
+                Ok(Command::SetBit { key, offset, bit })
+            }
+            "getbit" => {
+                let (key, offset) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                let offset = Self::parse_bit_offset(&offset)?;
+                Ok(Command::GetBit { key, offset })
+            }
+            "bitcount" => {
+                let key = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&key)?;
+                Ok(Command::BitCount { key })
+            }
+            "xadd" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 2 && args.len() != 3 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 2 or 3 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                let value = Self::unquote_string(&args[1]);
+                Self::validate_key(&key)?;
+                let mut max_len = None;
+                if let Some(option) = args.get(2) {
+                    let (name, length) = Self::parse_option(option)?;
+                    if name != "maxlen" {
+                        return Err(ApiError::InvalidCommand(format!(
+                            "Unknown xadd option: {}. Supported options: maxlen",
+                            name
+                        )));
+                    }
+                    match Self::unquote_string(&length).trim().parse::<u64>() {
+                        Ok(length) if length > 0 => max_len = Some(length),
+                        _ => return Err(ApiError::InvalidCommand("maxlen() takes a positive number of entries".to_string())),
+                    }
+                }
+                Ok(Command::Xadd { key, value, max_len })
+            }
+            "bfadd" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() < 2 || args.len() > 4 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 2 to 4 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                let item = Self::unquote_string(&args[1]);
+                Self::validate_key(&key)?;
+                // capacity(n) and error(rate) only size a filter bfadd creates.
+                let (mut capacity, mut error_rate) = (None, None);
+                for option in &args[2..] {
+                    let (name, value) = Self::parse_option(option)?;
+                    let value = Self::unquote_string(&value);
+                    match name.as_str() {
+                        "capacity" => match value.trim().parse::<u64>() {
+                            Ok(items) if (1..=sketches::MAX_BLOOM_CAPACITY).contains(&items) => capacity = Some(items),
+                            _ => return Err(ApiError::InvalidCommand(
+                                format!("capacity() takes a number of items between 1 and {}", sketches::MAX_BLOOM_CAPACITY)
+                            )),
+                        },
+                        "error" => match value.trim().parse::<f64>() {
+                            Ok(rate) if rate > 0.0 && rate < 1.0 => error_rate = Some(rate),
+                            _ => return Err(ApiError::InvalidCommand("error() takes a rate between 0 and 1 exclusive".to_string())),
+                        },
+                        _ => return Err(ApiError::InvalidCommand(format!(
+                            "Unknown bfadd option: {}. Supported options: capacity, error",
+                            name
+                        ))),
+                    }
+                }
+                Ok(Command::BfAdd { key, item, capacity, error_rate })
+            }
+            "bfexists" => {
+                let (key, item) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                Ok(Command::BfExists { key, item })
+            }
+            "cfadd" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 2 && args.len() != 3 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 2 or 3 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                let item = Self::unquote_string(&args[1]);
+                Self::validate_key(&key)?;
+                // capacity(n) only sizes a filter cfadd creates.
+                let capacity = match args.get(2) {
+                    Some(option) => {
+                        let (name, value) = Self::parse_option(option)?;
+                        if name != "capacity" {
+                            return Err(ApiError::InvalidCommand(format!(
+                                "Unknown cfadd option: {}. Supported options: capacity",
+                                name
+                            )));
+                        }
+                        match Self::unquote_string(&value).trim().parse::<u64>() {
+                            Ok(items) if (1..=sketches::MAX_CUCKOO_CAPACITY).contains(&items) => Some(items),
+                            _ => return Err(ApiError::InvalidCommand(
+                                format!("capacity() takes a number of items between 1 and {}", sketches::MAX_CUCKOO_CAPACITY)
+                            )),
+                        }
+                    }
+                    None => None,
+                };
+                Ok(Command::CfAdd { key, item, capacity })
+            }
+            "cfexists" => {
+                let (key, item) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                Ok(Command::CfExists { key, item })
+            }
+            "cfdel" => {
+                let (key, item) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                Ok(Command::CfDel { key, item })
+            }
+            "topk_add" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 2 && args.len() != 3 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 2 or 3 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                let item = Self::unquote_string(&args[1]);
+                Self::validate_key(&key)?;
+                // k(n) only sizes a sketch topk_add creates.
+                let k = match args.get(2) {
+                    Some(option) => {
+                        let (name, value) = Self::parse_option(option)?;
+                        if name != "k" {
+                            return Err(ApiError::InvalidCommand(format!(
+                                "Unknown topk_add option: {}. Supported options: k",
+                                name
+                            )));
+                        }
+                        match Self::unquote_string(&value).trim().parse::<usize>() {
+                            Ok(k) if (1..=sketches::MAX_TOPK).contains(&k) => Some(k),
+                            _ => return Err(ApiError::InvalidCommand(
+                                format!("k() takes a number of items between 1 and {}", sketches::MAX_TOPK)
+                            )),
+                        }
+                    }
+                    None => None,
+                };
+                Ok(Command::TopKAdd { key, item, k })
+            }
+            "topk_query" => {
+                let (key, item) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                Ok(Command::TopKQuery { key, item })
+            }
+            "topk_list" => {
+                let key = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&key)?;
+                Ok(Command::TopKList { key })
+            }
+            "xrange" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 3 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 3 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                Self::validate_key(&key)?;
+                let start = Self::parse_stream_id(&Self::unquote_string(&args[1]))?;
+                let end = Self::parse_stream_id(&Self::unquote_string(&args[2]))?;
+                Ok(Command::Xrange { key, start, end })
+            }
+            "xread" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.len() != 2 && args.len() != 3 {
+                    return Err(ApiError::InvalidCommand(
+                        format!("Function requires 2 or 3 arguments, got {}", args.len())
+                    ));
+                }
+                let key = Self::unquote_string(&args[0]);
+                Self::validate_key(&key)?;
+                let after = Self::parse_stream_id(&Self::unquote_string(&args[1]))?;
+                let block = match args.get(2) {
+                    Some(option) => {
+                        let (name, value) = Self::parse_option(option)?;
+                        if name != "block" {
+                            return Err(ApiError::InvalidCommand(format!(
+                                "Unknown xread option: {}. Supported options: block",
+                                name
+                            )));
+                        }
+                        Some(Self::parse_millis("block", &value)?)
+                    }
+                    None => None,
+                };
+                Ok(Command::Xread { key, after, block })
+            }
+            "meta" => {
                 let args = Self::parse_function_args_single(args_str)?;
                 Self::validate_key(&args)?;
-                Ok(Command::Get { key: args })
+                Ok(Command::Meta { key: args })
             }
             "delete" | "del" => {
                 let args = Self::parse_function_args_single(args_str)?;
                 Self::validate_key(&args)?;
                 Ok(Command::Delete { key: args })
             }
+            "undelete" => {
+                let key = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&key)?;
+                Ok(Command::Undelete { key })
+            }
+            "history" => {
+                let key = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&key)?;
+                Ok(Command::History { key })
+            }
+            "getversion" => {
+                let (key, n) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                let n = match n.trim().parse::<usize>() {
+                    Ok(n) if n >= 1 => n,
+                    _ => return Err(ApiError::InvalidCommand("Version must be a positive integer, 1 being the latest".to_string())),
+                };
+                Ok(Command::GetVersion { key, n })
+            }
             "keys" => {
-                if !args_str.trim().is_empty() {
+                if args_str.trim().is_empty() {
+                    return Ok(Command::Keys { sort: None, cursor: None });
+                }
+                let args = Self::split_function_args(args_str.trim())?;
+                let (args, cursor) = Self::take_cursor_option(args)?;
+                let (rest, sort) = Self::take_sort_option(args)?;
+                if !rest.is_empty() {
+                    return Err(ApiError::InvalidCommand(
+                        "keys() only accepts sort() and cursor() options".to_string(),
+                    ));
+                }
+                Ok(Command::Keys { sort, cursor })
+            }
+            "scan" => {
+                let args = Self::split_function_args(args_str.trim())?;
+                if args.is_empty() || args.len() > 2 {
                     return Err(ApiError::InvalidCommand(
-                        "keys() takes no arguments".to_string(),
+                        format!("Function requires 1 or 2 arguments, got {}", args.len())
                     ));
                 }
-                Ok(Command::Keys)
+                let cursor = ScanCursor::parse(&Self::unquote_string(&args[0]))
+                    .map_err(ApiError::InvalidCommand)?;
+                let count = match args.get(1) {
+                    Some(count) => match Self::unquote_string(count).parse::<usize>() {
+                        Ok(count) if count > 0 && count <= MAX_SCAN_COUNT => count,
+                        _ => return Err(ApiError::InvalidCommand(format!(
+                            "scan() count must be a positive integer up to {}", MAX_SCAN_COUNT
+                        ))),
+                    },
+                    None => DEFAULT_SCAN_COUNT,
+                };
+                Ok(Command::Scan { cursor, count })
             }
             "search" => {
-                let (search_type_str, queries) = Self::parse_search_args(args_str)?;
+                let args = Self::split_function_args(args_str.trim())?;
+                let (args, cursor) = Self::take_cursor_option(args)?;
+                let (rest, sort) = Self::take_sort_option(args)?;
+                let (search_type_str, queries) = Self::parse_search_args(&rest.join(", "))?;
                 let search_type = SearchType::parse(&search_type_str)
-                    .map_err(|e| ApiError::InvalidCommand(e))?;
-                Ok(Command::Search { search_type, queries })
+                    .map_err(ApiError::InvalidCommand)?;
+                Ok(Command::Search { search_type, queries, sort, cursor })
+            }
+            "tag" => {
+                let (key, tag) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                Self::validate_key(&tag)?;
+                Ok(Command::Tag { key, tag })
+            }
+            "expire" => {
+                let (key, ttl) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                let ttl = Self::parse_ttl(&ttl)?;
+                Ok(Command::Expire { key, ttl })
+            }
+            "ttl" => {
+                let key = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&key)?;
+                Ok(Command::Ttl { key })
+            }
+            "keysbytag" => {
+                let tag = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&tag)?;
+                Ok(Command::KeysByTag { tag })
+            }
+            "deletebytag" => {
+                let tag = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&tag)?;
+                Ok(Command::DeleteByTag { tag })
+            }
+            "invalidate" => {
+                let namespace = Self::parse_function_args_single(args_str)?;
+                Self::validate_key(&namespace)?;
+                if namespace.contains(':') {
+                    return Err(ApiError::InvalidCommand("Namespace cannot contain ':'".to_string()));
+                }
+                Ok(Command::Invalidate { namespace })
+            }
+            "lock" => {
+                let (key, ttl) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                let ttl = Self::parse_ttl(&ttl)?;
+                Ok(Command::Lock { key, ttl })
+            }
+            "unlock" => {
+                let (key, token) = Self::parse_function_args(args_str, 2)?;
+                Self::validate_key(&key)?;
+                let token = token.parse::<u64>()
+                    .map_err(|_| ApiError::InvalidCommand("Fencing token must be a positive integer".to_string()))?;
+                Ok(Command::Unlock { key, token })
+            }
+            "auth" => {
+                let token = Self::parse_function_args_single(args_str)?;
+                Ok(Command::Auth { token })
+            }
+            "hello" => {
+                let version = if args_str.trim().is_empty() {
+                    None
+                } else {
+                    let version = Self::parse_function_args_single(args_str)?
+                        .parse::<u8>()
+                        .map_err(|_| ApiError::InvalidCommand("hello() version must be a positive integer".to_string()))?;
+                    Some(version)
+                };
+                Ok(Command::Hello { version })
+            }
+            "onexpire" => {
+                let pattern = Self::parse_function_args_single(args_str)?;
+                if pattern.is_empty() {
+                    return Err(ApiError::InvalidCommand("onexpire() pattern cannot be empty".to_string()));
+                }
+                Ok(Command::OnExpire { pattern })
+            }
+            "time" => {
+                if !args_str.trim().is_empty() {
+                    return Err(ApiError::InvalidCommand(
+                        "time() takes no arguments".to_string(),
+                    ));
+                }
+                Ok(Command::Time)
+            }
+            "debug" => Self::parse_debug_args(args_str).map(Command::Debug),
+            "stats" => {
+                if args_str.trim().is_empty() {
+                    return Ok(Command::Stats);
+                }
+                let args: Vec<String> = Self::split_function_args(args_str.trim())?
+                    .iter()
+                    .map(|arg| Self::unquote_string(arg))
+                    .collect();
+                match args.as_slice() {
+                    [kind] if kind == "commands" => Ok(Command::BreakdownStats { by_key_pattern: false }),
+                    [kind] if kind == "patterns" => Ok(Command::BreakdownStats { by_key_pattern: true }),
+                    [kind] if kind == "queues" => Ok(Command::QueueStats),
+                    // Counters are kept per namespace, so only a whole one
+                    // can be asked for.
+                    [kind, prefix] if kind == "prefix" => match prefix.strip_suffix(':') {
+                        Some(namespace) if !namespace.is_empty() && !namespace.contains(':') => {
+                            Self::validate_key(namespace)?;
+                            Ok(Command::PrefixStats { prefix: prefix.clone() })
+                        }
+                        _ => Err(ApiError::InvalidCommand(
+                            "stats() prefix must be a namespace followed by ':'".to_string(),
+                        )),
+                    },
+                    _ => Err(ApiError::InvalidCommand(
+                        "Unknown stats() breakdown. Supported breakdowns: prefix, commands, patterns".to_string(),
+                    )),
+                }
+            }
+            "memory" => {
+                if args_str.trim().is_empty() {
+                    return Ok(Command::Memory);
+                }
+                let args: Vec<String> = Self::split_function_args(args_str.trim())?
+                    .iter()
+                    .map(|arg| Self::unquote_string(arg))
+                    .collect();
+                match args.as_slice() {
+                    [kind] if kind.eq_ignore_ascii_case("doctor") => Ok(Command::MemoryDoctor),
+                    [kind, key] if kind.eq_ignore_ascii_case("usage") => {
+                        Self::validate_key(key)?;
+                        Ok(Command::MemoryUsage { key: key.clone() })
+                    }
+                    _ => Err(ApiError::InvalidCommand(
+                        "Unknown memory() report. Supported reports: doctor, usage followed by a key".to_string(),
+                    )),
+                }
+            }
+            "bigkeys" => {
+                let count = if args_str.trim().is_empty() {
+                    DEFAULT_BIGKEYS_COUNT
+                } else {
+                    match Self::parse_function_args_single(args_str)?.parse::<usize>() {
+                        Ok(count) if count > 0 => count.min(MAX_BIGKEYS_COUNT),
+                        _ => return Err(ApiError::InvalidCommand(
+                            "bigkeys() count must be a positive integer".to_string()
+                        )),
+                    }
+                };
+                Ok(Command::BigKeys { count })
+            }
+            "defrag" => {
+                if !args_str.trim().is_empty() {
+                    return Err(ApiError::InvalidCommand(
+                        "defrag() takes no arguments".to_string(),
+                    ));
+                }
+                Ok(Command::Defrag)
+            }
+            "shutdown" => {
+                if !args_str.trim().is_empty() {
+                    return Err(ApiError::InvalidCommand(
+                        "shutdown() takes no arguments".to_string(),
+                    ));
+                }
+                Ok(Command::Shutdown)
+            }
+            cmd if plugins::find(cmd).is_some() => {
+                let args = if args_str.trim().is_empty() {
+                    Vec::new()
+                } else {
+                    Self::split_function_args(args_str)?.iter().map(|arg| Self::unquote_string(arg)).collect()
+                };
+                Ok(Command::Plugin { name: cmd.to_string(), args })
             }
             cmd => Err(ApiError::InvalidCommand(format!(
-                "Unknown function: {}. Supported functions: set, get, delete/del, keys, search",
+                "Unknown function: {}. Supported functions: set, get, setex, getorset, getset, getdel, mget, mset, incr, decr, incrby, setbit, getbit, bitcount, xadd, xrange, xread, bfadd, bfexists, cfadd, cfexists, cfdel, topk_add, topk_query, topk_list, meta, history, getversion, delete/del, undelete, keys, scan, search, tag, expire, ttl, keysbytag, deletebytag, invalidate, lock, unlock, auth, hello, onexpire, time, debug, stats, memory, bigkeys, defrag, shutdown",
                 cmd
             ))),
         }
     }
 
+    fn parse_bit_offset(offset: &str) -> ApiResult<u64> {
+        match offset.trim().parse::<u64>() {
+            Ok(offset) if offset <= MAX_BIT_OFFSET => Ok(offset),
+            _ => Err(ApiError::InvalidCommand(
+                format!("Bit offset must be an integer between 0 and {}", MAX_BIT_OFFSET)
+            )),
+        }
+    }
+
+    // Stream ids are plain integers; "-" and "+" stand for the first and
+    // last possible id.
+    fn parse_stream_id(id: &str) -> ApiResult<u64> {
+        match id.trim() {
+            "-" => Ok(0),
+            "+" => Ok(u64::MAX),
+            id => id.parse::<u64>()
+                .map_err(|_| ApiError::InvalidCommand(format!("Invalid stream id: {}", id))),
+        }
+    }
+
+    fn parse_millis(option: &str, value: &str) -> ApiResult<Duration> {
+        match Self::unquote_string(value).trim().parse::<u64>() {
+            Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+            _ => Err(ApiError::InvalidCommand(
+                format!("{}() takes a positive number of milliseconds", option)
+            )),
+        }
+    }
+
+    /// Parses a TTL given in whole seconds.
+    fn parse_ttl(ttl: &str) -> ApiResult<Duration> {
+        match ttl.trim().parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+            _ => Err(ApiError::InvalidCommand("TTL must be a positive number of seconds".to_string())),
+        }
+    }
+
+    fn parse_debug_args(args_str: &str) -> ApiResult<DebugCommand> {
+        let args: Vec<String> = Self::split_function_args(args_str.trim())?
+            .iter()
+            .map(|arg| Self::unquote_string(arg))
+            .collect();
+        let parse_ms = |ms: &str| ms.trim().parse::<u64>()
+            .map(Duration::from_millis)
+            .map_err(|_| ApiError::InvalidCommand("debug() durations are whole milliseconds".to_string()));
+
+        match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            [subcommand, ms] if subcommand.eq_ignore_ascii_case("sleep") => Ok(DebugCommand::Sleep(parse_ms(ms)?)),
+            [subcommand, key] if subcommand.eq_ignore_ascii_case("object") => {
+                Self::validate_key(key)?;
+                Ok(DebugCommand::Object { key: key.to_string() })
+            }
+            [subcommand, key, ms] if subcommand.eq_ignore_ascii_case("set-access-time") => {
+                Self::validate_key(key)?;
+                Ok(DebugCommand::SetAccessTime { key: key.to_string(), age: parse_ms(ms)? })
+            }
+            _ => Err(ApiError::InvalidCommand(
+                "Supported debug subcommands: sleep(ms), object(key), set-access-time(key, ms)".to_string(),
+            )),
+        }
+    }
+
     fn parse_function_args_single(args_str: &str) -> ApiResult<String> {
         let args_str = args_str.trim();
         if args_str.is_empty() {
@@ -140,9 +1141,8 @@ impl Command {
         let mut current_arg = String::new();
         let mut in_quotes = false;
         let mut in_brackets = 0;
-        let mut chars = args_str.chars().peekable();
-        
-        while let Some(ch) = chars.next() {
+        let mut in_parens = 0;
+        for ch in args_str.chars() {
             match ch {
                 '"' => {
                     in_quotes = !in_quotes;
@@ -156,7 +1156,15 @@ impl Command {
                     in_brackets -= 1;
                     current_arg.push(ch);
                 }
-                ',' if !in_quotes && in_brackets == 0 => {
+                '(' if !in_quotes => {
+                    in_parens += 1;
+                    current_arg.push(ch);
+                }
+                ')' if !in_quotes => {
+                    in_parens -= 1;
+                    current_arg.push(ch);
+                }
+                ',' if !in_quotes && in_brackets == 0 && in_parens == 0 => {
                     args.push(current_arg.trim().to_string());
                     current_arg.clear();
                 }
@@ -173,6 +1181,10 @@ impl Command {
         if in_brackets != 0 {
             return Err(ApiError::InvalidCommand("Unclosed bracket in arguments".to_string()));
         }
+
+        if in_parens != 0 {
+            return Err(ApiError::InvalidCommand("Unclosed parenthesis in arguments".to_string()));
+        }
         
         if !current_arg.trim().is_empty() {
             args.push(current_arg.trim().to_string());
@@ -181,6 +1193,122 @@ impl Command {
         Ok(args)
     }
 
+    // Trailing options use the same function syntax as commands, e.g.
+    // set("key", "value", tags(["a", "b"])).
+    fn parse_option(arg: &str) -> ApiResult<(String, String)> {
+        let arg = arg.trim();
+        let open_paren = match arg.find('(') {
+            Some(open_paren) if arg.ends_with(')') => open_paren,
+            _ => {
+                return Err(ApiError::InvalidCommand(format!(
+                    "Invalid option: {}. Options use name(value) syntax",
+                    arg
+                )));
+            }
+        };
+
+        let name = arg[..open_paren].trim().to_lowercase();
+        let value = arg[open_paren + 1..arg.len() - 1].to_string();
+        Ok((name, value))
+    }
+
+    // Splits a trailing sort("asc"|"desc"|"accessed") option off the arguments.
+    fn take_sort_option(mut args: Vec<String>) -> ApiResult<(Vec<String>, Option<SortOrder>)> {
+        let is_sort = args.last()
+            .is_some_and(|arg| arg.trim().to_lowercase().starts_with("sort("));
+        if !is_sort {
+            return Ok((args, None));
+        }
+
+        let (_, value) = Self::parse_option(&args.pop().unwrap_or_default())?;
+        let order = SortOrder::parse(&Self::unquote_string(&value))
+            .map_err(ApiError::InvalidCommand)?;
+        Ok((args, Some(order)))
+    }
+
+    // A trailing cursor(<key>) resumes a keys()/search() reply that was cut
+    // short by max_response_bytes, after the last key it returned.
+    fn take_cursor_option(mut args: Vec<String>) -> ApiResult<(Vec<String>, Option<String>)> {
+        let is_cursor = args.last()
+            .is_some_and(|arg| arg.trim().to_lowercase().starts_with("cursor("));
+        if !is_cursor {
+            return Ok((args, None));
+        }
+
+        let (_, value) = Self::parse_option(&args.pop().unwrap_or_default())?;
+        let cursor = Self::unquote_string(&value);
+        if cursor.is_empty() {
+            return Err(ApiError::InvalidCommand("cursor() takes the last key of the previous page".to_string()));
+        }
+        Ok((args, Some(cursor)))
+    }
+
+    fn parse_set_options(args: &[String]) -> ApiResult<SetOptions> {
+        let mut options = SetOptions::default();
+
+        for arg in args {
+            let (name, value) = Self::parse_option(arg)?;
+            match name.as_str() {
+                "tags" => {
+                    let tags = Self::parse_query_argument(&value)?;
+                    for tag in &tags {
+                        Self::validate_key(tag)?;
+                    }
+                    options.tags = tags;
+                }
+                "meta" => {
+                    options.metadata = Self::parse_metadata(&value)?;
+                }
+                // at(seconds) keeps the write invisible until that Unix time,
+                // the same clock time() reports.
+                "at" => {
+                    let seconds = value.trim().parse::<u64>().map_err(|_| {
+                        ApiError::InvalidCommand("at() takes a Unix time in whole seconds".to_string())
+                    })?;
+                    options.visible_at = Some(seconds.saturating_mul(1_000_000));
+                }
+                // jitter(percent) overrides ttl_jitter_percent for this write.
+                "jitter" => match value.trim().parse::<u8>() {
+                    Ok(percent) if percent <= 100 => options.ttl_jitter = Some(percent),
+                    _ => return Err(ApiError::InvalidCommand("jitter() takes a percent between 0 and 100".to_string())),
+                },
+                other => {
+                    return Err(ApiError::InvalidCommand(format!(
+                        "Unknown set option: {}. Supported options: tags, meta, at, jitter",
+                        other
+                    )));
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn parse_metadata(value: &str) -> ApiResult<Metadata> {
+        let fields = Self::parse_query_argument(value)?;
+        if fields.len() > MAX_METADATA_FIELDS {
+            return Err(ApiError::InvalidCommand(format!(
+                "Metadata is limited to {} fields",
+                MAX_METADATA_FIELDS
+            )));
+        }
+
+        let mut metadata: Metadata = Vec::with_capacity(fields.len());
+        for field in fields {
+            let (name, value) = field.split_once('=').ok_or_else(|| {
+                ApiError::InvalidCommand(format!("Invalid metadata field: {}. Use \"name=value\"", field))
+            })?;
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(ApiError::InvalidCommand("Metadata field name cannot be empty".to_string()));
+            }
+            metadata.retain(|(existing, _)| existing != name);
+            metadata.push((name.to_string(), value.trim().to_string()));
+        }
+
+        Ok(metadata)
+    }
+
     fn unquote_string(s: &str) -> String {
         let trimmed = s.trim();
         if trimmed.starts_with('"') && trimmed.ends_with('"') && trimmed.len() >= 2 {
@@ -232,9 +1360,7 @@ impl Command {
 
     fn find_operator(args_str: &str, operator: &str) -> Option<usize> {
         let mut in_quotes = false;
-        let mut chars = args_str.char_indices().peekable();
-        
-        while let Some((i, ch)) = chars.next() {
+        for (i, ch) in args_str.char_indices() {
             if ch == '"' {
                 in_quotes = !in_quotes;
             } else if !in_quotes && args_str[i..].starts_with(operator) {
@@ -313,13 +1439,24 @@ impl Command {
         }
     }
 
+    // The single [a, b, ...] argument of a multi-key command, unquoted.
+    fn parse_array_argument(function: &str, args_str: &str) -> ApiResult<Vec<String>> {
+        let args = Self::split_function_args(args_str.trim())?;
+        let array = match args.as_slice() {
+            [array] if array.starts_with('[') && array.ends_with(']') => &array[1..array.len() - 1],
+            _ => return Err(ApiError::InvalidCommand(format!("{}() takes a single [..] array", function))),
+        };
+        if array.trim().is_empty() {
+            return Err(ApiError::InvalidCommand("Empty array not allowed".to_string()));
+        }
+        Ok(Self::split_array_elements(array.trim())?.iter().map(|element| Self::unquote_string(element)).collect())
+    }
+
     fn split_array_elements(array_content: &str) -> ApiResult<Vec<String>> {
         let mut elements = Vec::new();
         let mut current_element = String::new();
         let mut in_quotes = false;
-        let mut chars = array_content.chars().peekable();
-        
-        while let Some(ch) = chars.next() {
+        for ch in array_content.chars() {
             match ch {
                 '"' => {
                     in_quotes = !in_quotes;
@@ -353,10 +1490,9 @@ impl Command {
 
     fn extract_first_quoted_term(s: &str) -> ApiResult<String> {
         let s = s.trim();
-        if s.starts_with('"') {
-            if let Some(end_quote) = s[1..].find('"') {
-                return Ok(s[1..end_quote + 1].to_string());
-            }
+        if let Some(rest) = s.strip_prefix('"')
+            && let Some(end_quote) = rest.find('"') {
+            return Ok(rest[..end_quote].to_string());
         }
         Err(ApiError::InvalidCommand("Expected quoted term".to_string()))
     }
@@ -389,9 +1525,9 @@ impl Command {
         }
 
         for ch in key.chars() {
-            if !ch.is_ascii_alphanumeric() && ch != '-' && ch != '_' {
+            if !ch.is_ascii_alphanumeric() && ch != '-' && ch != '_' && ch != ':' {
                 return Err(ApiError::InvalidCommand(format!(
-                    "Key contains invalid character '{}'. Keys can only contain letters, numbers, hyphens, and underscores",
+                    "Key contains invalid character '{}'. Keys can only contain letters, numbers, hyphens, underscores, and colons",
                     ch
                 )));
             }
@@ -399,10 +1535,10 @@ impl Command {
 
         let chars: Vec<char> = key.chars().collect();
         for (i, &ch) in chars.iter().enumerate() {
-            if ch == '-' || ch == '_' {
+            if ch == '-' || ch == '_' || ch == ':' {
                 if i == 0 || i == chars.len() - 1 {
                     return Err(ApiError::InvalidCommand(format!(
-                        "Key cannot start or end with '{}'. Hyphens and underscores must be between letters or numbers",
+                        "Key cannot start or end with '{}'. Hyphens, underscores, and colons must be between letters or numbers",
                         ch
                     )));
                 }
@@ -417,10 +1553,11 @@ impl Command {
                 }
             }
         }
+        let is_separator = |ch: char| ch == '-' || ch == '_' || ch == ':';
         for i in 0..chars.len() - 1 {
-            if (chars[i] == '-' || chars[i] == '_') && (chars[i + 1] == '-' || chars[i + 1] == '_') {
+            if is_separator(chars[i]) && is_separator(chars[i + 1]) {
                 return Err(ApiError::InvalidCommand(
-                    "Key cannot have consecutive hyphens or underscores".to_string()
+                    "Key cannot have consecutive hyphens, underscores, or colons".to_string()
                 ));
             }
         }
@@ -429,22 +1566,395 @@ impl Command {
     }
 }
 
-pub struct TcpApiServer {
-    listener: TcpListener,
+/// What request handling needs from a client's socket while a command runs.
+pub(crate) trait ClientConnection {
+    /// Resolves once the client has gone away; stays pending otherwise.
+    async fn closed(&mut self);
+
+    /// Whether the connection can send the client events between replies.
+    fn can_push(&self) -> bool {
+        true
+    }
+}
+
+impl ClientConnection for BufReader<OwnedReadHalf> {
+    async fn closed(&mut self) {
+        // Buffered or pending input means the client is still there and
+        // pipelining; only a clean EOF or a socket error counts as gone.
+        if !self.buffer().is_empty() {
+            return std::future::pending().await;
+        }
+
+        let mut probe = [0u8; 1];
+        match self.get_mut().peek(&mut probe).await {
+            Ok(0) | Err(_) => {}
+            Ok(_) => std::future::pending().await,
+        }
+    }
+}
+
+const MAX_BUFFERED_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Longest request line a backend buffers while waiting for its newline,
+/// before giving up on the connection.
+pub(crate) const MAX_REQUEST_BYTES: usize = 512 * 1024 * 1024;
+
+/// What handle_line() made of a request line.
+pub(crate) enum LineOutcome {
+    // The encoded reply, newline included.
+    Reply(String),
+    // A blank line, which gets no reply.
+    Empty,
+    // The connection is to be closed.
+    Close,
+}
+
+/// Encoded replies waiting to be written to one connection.
+#[derive(Debug, Default)]
+struct ResponseBuffer {
+    replies: Vec<String>,
+    bytes: usize,
+}
+
+impl ResponseBuffer {
+    fn push(&mut self, reply: String) {
+        self.bytes += reply.len();
+        self.replies.push(reply);
+    }
+
+    fn is_full(&self) -> bool {
+        self.bytes >= MAX_BUFFERED_RESPONSE_BYTES
+    }
+
+    // Writes every buffered reply, as few vectored writes as the socket
+    // allows.
+    async fn flush(&mut self, writer: &mut OwnedWriteHalf) -> std::io::Result<()> {
+        if self.replies.is_empty() {
+            return Ok(());
+        }
+
+        let mut slices: Vec<IoSlice<'_>> = self.replies.iter().map(|reply| IoSlice::new(reply.as_bytes())).collect();
+        let mut remaining = slices.as_mut_slice();
+        while !remaining.is_empty() {
+            let written = writer.write_vectored(remaining).await?;
+            if written == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            IoSlice::advance_slices(&mut remaining, written);
+        }
+
+        self.replies.clear();
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+/// Per-connection authentication state.
+#[derive(Debug)]
+pub(crate) struct Session {
+    authenticated: bool,
+    // Namespace the connection is confined to, None for full access.
+    namespace: Option<String>,
+    // Reply framing negotiated with hello().
+    protocol: u8,
+    pub(crate) rate_bucket: RateBucket,
+    // Set by the first onexpire().
+    expirations: Option<ExpirationFeed>,
+}
+
+impl Session {
+    pub(crate) fn new(config: &SodiumConfig) -> Self {
+        Self {
+            authenticated: config.auth_tokens.is_empty(),
+            namespace: None,
+            protocol: protocol::DEFAULT_PROTOCOL,
+            rate_bucket: RateBucket::new(config.rate_limit_per_sec),
+            expirations: None,
+        }
+    }
+
+    /// Waits for the next key expired under this connection's onexpire()
+    /// patterns; stays pending until it has registered one.
+    pub(crate) async fn next_expiration(&mut self) -> String {
+        match &mut self.expirations {
+            Some(feed) => feed.next().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Frames an expiration event as a push, `expired` followed by the key.
+    pub(crate) fn expiration_event(&self, key: &str) -> String {
+        let mut event = Reply::Push(vec![Reply::Bulk("expired".to_string()), Reply::Bulk(key.to_string())]).encode(self.protocol);
+        event.push('\n');
+        event
+    }
+
+    pub(crate) fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    fn authenticate(&mut self, token: &str, config: &SodiumConfig) -> bool {
+        // Every configured token is compared in full so response timing does
+        // not hint at how close a guess was.
+        let mut granted = None;
+        for (candidate, namespace) in &config.auth_tokens {
+            if constant_time_eq(candidate.as_bytes(), token.as_bytes()) {
+                granted = Some(namespace);
+            }
+        }
+
+        let Some(namespace) = granted else {
+            return false;
+        };
+        self.authenticated = true;
+        self.namespace = (namespace != "*").then(|| namespace.clone());
+        true
+    }
+
+    pub(crate) fn authorize(&self, command: &Command) -> Result<(), String> {
+        let Some(namespace) = &self.namespace else {
+            return Ok(());
+        };
+
+        match command {
+            Command::Stats
+            | Command::BreakdownStats { .. }
+            | Command::QueueStats
+            | Command::Memory
+            | Command::MemoryDoctor
+            | Command::BigKeys { .. }
+            | Command::Defrag
+            | Command::Shutdown
+            | Command::Plugin { .. } => Err("Permission denied for namespaced connections".to_string()),
+            Command::Invalidate { namespace: target } if target != namespace => {
+                Err(format!("Namespace {} is not accessible", target))
+            }
+            Command::MGet { keys } => authorize_keys(keys.iter().map(String::as_str), namespace),
+            Command::MSet { pairs } => authorize_keys(pairs.iter().map(|(key, _)| key.as_str()), namespace),
+            _ => authorize_keys(command.key(), namespace),
+        }
+    }
+}
+
+fn authorize_keys<'a>(keys: impl IntoIterator<Item = &'a str>, namespace: &str) -> Result<(), String> {
+    match keys.into_iter().find(|key| key_namespace(key) != Some(namespace)) {
+        Some(key) => Err(format!("Key {} is outside namespace {}", key, namespace)),
+        None => Ok(()),
+    }
+}
+
+const MAX_PREFIX_TOKEN_LEN: usize = 64;
+
+// Splits an optional "#<id> " prefix off a request. Ids are short tokens
+// so they can be logged and echoed back verbatim.
+fn split_request_id(request: &str) -> (Option<&str>, &str) {
+    split_prefix_token(request, '#')
+}
+
+// Splits an optional "!<token> " idempotency prefix off a command.
+fn split_idempotency_token(request: &str) -> (Option<&str>, &str) {
+    split_prefix_token(request, '!')
+}
+
+fn split_prefix_token(request: &str, marker: char) -> (Option<&str>, &str) {
+    let Some(rest) = request.strip_prefix(marker) else {
+        return (None, request);
+    };
+    let Some((token, command)) = rest.split_once(char::is_whitespace) else {
+        return (None, request);
+    };
+
+    let valid = !token.is_empty()
+        && token.len() <= MAX_PREFIX_TOKEN_LEN
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    if valid {
+        (Some(token), command.trim_start())
+    } else {
+        (None, request)
+    }
+}
+
+// Splits "cmd; cmd; ..." into its commands, ignoring separators inside
+// quotes, brackets and parentheses. None for a line holding a single command.
+fn split_batch(request: &str) -> Option<Vec<&str>> {
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut depth = 0i32;
+    for (index, ch) in request.char_indices() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            '(' | '[' if !in_quotes => depth += 1,
+            ')' | ']' if !in_quotes => depth -= 1,
+            ';' if !in_quotes && depth == 0 => {
+                commands.push(&request[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if commands.is_empty() {
+        return None;
+    }
+    commands.push(&request[start..]);
+    commands.retain(|command| !command.trim().is_empty());
+    Some(commands)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn retain_namespace(keys: &mut Vec<String>, namespace: Option<&str>) {
+    if let Some(namespace) = namespace {
+        keys.retain(|key| key_namespace(key) == Some(namespace));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkBackend {
+    Tokio,
+    IoUring,
+}
+
+impl NetworkBackend {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.trim().to_lowercase().as_str() {
+            "tokio" => Ok(NetworkBackend::Tokio),
+            "io_uring" | "io-uring" => Ok(NetworkBackend::IoUring),
+            _ => Err(format!("Invalid network backend: {}. Valid backends are: tokio, io_uring", input)),
+        }
+    }
+}
+
+// Set while the server hands over to a new process: connections answer the
+// request in hand, flush and close, so clients reconnect to the new process.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+// Set once draining timed out: connections still open are dropped on the
+// spot, so none acknowledges a write after the log has been handed over.
+static CLOSING: AtomicBool = AtomicBool::new(false);
+static DRAIN: Notify = Notify::const_new();
+static OPEN_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Closes every client connection once its current request is answered,
+/// waiting up to `timeout` for them to go. Connections still open then are
+/// dropped without answering the request they are running, waiting up to
+/// `timeout` again. Returns the number that had to be dropped.
+#[cfg(unix)]
+pub async fn drain_connections(timeout: Duration) -> usize {
+    DRAINING.store(true, Ordering::SeqCst);
+    DRAIN.notify_waiters();
+    wait_for_connections(timeout).await;
+
+    let open = OPEN_CONNECTIONS.load(Ordering::SeqCst);
+    if open > 0 {
+        CLOSING.store(true, Ordering::SeqCst);
+        DRAIN.notify_waiters();
+        wait_for_connections(timeout).await;
+    }
+    open
+}
+
+#[cfg(unix)]
+async fn wait_for_connections(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while OPEN_CONNECTIONS.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Lets connections accepted from now on stay open again.
+#[cfg(unix)]
+pub fn resume_connections() {
+    DRAINING.store(false, Ordering::SeqCst);
+    CLOSING.store(false, Ordering::SeqCst);
+}
+
+/// Resolves once connections are to finish the request in hand and close.
+pub(crate) async fn draining() {
+    wait_for(&DRAINING).await;
+}
+
+/// Resolves once connections are to close without finishing it.
+pub(crate) async fn closing() {
+    wait_for(&CLOSING).await;
+}
+
+async fn wait_for(flag: &AtomicBool) {
+    loop {
+        let notified = DRAIN.notified();
+        if flag.load(Ordering::SeqCst) {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Counts a connection as open for as long as it is held.
+pub(crate) struct OpenConnection;
+
+impl OpenConnection {
+    pub(crate) fn new() -> Self {
+        OPEN_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        OPEN_CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct TcpApiServer {
+    listener: TcpListener,
+    config: Arc<SodiumConfig>,
+    // Whether this listener accepts management commands.
+    admin: bool,
 }
 
 impl TcpApiServer {
-    pub async fn new(bind_addr: &str) -> ApiResult<Self> {
+    pub async fn new(bind_addr: &str, config: &SodiumConfig) -> ApiResult<Self> {
         let listener = TcpListener::bind(bind_addr).await?;
-        Ok(Self { listener })
+        Ok(Self { listener, config: Arc::new(config.clone()), admin: false })
+    }
+
+    /// Serves on a socket that is already bound and listening, such as one
+    /// passed in by systemd.
+    pub fn from_listener(listener: std::net::TcpListener, config: &SodiumConfig) -> ApiResult<Self> {
+        let listener = TcpListener::from_std(listener)?;
+        Ok(Self { listener, config: Arc::new(config.clone()), admin: false })
+    }
+
+    /// Serves management commands on an admin socket that is already bound,
+    /// such as one handed off by a previous process.
+    pub fn from_admin_listener(listener: std::net::TcpListener, config: &SodiumConfig) -> ApiResult<Self> {
+        let listener = TcpListener::from_std(listener)?;
+        Ok(Self { listener, config: Arc::new(config.clone()), admin: true })
+    }
+
+    /// Listener for management commands, bound to loopback only.
+    pub async fn new_admin(config: &SodiumConfig) -> ApiResult<Self> {
+        let listener = TcpListener::bind(config.admin_address()).await?;
+        Ok(Self { listener, config: Arc::new(config.clone()), admin: true })
     }
 
     pub async fn run(&self) -> ApiResult<()> {
+        // The admin listener carries little traffic and stays on tokio.
+        if !self.admin && NetworkBackend::parse(&self.config.network_backend) == Ok(NetworkBackend::IoUring) {
+            return self.run_io_uring().await;
+        }
+
         loop {
             match self.listener.accept().await {
                 Ok((stream, client_addr)) => {
+                    let config = self.config.clone();
+                    let admin = self.admin;
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_client(stream, client_addr).await {
+                        if let Err(e) = Self::handle_client(stream, client_addr, config, admin).await {
                             error!("Error handling client {}: {}", client_addr, e);
                         }
                     });
@@ -456,101 +1966,1067 @@ impl TcpApiServer {
         }
     }
 
-    async fn handle_client(stream: TcpStream, client_addr: SocketAddr) -> ApiResult<()> {
-        use tokio::io::{AsyncBufReadExt, BufReader};
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    async fn run_io_uring(&self) -> ApiResult<()> {
+        // The io_uring threads accept on their own handle to the same socket.
+        let listener = self.duplicate_listener()?;
+        crate::uring::spawn_workers(listener, self.config.clone())?;
+        std::future::pending().await
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    async fn run_io_uring(&self) -> ApiResult<()> {
+        Err(ApiError::NetworkError(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "the io_uring network backend needs a Linux build with the io-uring feature",
+        )))
+    }
+
+    async fn handle_client(stream: TcpStream, client_addr: SocketAddr, config: Arc<SodiumConfig>, admin: bool) -> ApiResult<()> {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt};
         
         let (reader, mut writer) = stream.into_split();
         let mut reader = BufReader::new(reader);
-        let mut line = String::new();
+        let mut line = Vec::new();
+        let mut session = Session::new(&config);
+        let mut responses = ResponseBuffer::default();
+        let _open = OpenConnection::new();
         
-        loop {
-            line.clear();
-            match reader.read_line(&mut line).await {
-                Ok(0) => break,
-                Ok(_) => {
-                    let request_str = line.trim();
-                    if request_str.is_empty() {
+        // Dropped mid-request, its reply unsent, once draining for a
+        // handoff times out.
+        let serve = async {
+            loop {
+                // Replies to pipelined requests wait while more complete
+                // requests are already buffered, and go out together before the
+                // next read could block, including on the rest of a request that
+                // has only partly arrived.
+                if (!reader.buffer().contains(&b'\n') || responses.is_full())
+                    && let Err(e) = responses.flush(&mut writer).await {
+                    error!("Failed to send response to {}: {}", client_addr, e);
+                    break;
+                }
+
+                // read_until keeps what it has read when an expiration event
+                // interrupts it, so a request that arrives in pieces is resumed.
+                let mut limited = (&mut reader).take(MAX_REQUEST_BYTES.saturating_sub(line.len()) as u64);
+                let read = tokio::select! {
+                    biased;
+                    _ = draining() => {
+                        if let Err(e) = responses.flush(&mut writer).await {
+                            error!("Failed to send response to {}: {}", client_addr, e);
+                        }
+                        break;
+                    }
+                    read = limited.read_until(b'\n', &mut line) => read,
+                    key = session.next_expiration() => {
+                        responses.push(session.expiration_event(&key));
                         continue;
                     }
-                    
-                    let response = match Command::parse(request_str) {
-                        Ok(command) => {
-                            info!("{}", request_str);
-                            Self::execute_command(command).await
-                        }
-                        Err(_) => {
-                            warn!("Invalid endpoint accessed: {}", request_str);
-                            format!("ERROR: Invalid endpoint format")
+                };
+                match read {
+                    Ok(0) if line.is_empty() => break,
+                    Ok(_) if line.len() >= MAX_REQUEST_BYTES && !line.ends_with(b"\n") => {
+                        error!("Request from {} exceeds {} bytes, closing the connection", client_addr, MAX_REQUEST_BYTES);
+                        break;
+                    }
+                    Ok(_) => {
+                        // Commands from one connection run strictly one at a time: the
+                        // next line is not read until this command's task has
+                        // completed on the pool. That is what gives a connection
+                        // read-your-writes regardless of which worker runs each task,
+                        // so anything that pipelines must keep this ordering.
+                        match Self::handle_line(&line, &mut session, &config, client_addr, admin, &mut reader).await {
+                            LineOutcome::Reply(reply) => responses.push(reply),
+                            LineOutcome::Empty => {}
+                            LineOutcome::Close => break,
                         }
-                    };
-                    
-                    let response_with_newline = format!("{}\n", response);
-                    if let Err(e) = writer.write_all(response_with_newline.as_bytes()).await {
-                        error!("Failed to send response to {}: {}", client_addr, e);
+                        line.clear();
+                    }
+                    Err(e) => {
+                        error!("Error reading from TCP stream {}: {}", client_addr, e);
                         break;
                     }
                 }
-                Err(e) => {
-                    error!("Error reading from TCP stream {}: {}", client_addr, e);
-                    break;
-                }
             }
+        };
+        tokio::select! {
+            biased;
+            _ = closing() => warn!("Closing connection from {} mid-request for a handoff", client_addr),
+            _ = serve => {}
         }
         
         Ok(())
     }
 
+    // Runs one request line as read off the socket, its newline included or,
+    // for the last request before EOF, missing. Shared by the network
+    // backends so they treat a connection's bytes the same way.
+    pub(crate) async fn handle_line(
+        line: &[u8],
+        session: &mut Session,
+        config: &SodiumConfig,
+        client_addr: SocketAddr,
+        admin: bool,
+        connection: &mut impl ClientConnection,
+    ) -> LineOutcome {
+        let Ok(request_str) = std::str::from_utf8(line) else {
+            error!("Error reading from TCP stream {}: stream did not contain valid UTF-8", client_addr);
+            return LineOutcome::Close;
+        };
+        let request_str = request_str.trim();
+        if request_str.is_empty() {
+            return LineOutcome::Empty;
+        }
+        match Self::handle_request(request_str, session, config, client_addr, admin, connection).await {
+            Some(reply) => LineOutcome::Reply(reply),
+            None => LineOutcome::Close,
+        }
+    }
+
+    // Runs one request line, which may hold a batch, and returns the encoded
+    // reply, or None when an injected fault drops the connection instead.
+    async fn handle_request(
+        request_str: &str,
+        session: &mut Session,
+        config: &SodiumConfig,
+        client_addr: SocketAddr,
+        admin: bool,
+        connection: &mut impl ClientConnection,
+    ) -> Option<String> {
+        let (request_id, request_str) = split_request_id(request_str);
+        let span = match request_id {
+            Some(id) => info_span!("request", id = %id),
+            None => Span::none(),
+        };
+        // Injected faults stay off the admin port so a chaos-testing
+        // server can still be managed.
+        let fault = (!admin && chaos::is_enabled(config))
+            .then(|| chaos::pick(config))
+            .flatten();
+        // A batch runs its commands in order on this connection and
+        // answers with each command's reply, as one array under protocol 2.
+        let mut batched = false;
+        let mut reply = match split_batch(request_str) {
+            _ if matches!(fault, Some(Fault::Error)) => {
+                error_response(ErrorCode::Busy, "Injected fault, retry the command")
+            }
+            Some(commands) if commands.len() > MAX_BATCH_COMMANDS => {
+                error_response(ErrorCode::Syntax, format!("A batch holds at most {} commands", MAX_BATCH_COMMANDS))
+            }
+            Some(commands) => {
+                batched = true;
+                let mut replies = Vec::with_capacity(commands.len());
+                for command in commands {
+                    let reply = Self::respond(command.trim(), session, config, client_addr, admin, connection)
+                        .instrument(span.clone())
+                        .await;
+                    replies.push(reply);
+                }
+                Reply::Array(replies)
+            }
+            None => Self::respond(request_str, session, config, client_addr, admin, connection)
+                .instrument(span)
+                .await,
+        };
+        if let Some(id) = request_id {
+            let replies = match &mut reply {
+                Reply::Array(replies) => replies.as_mut_slice(),
+                reply => std::slice::from_mut(reply),
+            };
+            for reply in replies {
+                if let Reply::Error(message) = reply {
+                    message.push_str(&format!(" (request {})", id));
+                }
+            }
+        }
+        
+        match fault {
+            Some(Fault::Delay(delay)) => tokio::time::sleep(delay).await,
+            Some(Fault::Drop) => {
+                warn!("Dropping connection to {} (injected fault)", client_addr);
+                return None;
+            }
+            _ => {}
+        }
+
+        // Protocol 1 would join the replies of a batch into one line, where
+        // a value holding spaces could not be told from two replies, so each
+        // reply goes on a line of its own instead.
+        let mut response = String::new();
+        match reply {
+            Reply::Array(replies) if batched && session.protocol < protocol::TYPED_PROTOCOL => {
+                for reply in replies {
+                    response.push_str(&reply.encode(session.protocol));
+                    response.push('\n');
+                }
+            }
+            reply => {
+                response.push_str(&reply.encode(session.protocol));
+                response.push('\n');
+            }
+        }
+        Some(response)
+    }
+
+    async fn respond(
+        request_str: &str,
+        session: &mut Session,
+        config: &SodiumConfig,
+        client_addr: SocketAddr,
+        admin: bool,
+        connection: &mut impl ClientConnection,
+    ) -> Reply {
+        let (token, request_str) = split_idempotency_token(request_str);
+        let mut request = Request { text: request_str, client_addr, admin, config, command: None, key: None };
+        let started = Instant::now();
+        let mut reply = match interceptors::pre_parse(session, &request) {
+            Some(reply) => reply,
+            None => match Command::parse(request_str) {
+                Ok(command) => {
+                    request.command = Some(command.name());
+                    request.key = command.key().map(str::to_string);
+                    Self::dispatch(command, token, &request, session, connection).await
+                }
+                Err(_) => {
+                    warn!("Invalid endpoint accessed: {}", request_str);
+                    error_response(ErrorCode::Syntax, "Invalid endpoint format")
+                }
+            },
+        };
+        interceptors::post_execute(session, &request, &mut reply, started.elapsed());
+        reply
+    }
+
+    async fn dispatch(
+        command: Command,
+        token: Option<&str>,
+        request: &Request<'_>,
+        session: &mut Session,
+        connection: &mut impl ClientConnection,
+    ) -> Reply {
+        let &Request { text: request_str, client_addr, config, .. } = request;
+        if let Some(reply) = interceptors::pre_execute(session, request, &command) {
+            return reply;
+        }
+
+        match command {
+            Command::Auth { token } => {
+                if session.authenticate(&token, config) {
+                    Reply::ok()
+                } else {
+                    warn!("Failed authentication attempt from {}", client_addr);
+                    error_response(ErrorCode::Auth, "Invalid token")
+                }
+            }
+            Command::Hello { version } => match version {
+                None => Reply::Integer(session.protocol as i64),
+                Some(version @ protocol::DEFAULT_PROTOCOL..=protocol::TYPED_PROTOCOL) => {
+                    session.protocol = version;
+                    // Protocol 1 has no frame to push events in.
+                    if version < protocol::TYPED_PROTOCOL {
+                        session.expirations = None;
+                    }
+                    Reply::ok()
+                }
+                Some(version) => error_response(
+                    ErrorCode::NoProto,
+                    format!("Unsupported protocol version {}, supported versions are 1 and 2", version),
+                ),
+            },
+            Command::OnExpire { pattern } => {
+                if !connection.can_push() {
+                    return error_response(ErrorCode::NoProto, "Expiration events are not available on this network backend");
+                }
+                // Plain replies have nothing to tell an event from the reply
+                // to the next command.
+                if session.protocol < protocol::TYPED_PROTOCOL {
+                    return error_response(ErrorCode::NoProto, "onexpire() needs protocol 2, switch with hello(2)");
+                }
+                let feed = session.expirations.get_or_insert_with(ExpirationFeed::new);
+                Reply::Integer(feed.listen(pattern) as i64)
+            }
+            Command::Shutdown => {
+                crate::request_shutdown();
+                Reply::ok()
+            }
+            // Space separated plain values cannot be told apart when they
+            // hold spaces themselves, or from a miss when they read NULL.
+            Command::MGet { .. } if session.protocol < protocol::TYPED_PROTOCOL => {
+                error_response(ErrorCode::NoProto, "mget() needs protocol 2, switch with hello(2)")
+            }
+            command => {
+                let token = token.filter(|_| command.is_mutating() && config.idempotency_window_secs > 0);
+                let mut pending = None;
+                if let Some(token) = token {
+                    let window = Duration::from_secs(config.idempotency_window_secs);
+                    match idempotency::claim(session.namespace.as_deref(), token, request_str, window, config.idempotency_max_tokens).await {
+                        Claim::Run(claim) => pending = Some(claim),
+                        Claim::Replay(reply) => {
+                            info!("Replayed reply for idempotency token {}", token);
+                            return reply;
+                        }
+                        Claim::Mismatch => {
+                            return error_response(ErrorCode::Syntax, "Idempotency token was already used for a different command");
+                        }
+                        Claim::Full => {
+                            return error_response(ErrorCode::Busy, "Too many idempotency tokens are being kept, retry later");
+                        }
+                    }
+                }
+
+                let bulk_read = command.is_bulk_read();
+                let cancelled = Arc::new(AtomicBool::new(false));
+                let execution = Self::execute_with_timeout(
+                    command,
+                    config,
+                    cancelled.clone(),
+                    session.namespace.as_deref(),
+                    request_str,
+                    client_addr,
+                );
+                tokio::pin!(execution);
+
+                // A client that goes away mid-command flags the work as
+                // cancelled. Bulk reads are dropped outright, so a task still
+                // queued is skipped by the worker; anything else finishes and
+                // its response is still attempted in case only the write half
+                // was closed.
+                let response = tokio::select! {
+                    response = &mut execution => response,
+                    _ = connection.closed() => {
+                        cancelled.store(true, Ordering::Relaxed);
+                        if bulk_read {
+                            warn!("Client {} disconnected, abandoning: {}", client_addr, request_str);
+                            return error_response(ErrorCode::Cancelled, "Client disconnected");
+                        }
+                        execution.await
+                    }
+                };
+                aof::wait_for_commit().await;
+                // Failures are not remembered, so a retry after BUSY or a
+                // timeout still gets to run.
+                if let Some(pending) = pending
+                    && !response.is_error() {
+                    pending.complete(response.clone());
+                }
+                response
+            }
+        }
+    }
+
+    // Bounds a command by command_timeout_ms. The worker cannot be preempted,
+    // so a timed-out command is flagged cancelled and its result discarded.
+    async fn execute_with_timeout(
+        command: Command,
+        config: &SodiumConfig,
+        cancelled: Arc<AtomicBool>,
+        namespace: Option<&str>,
+        request: &str,
+        client_addr: SocketAddr,
+    ) -> Reply {
+        if config.command_timeout_ms == 0 {
+            return Self::execute_command(command, config, cancelled, namespace, client_addr).await;
+        }
+
+        // Blocking reads are allowed their requested wait on top of the limit.
+        let mut timeout = Duration::from_millis(config.command_timeout_ms);
+        if let Command::Xread { block: Some(block), .. } = &command {
+            timeout += *block;
+        }
+
+        match tokio::time::timeout(timeout, Self::execute_command(command, config, cancelled.clone(), namespace, client_addr)).await {
+            Ok(response) => response,
+            Err(_) => {
+                cancelled.store(true, Ordering::Relaxed);
+                warn!("Command timed out after {}ms: {}", timeout.as_millis(), request);
+                error_response(ErrorCode::Timeout, "Command timed out")
+            }
+        }
+    }
+
+    // Returns entries after `after`, waiting up to `block` for the first one
+    // to be appended when there are none yet.
+    async fn read_stream(
+        key: String,
+        after: u64,
+        block: Option<Duration>,
+        cancelled: &AtomicBool,
+    ) -> threading::TaskResult<Vec<StreamEntry>> {
+        let start = after.saturating_add(1);
+        let Some(block) = block else {
+            return threading::execute_cache_stream_range(key, start, u64::MAX).await;
+        };
+
+        let deadline = Instant::now() + block;
+        let notify = get_cache().stream_notifier(&key);
+        let result = loop {
+            // Registered before reading so an append in between still wakes us.
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let entries = match threading::execute_cache_stream_range(key.clone(), start, u64::MAX).await {
+                Ok(entries) => entries,
+                Err(e) => break Err(e),
+            };
+
+            let now = Instant::now();
+            if !entries.is_empty() || now >= deadline || cancelled.load(Ordering::Relaxed) {
+                break Ok(entries);
+            }
+
+            let _ = tokio::time::timeout((deadline - now).min(STREAM_BLOCK_POLL), notified).await;
+        };
 
+        drop(notify);
+        get_cache().release_stream_notifier(&key);
+        result
+    }
+
+    fn format_stream_entries(entries: Vec<StreamEntry>) -> Reply {
+        let entries: Vec<serde_json::Value> = entries.into_iter()
+            .map(|(id, value)| serde_json::json!({ "id": id, "value": value }))
+            .collect();
+        Reply::Json(serde_json::Value::Array(entries))
+    }
+
+    fn key_list(keys: Vec<String>) -> Reply {
+        Reply::Array(keys.into_iter().map(Reply::Bulk).collect())
+    }
+
+    // Starts after the key `cursor` names and stops before the reply would
+    // pass `max_bytes`. A cut-short page is followed by "(partial)" and its
+    // last key, the cursor that continues it; at least one key is always
+    // returned so paging makes progress. Like scan(), the cursor is a key
+    // rather than a position, so keys written or deleted between pages do
+    // not shift it: unordered keys are sorted for paging, and only the
+    // access order, which moves on every read, resumes by position.
+    fn key_page(mut keys: Vec<String>, sort: Option<SortOrder>, cursor: Option<String>, max_bytes: usize) -> Reply {
+        if sort.is_none() && (max_bytes > 0 || cursor.is_some()) {
+            keys.sort_unstable();
+        }
+        let start = match (&cursor, sort) {
+            (None, _) => 0,
+            (Some(after), None | Some(SortOrder::Ascending)) => keys.partition_point(|key| key <= after),
+            (Some(after), Some(SortOrder::Descending)) => keys.partition_point(|key| key >= after),
+            // Access order has no place to resume from once the cursor key is
+            // gone; starting over would hand out the same pages again.
+            (Some(after), Some(SortOrder::Accessed)) => match keys.iter().position(|key| key == after) {
+                Some(index) => index + 1,
+                None => return error_response(ErrorCode::NotFound, "Stale cursor: the key it names was deleted or expired"),
+            },
+        };
+
+        let mut page = Vec::new();
+        let mut size = 0;
+        for key in keys.into_iter().skip(start) {
+            size += key.len() + 1;
+            if max_bytes > 0 && size > max_bytes && !page.is_empty() {
+                let next = page.last().cloned().unwrap_or_default();
+                return Reply::Array(vec![
+                    Self::key_list(page),
+                    Reply::Status("(partial)".to_string()),
+                    Reply::Bulk(next),
+                ]);
+            }
+            page.push(key);
+        }
+        Self::key_list(page)
+    }
 
-    async fn execute_command(command: Command) -> String {
+    async fn execute_command(
+        command: Command,
+        config: &SodiumConfig,
+        cancelled: Arc<AtomicBool>,
+        namespace: Option<&str>,
+        client_addr: SocketAddr,
+    ) -> Reply {
         match command {
-            Command::Set { key, value } => {
-                match threading::execute_cache_set(key, value).await {
-                    Ok(()) => "OK".to_string(),
-                    Err(e) => format!("ERROR: {}", e)
+            Command::Set { key, value, mut options } => {
+                // A backing store would serve the value on a miss before it
+                // is due, and nothing writes it there when it becomes visible.
+                if options.visible_at.is_some() && backing::is_enabled() {
+                    return error_response(ErrorCode::Backend, "Scheduled writes are not supported with a backing store");
+                }
+                if let Err(e) = backing::write(&key, &value).await {
+                    return failure(&*e);
+                }
+                options.writer = Some(client_addr);
+                match threading::execute_cache_set(key, value, options).await {
+                    Ok(()) => Reply::ok(),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Get { key, early } => {
+                let miss_key = backing::is_enabled().then(|| key.clone());
+                let result = match early {
+                    Some(recompute) => threading::execute_cache_get_early(key, recompute).await,
+                    None => threading::execute_cache_get(key).await,
+                };
+                match result {
+                    Ok(Some(value)) => Reply::Value(value),
+                    Ok(None) => match miss_key {
+                        Some(key) => match backing::load_on_miss(&key).await {
+                            Ok(Some(value)) => Reply::Bulk(value),
+                            Ok(None) => Reply::Null,
+                            Err(e) => failure(&*e)
+                        },
+                        None => Reply::Null,
+                    },
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Setex { key, value, ttl, sliding } => {
+                if let Err(e) = backing::write(&key, &value).await {
+                    return failure(&*e);
+                }
+                let options = SetOptions { ttl: Some(ttl), sliding, writer: Some(client_addr), ..SetOptions::default() };
+                match threading::execute_cache_set(key, value, options).await {
+                    Ok(()) => Reply::ok(),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::GetOrSet { key, value, ttl } => {
+                let options = SetOptions { ttl, writer: Some(client_addr), ..SetOptions::default() };
+                match threading::execute_cache_get_or_set(key, value, options).await {
+                    Ok(value) => Reply::Value(value),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::GetSet { key, value } => {
+                // The value being replaced may only be in the backing store. The
+                // read also refuses keys of another type before the backing store
+                // is written.
+                if backing::is_enabled() {
+                    match threading::execute_cache_get(key.clone()).await {
+                        Ok(Some(_)) => {}
+                        Ok(None) => if let Err(e) = backing::load_on_miss(&key).await {
+                            return failure(&*e);
+                        },
+                        Err(e) => return failure(&*e),
+                    }
+                }
+                if let Err(e) = backing::write(&key, &value).await {
+                    return failure(&*e);
+                }
+                match threading::execute_cache_get_set(key, value, Some(client_addr)).await {
+                    Ok(Some(previous)) => Reply::Value(previous),
+                    Ok(None) => Reply::Null,
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::GetDel { key } => {
+                if backing::is_enabled() {
+                    match threading::execute_cache_get(key.clone()).await {
+                        Ok(Some(_)) => {}
+                        Ok(None) => if let Err(e) = backing::load_on_miss(&key).await {
+                            return failure(&*e);
+                        },
+                        Err(e) => return failure(&*e),
+                    }
+                }
+                if let Err(e) = backing::remove(&key).await {
+                    return failure(&*e);
+                }
+                match threading::execute_cache_get_del(key).await {
+                    Ok(Some(value)) => Reply::Value(value),
+                    Ok(None) => Reply::Null,
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::MGet { keys } => {
+                let miss_keys = backing::is_enabled().then(|| keys.clone());
+                match threading::execute_cache_mget(keys).await {
+                    Ok(values) => {
+                        let mut reply = Vec::with_capacity(values.len());
+                        for (index, value) in values.into_iter().enumerate() {
+                            reply.push(match (value, &miss_keys) {
+                                (Some(value), _) => Reply::Value(value),
+                                (None, Some(keys)) => match backing::load_on_miss(&keys[index]).await {
+                                    Ok(Some(value)) => Reply::Bulk(value),
+                                    Ok(None) => Reply::Null,
+                                    Err(e) => return failure(&*e),
+                                },
+                                (None, None) => Reply::Null,
+                            });
+                        }
+                        Reply::Array(reply)
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::MSet { pairs } => {
+                for (key, value) in &pairs {
+                    if let Err(e) = backing::write(key, value).await {
+                        return failure(&*e);
+                    }
+                }
+                match threading::execute_cache_mset(pairs, Some(client_addr)).await {
+                    Ok(()) => Reply::ok(),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::IncrBy { key, delta } => {
+                // A counter missing from the cache may still be in the backing
+                // store, and the result is written back like any other write.
+                if backing::is_enabled()
+                    && let Ok(None) = threading::execute_cache_get(key.clone()).await
+                    && let Err(e) = backing::load_on_miss(&key).await {
+                    return failure(&*e);
+                }
+                match threading::execute_cache_incr_by(key.clone(), delta, Some(client_addr)).await {
+                    Ok(value) => match backing::write(&key, &value.to_string()).await {
+                        Ok(()) => Reply::Integer(value),
+                        Err(e) => failure(&*e),
+                    },
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::SetBit { key, offset, bit } => {
+                match threading::execute_cache_set_bit(key, offset, bit).await {
+                    Ok(previous) => Reply::Integer(previous as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::GetBit { key, offset } => {
+                match threading::execute_cache_get_bit(key, offset).await {
+                    Ok(bit) => Reply::Integer(bit as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::BitCount { key } => {
+                match threading::execute_cache_bit_count(key).await {
+                    Ok(count) => Reply::Integer(count as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Xadd { key, value, max_len } => {
+                let max_len = max_len.unwrap_or(config.stream_max_len);
+                match threading::execute_cache_stream_add(key, value, max_len).await {
+                    Ok(id) => Reply::Integer(id as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::BfAdd { key, item, capacity, error_rate } => {
+                let capacity = capacity.unwrap_or(config.bloom_capacity);
+                let error_rate = error_rate.unwrap_or(config.bloom_error_rate);
+                if sketches::bloom_dimensions(capacity, error_rate).0 > sketches::MAX_BLOOM_BITS {
+                    return error_response(ErrorCode::Syntax, "Bloom filter would exceed 512MB; lower capacity() or raise error()");
+                }
+                match threading::execute_cache_bloom_add(key, item, capacity, error_rate).await {
+                    Ok(added) => Reply::Integer(added as i64),
+                    Err(e) => failure(&*e)
                 }
             }
-            Command::Get { key } => {
-                match threading::execute_cache_get(key).await {
-                    Ok(Some(value)) => value,
-                    Ok(None) => "NULL".to_string(),
-                    Err(e) => format!("ERROR: {}", e)
+            Command::BfExists { key, item } => {
+                match threading::execute_cache_bloom_exists(key, item).await {
+                    Ok(exists) => Reply::Integer(exists as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::CfAdd { key, item, capacity } => {
+                let capacity = capacity.unwrap_or(config.cuckoo_capacity);
+                match threading::execute_cache_cuckoo_add(key, item, capacity).await {
+                    Ok(()) => Reply::Integer(1),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::CfExists { key, item } => {
+                match threading::execute_cache_cuckoo_exists(key, item).await {
+                    Ok(exists) => Reply::Integer(exists as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::CfDel { key, item } => {
+                match threading::execute_cache_cuckoo_delete(key, item).await {
+                    Ok(removed) => Reply::Integer(removed as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::TopKAdd { key, item, k } => {
+                let k = k.unwrap_or(config.topk_size as usize);
+                match threading::execute_cache_topk_add(key, item, k).await {
+                    Ok(count) => Reply::Integer(count as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::TopKQuery { key, item } => {
+                match threading::execute_cache_topk_query(key, item).await {
+                    Ok(count) => Reply::Integer(count as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::TopKList { key } => {
+                match threading::execute_cache_topk_list(key).await {
+                    Ok(items) => {
+                        let items: Vec<serde_json::Value> = items.into_iter()
+                            .map(|(item, count)| serde_json::json!({ "item": item, "count": count }))
+                            .collect();
+                        Reply::Json(serde_json::Value::Array(items))
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Xrange { key, start, end } => {
+                match threading::execute_cache_stream_range(key, start, end).await {
+                    Ok(entries) => Self::format_stream_entries(entries),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Xread { key, after, block } => {
+                match Self::read_stream(key, after, block, &cancelled).await {
+                    Ok(entries) => Self::format_stream_entries(entries),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Meta { key } => {
+                match threading::execute_cache_metadata(key).await {
+                    Ok(Some(metadata)) => {
+                        let object: serde_json::Map<String, serde_json::Value> = metadata.into_iter()
+                            .map(|(name, value)| (name, serde_json::Value::String(value)))
+                            .collect();
+                        Reply::Json(serde_json::Value::Object(object))
+                    }
+                    Ok(None) => Reply::Null,
+                    Err(e) => failure(&*e)
                 }
             }
             Command::Delete { key } => {
+                if let Err(e) = backing::remove(&key).await {
+                    return failure(&*e);
+                }
                 match threading::execute_cache_delete(key).await {
                     Ok(existed) => {
-                        if existed {
-                            "1".to_string()
-                        } else {
-                            "0".to_string()
+                        Reply::Integer(existed as i64)
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::History { key } => {
+                match threading::execute_cache_history(key).await {
+                    Ok(versions) => {
+                        let versions: Vec<serde_json::Value> = versions.into_iter()
+                            .enumerate()
+                            .map(|(index, version)| serde_json::json!({
+                                "version": index + 1,
+                                "value": &*version.value,
+                                "written_at": version.written_at,
+                                "writer": version.writer.map(|writer| writer.to_string()),
+                            }))
+                            .collect();
+                        Reply::Json(serde_json::Value::Array(versions))
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::GetVersion { key, n } => {
+                match threading::execute_cache_get_version(key, n).await {
+                    Ok(Some(value)) => Reply::Value(value),
+                    Ok(None) => Reply::Null,
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Undelete { key } => {
+                match threading::execute_cache_undelete(key.clone()).await {
+                    Ok(restored) => {
+                        // The delete removed the key from the backing store
+                        // too, so the restored value is written back.
+                        if restored
+                            && backing::is_enabled()
+                            && let Ok(Some(value)) = threading::execute_cache_get(key.clone()).await
+                            && let Err(e) = backing::write(&key, &value).await {
+                            return failure(&*e);
                         }
+                        Reply::Integer(restored as i64)
                     }
-                    Err(e) => format!("ERROR: {}", e)
+                    Err(e) => failure(&*e)
                 }
             }
-            Command::Keys => {
-                match threading::execute_cache_keys().await {
-                    Ok(keys) => {
-                        if keys.is_empty() {
-                            "(empty)".to_string()
+            Command::Keys { sort, cursor } => {
+                match threading::execute_cache_keys(sort).await {
+                    Ok(mut keys) => {
+                        retain_namespace(&mut keys, namespace);
+                        Self::key_page(keys, sort, cursor, config.max_response_bytes)
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Scan { cursor, count } => {
+                match threading::execute_cache_scan(cursor, count).await {
+                    Ok(mut page) => {
+                        retain_namespace(&mut page.keys, namespace);
+                        // The next cursor comes first, followed by the keys.
+                        let mut reply = vec![Reply::Bulk(page.cursor.to_string())];
+                        reply.extend(page.keys.into_iter().map(Reply::Bulk));
+                        Reply::Array(reply)
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Search { search_type, queries, sort, cursor } => {
+                let deadline = (config.search_timeout_ms > 0)
+                    .then(|| Instant::now() + Duration::from_millis(config.search_timeout_ms));
+                match threading::execute_cache_search_multiple(search_type, queries, sort, deadline, cancelled).await {
+                    Ok(mut result) => {
+                        retain_namespace(&mut result.keys, namespace);
+                        let keys = Self::key_page(result.keys, sort, cursor, config.max_response_bytes);
+                        if result.truncated {
+                            Reply::Array(vec![keys, Reply::Status("(truncated)".to_string())])
                         } else {
-                            keys.join(" ")
+                            keys
                         }
                     }
-                    Err(e) => format!("ERROR: {}", e)
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Tag { key, tag } => {
+                match threading::execute_cache_tag(key, tag).await {
+                    Ok(tagged) => {
+                        Reply::Integer(tagged as i64)
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Expire { key, ttl } => {
+                match threading::execute_cache_expire(key, ttl).await {
+                    Ok(expiring) => Reply::Integer(expiring as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Ttl { key } => {
+                // Seconds left, rounded up, or -1 for a key that never expires.
+                match threading::execute_cache_ttl(key).await {
+                    Ok(Some(Some(ttl))) => Reply::Integer(ttl.as_millis().div_ceil(1000) as i64),
+                    Ok(Some(None)) => Reply::Integer(-1),
+                    Ok(None) => Reply::Null,
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::KeysByTag { tag } => {
+                match threading::execute_cache_keys_by_tag(tag).await {
+                    Ok(mut keys) => {
+                        retain_namespace(&mut keys, namespace);
+                        Self::key_list(keys)
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::DeleteByTag { tag } => {
+                match threading::execute_cache_delete_by_tag(tag, namespace.map(str::to_string)).await {
+                    Ok(deleted) => Reply::Integer(deleted as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Invalidate { namespace } => {
+                match threading::execute_cache_invalidate(namespace).await {
+                    Ok(generation) => Reply::Integer(generation as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Lock { key, ttl } => {
+                match threading::execute_cache_lock(key, ttl).await {
+                    Ok(Some(token)) => Reply::Integer(token as i64),
+                    Ok(None) => Reply::Null,
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Unlock { key, token } => {
+                match threading::execute_cache_unlock(key, token).await {
+                    Ok(true) => Reply::Integer(1),
+                    Ok(false) => Reply::Integer(0),
+                    Err(e) => failure(&*e)
+                }
+            }
+            // Authentication, hello() and onexpire() change connection state, so respond()
+            // deals with them before dispatch.
+            Command::Auth { .. } => error_response(ErrorCode::Internal, "auth() cannot be executed here"),
+            Command::Hello { .. } => error_response(ErrorCode::Internal, "hello() cannot be executed here"),
+            Command::OnExpire { .. } => error_response(ErrorCode::Internal, "onexpire() cannot be executed here"),
+            Command::Defrag => {
+                match crate::background::start_defrag(config.defrag_keys_per_sec) {
+                    Ok(true) => Reply::ok(),
+                    Ok(false) => error_response(ErrorCode::Busy, "A defrag is already running"),
+                    Err(e) => error_response(ErrorCode::Internal, format!("Failed to start defrag: {}", e)),
+                }
+            }
+            Command::Shutdown => error_response(ErrorCode::Internal, "shutdown() cannot be executed here"),
+            Command::Plugin { name, args } => {
+                match threading::execute_plugin(name, args).await {
+                    Ok(reply) => reply,
+                    Err(e) => failure(&*e)
+                }
+            }
+            // Wall clock as seconds and microseconds since the Unix epoch,
+            // followed by the server's uptime in milliseconds.
+            Command::Time => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                Reply::Array(vec![
+                    Reply::Integer(now.as_secs() as i64),
+                    Reply::Integer(now.subsec_micros() as i64),
+                    Reply::Integer(get_cache().uptime().as_millis() as i64),
+                ])
+            }
+            Command::Debug(_) if !config.debug_commands => {
+                error_response(ErrorCode::NoPerm, "debug() is disabled, enable debug_commands to use it")
+            }
+            Command::Debug(DebugCommand::Sleep(duration)) => {
+                tokio::time::sleep(duration).await;
+                Reply::ok()
+            }
+            Command::Debug(DebugCommand::Object { key }) => {
+                match threading::execute_cache_object_info(key).await {
+                    Ok(info) => Reply::Json(serde_json::json!({
+                        "kind": info.kind,
+                        "memory": info.memory,
+                        "accessed_at": info.accessed_at,
+                        "expires_at": info.expires_at,
+                        "sliding_ttl": info.sliding_ttl,
+                        "tags": info.tags,
+                        "generation": info.generation,
+                    })),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Debug(DebugCommand::SetAccessTime { key, age }) => {
+                match threading::execute_cache_set_access_time(key, age).await {
+                    Ok(()) => Reply::ok(),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::Stats => {
+                match threading::execute_cache_stats().await {
+                    Ok(stats) => Reply::Bulk(format!(
+                        "keys={} used_memory={} max_memory={} total_operations={} hits={} misses={} evicted_keys={} expired_keys={}",
+                        stats.keys,
+                        stats.used_memory,
+                        stats.max_memory,
+                        stats.total_operations,
+                        stats.hits,
+                        stats.misses,
+                        stats.evicted_keys,
+                        stats.expired_keys,
+                    )),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::PrefixStats { prefix } => {
+                let namespace = prefix.trim_end_matches(':').to_string();
+                match threading::execute_cache_prefix_stats(namespace).await {
+                    Ok(Some(stats)) => {
+                        let lookups = stats.hits + stats.misses;
+                        let hit_ratio = if lookups == 0 { 0.0 } else { stats.hits as f64 / lookups as f64 };
+                        Reply::Bulk(format!(
+                            "prefix={} keys={} bytes={} hits={} misses={} hit_ratio={:.4}",
+                            prefix,
+                            stats.keys,
+                            stats.bytes,
+                            stats.hits,
+                            stats.misses,
+                            hit_ratio,
+                        ))
+                    }
+                    Ok(None) => error_response(ErrorCode::NoPerm, "Prefix statistics are disabled, enable prefix_stats to use them"),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::BreakdownStats { by_key_pattern } => {
+                let (label, breakdown) = if by_key_pattern {
+                    ("pattern", metrics::key_pattern_breakdown())
+                } else {
+                    ("command", metrics::command_breakdown())
+                };
+                let lines = breakdown.into_iter()
+                    .map(|(name, totals)| Reply::Bulk(format!(
+                        "{}={} calls={} errors={} usec={} usec_per_call={:.2}",
+                        label,
+                        name,
+                        totals.calls,
+                        totals.errors,
+                        totals.micros,
+                        totals.micros as f64 / totals.calls.max(1) as f64,
+                    )))
+                    .collect();
+                Reply::Array(lines)
+            }
+            // Read straight from the pool rather than through a task, so it
+            // still answers when the queues are full.
+            Command::QueueStats => {
+                let pool = threading::get_thread_pool();
+                let (waited, dequeued) = pool.queue_wait();
+                let mut lines = vec![Reply::Bulk(format!(
+                    "queued={} capacity={} dequeued={} wait_usec={} wait_usec_per_task={:.2} task_usec={}",
+                    pool.queue_depth(),
+                    pool.queue_capacity(),
+                    dequeued,
+                    waited.as_micros(),
+                    waited.as_micros() as f64 / dequeued.max(1) as f64,
+                    pool.average_task_time().as_micros(),
+                ))];
+                lines.extend(pool.worker_queues().into_iter().enumerate().map(|(worker, (depth, wait))| {
+                    Reply::Bulk(format!("worker={} depth={} wait_usec={}", worker, depth, wait.as_micros()))
+                }));
+                Reply::Array(lines)
+            }
+            Command::Memory => {
+                match threading::execute_cache_stats().await {
+                    Ok(stats) => Reply::Bulk(format!(
+                        "used_memory={} max_memory={} keys={} entry_memory={} history_memory={} avg_entry_size={}",
+                        stats.used_memory,
+                        stats.max_memory,
+                        stats.keys,
+                        stats.used_memory.saturating_sub(stats.history_memory),
+                        stats.history_memory,
+                        stats.used_memory.saturating_sub(stats.history_memory).checked_div(stats.keys).unwrap_or(0),
+                    )),
+                    Err(e) => failure(&*e)
                 }
             }
-            Command::Search { search_type, queries } => {
-                match threading::execute_cache_search_multiple(search_type, queries).await {
-                    Ok(keys) => {
-                        if keys.is_empty() {
-                            "(empty)".to_string()
+            Command::MemoryUsage { key } => {
+                match threading::execute_cache_key_memory(key).await {
+                    Ok(bytes) => Reply::Integer(bytes as i64),
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::MemoryDoctor => {
+                match threading::execute_cache_memory_doctor().await {
+                    Ok(report) => {
+                        let largest = report.largest_keys.iter()
+                            .map(|(key, size)| format!("{}:{}", key, size))
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        let suggestions = if report.suggestions.is_empty() {
+                            "no issues detected".to_string()
                         } else {
-                            keys.join(" ")
-                        }
+                            report.suggestions.join("; ")
+                        };
+                        Reply::Bulk(format!(
+                            "entries={} used_memory={} allocated_memory={} avg_entry_size={} fragmentation={:.2} largest={} suggestions: {}",
+                            report.entries,
+                            report.used_memory,
+                            report.allocated_memory,
+                            report.average_entry_size,
+                            report.fragmentation,
+                            if largest.is_empty() { "(empty)".to_string() } else { largest },
+                            suggestions,
+                        ))
+                    }
+                    Err(e) => failure(&*e)
+                }
+            }
+            Command::BigKeys { count } => {
+                match threading::execute_cache_big_keys(count).await {
+                    Ok(report) => {
+                        let largest = report.largest.iter()
+                            .map(|(key, size)| format!("{}:{}", key, size))
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        Reply::Bulk(format!(
+                            "scanned={} largest={}",
+                            report.scanned,
+                            if largest.is_empty() { "(empty)".to_string() } else { largest },
+                        ))
                     }
-                    Err(e) => format!("ERROR: {}", e)
+                    Err(e) => failure(&*e)
                 }
             }
         }
@@ -559,5 +3035,19 @@ impl TcpApiServer {
     pub fn local_addr(&self) -> ApiResult<SocketAddr> {
         Ok(self.listener.local_addr()?)
     }
+
+    /// A second handle to the listening socket, for passing to another
+    /// process.
+    #[cfg(unix)]
+    pub fn duplicate_listener(&self) -> ApiResult<std::net::TcpListener> {
+        use std::os::fd::AsFd;
+        Ok(std::net::TcpListener::from(self.listener.as_fd().try_clone_to_owned()?))
+    }
+
+    #[cfg(windows)]
+    pub fn duplicate_listener(&self) -> ApiResult<std::net::TcpListener> {
+        use std::os::windows::io::AsSocket;
+        Ok(std::net::TcpListener::from(self.listener.as_socket().try_clone_to_owned()?))
+    }
 }
 
@@ -0,0 +1,235 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Maintenance work (snapshots, AOF rewrites, expiry and tombstone sweeps
+// and eviction) runs on one low-priority thread of its own instead of on
+// the workers serving commands, so a long snapshot or a large eviction pass
+// never sits in front of a client's request.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::configuration::SodiumConfig;
+use crate::core::get_cache;
+use crate::{aof, snapshot, threading};
+
+// Niceness of the background thread; higher runs less eagerly.
+#[cfg(target_os = "linux")]
+const BACKGROUND_NICE: i32 = 10;
+
+// Longest the thread sleeps with nothing scheduled, as a backstop for a
+// missed wake-up.
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+static EVICTS: AtomicBool = AtomicBool::new(false);
+static DEFRAGGING: AtomicBool = AtomicBool::new(false);
+static WOKEN: Mutex<bool> = Mutex::new(false);
+static WAKE: Condvar = Condvar::new();
+
+/// Periodic work run on the background thread.
+pub struct Job {
+    name: &'static str,
+    every: Duration,
+    next: Instant,
+    run: Box<dyn FnMut() + Send>,
+}
+
+impl Job {
+    pub fn new(name: &'static str, every: Duration, run: impl FnMut() + Send + 'static) -> Self {
+        Self { name, every, next: Instant::now() + every, run: Box::new(run) }
+    }
+}
+
+/// Starts the background thread with the jobs `config` enables. In
+/// deterministic mode eviction stays with the single worker, and expiry
+/// sweeps are handed to it as tasks, so both run between commands rather
+/// than alongside them.
+pub fn start(config: &SodiumConfig) -> io::Result<()> {
+    let mut jobs: Vec<Job> = snapshot::snapshot_job(config).into_iter().collect();
+    jobs.extend(aof::rewrite_job(config));
+    if config.expiry_sweep_interval_ms > 0 {
+        let deterministic = config.deterministic;
+        let mut shard = 0;
+        jobs.push(Job::new("expiry sweep", Duration::from_millis(config.expiry_sweep_interval_ms), move || {
+            shard = if deterministic { threading::execute_expiry_sweep(shard) } else { sweep_expired(shard) };
+        }));
+    }
+
+    // Deletes purge tombstones too, but only every so often, which a quiet
+    // spell after a wave of them would never reach.
+    if config.tombstone_retention_secs > 0 {
+        jobs.push(Job::new("tombstone purge", Duration::from_secs(config.tombstone_retention_secs), || {
+            get_cache().purge_tombstones();
+        }));
+    }
+
+    let evicts = config.max_memory > 0 && !config.deterministic;
+    if jobs.is_empty() && !evicts {
+        return Ok(());
+    }
+
+    EVICTS.store(evicts, Ordering::Relaxed);
+    thread::Builder::new()
+        .name("sodium-background".to_string())
+        .spawn(move || run(jobs, evicts))?;
+    Ok(())
+}
+
+/// One expiry sweep pass: due scheduled writes, the storage shard `shard`,
+/// and lapsed leases once per walk of the shards. Returns the shard to
+/// sweep next.
+pub fn sweep_expired(shard: usize) -> usize {
+    let cache = get_cache();
+    cache.activate_scheduled();
+    let next = cache.sweep_shard(shard);
+    // Leases are few, so one pass per walk of the storage shards is enough.
+    if next == 0 {
+        cache.sweep_leases();
+    }
+    next
+}
+
+/// Whether eviction is left to the background thread.
+pub fn evicts() -> bool {
+    EVICTS.load(Ordering::Relaxed)
+}
+
+/// Asks the background thread for an eviction pass now.
+pub fn wake() {
+    *WOKEN.lock().unwrap() = true;
+    WAKE.notify_one();
+}
+
+/// Starts a defrag run on a thread of its own, rebuilding the storage
+/// shards that deletes left mostly empty one at a time, and pausing after
+/// each to rehash at most `keys_per_sec` entries a second (0 for no limit).
+/// Returns false when a run is already going.
+pub fn start_defrag(keys_per_sec: u64) -> io::Result<bool> {
+    if DEFRAGGING.swap(true, Ordering::AcqRel) {
+        return Ok(false);
+    }
+    let spawned = thread::Builder::new()
+        .name("sodium-defrag".to_string())
+        .spawn(move || {
+            lower_priority();
+            defrag(keys_per_sec);
+            DEFRAGGING.store(false, Ordering::Release);
+        });
+    if let Err(e) = spawned {
+        DEFRAGGING.store(false, Ordering::Release);
+        return Err(e);
+    }
+    Ok(true)
+}
+
+fn defrag(keys_per_sec: u64) {
+    let cache = get_cache();
+    let started = Instant::now();
+    let (mut rebuilt, mut freed) = (0, 0);
+    for shard in 0..cache.shard_count() {
+        let shard_started = Instant::now();
+        let (entries, shard_freed) = cache.defrag_shard(shard);
+        if shard_freed > 0 {
+            rebuilt += 1;
+            freed += shard_freed;
+        }
+        if keys_per_sec > 0 {
+            let allowed = Duration::from_secs_f64(entries as f64 / keys_per_sec as f64);
+            if let Some(ahead) = allowed.checked_sub(shard_started.elapsed()) {
+                thread::sleep(ahead);
+            }
+        }
+    }
+    info!("Defrag rebuilt {} of {} shards, freeing {} bytes, in {:?}", rebuilt, cache.shard_count(), freed, started.elapsed());
+}
+
+fn run(mut jobs: Vec<Job>, evicts: bool) {
+    lower_priority();
+
+    loop {
+        if evicts {
+            get_cache().evict_to_limit();
+        }
+
+        let mut deadline = Instant::now() + IDLE_WAIT;
+        for job in &mut jobs {
+            if job.next <= Instant::now() {
+                let started = Instant::now();
+                (job.run)();
+                job.next = Instant::now() + job.every;
+                if started.elapsed() > job.every {
+                    warn!("Background {} took {:?}, longer than its {:?} interval", job.name, started.elapsed(), job.every);
+                }
+            }
+            deadline = deadline.min(job.next);
+        }
+        // Expiry and eviction deletes are not waited on by a client, so they
+        // are fsynced here under fsync = "always".
+        aof::sync_appended();
+
+        let mut woken = WOKEN.lock().unwrap();
+        while !*woken {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            woken = WAKE.wait_timeout(woken, deadline - now).unwrap().0;
+        }
+        *woken = false;
+    }
+}
+
+// On Linux niceness is per thread, so only this thread is deprioritised.
+// Elsewhere it would apply to the whole process and is left alone.
+#[cfg(target_os = "linux")]
+fn lower_priority() {
+    // SAFETY: plain syscalls on the calling thread.
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS as _, libc::gettid() as libc::id_t, BACKGROUND_NICE);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lower_priority() {}
+
+/// Paces writes to at most `bytes_per_sec`, 0 for no limit, by sleeping
+/// once the bytes written get ahead of the allowed rate.
+pub struct Throttled<W> {
+    inner: W,
+    bytes_per_sec: u64,
+    started: Instant,
+    written: u64,
+}
+
+impl<W: Write> Throttled<W> {
+    pub fn new(inner: W, bytes_per_sec: u64) -> Self {
+        Self { inner, bytes_per_sec, started: Instant::now(), written: 0 }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if self.bytes_per_sec > 0 {
+            self.written += written as u64;
+            let allowed = Duration::from_secs_f64(self.written as f64 / self.bytes_per_sec as f64);
+            if let Some(ahead) = allowed.checked_sub(self.started.elapsed()) {
+                thread::sleep(ahead);
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
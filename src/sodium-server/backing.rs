@@ -0,0 +1,386 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use crate::configuration::{self, LoaderConfig, SodiumConfig};
+use crate::core::SetOptions;
+use crate::threading::{self, TaskResult};
+use crate::webhooks::pattern_matches;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info};
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+// How often sodium.toml is checked for changed loaders.
+const LOADER_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackingStoreError {
+    #[error("Backing store IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Backing store request timed out")]
+    Timeout,
+    #[error("Backing store returned status {0}")]
+    Status(u16),
+    #[error("Malformed backing store response: {0}")]
+    Malformed(String),
+    #[error("Invalid backing store URL: {0}")]
+    InvalidUrl(String),
+}
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BackingStoreError>> + Send + 'a>>;
+
+/// Origin behind the cache: consulted on misses and kept up to date on writes.
+pub trait BackingStore: Send + Sync {
+    fn load<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>>;
+    fn store<'a>(&'a self, key: &'a str, value: &'a str) -> StoreFuture<'a, ()>;
+    fn remove<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteMode {
+    WriteThrough,
+    WriteBehind,
+}
+
+impl WriteMode {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.trim().to_lowercase().as_str() {
+            "write-through" => Ok(WriteMode::WriteThrough),
+            "write-behind" => Ok(WriteMode::WriteBehind),
+            _ => Err(format!("Invalid backing store mode: {}. Valid modes are: write-through, write-behind", input)),
+        }
+    }
+}
+
+enum PendingWrite {
+    Store { key: String, value: String },
+    Remove { key: String },
+}
+
+struct Backing {
+    store: Arc<dyn BackingStore>,
+    // Present in write-behind mode; writes are queued and applied in order
+    // by a background task instead of being awaited by the client.
+    pending: Option<mpsc::UnboundedSender<PendingWrite>>,
+}
+
+// A read-through origin for the keys matching one pattern.
+struct Loader {
+    pattern: String,
+    store: Arc<dyn BackingStore>,
+    ttl: Option<Duration>,
+}
+
+type LoadOutcome = Result<Option<String>, String>;
+
+static BACKING_STORE: OnceLock<Backing> = OnceLock::new();
+// Longest pattern first, so the first match is the most specific one.
+static LOADERS: RwLock<Vec<Loader>> = RwLock::new(Vec::new());
+static HAS_LOADERS: AtomicBool = AtomicBool::new(false);
+static INFLIGHT_LOADS: OnceLock<DashMap<String, broadcast::Sender<LoadOutcome>>> = OnceLock::new();
+
+fn inflight_loads() -> &'static DashMap<String, broadcast::Sender<LoadOutcome>> {
+    INFLIGHT_LOADS.get_or_init(DashMap::new)
+}
+
+// Clears the in-flight slot even if the leading load is dropped midway, so
+// waiters see a closed channel instead of hanging.
+struct InflightLoad<'a> {
+    key: &'a str,
+}
+
+impl Drop for InflightLoad<'_> {
+    fn drop(&mut self) {
+        inflight_loads().remove(self.key);
+    }
+}
+
+/// Talks to an HTTP origin: GET/PUT/DELETE on `<url>/<key>`, where a 404 on
+/// GET is a miss.
+pub struct HttpBackingStore {
+    address: String,
+    base_path: String,
+}
+
+impl HttpBackingStore {
+    pub fn new(url: &str) -> Result<Self, BackingStoreError> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| BackingStoreError::InvalidUrl(format!("{} (only http:// is supported)", url)))?;
+        let (address, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, ""),
+        };
+
+        if address.is_empty() {
+            return Err(BackingStoreError::InvalidUrl(url.to_string()));
+        }
+
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:80", address)
+        };
+
+        Ok(Self {
+            address,
+            base_path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    async fn request(&self, method: &str, key: &str, body: &str) -> Result<(u16, String), BackingStoreError> {
+        tokio::time::timeout(HTTP_TIMEOUT, self.send(method, key, body))
+            .await
+            .map_err(|_| BackingStoreError::Timeout)?
+    }
+
+    async fn send(&self, method: &str, key: &str, body: &str) -> Result<(u16, String), BackingStoreError> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+
+        // HTTP/1.0 keeps responses unchunked and closes the connection after
+        // the body, so reading to EOF yields the whole response.
+        let request = format!(
+            "{} {}/{} HTTP/1.0\r\nHost: {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            self.base_path,
+            key,
+            self.address,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let response = String::from_utf8(response)
+            .map_err(|_| BackingStoreError::Malformed("response is not valid UTF-8".to_string()))?;
+
+        let (head, body) = response.split_once("\r\n\r\n")
+            .ok_or_else(|| BackingStoreError::Malformed("missing header terminator".to_string()))?;
+        let status = head.split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| BackingStoreError::Malformed("missing status code".to_string()))?;
+
+        Ok((status, body.to_string()))
+    }
+}
+
+impl BackingStore for HttpBackingStore {
+    fn load<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<String>> {
+        Box::pin(async move {
+            match self.request("GET", key, "").await? {
+                (200, body) => Ok(Some(body)),
+                (404, _) => Ok(None),
+                (status, _) => Err(BackingStoreError::Status(status)),
+            }
+        })
+    }
+
+    fn store<'a>(&'a self, key: &'a str, value: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            match self.request("PUT", key, value).await? {
+                (status, _) if (200..300).contains(&status) => Ok(()),
+                (status, _) => Err(BackingStoreError::Status(status)),
+            }
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            match self.request("DELETE", key, "").await? {
+                (status, _) if (200..300).contains(&status) || status == 404 => Ok(()),
+                (status, _) => Err(BackingStoreError::Status(status)),
+            }
+        })
+    }
+}
+
+pub fn initialize_backing_store(config: &SodiumConfig) -> Result<(), BackingStoreError> {
+    if config.backing_store_url.is_empty() {
+        return Ok(());
+    }
+
+    let store = HttpBackingStore::new(&config.backing_store_url)?;
+    let mode = WriteMode::parse(&config.backing_store_mode).unwrap_or(WriteMode::WriteThrough);
+    install(Arc::new(store), mode);
+    Ok(())
+}
+
+pub fn install(store: Arc<dyn BackingStore>, mode: WriteMode) {
+    let pending = (mode == WriteMode::WriteBehind).then(|| {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(drain_pending_writes(store.clone(), receiver));
+        sender
+    });
+
+    let _ = BACKING_STORE.set(Backing { store, pending });
+}
+
+/// Installs the [loaders] from `config` and starts watching sodium.toml
+/// so changes to them apply without a restart. Must be called from within
+/// the runtime.
+pub fn initialize_loaders(config: &SodiumConfig) -> Result<(), BackingStoreError> {
+    apply_loaders(&config.loaders)?;
+    tokio::spawn(watch_loaders(config.loaders.clone()));
+    Ok(())
+}
+
+fn apply_loaders(config: &BTreeMap<String, LoaderConfig>) -> Result<(), BackingStoreError> {
+    let mut loaders = Vec::with_capacity(config.len());
+    for (pattern, loader) in config {
+        loaders.push(Loader {
+            pattern: pattern.clone(),
+            store: Arc::new(HttpBackingStore::new(&loader.url)?),
+            ttl: (loader.ttl_secs > 0).then(|| Duration::from_secs(loader.ttl_secs)),
+        });
+    }
+    loaders.sort_by_key(|loader| std::cmp::Reverse(loader.pattern.len()));
+
+    HAS_LOADERS.store(!loaders.is_empty(), Ordering::Relaxed);
+    *LOADERS.write().unwrap() = loaders;
+    Ok(())
+}
+
+// A file that fails to parse, or names a bad URL, leaves the loaders in
+// place until it is fixed.
+async fn watch_loaders(mut applied: BTreeMap<String, LoaderConfig>) {
+    let modified = || std::fs::metadata(configuration::CONFIG_PATH).and_then(|metadata| metadata.modified()).ok();
+    let mut seen: Option<SystemTime> = modified();
+    loop {
+        tokio::time::sleep(LOADER_POLL_INTERVAL).await;
+        let current = modified();
+        if current == seen {
+            continue;
+        }
+        seen = current;
+
+        let config = match SodiumConfig::reload() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed to reload loaders from {}: {}", configuration::CONFIG_PATH, e);
+                continue;
+            }
+        };
+        if config.loaders == applied {
+            continue;
+        }
+        match apply_loaders(&config.loaders) {
+            Ok(()) => {
+                info!("Reloaded {} read-through loaders", config.loaders.len());
+                applied = config.loaders;
+            }
+            Err(e) => error!("Failed to reload loaders from {}: {}", configuration::CONFIG_PATH, e),
+        }
+    }
+}
+
+/// Whether a miss may be loaded from an origin.
+pub fn is_enabled() -> bool {
+    BACKING_STORE.get().is_some() || HAS_LOADERS.load(Ordering::Relaxed)
+}
+
+// The origin a missed key is loaded from, and the TTL it is cached with.
+fn origin_for(key: &str) -> Option<(Arc<dyn BackingStore>, Option<Duration>)> {
+    if HAS_LOADERS.load(Ordering::Relaxed) {
+        let loaders = LOADERS.read().unwrap();
+        if let Some(loader) = loaders.iter().find(|loader| pattern_matches(&loader.pattern, key)) {
+            return Some((loader.store.clone(), loader.ttl));
+        }
+    }
+    BACKING_STORE.get().map(|backing| (backing.store.clone(), None))
+}
+
+/// Loads a missed key from its origin and caches it. Concurrent misses on
+/// the same key share a single origin request.
+pub async fn load_on_miss(key: &str) -> TaskResult<Option<String>> {
+    let Some((store, ttl)) = origin_for(key) else {
+        return Ok(None);
+    };
+
+    let sender = match inflight_loads().entry(key.to_string()) {
+        Entry::Occupied(entry) => {
+            let mut receiver = entry.get().subscribe();
+            drop(entry);
+            return match receiver.recv().await {
+                Ok(outcome) => Ok(outcome?),
+                Err(_) => Err("Backing store load was abandoned".into()),
+            };
+        }
+        Entry::Vacant(entry) => {
+            let (sender, _) = broadcast::channel(1);
+            entry.insert(sender.clone());
+            sender
+        }
+    };
+
+    let guard = InflightLoad { key };
+    let outcome = load_and_cache(store.as_ref(), ttl, key).await.map_err(|e| e.to_string());
+    drop(guard);
+
+    let _ = sender.send(outcome.clone());
+    Ok(outcome?)
+}
+
+async fn load_and_cache(store: &dyn BackingStore, ttl: Option<Duration>, key: &str) -> TaskResult<Option<String>> {
+    match store.load(key).await? {
+        Some(value) => {
+            let options = SetOptions { ttl, ..SetOptions::default() };
+            threading::execute_cache_set(key.to_string(), value.clone(), options).await?;
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn write(key: &str, value: &str) -> TaskResult<()> {
+    let Some(backing) = BACKING_STORE.get() else {
+        return Ok(());
+    };
+
+    match &backing.pending {
+        Some(pending) => {
+            pending.send(PendingWrite::Store { key: key.to_string(), value: value.to_string() })
+                .map_err(|_| "Backing store write queue closed")?;
+            Ok(())
+        }
+        None => Ok(backing.store.store(key, value).await?),
+    }
+}
+
+pub async fn remove(key: &str) -> TaskResult<()> {
+    let Some(backing) = BACKING_STORE.get() else {
+        return Ok(());
+    };
+
+    match &backing.pending {
+        Some(pending) => {
+            pending.send(PendingWrite::Remove { key: key.to_string() })
+                .map_err(|_| "Backing store write queue closed")?;
+            Ok(())
+        }
+        None => Ok(backing.store.remove(key).await?),
+    }
+}
+
+async fn drain_pending_writes(store: Arc<dyn BackingStore>, mut receiver: mpsc::UnboundedReceiver<PendingWrite>) {
+    while let Some(write) = receiver.recv().await {
+        let (key, result) = match &write {
+            PendingWrite::Store { key, value } => (key, store.store(key, value).await),
+            PendingWrite::Remove { key } => (key, store.remove(key).await),
+        };
+
+        if let Err(e) = result {
+            error!("Write-behind to backing store failed for {}: {}", key, e);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Fault injection for testing clients against a misbehaving server. Every
+// rate defaults to 0, which leaves requests untouched.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::configuration::SodiumConfig;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    /// Hold the reply back for this long before sending it.
+    Delay(Duration),
+    /// Run the command, then close the connection without replying.
+    Drop,
+    /// Reply with a transient error without running the command.
+    Error,
+}
+
+pub fn is_enabled(config: &SodiumConfig) -> bool {
+    config.chaos_delay_rate > 0.0 || config.chaos_drop_rate > 0.0 || config.chaos_error_rate > 0.0
+}
+
+/// Picks at most one fault for a request.
+pub fn pick(config: &SodiumConfig) -> Option<Fault> {
+    let mut rng = rand::thread_rng();
+    if rng.r#gen::<f64>() < config.chaos_error_rate {
+        return Some(Fault::Error);
+    }
+    if rng.r#gen::<f64>() < config.chaos_drop_rate {
+        return Some(Fault::Drop);
+    }
+    if rng.r#gen::<f64>() < config.chaos_delay_rate {
+        let delay = rng.gen_range(0..=config.chaos_delay_max_ms);
+        return Some(Fault::Delay(Duration::from_millis(delay)));
+    }
+    None
+}
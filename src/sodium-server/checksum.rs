@@ -0,0 +1,52 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Line checksums for the AOF and snapshot files. A sealed line is a JSON
+// document, a tab and the CRC-32 of the document in hex; compact JSON never
+// holds a raw tab, so the split is unambiguous. Lines written before
+// checksums existed carry no suffix and are read as they are.
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+// Reflected CRC-32 (IEEE), the one zlib and gzip use.
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// Appends the checksum suffix to `line`, a JSON document without its
+/// newline.
+pub fn seal(line: &mut Vec<u8>) {
+    let checksum = crc32(line);
+    line.extend_from_slice(format!("\t{:08x}", checksum).as_bytes());
+}
+
+/// The JSON document of a line, checked against its checksum when it has
+/// one.
+pub fn unseal(line: &str) -> Result<&str, String> {
+    let Some((document, checksum)) = line.rsplit_once('\t') else {
+        return Ok(line);
+    };
+    let expected = u32::from_str_radix(checksum.trim_end(), 16)
+        .map_err(|_| format!("malformed checksum {:?}", checksum))?;
+    let actual = crc32(document.as_bytes());
+    if actual != expected {
+        return Err(format!("checksum mismatch, expected {:08x}, computed {:08x}", expected, actual));
+    }
+    Ok(document)
+}
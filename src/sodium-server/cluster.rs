@@ -10,6 +10,7 @@ type ConfigResult<T> = Result<T, ConfigError>;
 
 const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
 const NODE_ID_LENGTH: usize = 7;
+const SLOT_COUNT: u32 = 16384;
 
 fn generate_node_id() -> String {
     let mut rng = rand::thread_rng();
@@ -21,12 +22,32 @@ fn generate_node_id() -> String {
         .collect()
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeRole {
+    PrimaryEligible,
+    ReplicaOnly,
+}
+
+impl NodeRole {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.trim().to_lowercase().as_str() {
+            "primary-eligible" => Ok(NodeRole::PrimaryEligible),
+            "replica-only" => Ok(NodeRole::ReplicaOnly),
+            _ => Err(format!("Invalid cluster role: {}. Valid roles are: primary-eligible, replica-only", input)),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClusterNode {
     pub node_id: String,
     pub node_validation: u32,
     pub address: String,
-    pub slots: [u32; 2],
+    pub role: NodeRole,
+    pub weight: u32,
+    // Inclusive slot range; None for nodes that hold no slots.
+    pub slots: Option<[u32; 2]>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,15 +61,45 @@ pub fn generate_cluster_file(config: &SodiumConfig) -> ConfigResult<()> {
         node_id: generate_node_id(),
         node_validation: 0,
         address: config.bind_address(),
-        slots: [0, 16383],
+        role: NodeRole::parse(&config.cluster_role).unwrap_or(NodeRole::PrimaryEligible),
+        weight: config.cluster_weight.max(1),
+        slots: None,
     };
 
+    let mut nodes = vec![cluster_node];
+    assign_slots(&mut nodes);
+
     let cluster_config = ClusterConfig {
         cluster_validation: 0,
-        nodes: vec![cluster_node],
+        nodes,
     };
 
     let content = serde_json::to_string_pretty(&cluster_config)?;
     fs::write("cluster.json", content)?;
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// Splits the slot space into contiguous ranges across primary-eligible
+/// nodes in proportion to their weights. Replica-only nodes get no slots.
+pub fn assign_slots(nodes: &mut [ClusterNode]) {
+    let total_weight: u64 = nodes.iter()
+        .filter(|node| node.role == NodeRole::PrimaryEligible)
+        .map(|node| node.weight.max(1) as u64)
+        .sum();
+
+    let mut next_slot = 0u32;
+    let mut weight_so_far = 0u64;
+    for node in nodes.iter_mut() {
+        if node.role != NodeRole::PrimaryEligible {
+            node.slots = None;
+            continue;
+        }
+
+        // Range ends come from the running weight total, so rounding never
+        // leaves slots unassigned and the last node always ends at 16383.
+        weight_so_far += node.weight.max(1) as u64;
+        let end = (weight_so_far * SLOT_COUNT as u64 / total_weight) as u32;
+        node.slots = (end > next_slot).then(|| [next_slot, end - 1]);
+        next_slot = end;
+    }
+}
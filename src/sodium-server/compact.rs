@@ -0,0 +1,93 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+/// Longest string kept inline. Chosen so a CompactStr is the same size as a
+/// String header.
+pub const INLINE_CAPACITY: usize = 22;
+
+const _: () = assert!(std::mem::size_of::<CompactStr>() == std::mem::size_of::<String>());
+
+/// An immutable string stored inline when it fits in INLINE_CAPACITY bytes,
+/// so small keys cost no allocation of their own.
+#[derive(Clone)]
+pub enum CompactStr {
+    Inline { len: u8, bytes: [u8; INLINE_CAPACITY] },
+    Heap(Box<str>),
+}
+
+impl CompactStr {
+    pub fn as_str(&self) -> &str {
+        match self {
+            // SAFETY: inline bytes are only ever copied from a &str.
+            CompactStr::Inline { len, bytes } => unsafe { std::str::from_utf8_unchecked(&bytes[..*len as usize]) },
+            CompactStr::Heap(text) => text,
+        }
+    }
+}
+
+/// Heap bytes a string of `len` bytes takes once compacted.
+pub fn heap_len(len: usize) -> usize {
+    if len <= INLINE_CAPACITY { 0 } else { len }
+}
+
+impl From<&str> for CompactStr {
+    fn from(text: &str) -> Self {
+        if text.len() <= INLINE_CAPACITY {
+            let mut bytes = [0u8; INLINE_CAPACITY];
+            bytes[..text.len()].copy_from_slice(text.as_bytes());
+            CompactStr::Inline { len: text.len() as u8, bytes }
+        } else {
+            CompactStr::Heap(text.into())
+        }
+    }
+}
+
+impl From<String> for CompactStr {
+    fn from(text: String) -> Self {
+        if text.len() <= INLINE_CAPACITY {
+            CompactStr::from(text.as_str())
+        } else {
+            CompactStr::Heap(text.into_boxed_str())
+        }
+    }
+}
+
+impl Deref for CompactStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for CompactStr {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// Hashes and compares as the text alone, which map lookups by &str rely on.
+impl Hash for CompactStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl PartialEq for CompactStr {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for CompactStr {}
+
+impl fmt::Debug for CompactStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
@@ -2,11 +2,18 @@
 // A scalable and optimized Key Value Caching System, written in Rust.
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use thiserror::Error;
 use crate::cluster;
 
+pub const CONFIG_PATH: &str = "sodium.toml";
+
+// The profile the server started with, applied again by reload().
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
+
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("IO error: {0}")]
@@ -17,6 +24,12 @@ pub enum ConfigError {
     TomlSerialize(#[from] toml::ser::Error),
     #[error("JSON serialization error: {0}")]
     JsonSerialize(#[from] serde_json::Error),
+    #[error("Failed to include {path}: {reason}")]
+    Include { path: String, reason: String },
+    #[error("Unknown config profile: {0}")]
+    UnknownProfile(String),
+    #[error(transparent)]
+    Secret(#[from] crate::secrets::SecretError),
 }
 
 type ConfigResult<T> = Result<T, ConfigError>;
@@ -32,8 +45,170 @@ pub struct SodiumConfig {
     #[serde(rename = "bind-public-port")]
     pub bind_public_port: u16,
     pub silent: bool,
+    /// Print the startup banner; ignored when silent.
+    pub banner: bool,
+    /// Most verbose log level shown: "error", "warn", "info", "debug" or "trace".
+    pub log_level: String,
+    /// File the server writes its pid to while running; empty for none.
+    pub pidfile: String,
+    /// Unix socket a server started with --handoff connects to, to take
+    /// over from the one running; empty disables handoff.
+    pub handoff_socket: String,
     pub cluster_enabled: bool,
+    /// "primary-eligible" nodes hold slots, "replica-only" nodes never do.
+    pub cluster_role: String,
+    /// Share of the slot space relative to other primary-eligible nodes.
+    pub cluster_weight: u32,
     pub whisper_timeout: u32,
+    pub max_memory: u64,
+    pub eviction_samples: u32,
+    /// Which entries make room once max_memory is reached: "lru", "lfu",
+    /// "random", or "noeviction" to fail writes instead.
+    pub eviction_policy: String,
+    pub metrics_port: u16,
+    /// Key patterns, with `*` wildcards, that command counts and latency are
+    /// broken down by; a key goes to the first pattern it matches.
+    pub metrics_key_patterns: Vec<String>,
+    /// Loopback port for management commands; 0 keeps them on the main port.
+    pub admin_port: u16,
+    pub search_timeout_ms: u64,
+    pub command_timeout_ms: u64,
+    /// Largest keys()/search() reply in bytes before it is cut short with a
+    /// continuation cursor; 0 disables the cap.
+    pub max_response_bytes: usize,
+    /// How long a reply to a command sent with an idempotency token is kept
+    /// for retries; 0 ignores tokens.
+    pub idempotency_window_secs: u64,
+    /// Most idempotency tokens kept at once; commands bringing a new token
+    /// beyond it are refused with ERR_BUSY until older ones expire.
+    pub idempotency_max_tokens: usize,
+    /// Commands a client connection may send per second, in bursts of up to
+    /// as many; 0 disables the limit. The admin port is never limited.
+    pub rate_limit_per_sec: u64,
+    /// Enables the debug() testing commands; never turn on in production.
+    pub debug_commands: bool,
+    /// Runs commands one at a time on a single worker and runtime thread,
+    /// with key hashing and random sampling seeded by deterministic_seed, so
+    /// a test replaying the same commands sees the same evictions. Expiry
+    /// sweeps run on that worker too, between commands.
+    pub deterministic: bool,
+    pub deterministic_seed: u64,
+    /// Fraction of requests, 0.0 to 1.0, whose reply is held back by up to
+    /// chaos_delay_max_ms. The chaos_ rates inject faults for client testing.
+    pub chaos_delay_rate: f64,
+    pub chaos_delay_max_ms: u64,
+    /// Fraction of requests whose connection is closed instead of replying.
+    pub chaos_drop_rate: f64,
+    /// Fraction of requests answered with a transient error.
+    pub chaos_error_rate: f64,
+    /// Values up to this many bytes are interned, so keys holding the same
+    /// small value share one copy of it; 0 disables interning.
+    pub intern_max_len: usize,
+    /// Socket layer for the main port: "tokio", or "io_uring" on Linux
+    /// builds with the io-uring feature.
+    pub network_backend: String,
+    /// Pending tasks each worker queue holds before commands get BUSY.
+    pub queue_capacity: usize,
+    /// Seconds a deleted key is kept as a tombstone that undelete() can
+    /// restore; 0 disables tombstones.
+    pub tombstone_retention_secs: u64,
+    /// Values kept per key for history() and getversion(), counted against
+    /// max_memory; 0 keeps none.
+    pub version_history: usize,
+    /// Percent, up to 100, by which TTLs are lengthened at random so keys
+    /// written together do not expire together; 0 disables jitter. set()
+    /// can override it per write with jitter(percent).
+    pub ttl_jitter_percent: u64,
+    /// Items a bfadd() filter is sized for when created without capacity().
+    pub bloom_capacity: u64,
+    /// False positive rate, between 0 and 1 exclusive, a bfadd() filter is
+    /// sized for when created without error().
+    pub bloom_error_rate: f64,
+    /// Items a topk_add() sketch keeps by name when created without k(),
+    /// up to 1000.
+    pub topk_size: u64,
+    /// Items a cfadd() filter is sized for when created without capacity().
+    pub cuckoo_capacity: u64,
+    /// Entries an xadd() stream keeps when written without maxlen(), the
+    /// oldest dropped first; 0 keeps every entry.
+    pub stream_max_len: u64,
+    /// Keeps key, byte, hit and miss counts per key namespace for
+    /// stats("prefix", "<namespace>:"), at the cost of a counter update on
+    /// every write and lookup.
+    pub prefix_stats: bool,
+    /// Milliseconds between background passes dropping expired keys, one
+    /// shard per pass; 0 leaves them, due scheduled writes and lapsed leases
+    /// until they are next touched.
+    pub expiry_sweep_interval_ms: u64,
+    /// Cap on the disk write rate of background work such as snapshots;
+    /// 0 leaves it unthrottled.
+    pub background_io_bytes_per_sec: u64,
+    /// Entries defrag() rehashes per second at most, pacing the rebuild of
+    /// fragmented shards; 0 leaves it unthrottled.
+    pub defrag_keys_per_sec: u64,
+    pub backing_store_url: String,
+    /// File holding backing_store_url instead, so credentials in it stay out
+    /// of sodium.toml; empty for none.
+    pub backing_store_url_file: String,
+    pub backing_store_mode: String,
+    pub aof_enabled: bool,
+    pub aof_path: String,
+    /// When the AOF is fsynced: "always", "everysec" or "no".
+    pub fsync: String,
+    /// Window in microseconds over which AOF appends are batched into one
+    /// write and fsync; 0 writes each append on its own.
+    pub aof_group_commit_us: u64,
+    pub snapshot_path: String,
+    /// Seconds between snapshots; 0 disables snapshotting.
+    pub snapshot_interval_secs: u64,
+    /// Every Nth snapshot is full; the ones in between only hold the keys
+    /// changed since the previous snapshot.
+    pub snapshot_full_every: u32,
+    /// Seconds between AOF rewrites, which drop the records the last full
+    /// snapshot already covers; 0 lets the log grow. Only runs with
+    /// snapshots on.
+    pub aof_rewrite_interval_secs: u64,
+    /// TOML file of `key = value` pairs written at startup when missing, like
+    /// [seed]; keys in [seed] win over the file. Empty for none.
+    pub seed_file: String,
+    /// Key in [encryption_keys] the AOF and snapshot files are encrypted
+    /// with; empty writes them in the clear.
+    pub encryption_key_id: String,
+    /// TOML file of `token = "namespace"` pairs added to [auth_tokens];
+    /// tokens in [auth_tokens] win over the file. Empty for none.
+    pub auth_tokens_file: String,
+    /// TOML file of `id = "key"` pairs added to [encryption_keys], as
+    /// auth_tokens_file does for tokens. Empty for none.
+    pub encryption_keys_file: String,
+    /// Maps each auth token to the namespace it scopes a connection to, or
+    /// "*" for unrestricted access. Authentication is off while empty.
+    pub auth_tokens: BTreeMap<String, String>,
+    /// Key pattern, with `*` wildcards, to the target notified of events on
+    /// matching keys: an http:// URL or "pipe:<command>".
+    pub webhooks: BTreeMap<String, String>,
+    /// Key pattern, with `*` wildcards, to the origin keys matching it are
+    /// read through on a miss, ahead of backing_store_url. The longest
+    /// matching pattern wins. Picked up again when the file changes.
+    pub loaders: BTreeMap<String, LoaderConfig>,
+    /// Keys written at startup, once recovery is done, unless they already
+    /// hold a value, so defaults such as feature flags always exist.
+    pub seed: BTreeMap<String, String>,
+    /// Key id to a 256-bit AES key in hex. Keys no longer current stay
+    /// listed for as long as files written with them remain.
+    pub encryption_keys: BTreeMap<String, String>,
+    /// Auth namespace to the key in [encryption_keys] its keys are written
+    /// with, in place of encryption_key_id.
+    pub namespace_encryption_keys: BTreeMap<String, String>,
+}
+
+/// A read-through origin for one key pattern.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LoaderConfig {
+    /// http:// URL keys are loaded from, as with backing_store_url.
+    pub url: String,
+    /// TTL given to keys loaded through it; 0 keeps them until evicted.
+    #[serde(default)]
+    pub ttl_secs: u64,
 }
 
 impl Default for SodiumConfig {
@@ -44,8 +219,69 @@ impl Default for SodiumConfig {
             bind_public_ip: "0.0.0.0".to_string(),
             bind_public_port: 1123,
             silent: false,
+            banner: true,
+            log_level: "info".to_string(),
+            pidfile: String::new(),
+            handoff_socket: String::new(),
             cluster_enabled: false,
+            cluster_role: "primary-eligible".to_string(),
+            cluster_weight: 1,
             whisper_timeout: 1,
+            max_memory: 0,
+            eviction_samples: 5,
+            eviction_policy: "lru".to_string(),
+            metrics_port: 0,
+            metrics_key_patterns: Vec::new(),
+            admin_port: 0,
+            search_timeout_ms: 0,
+            command_timeout_ms: 0,
+            max_response_bytes: 16 * 1024 * 1024,
+            idempotency_window_secs: 300,
+            idempotency_max_tokens: 100_000,
+            rate_limit_per_sec: 0,
+            debug_commands: false,
+            deterministic: false,
+            deterministic_seed: 0,
+            chaos_delay_rate: 0.0,
+            chaos_delay_max_ms: 1000,
+            chaos_drop_rate: 0.0,
+            chaos_error_rate: 0.0,
+            intern_max_len: 0,
+            network_backend: "tokio".to_string(),
+            queue_capacity: 10_000,
+            tombstone_retention_secs: 0,
+            version_history: 0,
+            ttl_jitter_percent: 0,
+            bloom_capacity: 10_000,
+            bloom_error_rate: 0.01,
+            topk_size: 10,
+            cuckoo_capacity: 10_000,
+            stream_max_len: 0,
+            prefix_stats: false,
+            expiry_sweep_interval_ms: 100,
+            background_io_bytes_per_sec: 0,
+            defrag_keys_per_sec: 1_000_000,
+            backing_store_url: String::new(),
+            backing_store_url_file: String::new(),
+            backing_store_mode: "write-through".to_string(),
+            aof_enabled: false,
+            aof_path: "sodium.aof".to_string(),
+            fsync: "everysec".to_string(),
+            aof_group_commit_us: 0,
+            snapshot_path: "sodium.snapshot".to_string(),
+            snapshot_interval_secs: 0,
+            snapshot_full_every: 10,
+            aof_rewrite_interval_secs: 3600,
+            seed_file: String::new(),
+            encryption_key_id: String::new(),
+            auth_tokens_file: String::new(),
+            encryption_keys_file: String::new(),
+            auth_tokens: BTreeMap::new(),
+            webhooks: BTreeMap::new(),
+            loaders: BTreeMap::new(),
+            seed: BTreeMap::new(),
+            encryption_keys: BTreeMap::new(),
+            namespace_encryption_keys: BTreeMap::new(),
         }
     }
 }
@@ -59,16 +295,32 @@ impl SodiumConfig {
         format!("{}:{}", self.bind_public_ip, self.bind_public_port)
     }
 
-    pub fn load_or_create() -> ConfigResult<Self> {
-        let config_path = "sodium.toml";
+    pub fn metrics_address(&self) -> String {
+        format!("{}:{}", self.bind_ip, self.metrics_port)
+    }
+
+    pub fn admin_address(&self) -> String {
+        format!("127.0.0.1:{}", self.admin_port)
+    }
+
+    /// Loads sodium.toml, creating it with defaults when missing. `profile`
+    /// selects a `[profile.<name>]` table to apply on top of the file and its
+    /// includes.
+    pub fn load_or_create(profile: Option<&str>) -> ConfigResult<Self> {
+        let config_path = CONFIG_PATH;
+        let _ = PROFILE.set(profile.map(str::to_string));
         
-        let config = if Path::new(config_path).exists() {
-            Self::load_and_heal(config_path)?
+        let mut config = if Path::new(config_path).exists() {
+            Self::load_and_heal(config_path, profile)?
+        } else if let Some(profile) = profile {
+            return Err(ConfigError::UnknownProfile(profile.to_string()));
         } else {
             let default_config = Self::default();
             default_config.save_to_file(config_path)?;
             default_config
         };
+        // After any write back, so resolved secrets never reach the file.
+        crate::secrets::resolve(&mut config)?;
         
         if config.cluster_enabled {
             cluster::generate_cluster_file(&config)?;
@@ -77,8 +329,39 @@ impl SodiumConfig {
         Ok(config)
     }
 
-    fn load_and_heal(path: &str) -> ConfigResult<Self> {
+    /// Reads sodium.toml again, under the profile the server started with,
+    /// for settings that apply while running. The file is left as it is.
+    pub fn reload() -> ConfigResult<Self> {
+        let profile = PROFILE.get().and_then(Option::as_deref);
+        let content = fs::read_to_string(CONFIG_PATH)?;
+        let table: toml::Table = toml::from_str(&content)?;
+        let content = if profile.is_some() || table.contains_key("include") || table.contains_key("profile") {
+            toml::to_string(&Self::resolve_layers(CONFIG_PATH, profile)?)?
+        } else {
+            content
+        };
+        let mut config = match toml::from_str::<SodiumConfig>(&content) {
+            Ok(config) => Self::heal_config(config),
+            Err(_) => Self::heal_config(Self::parse_partial_config(&content)?),
+        };
+        crate::secrets::resolve(&mut config)?;
+        Ok(config)
+    }
+
+    fn load_and_heal(path: &str, profile: Option<&str>) -> ConfigResult<Self> {
         let content = fs::read_to_string(path)?;
+        let table: toml::Table = toml::from_str(&content)?;
+
+        // A layered config is assembled from several tables, so healing it
+        // cannot be written back without flattening the layers into one file.
+        if profile.is_some() || table.contains_key("include") || table.contains_key("profile") {
+            let merged = Self::resolve_layers(path, profile)?;
+            let content = toml::to_string(&merged)?;
+            return match toml::from_str::<SodiumConfig>(&content) {
+                Ok(config) => Ok(Self::heal_config(config)),
+                Err(_) => Ok(Self::heal_config(Self::parse_partial_config(&content)?)),
+            };
+        }
         
         match toml::from_str::<SodiumConfig>(&content) {
             Ok(config) => {
@@ -95,6 +378,58 @@ impl SodiumConfig {
         }
     }
 
+    // The file itself, then each of its `include = [...]` files in order, then
+    // the selected profile, with later layers overriding earlier ones.
+    fn resolve_layers(path: &str, profile: Option<&str>) -> ConfigResult<toml::Table> {
+        let mut merged = Self::read_with_includes(Path::new(path), &mut Vec::new())?;
+        let profiles = merged.remove("profile");
+
+        if let Some(name) = profile {
+            let selected = match profiles {
+                Some(toml::Value::Table(mut profiles)) => profiles.remove(name),
+                _ => None,
+            };
+            match selected {
+                Some(toml::Value::Table(overrides)) => merge_tables(&mut merged, overrides),
+                _ => return Err(ConfigError::UnknownProfile(name.to_string())),
+            }
+        }
+
+        Ok(merged)
+    }
+
+    fn read_with_includes(path: &Path, chain: &mut Vec<PathBuf>) -> ConfigResult<toml::Table> {
+        let include_error = |reason: String| ConfigError::Include { path: path.display().to_string(), reason };
+
+        let canonical = path.canonicalize().map_err(|e| include_error(e.to_string()))?;
+        if chain.contains(&canonical) {
+            return Err(include_error("include cycle".to_string()));
+        }
+
+        let content = fs::read_to_string(path).map_err(|e| include_error(e.to_string()))?;
+        let mut table: toml::Table = toml::from_str(&content).map_err(|e| include_error(e.to_string()))?;
+        let includes = match table.remove("include") {
+            None => Vec::new(),
+            Some(toml::Value::Array(includes)) => includes,
+            Some(toml::Value::String(include)) => vec![toml::Value::String(include)],
+            Some(_) => return Err(include_error("include must be a list of paths".to_string())),
+        };
+
+        // Included paths are relative to the file that names them.
+        chain.push(canonical);
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        for include in includes {
+            let toml::Value::String(include) = include else {
+                return Err(include_error("include must be a list of paths".to_string()));
+            };
+            let included = Self::read_with_includes(&directory.join(include), chain)?;
+            merge_tables(&mut table, included);
+        }
+        chain.pop();
+
+        Ok(table)
+    }
+
     fn parse_partial_config(content: &str) -> ConfigResult<Self> {
         let toml_value: toml::Value = toml::from_str(content)?;
         
@@ -116,18 +451,281 @@ impl SodiumConfig {
             if let Some(toml::Value::Boolean(silent)) = table.get("silent") {
                 config.silent = *silent;
             }
+            if let Some(toml::Value::Boolean(banner)) = table.get("banner") {
+                config.banner = *banner;
+            }
+            if let Some(toml::Value::String(level)) = table.get("log_level") {
+                config.log_level = level.clone();
+            }
+            if let Some(toml::Value::String(pidfile)) = table.get("pidfile") {
+                config.pidfile = pidfile.clone();
+            }
+            if let Some(toml::Value::String(path)) = table.get("handoff_socket") {
+                config.handoff_socket = path.clone();
+            }
             if let Some(toml::Value::Boolean(enabled)) = table.get("cluster_enabled") {
                 config.cluster_enabled = *enabled;
             }
+            if let Some(toml::Value::String(role)) = table.get("cluster_role") {
+                config.cluster_role = role.clone();
+            }
+            if let Some(toml::Value::Integer(weight)) = table.get("cluster_weight") {
+                config.cluster_weight = *weight as u32;
+            }
             if let Some(toml::Value::Integer(timeout)) = table.get("whisper_timeout") {
                 config.whisper_timeout = *timeout as u32;
             }
+            if let Some(toml::Value::Integer(max_memory)) = table.get("max_memory") {
+                config.max_memory = *max_memory as u64;
+            }
+            if let Some(toml::Value::Integer(samples)) = table.get("eviction_samples") {
+                config.eviction_samples = *samples as u32;
+            }
+            if let Some(toml::Value::String(policy)) = table.get("eviction_policy") {
+                config.eviction_policy = policy.clone();
+            }
+            if let Some(toml::Value::Integer(port)) = table.get("metrics_port") {
+                config.metrics_port = *port as u16;
+            }
+            if let Some(toml::Value::Array(patterns)) = table.get("metrics_key_patterns") {
+                config.metrics_key_patterns = patterns.iter()
+                    .filter_map(|pattern| pattern.as_str().map(str::to_string))
+                    .collect();
+            }
+            if let Some(toml::Value::Integer(port)) = table.get("admin_port") {
+                config.admin_port = *port as u16;
+            }
+            if let Some(toml::Value::Integer(timeout)) = table.get("search_timeout_ms") {
+                config.search_timeout_ms = *timeout as u64;
+            }
+            if let Some(toml::Value::Integer(timeout)) = table.get("command_timeout_ms") {
+                config.command_timeout_ms = *timeout as u64;
+            }
+            if let Some(toml::Value::Integer(bytes)) = table.get("max_response_bytes") {
+                config.max_response_bytes = *bytes as usize;
+            }
+            if let Some(toml::Value::Integer(window)) = table.get("idempotency_window_secs") {
+                config.idempotency_window_secs = *window as u64;
+            }
+            if let Some(toml::Value::Integer(tokens)) = table.get("idempotency_max_tokens") {
+                config.idempotency_max_tokens = *tokens as usize;
+            }
+            if let Some(toml::Value::Integer(rate)) = table.get("rate_limit_per_sec") {
+                config.rate_limit_per_sec = *rate as u64;
+            }
+            if let Some(toml::Value::Boolean(enabled)) = table.get("debug_commands") {
+                config.debug_commands = *enabled;
+            }
+            if let Some(toml::Value::Boolean(enabled)) = table.get("deterministic") {
+                config.deterministic = *enabled;
+            }
+            if let Some(toml::Value::Integer(seed)) = table.get("deterministic_seed") {
+                config.deterministic_seed = *seed as u64;
+            }
+            if let Some(toml::Value::Float(rate)) = table.get("chaos_delay_rate") {
+                config.chaos_delay_rate = *rate;
+            }
+            if let Some(toml::Value::Integer(delay)) = table.get("chaos_delay_max_ms") {
+                config.chaos_delay_max_ms = *delay as u64;
+            }
+            if let Some(toml::Value::Float(rate)) = table.get("chaos_drop_rate") {
+                config.chaos_drop_rate = *rate;
+            }
+            if let Some(toml::Value::Float(rate)) = table.get("chaos_error_rate") {
+                config.chaos_error_rate = *rate;
+            }
+            if let Some(toml::Value::Integer(max_len)) = table.get("intern_max_len") {
+                config.intern_max_len = *max_len as usize;
+            }
+            if let Some(toml::Value::String(backend)) = table.get("network_backend") {
+                config.network_backend = backend.clone();
+            }
+            if let Some(toml::Value::Integer(capacity)) = table.get("queue_capacity") {
+                config.queue_capacity = *capacity as usize;
+            }
+            if let Some(toml::Value::Integer(secs)) = table.get("tombstone_retention_secs") {
+                config.tombstone_retention_secs = *secs as u64;
+            }
+            if let Some(toml::Value::Integer(count)) = table.get("version_history") {
+                config.version_history = *count as usize;
+            }
+            if let Some(toml::Value::Integer(percent)) = table.get("ttl_jitter_percent") {
+                config.ttl_jitter_percent = *percent as u64;
+            }
+            if let Some(toml::Value::Integer(capacity)) = table.get("bloom_capacity") {
+                config.bloom_capacity = *capacity as u64;
+            }
+            if let Some(toml::Value::Float(rate)) = table.get("bloom_error_rate") {
+                config.bloom_error_rate = *rate;
+            }
+            if let Some(toml::Value::Integer(size)) = table.get("topk_size") {
+                config.topk_size = *size as u64;
+            }
+            if let Some(toml::Value::Integer(capacity)) = table.get("cuckoo_capacity") {
+                config.cuckoo_capacity = *capacity as u64;
+            }
+            if let Some(toml::Value::Integer(length)) = table.get("stream_max_len") {
+                config.stream_max_len = *length as u64;
+            }
+            if let Some(toml::Value::Boolean(enabled)) = table.get("prefix_stats") {
+                config.prefix_stats = *enabled;
+            }
+            if let Some(toml::Value::Integer(interval)) = table.get("expiry_sweep_interval_ms") {
+                config.expiry_sweep_interval_ms = *interval as u64;
+            }
+            if let Some(toml::Value::Integer(rate)) = table.get("background_io_bytes_per_sec") {
+                config.background_io_bytes_per_sec = *rate as u64;
+            }
+            if let Some(toml::Value::Integer(rate)) = table.get("defrag_keys_per_sec") {
+                config.defrag_keys_per_sec = *rate as u64;
+            }
+            if let Some(toml::Value::String(url)) = table.get("backing_store_url") {
+                config.backing_store_url = url.clone();
+            }
+            if let Some(toml::Value::String(path)) = table.get("backing_store_url_file") {
+                config.backing_store_url_file = path.clone();
+            }
+            if let Some(toml::Value::String(mode)) = table.get("backing_store_mode") {
+                config.backing_store_mode = mode.clone();
+            }
+            if let Some(toml::Value::Boolean(enabled)) = table.get("aof_enabled") {
+                config.aof_enabled = *enabled;
+            }
+            if let Some(toml::Value::String(path)) = table.get("aof_path") {
+                config.aof_path = path.clone();
+            }
+            if let Some(toml::Value::String(fsync)) = table.get("fsync") {
+                config.fsync = fsync.clone();
+            }
+            if let Some(toml::Value::Integer(window)) = table.get("aof_group_commit_us") {
+                config.aof_group_commit_us = *window as u64;
+            }
+            if let Some(toml::Value::String(path)) = table.get("snapshot_path") {
+                config.snapshot_path = path.clone();
+            }
+            if let Some(toml::Value::Integer(interval)) = table.get("snapshot_interval_secs") {
+                config.snapshot_interval_secs = *interval as u64;
+            }
+            if let Some(toml::Value::Integer(full_every)) = table.get("snapshot_full_every") {
+                config.snapshot_full_every = *full_every as u32;
+            }
+            if let Some(toml::Value::Integer(interval)) = table.get("aof_rewrite_interval_secs") {
+                config.aof_rewrite_interval_secs = *interval as u64;
+            }
+            if let Some(toml::Value::String(path)) = table.get("seed_file") {
+                config.seed_file = path.clone();
+            }
+            if let Some(toml::Value::String(id)) = table.get("encryption_key_id") {
+                config.encryption_key_id = id.clone();
+            }
+            if let Some(toml::Value::String(path)) = table.get("auth_tokens_file") {
+                config.auth_tokens_file = path.clone();
+            }
+            if let Some(toml::Value::String(path)) = table.get("encryption_keys_file") {
+                config.encryption_keys_file = path.clone();
+            }
+            if let Some(toml::Value::Table(tokens)) = table.get("auth_tokens") {
+                for (token, namespace) in tokens {
+                    if let toml::Value::String(namespace) = namespace {
+                        config.auth_tokens.insert(token.clone(), namespace.clone());
+                    }
+                }
+            }
+            if let Some(toml::Value::Table(webhooks)) = table.get("webhooks") {
+                for (pattern, target) in webhooks {
+                    if let toml::Value::String(target) = target {
+                        config.webhooks.insert(pattern.clone(), target.clone());
+                    }
+                }
+            }
+            if let Some(toml::Value::Table(loaders)) = table.get("loaders") {
+                for (pattern, loader) in loaders {
+                    if let Ok(loader) = loader.clone().try_into::<LoaderConfig>() {
+                        config.loaders.insert(pattern.clone(), loader);
+                    }
+                }
+            }
+            if let Some(toml::Value::Table(seed)) = table.get("seed") {
+                for (key, value) in seed {
+                    if let Some(value) = crate::seed::seed_value(value) {
+                        config.seed.insert(key.clone(), value);
+                    }
+                }
+            }
+            if let Some(toml::Value::Table(keys)) = table.get("encryption_keys") {
+                for (id, key) in keys {
+                    if let toml::Value::String(key) = key {
+                        config.encryption_keys.insert(id.clone(), key.clone());
+                    }
+                }
+            }
+            if let Some(toml::Value::Table(namespaces)) = table.get("namespace_encryption_keys") {
+                for (namespace, id) in namespaces {
+                    if let toml::Value::String(id) = id {
+                        config.namespace_encryption_keys.insert(namespace.clone(), id.clone());
+                    }
+                }
+            }
         }
         
         Ok(config)
     }
 
-    fn heal_config(config: SodiumConfig) -> Self {
+    fn heal_config(mut config: SodiumConfig) -> Self {
+        if config.log_level.parse::<tracing::Level>().is_err() {
+            config.log_level = Self::default().log_level;
+        }
+        if cluster::NodeRole::parse(&config.cluster_role).is_err() {
+            config.cluster_role = Self::default().cluster_role;
+        }
+        if config.cluster_weight == 0 {
+            config.cluster_weight = Self::default().cluster_weight;
+        }
+        if config.eviction_samples == 0 {
+            config.eviction_samples = Self::default().eviction_samples;
+        }
+        if crate::core::eviction_policy(&config.eviction_policy).is_err() {
+            config.eviction_policy = Self::default().eviction_policy;
+        }
+        if config.snapshot_full_every == 0 {
+            config.snapshot_full_every = Self::default().snapshot_full_every;
+        }
+        if config.queue_capacity == 0 {
+            config.queue_capacity = Self::default().queue_capacity;
+        }
+        for rate in [&mut config.chaos_delay_rate, &mut config.chaos_drop_rate, &mut config.chaos_error_rate] {
+            if !(0.0..=1.0).contains(rate) {
+                *rate = 0.0;
+            }
+        }
+        if config.ttl_jitter_percent > 100 {
+            config.ttl_jitter_percent = Self::default().ttl_jitter_percent;
+        }
+        if config.bloom_capacity == 0 || config.bloom_capacity > crate::sketches::MAX_BLOOM_CAPACITY {
+            config.bloom_capacity = Self::default().bloom_capacity;
+        }
+        if !(config.bloom_error_rate > 0.0 && config.bloom_error_rate < 1.0) {
+            config.bloom_error_rate = Self::default().bloom_error_rate;
+        }
+        if crate::sketches::bloom_dimensions(config.bloom_capacity, config.bloom_error_rate).0 > crate::sketches::MAX_BLOOM_BITS {
+            config.bloom_capacity = Self::default().bloom_capacity;
+            config.bloom_error_rate = Self::default().bloom_error_rate;
+        }
+        if config.topk_size == 0 || config.topk_size > crate::sketches::MAX_TOPK as u64 {
+            config.topk_size = Self::default().topk_size;
+        }
+        if config.cuckoo_capacity == 0 || config.cuckoo_capacity > crate::sketches::MAX_CUCKOO_CAPACITY {
+            config.cuckoo_capacity = Self::default().cuckoo_capacity;
+        }
+        if crate::api::NetworkBackend::parse(&config.network_backend).is_err() {
+            config.network_backend = Self::default().network_backend;
+        }
+        if crate::aof::FsyncPolicy::parse(&config.fsync).is_err() {
+            config.fsync = Self::default().fsync;
+        }
+        if crate::backing::WriteMode::parse(&config.backing_store_mode).is_err() {
+            config.backing_store_mode = Self::default().backing_store_mode;
+        }
         config
     }
 
@@ -136,4 +734,17 @@ impl SodiumConfig {
         fs::write(path, content)?;
         Ok(())
     }
+}
+
+// Tables merge key by key, so an override can change one auth token without
+// restating the rest; any other value replaces what was there.
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => merge_tables(existing, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
 } 
\ No newline at end of file
@@ -1,198 +1,2926 @@
 // Copyright (c) 2025, TheByteSlayer, Sodium
 // A scalable and optimized Key Value Caching System, written in Rust.
 
-use std::sync::{Arc, OnceLock};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::sync::atomic::{AtomicU64, Ordering};
-use dashmap::DashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashSet, VecDeque};
+use std::hash::{BuildHasher, DefaultHasher, Hasher, RandomState};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use dashmap::{DashMap, DashSet, Entry};
+use dashmap::mapref::entry::OccupiedEntry;
+use dashmap::mapref::one::Ref;
+use rand::{Rng, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::info;
+use crate::aof::{self, AofRecord};
+use crate::background;
+use crate::compact::{self, CompactStr};
+use crate::counter::ShardedCounter;
+use crate::sketches::{self, Bloom, Cuckoo, TopK};
+use crate::configuration::SodiumConfig;
+use crate::webhooks::{self, KeyEvent};
+
+// Fixed per-entry cost on top of the key and value bytes: the key's header
+// plus the entry itself.
+const ENTRY_OVERHEAD: u64 = (std::mem::size_of::<CompactStr>() + std::mem::size_of::<CacheEntry>()) as u64;
+
+// Interned values nothing refers to anymore are dropped once per this many
+// additions to the pool.
+const INTERN_SWEEP_EVERY: u64 = 1024;
+
+// Samples in a row that find nothing to evict before evict_to_limit gives
+// up until the next write.
+const MAX_EMPTY_EVICTION_SAMPLES: u32 = 16;
+
+// Deletes between passes dropping tombstones past their retention.
+const TOMBSTONE_PURGE_EVERY: u64 = 1024;
+
+fn tag_memory_usage(tag: &str) -> u64 {
+    (std::mem::size_of::<String>() + tag.len()) as u64
+}
+
+// Shard count used in deterministic mode, where the default depends on the
+// number of CPUs.
+const DETERMINISTIC_SHARDS: usize = 16;
+
+const DOCTOR_LARGEST_KEYS: usize = 3;
+const BIGKEYS_PROGRESS_INTERVAL: u64 = 100_000;
+// defrag_shard() leaves tables smaller than this, or over a quarter full,
+// alone: rebuilding them would free little.
+const DEFRAG_MIN_BUCKETS: usize = 1024;
+const DEFRAG_MAX_LOAD: usize = 4;
+const BUCKET_BYTES: usize = std::mem::size_of::<(CompactStr, dashmap::SharedValue<CacheEntry>)>() + 1;
+
+/// Key hashing for the main table. Deterministic mode hashes with a fixed
+/// seed so keys land in the same shards and buckets on every run, which
+/// keeps eviction sampling and scan order reproducible.
+#[derive(Debug, Clone)]
+pub enum StorageHasher {
+    Random(RandomState),
+    Seeded(u64),
+}
+
+impl BuildHasher for StorageHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        match self {
+            StorageHasher::Random(state) => state.build_hasher(),
+            StorageHasher::Seeded(seed) => {
+                let mut hasher = DefaultHasher::new();
+                hasher.write_u64(*seed);
+                hasher
+            }
+        }
+    }
+}
+
+// Set only in deterministic mode; everything else draws from thread_rng.
+static SEEDED_RNG: OnceLock<Mutex<StdRng>> = OnceLock::new();
+
+fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    match SEEDED_RNG.get() {
+        Some(rng) => f(&mut *rng.lock().unwrap_or_else(PoisonError::into_inner)),
+        None => f(&mut rand::thread_rng()),
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum CacheError {
     #[error("Key not found: {0}")]
     KeyNotFound(String),
+    #[error("Operation against a key holding the wrong kind of value: {0}")]
+    WrongType(String),
+    #[error("Not enough memory to write {0} under max_memory with eviction disabled")]
+    OutOfMemory(String),
+    #[error("Value at {0} is not an integer or the result is out of range")]
+    NotAnInteger(String),
+    #[error("No room left in the cuckoo filter at {0}")]
+    FilterFull(String),
+}
+
+pub type Metadata = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+    Accessed,
+}
+
+// Seconds of idleness that halve an entry's access count under LFU, so keys
+// that were hot once do not stay ahead of keys that are hot now.
+const LFU_DECAY_SECS: u64 = 60;
+
+/// What a policy knows about a sampled entry.
+pub(crate) struct EntryUsage {
+    // Microseconds since the epoch.
+    pub accessed_at: u64,
+    pub accesses: u32,
+}
+
+/// Chooses which entries make room once usage passes max_memory, picked
+/// with the eviction_policy setting.
+pub(crate) trait EvictionPolicy: Send + Sync + std::fmt::Debug {
+    /// The eviction_policy setting that picks it.
+    fn name(&self) -> &'static str;
+
+    /// Without eviction, writes that need room fail instead.
+    fn evicts(&self) -> bool {
+        true
+    }
+
+    /// Of the entries sampled, the one ranked lowest is evicted.
+    fn rank(&self, usage: &EntryUsage, rng: &mut dyn RngCore) -> u64;
+}
+
+#[derive(Debug)]
+struct LeastRecentlyUsed;
+
+impl EvictionPolicy for LeastRecentlyUsed {
+    fn name(&self) -> &'static str {
+        "lru"
+    }
+
+    fn rank(&self, usage: &EntryUsage, _rng: &mut dyn RngCore) -> u64 {
+        usage.accessed_at
+    }
+}
+
+#[derive(Debug)]
+struct LeastFrequentlyUsed;
+
+impl EvictionPolicy for LeastFrequentlyUsed {
+    fn name(&self) -> &'static str {
+        "lfu"
+    }
+
+    // The decayed count in the high half, ties going to the entry accessed
+    // longest ago.
+    fn rank(&self, usage: &EntryUsage, _rng: &mut dyn RngCore) -> u64 {
+        let idle_secs = now_micros().saturating_sub(usage.accessed_at) / 1_000_000;
+        let accesses = usage.accesses >> (idle_secs / LFU_DECAY_SECS).min(31);
+        ((accesses as u64) << 32) | (usage.accessed_at / 1_000_000) as u32 as u64
+    }
+}
+
+#[derive(Debug)]
+struct RandomEviction;
+
+impl EvictionPolicy for RandomEviction {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn rank(&self, _usage: &EntryUsage, rng: &mut dyn RngCore) -> u64 {
+        rng.next_u64()
+    }
+}
+
+#[derive(Debug)]
+struct NoEviction;
+
+impl EvictionPolicy for NoEviction {
+    fn name(&self) -> &'static str {
+        "noeviction"
+    }
+
+    fn evicts(&self) -> bool {
+        false
+    }
+
+    fn rank(&self, _usage: &EntryUsage, _rng: &mut dyn RngCore) -> u64 {
+        0
+    }
+}
+
+pub(crate) fn eviction_policy(name: &str) -> Result<Box<dyn EvictionPolicy>, String> {
+    match name.trim().to_lowercase().as_str() {
+        "lru" => Ok(Box::new(LeastRecentlyUsed)),
+        "lfu" => Ok(Box::new(LeastFrequentlyUsed)),
+        "random" => Ok(Box::new(RandomEviction)),
+        "noeviction" => Ok(Box::new(NoEviction)),
+        _ => Err(format!("Invalid eviction policy: {}. Valid policies are: lru, lfu, random, noeviction", name)),
+    }
+}
+
+impl SortOrder {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.trim().to_lowercase().as_str() {
+            "asc" => Ok(SortOrder::Ascending),
+            "desc" => Ok(SortOrder::Descending),
+            "accessed" => Ok(SortOrder::Accessed),
+            _ => Err(format!("Invalid sort order: {}. Valid orders are: asc, desc, accessed", input)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SetOptions {
+    pub tags: Vec<String>,
+    pub metadata: Metadata,
+    pub ttl: Option<Duration>,
+    pub sliding: bool,
+    // Most a fixed TTL is lengthened by at random, in percent; None takes
+    // ttl_jitter_percent. See jittered_ttl.
+    pub ttl_jitter: Option<u8>,
+    // Client the write came from, recorded in the key's version history.
+    pub writer: Option<SocketAddr>,
+    // Microseconds since the epoch before which set() keeps the write
+    // invisible, leaving the previous value in place.
+    pub visible_at: Option<u64>,
+}
+
+/// A value written to a key, kept in its history when version_history is on.
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub value: Arc<str>,
+    // Microseconds since the epoch.
+    pub written_at: u64,
+    pub writer: Option<SocketAddr>,
+}
+
+impl Version {
+    fn memory_usage(&self) -> u64 {
+        (std::mem::size_of::<Version>() + self.value.len()) as u64
+    }
+}
+
+/// Namespace of a "namespace:rest" key, used by invalidate() and tenant scoping.
+pub fn key_namespace(key: &str) -> Option<&str> {
+    key.split_once(':').map(|(namespace, _)| namespace)
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64
+}
+
+pub type StreamEntry = (u64, String);
+
+/// Append-only log; ids start at 1 and increase with every append.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Stream {
+    entries: Vec<StreamEntry>,
+    last_id: u64,
+}
+
+impl Stream {
+    // Drops the oldest entries beyond `max_len`, 0 keeping them all. Ids
+    // keep counting from last_id.
+    fn append(&mut self, value: String, max_len: u64) -> u64 {
+        self.last_id += 1;
+        self.entries.push((self.last_id, value));
+        if max_len > 0 && self.entries.len() as u64 > max_len {
+            let excess = self.entries.len() - max_len as usize;
+            self.entries.drain(..excess);
+        }
+        self.last_id
+    }
+
+    fn range(&self, start: u64, end: u64) -> Vec<StreamEntry> {
+        let from = self.entries.partition_point(|(id, _)| *id < start);
+        self.entries[from..].iter()
+            .take_while(|(id, _)| *id <= end)
+            .cloned()
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.entries.iter().map(|(_, value)| std::mem::size_of::<StreamEntry>() + value.len()).sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Value {
+    // Shared so reads hand out a reference instead of copying the text.
+    Text(Arc<str>),
+    // Bits are numbered from the most significant bit of the first byte.
+    Bitmap(Vec<u8>),
+    Stream(Stream),
+    Bloom(Bloom),
+    TopK(TopK),
+    Cuckoo(Cuckoo),
+}
+
+impl Value {
+    fn len(&self) -> usize {
+        match self {
+            Value::Text(text) => text.len(),
+            Value::Bitmap(bytes) => bytes.len(),
+            Value::Stream(stream) => stream.size(),
+            Value::Bloom(bloom) => bloom.size(),
+            Value::TopK(top) => top.size(),
+            Value::Cuckoo(cuckoo) => cuckoo.size(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Value::Text(text) => text.len(),
+            Value::Bitmap(bytes) => bytes.capacity(),
+            Value::Stream(stream) => stream.size(),
+            Value::Bloom(bloom) => bloom.size(),
+            Value::TopK(top) => top.size(),
+            Value::Cuckoo(cuckoo) => cuckoo.size(),
+        }
+    }
+
+    // Only text and bitmaps can be read and edited as bits.
+    fn holds_bits(&self) -> bool {
+        matches!(self, Value::Text(_) | Value::Bitmap(_))
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Text(_) => "text",
+            Value::Bitmap(_) => "bitmap",
+            Value::Stream(_) => "stream",
+            Value::Bloom(_) => "bloom",
+            Value::TopK(_) => "topk",
+            Value::Cuckoo(_) => "cuckoo",
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Value::Text(text) => text.as_bytes(),
+            Value::Bitmap(bytes) => bytes,
+            Value::Stream(_) | Value::Bloom(_) | Value::TopK(_) | Value::Cuckoo(_) => &[],
+        }
+    }
+
+    // Bitmaps are not guaranteed to be valid UTF-8, so they are rendered as
+    // lowercase hex for the text protocol. Streams are only readable through
+    // xrange/xread, Bloom and cuckoo filters through bfexists/cfexists and
+    // top-k sketches through topk_query/topk_list.
+    fn render(&self) -> Option<Arc<str>> {
+        match self {
+            Value::Text(text) => Some(text.clone()),
+            Value::Bitmap(bytes) => Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>().into()),
+            Value::Stream(_) | Value::Bloom(_) | Value::TopK(_) | Value::Cuckoo(_) => None,
+        }
+    }
+
+    fn get_bit(&self, offset: u64) -> bool {
+        let index = (offset / 8) as usize;
+        let mask = 0x80u8 >> (offset % 8);
+        self.bytes().get(index).is_some_and(|byte| byte & mask != 0)
+    }
+
+    /// Sets the bit at `offset`, growing the value as needed, and returns the
+    /// previous bit. Text values stay text while the edit leaves them valid
+    /// UTF-8 and become bitmaps otherwise.
+    fn set_bit(&mut self, offset: u64, bit: bool) -> bool {
+        let (mut bytes, was_text) = match std::mem::replace(self, Value::Bitmap(Vec::new())) {
+            Value::Text(text) => (text.as_bytes().to_vec(), true),
+            Value::Bitmap(bytes) => (bytes, false),
+            Value::Stream(_) | Value::Bloom(_) | Value::TopK(_) | Value::Cuckoo(_) => unreachable!("set_bit on a value without bits"),
+        };
+
+        let index = (offset / 8) as usize;
+        let mask = 0x80u8 >> (offset % 8);
+        if bytes.len() <= index {
+            bytes.resize(index + 1, 0);
+        }
+
+        let previous = bytes[index] & mask != 0;
+        if bit {
+            bytes[index] |= mask;
+        } else {
+            bytes[index] &= !mask;
+        }
+
+        *self = match was_text {
+            true => match String::from_utf8(bytes) {
+                Ok(text) => Value::Text(text.into()),
+                Err(e) => Value::Bitmap(e.into_bytes()),
+            },
+            false => Value::Bitmap(bytes),
+        };
+        previous
+    }
+
+    fn bit_count(&self) -> u64 {
+        self.bytes().iter().map(|byte| byte.count_ones() as u64).sum()
+    }
 }
 
 #[derive(Debug)]
 struct CacheEntry {
-    value: String,
+    value: Value,
     accessed_at: AtomicU64,
+    // Reads and writes since the entry was stored, for LFU eviction.
+    accesses: AtomicU32,
+    // Microseconds since the epoch, 0 when the entry never expires.
+    expires_at: AtomicU64,
+    // TTL in microseconds re-applied on every access, 0 when not sliding.
+    sliding_ttl: u64,
+    tags: Vec<String>,
+    metadata: Metadata,
+    generation: u64,
 }
 
 impl CacheEntry {
-    fn new(value: String) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
+    fn new(value: Value) -> Self {
         Self {
             value,
-            accessed_at: AtomicU64::new(now),
+            accessed_at: AtomicU64::new(now_micros()),
+            accesses: AtomicU32::new(1),
+            expires_at: AtomicU64::new(0),
+            sliding_ttl: 0,
+            tags: Vec::new(),
+            metadata: Vec::new(),
+            generation: 0,
         }
     }
 
     fn update_access_time(&self) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let now = now_micros();
         self.accessed_at.store(now, Ordering::Relaxed);
+        // A lost increment between racing readers does not matter here.
+        let accesses = self.accesses.load(Ordering::Relaxed);
+        self.accesses.store(accesses.saturating_add(1), Ordering::Relaxed);
+        if self.sliding_ttl != 0 {
+            self.expires_at.store(now.saturating_add(self.sliding_ttl), Ordering::Relaxed);
+        }
+    }
+
+    fn set_ttl(&self, ttl: Duration) {
+        self.expires_at.store(now_micros().saturating_add(saturating_micros(ttl)), Ordering::Relaxed);
+    }
+
+    // XFetch: report expiry early with a probability that rises as expiry
+    // nears, scaled by how long the caller needs to recompute the value, so
+    // one caller refreshes a hot key before the rest see it expire.
+    fn expires_early(&self, recompute: Duration) -> bool {
+        let expires_at = self.expires_at.load(Ordering::Relaxed);
+        if expires_at == 0 {
+            return false;
+        }
+
+        let sample: f64 = with_rng(|rng| rng.r#gen::<f64>()).max(f64::MIN_POSITIVE);
+        let gap = recompute.as_micros() as f64 * -sample.ln();
+        now_micros() as f64 + gap >= expires_at as f64
+    }
+
+    fn is_expired(&self) -> bool {
+        let expires_at = self.expires_at.load(Ordering::Relaxed);
+        expires_at != 0 && now_micros() >= expires_at
+    }
+
+    fn memory_usage(&self, key: &str) -> u64 {
+        let tags: u64 = self.tags.iter().map(|tag| tag_memory_usage(tag)).sum();
+        let metadata: u64 = self.metadata.iter()
+            .map(|(name, value)| (2 * std::mem::size_of::<String>() + name.len() + value.len()) as u64)
+            .sum();
+        ENTRY_OVERHEAD + compact::heap_len(key.len()) as u64 + self.value.len() as u64 + tags + metadata
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+    pub keys: u64,
+    pub used_memory: u64,
+    // The part of used_memory held by version history.
+    pub history_memory: u64,
+    pub max_memory: u64,
+    pub eviction_policy: &'static str,
+    pub total_operations: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evicted_keys: u64,
+    pub expired_keys: u64,
+}
+
+/// Totals for the keys of one namespace, for stats("prefix", ...).
+#[derive(Debug, Clone, Default)]
+pub struct PrefixStats {
+    pub keys: u64,
+    pub bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// Adjusted alongside used_memory and the hit and miss counters. Key and
+// byte counts are signed because memory is charged and released in either
+// order around an entry swap.
+#[derive(Debug, Default)]
+struct PrefixCounters {
+    keys: AtomicI64,
+    bytes: AtomicI64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+    pub entries: u64,
+    pub used_memory: u64,
+    pub allocated_memory: u64,
+    pub average_entry_size: u64,
+    pub fragmentation: f64,
+    pub largest_keys: Vec<(String, u64)>,
+    pub suggestions: Vec<String>,
+}
+
+/// One entry's internals, for debug("object", key). Times are microseconds
+/// since the Unix epoch, or 0 when unset.
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub kind: &'static str,
+    pub memory: u64,
+    pub accessed_at: u64,
+    pub expires_at: u64,
+    pub sliding_ttl: u64,
+    pub tags: usize,
+    pub generation: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BigKeysReport {
+    pub scanned: u64,
+    pub largest: Vec<(String, u64)>,
+}
+
+/// Position of an in-progress scan: the shard being walked and the last key
+/// returned from it. Rendered as "0" at the start and end of a scan, then
+/// "<shard>" or "<shard>:<last key>".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanCursor {
+    pub shard: usize,
+    pub after: Option<String>,
+}
+
+impl ScanCursor {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid scan cursor: {}", input);
+        let (shard, after) = match input.split_once(':') {
+            Some((shard, after)) if !after.is_empty() => (shard, Some(after.to_string())),
+            Some(_) => return Err(invalid()),
+            None => (input, None),
+        };
+        let shard = shard.parse::<usize>().map_err(|_| invalid())?;
+        Ok(Self { shard, after })
+    }
+}
+
+impl std::fmt::Display for ScanCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.after {
+            Some(after) => write!(f, "{}:{}", self.shard, after),
+            None => write!(f, "{}", self.shard),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanPage {
+    pub cursor: ScanCursor,
+    pub keys: Vec<String>,
+}
+
+/// A live entry as written to a snapshot. Expiry is absolute, in
+/// microseconds since the epoch, so loading does not extend it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    value: Value,
+    tags: Vec<String>,
+    metadata: Metadata,
+    expires_at: u64,
+    sliding_ttl: u64,
+    generation: u64,
+}
+
+// A deleted entry kept so undelete() can restore it.
+#[derive(Debug)]
+struct Tombstone {
+    entry: CacheEntry,
+    deleted_at: Instant,
+}
+
+// A set() waiting for its visible_at time.
+#[derive(Debug)]
+struct ScheduledWrite {
+    value: String,
+    options: SetOptions,
+    // Charged to used_memory until the write is stored.
+    size: u64,
+}
+
+/// A write waiting for its visible_at time, as written to a snapshot. The
+/// TTL, in microseconds and 0 for none, starts once the write is visible.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduledEntry {
+    value: String,
+    tags: Vec<String>,
+    metadata: Metadata,
+    ttl: u64,
+    sliding: bool,
+    ttl_jitter: Option<u8>,
+    visible_at: u64,
+}
+
+#[derive(Debug)]
+struct Lease {
+    token: u64,
+    expires_at: Instant,
+}
+
+impl Lease {
+    fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
     }
 }
 
 #[derive(Debug)]
 pub struct Sodium {
-    storage: DashMap<String, CacheEntry>,
-    total_operations: AtomicU64,
-    hit_count: AtomicU64,
-    miss_count: AtomicU64,
+    storage: DashMap<CompactStr, CacheEntry, StorageHasher>,
+    tag_index: DashMap<String, HashSet<String>>,
+    generations: DashMap<String, u64>,
+    leases: DashMap<String, Lease>,
+    stream_waiters: DashMap<String, Arc<Notify>>,
+    next_fencing_token: AtomicU64,
+    total_operations: ShardedCounter,
+    hit_count: ShardedCounter,
+    miss_count: ShardedCounter,
+    evicted_keys: AtomicU64,
+    expired_keys: AtomicU64,
+    used_memory: AtomicU64,
+    max_memory: u64,
+    eviction_samples: usize,
+    eviction_policy: Box<dyn EvictionPolicy>,
+    // Keys written or removed since the last snapshot, tracked only while
+    // snapshots are enabled so incremental snapshots can skip the rest.
+    track_dirty: bool,
+    dirty_keys: DashSet<String>,
+    // Values up to intern_max_len bytes share one allocation per distinct
+    // text; 0 turns interning off.
+    interned: DashSet<Arc<str>>,
+    intern_max_len: usize,
+    intern_additions: AtomicU64,
+    // Entries removed by delete(), kept for tombstone_retention so they can
+    // be undeleted; zero retention turns tombstones off.
+    tombstones: DashMap<String, Tombstone>,
+    tombstone_retention: Duration,
+    tombstone_additions: AtomicU64,
+    // The last version_history values written to each key, newest first,
+    // dropped with the key; 0 keeps no history.
+    versions: DashMap<String, VecDeque<Version>>,
+    version_history: usize,
+    history_memory: AtomicU64,
+    ttl_jitter_percent: u8,
+    // Per-namespace key, byte, hit and miss counts, kept only while
+    // prefix_stats is on.
+    prefix_stats: bool,
+    prefixes: DashMap<String, PrefixCounters>,
+    // Writes not visible yet, at most one per key. They are held in memory
+    // only and reach the AOF and snapshots once they become visible.
+    scheduled: DashMap<String, ScheduledWrite>,
+    scheduled_count: AtomicUsize,
+    started_at: Instant,
 }
 
 impl Sodium {
     pub fn new() -> Self {
         Self {
-            storage: DashMap::new(),
-            total_operations: AtomicU64::new(0),
-            hit_count: AtomicU64::new(0),
-            miss_count: AtomicU64::new(0),
+            storage: DashMap::with_hasher(StorageHasher::Random(RandomState::new())),
+            tag_index: DashMap::new(),
+            generations: DashMap::new(),
+            leases: DashMap::new(),
+            stream_waiters: DashMap::new(),
+            next_fencing_token: AtomicU64::new(1),
+            total_operations: ShardedCounter::new(),
+            hit_count: ShardedCounter::new(),
+            miss_count: ShardedCounter::new(),
+            evicted_keys: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            used_memory: AtomicU64::new(0),
+            max_memory: 0,
+            eviction_samples: 5,
+            eviction_policy: Box::new(LeastRecentlyUsed),
+            track_dirty: false,
+            dirty_keys: DashSet::new(),
+            interned: DashSet::new(),
+            intern_max_len: 0,
+            intern_additions: AtomicU64::new(0),
+            tombstones: DashMap::new(),
+            tombstone_retention: Duration::ZERO,
+            tombstone_additions: AtomicU64::new(0),
+            versions: DashMap::new(),
+            version_history: 0,
+            history_memory: AtomicU64::new(0),
+            ttl_jitter_percent: 0,
+            prefix_stats: false,
+            prefixes: DashMap::new(),
+            scheduled: DashMap::new(),
+            scheduled_count: AtomicUsize::new(0),
+            started_at: Instant::now(),
         }
     }
 
-    pub async fn set(&self, key: String, value: String) -> Result<(), CacheError> {
-        self.total_operations.fetch_add(1, Ordering::Relaxed);
-        
-        let entry = CacheEntry::new(value);
-        self.storage.insert(key, entry);
-        
-        Ok(())
-    }
-
-    pub async fn get(&self, key: &str) -> Result<String, CacheError> {
-        self.total_operations.fetch_add(1, Ordering::Relaxed);
-        
-        if let Some(entry) = self.storage.get(key) {
-            entry.update_access_time();
-            self.hit_count.fetch_add(1, Ordering::Relaxed);
-            Ok(entry.value.clone())
+    pub fn with_config(config: &SodiumConfig) -> Self {
+        let storage = if config.deterministic {
+            let hasher = StorageHasher::Seeded(config.deterministic_seed);
+            DashMap::with_hasher_and_shard_amount(hasher, DETERMINISTIC_SHARDS)
         } else {
-            self.miss_count.fetch_add(1, Ordering::Relaxed);
-            Err(CacheError::KeyNotFound(key.to_string()))
+            DashMap::with_hasher(StorageHasher::Random(RandomState::new()))
+        };
+        Self {
+            storage,
+            max_memory: config.max_memory,
+            eviction_samples: config.eviction_samples.max(1) as usize,
+            eviction_policy: eviction_policy(&config.eviction_policy).unwrap_or_else(|_| Box::new(LeastRecentlyUsed)),
+            track_dirty: config.snapshot_interval_secs > 0,
+            intern_max_len: config.intern_max_len,
+            tombstone_retention: Duration::from_secs(config.tombstone_retention_secs),
+            version_history: config.version_history,
+            ttl_jitter_percent: config.ttl_jitter_percent.min(100) as u8,
+            prefix_stats: config.prefix_stats,
+            ..Self::new()
         }
     }
 
-    pub async fn delete(&self, key: &str) -> Result<bool, CacheError> {
-        self.total_operations.fetch_add(1, Ordering::Relaxed);
-        
-        match self.storage.remove(key) {
-            Some(_) => Ok(true),
-            None => Ok(false),
-        }
-    }
+    pub async fn set(&self, key: String, value: String, options: SetOptions) -> Result<(), CacheError> {
+        self.total_operations.increment();
+        self.ensure_fits(&key, ENTRY_OVERHEAD + compact::heap_len(key.len()) as u64 + value.len() as u64)?;
+        self.ensure_room(&key)?;
 
-    pub async fn keys(&self) -> Result<Vec<String>, CacheError> {
-        self.total_operations.fetch_add(1, Ordering::Relaxed);
-        
-        let keys: Vec<String> = self.storage.iter()
-            .map(|entry| entry.key().clone())
-            .collect();
-        
-        Ok(keys)
-    }
-}
+        if let Some(visible_at) = options.visible_at
+            && visible_at > now_micros() {
+            self.schedule(key, value, options, true);
+            self.evict_if_needed();
+            return Ok(());
+        }
 
-impl Default for Sodium {
-    fn default() -> Self {
-        Self::new()
+        self.activate_due(&key);
+        self.store(key, value, options);
+        Ok(())
     }
-}
 
-static GLOBAL_CACHE: OnceLock<Arc<Sodium>> = OnceLock::new();
+    fn store(&self, key: String, value: String, options: SetOptions) {
+        let writer = options.writer;
+        let value = self.intern(value);
+        let entry = self.build_entry(&key, value.clone(), options);
+        self.charge(&key, entry.memory_usage(&key));
 
-pub fn initialize_cache() {
-    let _ = GLOBAL_CACHE.set(Arc::new(Sodium::new()));
-}
+        // Tag index updates happen under the entry lock so concurrent writers
+        // of the same key cannot leave the index out of sync with the entry.
+        match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                aof::append(|| set_record(occupied.key(), &entry));
+                self.mark_dirty(occupied.key());
+                webhooks::notify(KeyEvent::Set, occupied.key());
+                self.record_version(occupied.key(), value, writer);
+                self.replace_occupied(&mut occupied, entry);
+            }
+            Entry::Vacant(vacant) => {
+                aof::append(|| set_record(vacant.key(), &entry));
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::Set, vacant.key());
+                self.record_version(vacant.key(), value, writer);
+                self.index_tags(vacant.key(), &entry.tags);
+                self.count_key(vacant.key(), 1);
+                vacant.insert(entry);
+            }
+        }
 
-pub fn get_cache() -> &'static Arc<Sodium> {
-    GLOBAL_CACHE.get().expect("Cache not initialized")
-}
+        self.evict_if_needed();
+    }
 
-pub fn execute_get(key: &str) -> super::threading::TaskResult<Option<String>> {
-    let cache = get_cache();
-    match tokio::runtime::Handle::try_current() {
-        Ok(handle) => {
-            handle.block_on(async {
-                match cache.get(key).await {
-                    Ok(value) => Ok(Some(value)),
-                    Err(CacheError::KeyNotFound(_)) => Ok(None),
+    // Pending writes are charged and logged like stored ones, so they count
+    // against max_memory and survive a restart. `log` is off for writes
+    // restored from a snapshot.
+    fn schedule(&self, key: String, value: String, options: SetOptions, log: bool) {
+        let size = ENTRY_OVERHEAD + compact::heap_len(key.len()) as u64 + value.len() as u64;
+        self.charge(&key, size);
+        match self.scheduled.entry(key) {
+            Entry::Occupied(mut occupied) => {
+                if log {
+                    aof::append(|| schedule_record(occupied.key(), &value, &options));
                 }
-            })
-        }
-        Err(_) => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(async {
-                match cache.get(key).await {
-                    Ok(value) => Ok(Some(value)),
-                    Err(CacheError::KeyNotFound(_)) => Ok(None),
+                self.mark_dirty(occupied.key());
+                let previous = occupied.insert(ScheduledWrite { value, options, size });
+                self.release(occupied.key(), previous.size);
+            }
+            Entry::Vacant(vacant) => {
+                if log {
+                    aof::append(|| schedule_record(vacant.key(), &value, &options));
                 }
-            })
+                self.mark_dirty(vacant.key());
+                vacant.insert(ScheduledWrite { value, options, size });
+                self.scheduled_count.fetch_add(1, Ordering::Relaxed);
+            }
         }
     }
-}
 
-pub fn execute_set(key: String, value: String) -> super::threading::TaskResult<()> {
-    let cache = get_cache();
-    match tokio::runtime::Handle::try_current() {
-        Ok(handle) => {
-            handle.block_on(async {
-                cache.set(key, value).await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
+    /// Makes the write scheduled for `key` visible once its time has come.
+    /// Runs before anything reads or writes the key, so a due write is never
+    /// seen late or applied over a newer one.
+    fn activate_due(&self, key: &str) {
+        if self.scheduled_count.load(Ordering::Relaxed) == 0 {
+            return;
         }
-        Err(_) => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(async {
-                cache.set(key, value).await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
+
+        let now = now_micros();
+        let due = self.scheduled.remove_if(key, |_, write| write.options.visible_at.is_some_and(|at| at <= now));
+        if let Some((key, write)) = due {
+            self.scheduled_count.fetch_sub(1, Ordering::Relaxed);
+            self.release(&key, write.size);
+            self.store(key, write.value, write.options);
         }
     }
-}
 
-pub fn execute_delete(key: &str) -> super::threading::TaskResult<bool> {
-    let cache = get_cache();
-    match tokio::runtime::Handle::try_current() {
-        Ok(handle) => {
-            handle.block_on(async {
-                cache.delete(key).await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
+    /// Makes every scheduled write whose time has come visible.
+    pub fn activate_scheduled(&self) {
+        if self.scheduled_count.load(Ordering::Relaxed) == 0 {
+            return;
         }
-        Err(_) => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(async {
-                cache.delete(key).await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
+
+        let now = now_micros();
+        let due: Vec<String> = self.scheduled.iter()
+            .filter(|write| write.options.visible_at.is_some_and(|at| at <= now))
+            .map(|write| write.key().clone())
+            .collect();
+        for key in due {
+            self.activate_due(&key);
         }
     }
-}
 
-pub fn execute_keys() -> super::threading::TaskResult<Vec<String>> {
-    let cache = get_cache();
-    match tokio::runtime::Handle::try_current() {
-        Ok(handle) => {
-            handle.block_on(async {
-                cache.keys().await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
-        }
-        Err(_) => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(async {
-                cache.keys().await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
-        }
+    /// Writes waiting to become visible.
+    pub fn scheduled_writes(&self) -> usize {
+        self.scheduled_count.load(Ordering::Relaxed)
     }
-}
 
+    /// Returns the live value of `key`, or stores `value` and returns it when
+    /// the key is missing, expired or invalidated.
+    pub async fn get_or_set(&self, key: String, value: String, options: SetOptions) -> Result<Arc<str>, CacheError> {
+        self.total_operations.increment();
+        self.activate_due(&key);
+
+        let writer = options.writer;
+        let options_value = self.intern(value);
+        let entry = self.build_entry(&key, options_value.clone(), options);
+        self.ensure_fits(&key, entry.memory_usage(&key))?;
+        let value = match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                if !self.is_stale(occupied.key(), occupied.get()) {
+                    occupied.get().update_access_time();
+                    self.record_lookup(occupied.key(), true);
+                    return occupied.get().value.render()
+                        .ok_or_else(|| CacheError::WrongType(occupied.key().to_string()));
+                }
+
+                self.record_lookup(occupied.key(), false);
+                self.ensure_room(occupied.key())?;
+                self.charge(occupied.key(), entry.memory_usage(occupied.key()));
+                let value = options_value.clone();
+                aof::append(|| set_record(occupied.key(), &entry));
+                self.mark_dirty(occupied.key());
+                webhooks::notify(KeyEvent::Set, occupied.key());
+                self.record_version(occupied.key(), value.clone(), writer);
+                self.replace_occupied(&mut occupied, entry);
+                value
+            }
+            Entry::Vacant(vacant) => {
+                self.record_lookup(vacant.key(), false);
+                self.ensure_room(vacant.key())?;
+                self.charge(vacant.key(), entry.memory_usage(vacant.key()));
+                aof::append(|| set_record(vacant.key(), &entry));
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::Set, vacant.key());
+                self.record_version(vacant.key(), options_value.clone(), writer);
+                self.index_tags(vacant.key(), &entry.tags);
+                self.count_key(vacant.key(), 1);
+                vacant.insert(entry);
+                options_value
+            }
+        };
+
+        self.evict_if_needed();
+
+        Ok(value)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Arc<str>, CacheError> {
+        self.total_operations.increment();
+        
+        if let Some(entry) = self.live_entry(key) {
+            entry.update_access_time();
+            self.record_lookup(key, true);
+            entry.value.render().ok_or_else(|| CacheError::WrongType(key.to_string()))
+        } else {
+            self.record_lookup(key, false);
+            Err(CacheError::KeyNotFound(key.to_string()))
+        }
+    }
+
+    /// Stores `value` at `key`, without TTL or tags like a plain set(), and
+    /// returns the value it replaced, None when the key was missing. The
+    /// read and the write happen under one entry lock, so no other write
+    /// lands between them.
+    pub async fn get_set(&self, key: String, value: String, writer: Option<SocketAddr>) -> Result<Option<Arc<str>>, CacheError> {
+        self.total_operations.increment();
+        self.ensure_room(&key)?;
+        self.activate_due(&key);
+
+        let value = self.intern(value);
+        let entry = self.build_entry(&key, value.clone(), SetOptions { writer, ..SetOptions::default() });
+        self.ensure_fits(&key, entry.memory_usage(&key))?;
+        let previous = match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                let previous = if self.is_stale(occupied.key(), occupied.get()) {
+                    None
+                } else {
+                    let previous = occupied.get().value.render()
+                        .ok_or_else(|| CacheError::WrongType(occupied.key().to_string()))?;
+                    Some(previous)
+                };
+                self.record_lookup(occupied.key(), previous.is_some());
+                self.charge(occupied.key(), entry.memory_usage(occupied.key()));
+                aof::append(|| set_record(occupied.key(), &entry));
+                self.mark_dirty(occupied.key());
+                webhooks::notify(KeyEvent::Set, occupied.key());
+                self.record_version(occupied.key(), value, writer);
+                self.replace_occupied(&mut occupied, entry);
+                previous
+            }
+            Entry::Vacant(vacant) => {
+                self.record_lookup(vacant.key(), false);
+                self.charge(vacant.key(), entry.memory_usage(vacant.key()));
+                aof::append(|| set_record(vacant.key(), &entry));
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::Set, vacant.key());
+                self.record_version(vacant.key(), value, writer);
+                self.index_tags(vacant.key(), &entry.tags);
+                self.count_key(vacant.key(), 1);
+                vacant.insert(entry);
+                None
+            }
+        };
+
+        self.evict_if_needed();
+        Ok(previous)
+    }
+
+    /// Values of `keys` in order, None for a key that is missing or holds a
+    /// value get() cannot return.
+    pub async fn mget(&self, keys: &[String]) -> Vec<Option<Arc<str>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await.ok());
+        }
+        values
+    }
+
+    /// Sets each pair in order. Not atomic: when a write fails, with
+    /// ERR_OOM under noeviction say, the pairs before it stay written.
+    pub async fn mset(&self, pairs: Vec<(String, String)>, writer: Option<SocketAddr>) -> Result<(), CacheError> {
+        for (key, value) in pairs {
+            self.set(key, value, SetOptions { writer, ..SetOptions::default() }).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds `delta` to the integer stored at `key`, starting from 0 when the
+    /// key is missing, and returns the result. The entry keeps its TTL and
+    /// tags.
+    pub async fn incr_by(&self, key: String, delta: i64, writer: Option<SocketAddr>) -> Result<i64, CacheError> {
+        self.total_operations.increment();
+        // The new value is at most the 20 characters of i64::MIN.
+        self.ensure_fits(&key, ENTRY_OVERHEAD + compact::heap_len(key.len()) as u64 + 20)?;
+        self.ensure_room(&key)?;
+        self.activate_due(&key);
+
+        let generation = self.namespace_generation(&key);
+        let fresh = || CacheEntry { generation, ..CacheEntry::new(Value::Text(delta.to_string().into())) };
+
+        let result = match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                let result = if self.is_stale(occupied.key(), occupied.get()) {
+                    let fresh = fresh();
+                    self.charge(occupied.key(), fresh.memory_usage(occupied.key()));
+                    self.replace_occupied(&mut occupied, fresh);
+                    delta
+                } else {
+                    let Value::Text(text) = &occupied.get().value else {
+                        return Err(CacheError::WrongType(occupied.key().to_string()));
+                    };
+                    let result = text.parse::<i64>().ok()
+                        .and_then(|current| current.checked_add(delta))
+                        .ok_or_else(|| CacheError::NotAnInteger(occupied.key().to_string()))?;
+                    let before = occupied.get().memory_usage(occupied.key());
+                    occupied.get_mut().value = Value::Text(result.to_string().into());
+                    let after = occupied.get().memory_usage(occupied.key());
+                    self.charge(occupied.key(), after);
+                    self.release(occupied.key(), before);
+                    occupied.get().update_access_time();
+                    result
+                };
+
+                // Logged as the resulting value, so replaying it twice is harmless.
+                aof::append(|| set_record(occupied.key(), occupied.get()));
+                self.mark_dirty(occupied.key());
+                webhooks::notify(KeyEvent::Incr, occupied.key());
+                self.record_version(occupied.key(), result.to_string().into(), writer);
+                result
+            }
+            Entry::Vacant(vacant) => {
+                let entry = fresh();
+                aof::append(|| set_record(vacant.key(), &entry));
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::Incr, vacant.key());
+                self.record_version(vacant.key(), delta.to_string().into(), writer);
+                self.charge(vacant.key(), entry.memory_usage(vacant.key()));
+                self.count_key(vacant.key(), 1);
+                vacant.insert(entry);
+                delta
+            }
+        };
+
+        self.evict_if_needed();
+
+        Ok(result)
+    }
+
+    /// Sets the bit at `offset` in the value of `key`, creating an empty
+    /// bitmap when the key is missing, and returns the previous bit.
+    pub async fn set_bit(&self, key: String, offset: u64, bit: bool) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+        self.ensure_room(&key)?;
+        self.activate_due(&key);
+
+        let mut fresh = CacheEntry::new(Value::Bitmap(Vec::new()));
+        fresh.generation = self.namespace_generation(&key);
+
+        let previous = match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                if self.is_stale(occupied.key(), occupied.get()) {
+                    self.charge(occupied.key(), fresh.memory_usage(occupied.key()));
+                    self.replace_occupied(&mut occupied, fresh);
+                } else if !occupied.get().value.holds_bits() {
+                    return Err(CacheError::WrongType(occupied.key().to_string()));
+                }
+                // A far offset grows the bitmap by up to 512MB in one write.
+                let growth = bitmap_len(offset).saturating_sub(occupied.get().value.bytes().len() as u64);
+                self.ensure_allocation(occupied.key(), growth)?;
+
+                aof::append(|| AofRecord::SetBit { key: occupied.key().to_string(), offset, bit });
+                self.mark_dirty(occupied.key());
+                webhooks::notify(KeyEvent::SetBit, occupied.key());
+                let before = occupied.get().memory_usage(occupied.key());
+                let previous = occupied.get_mut().value.set_bit(offset, bit);
+                let after = occupied.get().memory_usage(occupied.key());
+                self.charge(occupied.key(), after);
+                self.release(occupied.key(), before);
+                occupied.get().update_access_time();
+                previous
+            }
+            Entry::Vacant(vacant) => {
+                self.ensure_allocation(vacant.key(), bitmap_len(offset))?;
+                aof::append(|| AofRecord::SetBit { key: vacant.key().to_string(), offset, bit });
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::SetBit, vacant.key());
+                fresh.value.set_bit(offset, bit);
+                self.charge(vacant.key(), fresh.memory_usage(vacant.key()));
+                self.count_key(vacant.key(), 1);
+                vacant.insert(fresh);
+                false
+            }
+        };
+
+        self.evict_if_needed();
+
+        Ok(previous)
+    }
+
+    pub async fn get_bit(&self, key: &str, offset: u64) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) if !entry.value.holds_bits() => Err(CacheError::WrongType(key.to_string())),
+            Some(entry) => Ok(entry.value.get_bit(offset)),
+            None => Ok(false),
+        }
+    }
+
+    pub async fn bit_count(&self, key: &str) -> Result<u64, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) if !entry.value.holds_bits() => Err(CacheError::WrongType(key.to_string())),
+            Some(entry) => Ok(entry.value.bit_count()),
+            None => Ok(0),
+        }
+    }
+
+    /// Appends `value` to the stream at `key`, creating it when missing, and
+    /// wakes readers blocked on the stream. The stream is trimmed to its
+    /// newest `max_len` entries, 0 for no limit.
+    pub async fn stream_add(&self, key: String, value: String, max_len: u64) -> Result<u64, CacheError> {
+        self.total_operations.increment();
+        self.ensure_fits(&key, ENTRY_OVERHEAD + compact::heap_len(key.len()) as u64 + (std::mem::size_of::<StreamEntry>() + value.len()) as u64)?;
+        self.ensure_room(&key)?;
+        self.activate_due(&key);
+
+        let mut fresh = CacheEntry::new(Value::Stream(Stream::default()));
+        fresh.generation = self.namespace_generation(&key);
+
+        let notify_key = key.clone();
+        let id = match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                if self.is_stale(occupied.key(), occupied.get()) {
+                    self.charge(occupied.key(), fresh.memory_usage(occupied.key()));
+                    self.replace_occupied(&mut occupied, fresh);
+                }
+
+                let before = occupied.get().memory_usage(occupied.key());
+                let Value::Stream(stream) = &mut occupied.get_mut().value else {
+                    return Err(CacheError::WrongType(occupied.key().to_string()));
+                };
+                aof::append(|| AofRecord::StreamAdd { key: notify_key.clone(), value: value.clone(), id: stream.last_id + 1, max_len });
+                self.mark_dirty(&notify_key);
+                webhooks::notify(KeyEvent::StreamAdd, &notify_key);
+                let id = stream.append(value, max_len);
+                let after = occupied.get().memory_usage(occupied.key());
+                self.charge(occupied.key(), after);
+                self.release(occupied.key(), before);
+                occupied.get().update_access_time();
+                id
+            }
+            Entry::Vacant(vacant) => {
+                let Value::Stream(stream) = &mut fresh.value else {
+                    unreachable!();
+                };
+                aof::append(|| AofRecord::StreamAdd { key: vacant.key().to_string(), value: value.clone(), id: stream.last_id + 1, max_len });
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::StreamAdd, vacant.key());
+                let id = stream.append(value, max_len);
+                self.charge(vacant.key(), fresh.memory_usage(vacant.key()));
+                self.count_key(vacant.key(), 1);
+                vacant.insert(fresh);
+                id
+            }
+        };
+
+        if let Some(notify) = self.stream_waiters.get(&notify_key) {
+            notify.notify_waiters();
+        }
+        self.evict_if_needed();
+
+        Ok(id)
+    }
+
+    /// Adds `item` to the Bloom filter at `key`, creating the filter sized for
+    /// `capacity` items at `error_rate` when missing. Returns whether the item
+    /// was new to the filter.
+    pub async fn bloom_add(&self, key: String, item: String, capacity: u64, error_rate: f64) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+        self.ensure_room(&key)?;
+        self.activate_due(&key);
+
+        // Built only when the key is missing or stale, as filters are large,
+        // and only once their size is known to fit.
+        let generation = self.namespace_generation(&key);
+        let fresh = || CacheEntry { generation, ..CacheEntry::new(Value::Bloom(Bloom::new(capacity, error_rate))) };
+        let filter_bytes = sketches::bloom_dimensions(capacity, error_rate).0 / 8;
+
+        let added = match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                if self.is_stale(occupied.key(), occupied.get()) {
+                    self.ensure_allocation(occupied.key(), filter_bytes)?;
+                    let fresh = fresh();
+                    self.charge(occupied.key(), fresh.memory_usage(occupied.key()));
+                    self.replace_occupied(&mut occupied, fresh);
+                }
+
+                let key = occupied.key().to_string();
+                let Value::Bloom(bloom) = &mut occupied.get_mut().value else {
+                    return Err(CacheError::WrongType(key));
+                };
+                // The filter keeps the size it was created with.
+                let (capacity, error_rate) = (bloom.capacity(), bloom.error_rate());
+                aof::append(|| AofRecord::BloomAdd { key: key.clone(), item: item.clone(), capacity, error_rate });
+                self.mark_dirty(&key);
+                webhooks::notify(KeyEvent::BloomAdd, &key);
+                let added = bloom.insert(&item);
+                occupied.get().update_access_time();
+                added
+            }
+            Entry::Vacant(vacant) => {
+                self.ensure_allocation(vacant.key(), filter_bytes)?;
+                let mut fresh = fresh();
+                let Value::Bloom(bloom) = &mut fresh.value else {
+                    unreachable!();
+                };
+                aof::append(|| AofRecord::BloomAdd { key: vacant.key().to_string(), item: item.clone(), capacity, error_rate });
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::BloomAdd, vacant.key());
+                let added = bloom.insert(&item);
+                self.charge(vacant.key(), fresh.memory_usage(vacant.key()));
+                self.count_key(vacant.key(), 1);
+                vacant.insert(fresh);
+                added
+            }
+        };
+
+        self.evict_if_needed();
+
+        Ok(added)
+    }
+
+    /// Whether `item` was probably added to the Bloom filter at `key`. A
+    /// missing key holds no items.
+    pub async fn bloom_exists(&self, key: &str, item: &str) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) => match &entry.value {
+                Value::Bloom(bloom) => {
+                    entry.update_access_time();
+                    Ok(bloom.contains(item))
+                }
+                _ => Err(CacheError::WrongType(key.to_string())),
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Adds `item` to the cuckoo filter at `key`, creating the filter sized
+    /// for `capacity` items when missing. Fails with FilterFull, changing
+    /// nothing, once the filter has no room for the item.
+    pub async fn cuckoo_add(&self, key: String, item: String, capacity: u64) -> Result<(), CacheError> {
+        self.total_operations.increment();
+        self.ensure_room(&key)?;
+        self.activate_due(&key);
+
+        // Built only when the key is missing or stale, as filters are large.
+        let generation = self.namespace_generation(&key);
+        let fresh = || CacheEntry { generation, ..CacheEntry::new(Value::Cuckoo(Cuckoo::new(capacity))) };
+        let filter_bytes = sketches::cuckoo_bytes(capacity);
+
+        match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                if self.is_stale(occupied.key(), occupied.get()) {
+                    self.ensure_allocation(occupied.key(), filter_bytes)?;
+                    let fresh = fresh();
+                    self.charge(occupied.key(), fresh.memory_usage(occupied.key()));
+                    self.replace_occupied(&mut occupied, fresh);
+                }
+
+                let key = occupied.key().to_string();
+                let Value::Cuckoo(cuckoo) = &mut occupied.get_mut().value else {
+                    return Err(CacheError::WrongType(key));
+                };
+                if !cuckoo.insert(&item) {
+                    return Err(CacheError::FilterFull(key));
+                }
+                let capacity = cuckoo.capacity();
+                aof::append(|| AofRecord::CuckooAdd { key: key.clone(), item, capacity });
+                self.mark_dirty(&key);
+                webhooks::notify(KeyEvent::CuckooAdd, &key);
+                occupied.get().update_access_time();
+            }
+            Entry::Vacant(vacant) => {
+                self.ensure_allocation(vacant.key(), filter_bytes)?;
+                let mut fresh = fresh();
+                let Value::Cuckoo(cuckoo) = &mut fresh.value else {
+                    unreachable!();
+                };
+                if !cuckoo.insert(&item) {
+                    return Err(CacheError::FilterFull(vacant.key().to_string()));
+                }
+                aof::append(|| AofRecord::CuckooAdd { key: vacant.key().to_string(), item, capacity });
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::CuckooAdd, vacant.key());
+                self.charge(vacant.key(), fresh.memory_usage(vacant.key()));
+                self.count_key(vacant.key(), 1);
+                vacant.insert(fresh);
+            }
+        }
+
+        self.evict_if_needed();
+
+        Ok(())
+    }
+
+    /// Whether `item` is probably in the cuckoo filter at `key`. A missing
+    /// key holds no items.
+    pub async fn cuckoo_exists(&self, key: &str, item: &str) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) => match &entry.value {
+                Value::Cuckoo(cuckoo) => {
+                    entry.update_access_time();
+                    Ok(cuckoo.contains(item))
+                }
+                _ => Err(CacheError::WrongType(key.to_string())),
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Removes one occurrence of `item` from the cuckoo filter at `key` and
+    /// returns whether it was there. The filter stays when it empties.
+    pub async fn cuckoo_delete(&self, key: &str, item: &str) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+        self.activate_due(key);
+
+        let Some(mut entry) = self.storage.get_mut(key) else {
+            return Ok(false);
+        };
+        if self.is_stale(key, &entry) {
+            return Ok(false);
+        }
+        let Value::Cuckoo(cuckoo) = &mut entry.value else {
+            return Err(CacheError::WrongType(key.to_string()));
+        };
+        if !cuckoo.remove(item) {
+            return Ok(false);
+        }
+
+        aof::append(|| AofRecord::CuckooDelete { key: key.to_string(), item: item.to_string() });
+        self.mark_dirty(key);
+        webhooks::notify(KeyEvent::CuckooDelete, key);
+        entry.update_access_time();
+        Ok(true)
+    }
+
+    /// Counts one occurrence of `item` in the top-k sketch at `key`, creating
+    /// it to track the `k` heaviest items when missing, and returns the
+    /// item's estimated count.
+    pub async fn topk_add(&self, key: String, item: String, k: usize) -> Result<u64, CacheError> {
+        self.total_operations.increment();
+        self.ensure_room(&key)?;
+        self.activate_due(&key);
+
+        // Built only when the key is missing or stale, as filters are large.
+        let generation = self.namespace_generation(&key);
+        let fresh = || CacheEntry { generation, ..CacheEntry::new(Value::TopK(TopK::new(k))) };
+
+        let count = match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => {
+                if self.is_stale(occupied.key(), occupied.get()) {
+                    let fresh = fresh();
+                    self.charge(occupied.key(), fresh.memory_usage(occupied.key()));
+                    self.replace_occupied(&mut occupied, fresh);
+                }
+
+                let key = occupied.key().to_string();
+                let before = occupied.get().memory_usage(occupied.key());
+                let Value::TopK(top) = &mut occupied.get_mut().value else {
+                    return Err(CacheError::WrongType(key));
+                };
+                let k = top.k();
+                aof::append(|| AofRecord::TopKAdd { key: key.clone(), item: item.clone(), k });
+                self.mark_dirty(&key);
+                webhooks::notify(KeyEvent::TopKAdd, &key);
+                let count = top.add(&item);
+                let after = occupied.get().memory_usage(occupied.key());
+                self.charge(occupied.key(), after);
+                self.release(occupied.key(), before);
+                occupied.get().update_access_time();
+                count
+            }
+            Entry::Vacant(vacant) => {
+                let mut fresh = fresh();
+                let Value::TopK(top) = &mut fresh.value else {
+                    unreachable!();
+                };
+                aof::append(|| AofRecord::TopKAdd { key: vacant.key().to_string(), item: item.clone(), k });
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::TopKAdd, vacant.key());
+                let count = top.add(&item);
+                self.charge(vacant.key(), fresh.memory_usage(vacant.key()));
+                self.count_key(vacant.key(), 1);
+                vacant.insert(fresh);
+                count
+            }
+        };
+
+        self.evict_if_needed();
+
+        Ok(count)
+    }
+
+    /// Estimated count of `item` in the top-k sketch at `key`; a missing key
+    /// has counted nothing.
+    pub async fn topk_query(&self, key: &str, item: &str) -> Result<u64, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) => match &entry.value {
+                Value::TopK(top) => {
+                    entry.update_access_time();
+                    Ok(top.count(item))
+                }
+                _ => Err(CacheError::WrongType(key.to_string())),
+            },
+            None => Ok(0),
+        }
+    }
+
+    /// The heaviest items in the top-k sketch at `key` with their estimated
+    /// counts, highest first.
+    pub async fn topk_list(&self, key: &str) -> Result<Vec<(String, u64)>, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) => match &entry.value {
+                Value::TopK(top) => {
+                    entry.update_access_time();
+                    Ok(top.top().to_vec())
+                }
+                _ => Err(CacheError::WrongType(key.to_string())),
+            },
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Entries of the stream at `key` with ids in `start..=end`.
+    pub async fn stream_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<StreamEntry>, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) => match &entry.value {
+                Value::Stream(stream) => {
+                    entry.update_access_time();
+                    Ok(stream.range(start, end))
+                }
+                _ => Err(CacheError::WrongType(key.to_string())),
+            },
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the notifier signalled whenever `key` gets a new stream entry.
+    pub fn stream_notifier(&self, key: &str) -> Arc<Notify> {
+        self.stream_waiters.entry(key.to_string()).or_default().clone()
+    }
+
+    /// Drops the notifier for `key` once no reader is holding it.
+    pub fn release_stream_notifier(&self, key: &str) {
+        self.stream_waiters.remove_if(key, |_, notify| Arc::strong_count(notify) == 1);
+    }
+
+    /// Like get(), but may report a miss ahead of the entry's expiry; see
+    /// CacheEntry::expires_early. The entry itself stays in place.
+    pub async fn get_early(&self, key: &str, recompute: Duration) -> Result<Arc<str>, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) if !entry.expires_early(recompute) => {
+                entry.update_access_time();
+                self.record_lookup(key, true);
+                entry.value.render().ok_or_else(|| CacheError::WrongType(key.to_string()))
+            }
+            _ => {
+                self.record_lookup(key, false);
+                Err(CacheError::KeyNotFound(key.to_string()))
+            }
+        }
+    }
+
+    pub async fn metadata(&self, key: &str) -> Result<Metadata, CacheError> {
+        self.total_operations.increment();
+
+        match self.live_entry(key) {
+            Some(entry) => Ok(entry.metadata.clone()),
+            None => Err(CacheError::KeyNotFound(key.to_string())),
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+        self.activate_due(key);
+
+        let removed = self.remove_entry_if(key, |key, _| {
+            aof::append(|| AofRecord::Delete { key: key.to_string() });
+            webhooks::notify(KeyEvent::Delete, key);
+            true
+        });
+        match removed {
+            Some((key, entry)) if !self.is_stale(&key, &entry) => {
+                self.bury(&key, entry);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Deletes `key` and returns the value it held, None when it was
+    /// missing, in one step under the entry lock. A key holding a value
+    /// get() cannot return is left in place.
+    pub async fn get_del(&self, key: &str) -> Result<Option<Arc<str>>, CacheError> {
+        self.total_operations.increment();
+        self.activate_due(key);
+
+        let mut wrong_type = false;
+        let removed = self.remove_entry_if(key, |key, entry| {
+            if !self.is_stale(key, entry) && entry.value.render().is_none() {
+                wrong_type = true;
+                return false;
+            }
+            aof::append(|| AofRecord::Delete { key: key.to_string() });
+            webhooks::notify(KeyEvent::Delete, key);
+            true
+        });
+        if wrong_type {
+            return Err(CacheError::WrongType(key.to_string()));
+        }
+        match removed {
+            Some((key, entry)) if !self.is_stale(&key, &entry) => {
+                self.record_lookup(&key, true);
+                let value = entry.value.render();
+                self.bury(&key, entry);
+                Ok(value)
+            }
+            _ => {
+                self.record_lookup(key, false);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Restores the entry a delete removed from `key` within the tombstone
+    /// retention window. Returns false when there is nothing to restore or
+    /// the key has been written again since.
+    pub async fn undelete(&self, key: &str) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+
+        let Some((_, tombstone)) = self.tombstones.remove(key) else {
+            return Ok(false);
+        };
+        if tombstone.deleted_at.elapsed() > self.tombstone_retention || self.is_stale(key, &tombstone.entry) {
+            return Ok(false);
+        }
+
+        let entry = tombstone.entry;
+        match self.storage.entry(key.into()) {
+            Entry::Occupied(occupied) if !self.is_stale(occupied.key(), occupied.get()) => {
+                // Kept, so the key can still be restored once the newer
+                // value is deleted in turn.
+                drop(occupied);
+                self.tombstones.insert(key.to_string(), Tombstone { entry, deleted_at: tombstone.deleted_at });
+                return Ok(false);
+            }
+            Entry::Occupied(mut occupied) => {
+                self.charge(occupied.key(), entry.memory_usage(occupied.key()));
+                aof::append(|| set_record(occupied.key(), &entry));
+                self.mark_dirty(occupied.key());
+                webhooks::notify(KeyEvent::Set, occupied.key());
+                self.replace_occupied(&mut occupied, entry);
+            }
+            Entry::Vacant(vacant) => {
+                self.charge(vacant.key(), entry.memory_usage(vacant.key()));
+                aof::append(|| set_record(vacant.key(), &entry));
+                self.mark_dirty(vacant.key());
+                webhooks::notify(KeyEvent::Set, vacant.key());
+                self.index_tags(vacant.key(), &entry.tags);
+                self.count_key(vacant.key(), 1);
+                vacant.insert(entry);
+            }
+        }
+
+        self.evict_if_needed();
+
+        Ok(true)
+    }
+
+    /// The values last written to `key`, newest first.
+    pub async fn history(&self, key: &str) -> Result<Vec<Version>, CacheError> {
+        self.total_operations.increment();
+        Ok(self.versions.get(key).map_or_else(Vec::new, |versions| versions.iter().cloned().collect()))
+    }
+
+    /// The `n`th most recent value written to `key`, 1 being the latest.
+    pub async fn get_version(&self, key: &str, n: usize) -> Result<Arc<str>, CacheError> {
+        self.total_operations.increment();
+        self.versions.get(key)
+            .and_then(|versions| Some(versions.get(n.checked_sub(1)?)?.value.clone()))
+            .ok_or_else(|| CacheError::KeyNotFound(key.to_string()))
+    }
+
+    // Called under the entry lock, so the history is in the order the
+    // writes were applied.
+    fn record_version(&self, key: &str, value: Arc<str>, writer: Option<SocketAddr>) {
+        if self.version_history == 0 {
+            return;
+        }
+
+        let version = Version { value, written_at: now_micros(), writer };
+        self.charge(key, version.memory_usage());
+        self.history_memory.fetch_add(version.memory_usage(), Ordering::Relaxed);
+        let mut versions = self.versions.entry(key.to_string()).or_default();
+        versions.push_front(version);
+        while versions.len() > self.version_history {
+            if let Some(oldest) = versions.pop_back() {
+                self.release(key, oldest.memory_usage());
+                self.history_memory.fetch_sub(oldest.memory_usage(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Adds the values of queued sets folded into the set just stored at
+    /// `key` to its history, behind that set's own version. `superseded` is
+    /// oldest first.
+    pub fn record_superseded(&self, key: &str, superseded: Vec<(String, Option<SocketAddr>)>) {
+        if self.version_history == 0 || superseded.is_empty() {
+            return;
+        }
+
+        let Some(mut versions) = self.versions.get_mut(key) else {
+            return;
+        };
+        let written_at = versions.front().map_or_else(now_micros, |latest| latest.written_at);
+        for (value, writer) in superseded {
+            let version = Version { value: self.intern(value), written_at, writer };
+            self.charge(key, version.memory_usage());
+            self.history_memory.fetch_add(version.memory_usage(), Ordering::Relaxed);
+            let behind_latest = versions.len().min(1);
+            versions.insert(behind_latest, version);
+        }
+        while versions.len() > self.version_history {
+            if let Some(oldest) = versions.pop_back() {
+                self.release(key, oldest.memory_usage());
+                self.history_memory.fetch_sub(oldest.memory_usage(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn drop_versions(&self, key: &str) {
+        if let Some((_, versions)) = self.versions.remove(key) {
+            let released: u64 = versions.iter().map(Version::memory_usage).sum();
+            self.release(key, released);
+            self.history_memory.fetch_sub(released, Ordering::Relaxed);
+        }
+    }
+
+    // Keeps a deleted entry as a tombstone when retention is on. Tombstones
+    // are held outside max_memory and dropped once past retention, checked
+    // every TOMBSTONE_PURGE_EVERY deletes and by the background thread.
+    fn bury(&self, key: &str, entry: CacheEntry) {
+        if self.tombstone_retention.is_zero() {
+            return;
+        }
+
+        self.tombstones.insert(key.to_string(), Tombstone { entry, deleted_at: Instant::now() });
+        if self.tombstone_additions.fetch_add(1, Ordering::Relaxed).is_multiple_of(TOMBSTONE_PURGE_EVERY) {
+            self.purge_tombstones();
+        }
+    }
+
+    /// Drops tombstones past their retention.
+    pub fn purge_tombstones(&self) {
+        self.tombstones.retain(|_, tombstone| tombstone.deleted_at.elapsed() <= self.tombstone_retention);
+    }
+
+    pub async fn tag(&self, key: &str, tag: String) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+        self.activate_due(key);
+
+        let Some(mut entry) = self.storage.get_mut(key) else {
+            return Ok(false);
+        };
+
+        if self.is_stale(key, &entry) {
+            drop(entry);
+            self.remove_stale(key);
+            return Ok(false);
+        }
+
+        if !entry.tags.contains(&tag) {
+            aof::append(|| AofRecord::Tag { key: key.to_string(), tag: tag.clone() });
+            self.mark_dirty(key);
+            webhooks::notify(KeyEvent::Tag, key);
+            self.charge(key, tag_memory_usage(&tag));
+            self.index_tags(key, std::slice::from_ref(&tag));
+            entry.tags.push(tag);
+        }
+
+        Ok(true)
+    }
+
+    /// Gives an existing key a TTL, replacing any it had, sliding or not. The
+    /// TTL is lengthened like set()'s by up to `jitter` percent, None taking
+    /// ttl_jitter_percent. Returns false when there is no such key.
+    pub async fn expire(&self, key: &str, ttl: Duration, jitter: Option<u8>) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+        self.activate_due(key);
+
+        let Some(mut entry) = self.storage.get_mut(key) else {
+            return Ok(false);
+        };
+
+        if self.is_stale(key, &entry) {
+            drop(entry);
+            self.remove_stale(key);
+            return Ok(false);
+        }
+
+        entry.sliding_ttl = 0;
+        entry.set_ttl(jittered_ttl(ttl, jitter.unwrap_or(self.ttl_jitter_percent)));
+        aof::append(|| AofRecord::Expire { key: key.to_string(), expires_at: entry.expires_at.load(Ordering::Relaxed) });
+        self.mark_dirty(key);
+        Ok(true)
+    }
+
+    /// Time left before `key` expires: None when it is missing, Some(None)
+    /// when it never expires.
+    pub async fn ttl(&self, key: &str) -> Result<Option<Option<Duration>>, CacheError> {
+        self.total_operations.increment();
+
+        let Some(entry) = self.live_entry(key) else {
+            return Ok(None);
+        };
+        let expires_at = entry.expires_at.load(Ordering::Relaxed);
+        if expires_at == 0 {
+            return Ok(Some(None));
+        }
+        Ok(Some(Some(Duration::from_micros(expires_at.saturating_sub(now_micros())))))
+    }
+
+    pub async fn keys_by_tag(&self, tag: &str) -> Result<Vec<String>, CacheError> {
+        self.total_operations.increment();
+
+        let candidates: Vec<String> = match self.tag_index.get(tag) {
+            Some(keys) => keys.iter().cloned().collect(),
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(candidates.into_iter()
+            .filter(|key| {
+                self.live_entry(key)
+                    .is_some_and(|entry| entry.tags.iter().any(|t| t == tag))
+            })
+            .collect())
+    }
+
+    /// Deletes the keys carrying `tag`, limited to `namespace` when given.
+    pub async fn delete_by_tag(&self, tag: &str, namespace: Option<&str>) -> Result<u64, CacheError> {
+        self.total_operations.increment();
+
+        let candidates: Vec<String> = match self.tag_index.get(tag) {
+            Some(keys) => keys.iter()
+                .filter(|key| namespace.is_none() || key_namespace(key) == namespace)
+                .cloned()
+                .collect(),
+            None => return Ok(0),
+        };
+
+        let mut deleted = 0;
+        for key in candidates {
+            let removed = self.remove_entry_if(&key, |key, entry| {
+                let tagged = entry.tags.iter().any(|t| t == tag);
+                if tagged {
+                    aof::append(|| AofRecord::Delete { key: key.to_string() });
+                    webhooks::notify(KeyEvent::Delete, key);
+                }
+                tagged
+            });
+            if removed.is_some_and(|(key, entry)| !self.is_stale(&key, &entry)) {
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    pub async fn keys(&self) -> Result<Vec<String>, CacheError> {
+        self.total_operations.increment();
+        
+        let keys: Vec<String> = self.storage.iter()
+            .filter(|entry| !self.is_stale(entry.key(), entry.value()))
+            .map(|entry| entry.key().to_string())
+            .collect();
+        
+        Ok(keys)
+    }
+
+    /// Returns up to `count` keys following `cursor` and the cursor to resume
+    /// from, which is back at 0 once every shard has been walked.
+    ///
+    /// Keys map to a fixed shard and are visited in key order within it, so
+    /// table resizes never move a key behind the cursor: every key present for
+    /// the whole scan is returned exactly once, while keys added or removed
+    /// mid-scan may or may not be. Each call costs a pass over one shard.
+    pub async fn scan(&self, cursor: ScanCursor, count: usize) -> Result<ScanPage, CacheError> {
+        self.total_operations.increment();
+
+        let shards = self.storage.shards();
+        let ScanCursor { shard: mut shard_index, mut after } = cursor;
+        let mut keys = Vec::new();
+
+        while shard_index < shards.len() && keys.len() < count {
+            let wanted = count - keys.len();
+            let shard = shards[shard_index].read();
+            let mut smallest: BinaryHeap<String> = BinaryHeap::with_capacity(wanted.min(shard.len()) + 1);
+            // SAFETY: the shard read lock is held for the whole iteration.
+            for bucket in unsafe { shard.iter() } {
+                let (key, entry) = unsafe { bucket.as_ref() };
+                if after.as_ref().is_some_and(|after| key.as_str() <= after.as_str()) || self.is_stale(key, entry.get()) {
+                    continue;
+                }
+                if smallest.len() < wanted {
+                    smallest.push(key.to_string());
+                } else if smallest.peek().is_some_and(|largest| key.as_str() < largest.as_str()) {
+                    smallest.pop();
+                    smallest.push(key.to_string());
+                }
+            }
+            drop(shard);
+
+            let batch = smallest.into_sorted_vec();
+            if batch.len() < wanted {
+                shard_index += 1;
+                after = None;
+            } else {
+                after = batch.last().cloned();
+            }
+            keys.extend(batch);
+        }
+
+        let cursor = if shard_index < shards.len() {
+            ScanCursor { shard: shard_index, after }
+        } else {
+            ScanCursor::default()
+        };
+
+        Ok(ScanPage { cursor, keys })
+    }
+
+    pub fn sort_keys(&self, keys: &mut [String], order: SortOrder) {
+        match order {
+            SortOrder::Ascending => keys.sort_unstable(),
+            SortOrder::Descending => keys.sort_unstable_by(|a, b| b.cmp(a)),
+            // Most recently used first
+            SortOrder::Accessed => keys.sort_by_cached_key(|key| {
+                Reverse(self.storage.get(key.as_str()).map_or(0, |entry| entry.accessed_at.load(Ordering::Relaxed)))
+            }),
+        }
+    }
+
+    // Reads the entry without counting as an access, so inspecting a key
+    // does not change its eviction order.
+    pub fn object_info(&self, key: &str) -> Result<ObjectInfo, CacheError> {
+        let entry = self.live_entry(key).ok_or_else(|| CacheError::KeyNotFound(key.to_string()))?;
+        Ok(ObjectInfo {
+            kind: entry.value.kind(),
+            memory: entry.memory_usage(key),
+            accessed_at: entry.accessed_at.load(Ordering::Relaxed),
+            expires_at: entry.expires_at.load(Ordering::Relaxed),
+            sliding_ttl: entry.sliding_ttl,
+            tags: entry.tags.len(),
+            generation: entry.generation,
+        })
+    }
+
+    /// Bytes `key` counts against max_memory: its entry plus the versions
+    /// kept for history().
+    pub fn key_memory(&self, key: &str) -> Result<u64, CacheError> {
+        let entry = self.live_entry(key).ok_or_else(|| CacheError::KeyNotFound(key.to_string()))?;
+        let history: u64 = self.versions.get(key)
+            .map_or(0, |versions| versions.iter().map(Version::memory_usage).sum());
+        Ok(entry.memory_usage(key) + history)
+    }
+
+    /// Backdates an entry's last access by `age`, for exercising eviction.
+    pub fn set_access_time(&self, key: &str, age: Duration) -> Result<(), CacheError> {
+        let entry = self.live_entry(key).ok_or_else(|| CacheError::KeyNotFound(key.to_string()))?;
+        let accessed_at = now_micros().saturating_sub(age.as_micros() as u64);
+        entry.accessed_at.store(accessed_at, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            keys: self.storage.len() as u64,
+            used_memory: self.used_memory.load(Ordering::Relaxed),
+            history_memory: self.history_memory.load(Ordering::Relaxed),
+            max_memory: self.max_memory,
+            eviction_policy: self.eviction_policy.name(),
+            total_operations: self.total_operations.sum(),
+            hits: self.hit_count.sum(),
+            misses: self.miss_count.sum(),
+            evicted_keys: self.evicted_keys.load(Ordering::Relaxed),
+            expired_keys: self.expired_keys.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn memory_doctor(&self) -> MemoryReport {
+        let stats = self.stats();
+        let mut used_memory = 0u64;
+        let mut allocated_memory = 0u64;
+        let mut largest_keys: Vec<(String, u64)> = Vec::with_capacity(DOCTOR_LARGEST_KEYS + 1);
+
+        for entry in self.storage.iter() {
+            let usage = entry.value().memory_usage(entry.key());
+            used_memory += usage;
+            allocated_memory += ENTRY_OVERHEAD
+                + compact::heap_len(entry.key().len()) as u64
+                + entry.value().value.capacity() as u64;
+
+            push_largest(&mut largest_keys, DOCTOR_LARGEST_KEYS, entry.key(), usage);
+        }
+
+        // Empty slots in the hash tables are allocated but hold nothing.
+        for shard in self.storage.shards() {
+            let shard = shard.read();
+            if !shard.is_empty() {
+                allocated_memory += (shard.buckets() - shard.len()) as u64 * ENTRY_OVERHEAD;
+            }
+        }
+
+        let entries = stats.keys;
+        let average_entry_size = used_memory.checked_div(entries).unwrap_or(0);
+        let fragmentation = if used_memory > 0 {
+            allocated_memory as f64 / used_memory as f64
+        } else {
+            1.0
+        };
+
+        let mut suggestions = Vec::new();
+        if fragmentation >= 1.5 {
+            suggestions.push(format!(
+                "fragmentation is {:.2}, most of the allocated memory is empty table slots or oversized buffers left behind by deletes",
+                fragmentation
+            ));
+        }
+        if stats.max_memory == 0 && entries > 0 {
+            suggestions.push("max_memory is unset, the cache grows until the process runs out of memory".to_string());
+        }
+        if stats.evicted_keys > 0 {
+            suggestions.push(format!(
+                "{} keys were evicted to stay under max_memory, raise it if misses are unexpected",
+                stats.evicted_keys
+            ));
+        }
+        if let Some((key, size)) = largest_keys.first()
+            && entries > 1
+            && *size * 4 > used_memory {
+            suggestions.push(format!(
+                "key {} alone holds {}% of used memory",
+                key,
+                size * 100 / used_memory
+            ));
+        }
+        let lookups = stats.hits + stats.misses;
+        if lookups >= 100 && stats.hits * 2 < lookups {
+            suggestions.push(format!(
+                "hit ratio is {}%, most lookups miss the cache",
+                stats.hits * 100 / lookups
+            ));
+        }
+
+        MemoryReport {
+            entries,
+            used_memory,
+            allocated_memory,
+            average_entry_size,
+            fragmentation,
+            largest_keys,
+            suggestions,
+        }
+    }
+
+    pub fn big_keys(&self, count: usize) -> BigKeysReport {
+        let total = self.storage.len() as u64;
+        let mut scanned = 0u64;
+        let mut largest: Vec<(String, u64)> = Vec::with_capacity(count.min(total as usize) + 1);
+
+        for entry in self.storage.iter() {
+            push_largest(&mut largest, count, entry.key(), entry.value().value.len() as u64);
+
+            scanned += 1;
+            if scanned.is_multiple_of(BIGKEYS_PROGRESS_INTERVAL) {
+                info!("bigkeys: scanned {} of ~{} keys", scanned, total);
+            }
+        }
+
+        BigKeysReport { scanned, largest }
+    }
+
+    pub async fn invalidate(&self, namespace: &str) -> Result<u64, CacheError> {
+        self.total_operations.increment();
+
+        let mut generation = self.generations.entry(namespace.to_string()).or_insert(0);
+        *generation += 1;
+        aof::append(|| AofRecord::Invalidate { namespace: namespace.to_string(), generation: *generation });
+        Ok(*generation)
+    }
+
+    /// Acquires the lease on `key` for `ttl`, returning a fencing token that
+    /// increases with every grant, or None while another holder's lease is live.
+    pub async fn lock(&self, key: &str, ttl: Duration) -> Result<Option<u64>, CacheError> {
+        self.total_operations.increment();
+
+        let expires_at = Instant::now() + ttl;
+        match self.leases.entry(key.to_string()) {
+            Entry::Occupied(mut entry) => {
+                if !entry.get().is_expired() {
+                    return Ok(None);
+                }
+                let token = self.next_fencing_token.fetch_add(1, Ordering::Relaxed);
+                entry.insert(Lease { token, expires_at });
+                Ok(Some(token))
+            }
+            Entry::Vacant(entry) => {
+                let token = self.next_fencing_token.fetch_add(1, Ordering::Relaxed);
+                entry.insert(Lease { token, expires_at });
+                Ok(Some(token))
+            }
+        }
+    }
+
+    /// Releases the lease on `key` if `token` still holds it.
+    pub async fn unlock(&self, key: &str, token: u64) -> Result<bool, CacheError> {
+        self.total_operations.increment();
+
+        let removed = self.leases.remove_if(key, |_, lease| lease.token == token && !lease.is_expired());
+        if removed.is_none() {
+            self.leases.remove_if(key, |_, lease| lease.is_expired());
+        }
+        Ok(removed.is_some())
+    }
+
+    /// The entry at `key` as it would be written to a snapshot, or None when
+    /// the key is missing or stale.
+    pub fn snapshot_entry(&self, key: &str) -> Option<SnapshotEntry> {
+        let entry = self.storage.get(key)?;
+        if self.is_stale(key, &entry) {
+            return None;
+        }
+        Some(SnapshotEntry {
+            value: entry.value.clone(),
+            tags: entry.tags.clone(),
+            metadata: entry.metadata.clone(),
+            expires_at: entry.expires_at.load(Ordering::Relaxed),
+            sliding_ttl: entry.sliding_ttl,
+            generation: entry.generation,
+        })
+    }
+
+    /// Every stored key, including ones not yet found to be stale, and every
+    /// key with a write scheduled.
+    pub fn stored_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.storage.iter().map(|entry| entry.key().to_string()).collect();
+        keys.extend(self.scheduled.iter()
+            .filter(|write| !self.storage.contains_key(write.key().as_str()))
+            .map(|write| write.key().clone()));
+        keys
+    }
+
+    /// The write scheduled for `key` as it would be written to a snapshot.
+    pub fn scheduled_entry(&self, key: &str) -> Option<ScheduledEntry> {
+        let write = self.scheduled.get(key)?;
+        Some(ScheduledEntry {
+            value: write.value.clone(),
+            tags: write.options.tags.clone(),
+            metadata: write.options.metadata.clone(),
+            ttl: write.options.ttl.map_or(0, saturating_micros),
+            sliding: write.options.sliding,
+            ttl_jitter: write.options.ttl_jitter,
+            visible_at: write.options.visible_at.unwrap_or_default(),
+        })
+    }
+
+    /// Puts a snapshotted scheduled write back, or drops the one pending
+    /// for `key` when the snapshot holds none.
+    pub fn restore_scheduled(&self, key: String, scheduled: Option<ScheduledEntry>) {
+        let Some(scheduled) = scheduled else {
+            if let Some((key, write)) = self.scheduled.remove(&key) {
+                self.scheduled_count.fetch_sub(1, Ordering::Relaxed);
+                self.release(&key, write.size);
+            }
+            return;
+        };
+        let options = SetOptions {
+            tags: scheduled.tags,
+            metadata: scheduled.metadata,
+            ttl: (scheduled.ttl != 0).then(|| Duration::from_micros(scheduled.ttl)),
+            sliding: scheduled.sliding,
+            ttl_jitter: scheduled.ttl_jitter,
+            writer: None,
+            visible_at: Some(scheduled.visible_at),
+        };
+        self.schedule(key, scheduled.value, options, false);
+    }
+
+    /// Keys changed since the previous call; each is handed out once.
+    pub fn take_dirty_keys(&self) -> Vec<String> {
+        let keys: Vec<String> = self.dirty_keys.iter().map(|key| key.clone()).collect();
+        for key in &keys {
+            self.dirty_keys.remove(key);
+        }
+        keys
+    }
+
+    pub fn clear_dirty_keys(&self) {
+        self.dirty_keys.clear();
+    }
+
+    pub fn generations(&self) -> BTreeMap<String, u64> {
+        self.generations.iter().map(|generation| (generation.key().clone(), *generation.value())).collect()
+    }
+
+    /// Raises namespace generations to the given values; generations never
+    /// move backwards, so restoring an older value is a no-op.
+    pub fn restore_generations(&self, generations: BTreeMap<String, u64>) {
+        for (namespace, generation) in generations {
+            let mut current = self.generations.entry(namespace).or_insert(0);
+            *current = (*current).max(generation);
+        }
+    }
+
+    /// Puts a snapshotted entry back exactly as it was written.
+    pub fn restore_entry(&self, key: String, snapshot: SnapshotEntry) {
+        let value = match snapshot.value {
+            Value::Text(text) => Value::Text(self.intern(text)),
+            value => value,
+        };
+        let mut entry = CacheEntry::new(value);
+        entry.tags = snapshot.tags;
+        entry.metadata = snapshot.metadata;
+        entry.expires_at = AtomicU64::new(snapshot.expires_at);
+        entry.sliding_ttl = snapshot.sliding_ttl;
+        entry.generation = snapshot.generation;
+        self.charge(&key, entry.memory_usage(&key));
+
+        match self.storage.entry(key.into()) {
+            Entry::Occupied(mut occupied) => self.replace_occupied(&mut occupied, entry),
+            Entry::Vacant(vacant) => {
+                self.index_tags(vacant.key(), &entry.tags);
+                self.count_key(vacant.key(), 1);
+                vacant.insert(entry);
+            }
+        }
+    }
+
+    /// Totals for the keys of `namespace`, or None while prefix_stats is off.
+    /// They start over once the namespace has held no keys.
+    pub fn prefix_stats(&self, namespace: &str) -> Option<PrefixStats> {
+        if !self.prefix_stats {
+            return None;
+        }
+        let Some(counters) = self.prefixes.get(namespace) else {
+            return Some(PrefixStats::default());
+        };
+        Some(PrefixStats {
+            keys: counters.keys.load(Ordering::Relaxed).max(0) as u64,
+            bytes: counters.bytes.load(Ordering::Relaxed).max(0) as u64,
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+        })
+    }
+
+    // Counters only exist for namespaces holding keys: storing creates them
+    // and they go with the namespace's last key, so lookups of namespaces
+    // that hold nothing leave nothing behind.
+    fn update_prefix(&self, key: &str, create: bool, update: impl FnOnce(&PrefixCounters)) {
+        if !self.prefix_stats {
+            return;
+        }
+        let Some(namespace) = key_namespace(key) else {
+            return;
+        };
+        match self.prefixes.get(namespace) {
+            Some(counters) => update(&counters),
+            None if create => update(&self.prefixes.entry(namespace.to_string()).or_default()),
+            None => {}
+        }
+    }
+
+    fn charge(&self, key: &str, bytes: u64) {
+        self.used_memory.fetch_add(bytes, Ordering::Relaxed);
+        self.update_prefix(key, true, |counters| {
+            counters.bytes.fetch_add(bytes as i64, Ordering::Relaxed);
+        });
+    }
+
+    fn release(&self, key: &str, bytes: u64) {
+        self.used_memory.fetch_sub(bytes, Ordering::Relaxed);
+        self.update_prefix(key, false, |counters| {
+            counters.bytes.fetch_sub(bytes as i64, Ordering::Relaxed);
+        });
+    }
+
+    fn count_key(&self, key: &str, delta: i64) {
+        let mut emptied = false;
+        self.update_prefix(key, delta > 0, |counters| {
+            emptied = counters.keys.fetch_add(delta, Ordering::Relaxed) + delta <= 0;
+        });
+        if emptied && let Some(namespace) = key_namespace(key) {
+            self.prefixes.remove_if(namespace, |_, counters| counters.keys.load(Ordering::Relaxed) <= 0);
+        }
+    }
+
+    fn record_lookup(&self, key: &str, hit: bool) {
+        if hit {
+            self.hit_count.increment();
+        } else {
+            self.miss_count.increment();
+        }
+        self.update_prefix(key, false, |counters| {
+            let counter = if hit { &counters.hits } else { &counters.misses };
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    fn mark_dirty(&self, key: &str) {
+        if self.track_dirty {
+            self.dirty_keys.insert(key.to_string());
+        }
+    }
+
+    fn intern(&self, value: impl AsRef<str> + Into<Arc<str>>) -> Arc<str> {
+        let text = value.as_ref();
+        if self.intern_max_len == 0 || text.len() > self.intern_max_len {
+            return value.into();
+        }
+        if let Some(shared) = self.interned.get(text) {
+            return shared.clone();
+        }
+
+        let shared: Arc<str> = value.into();
+        self.interned.insert(shared.clone());
+        if self.intern_additions.fetch_add(1, Ordering::Relaxed).is_multiple_of(INTERN_SWEEP_EVERY) {
+            self.interned.retain(|value| Arc::strong_count(value) > 1);
+        }
+        shared
+    }
+
+    fn build_entry(&self, key: &str, value: Arc<str>, options: SetOptions) -> CacheEntry {
+        let mut entry = CacheEntry::new(Value::Text(value));
+        entry.tags = options.tags;
+        entry.tags.sort();
+        entry.tags.dedup();
+        entry.metadata = options.metadata;
+        entry.generation = self.namespace_generation(key);
+        if let Some(ttl) = options.ttl {
+            if options.sliding {
+                entry.set_ttl(ttl);
+                entry.sliding_ttl = saturating_micros(ttl);
+            } else {
+                entry.set_ttl(jittered_ttl(ttl, options.ttl_jitter.unwrap_or(self.ttl_jitter_percent)));
+            }
+        }
+        entry
+    }
+
+    // Keys of the form "namespace:rest" belong to a namespace whose generation
+    // is bumped by invalidate(); entries written under an older generation are
+    // treated as gone and dropped lazily, the same way expired entries are.
+    fn namespace_generation(&self, key: &str) -> u64 {
+        match key_namespace(key) {
+            Some(namespace) => self.generations.get(namespace).map_or(0, |generation| *generation),
+            None => 0,
+        }
+    }
+
+    fn is_stale(&self, key: &str, entry: &CacheEntry) -> bool {
+        entry.is_expired() || entry.generation != self.namespace_generation(key)
+    }
+
+    fn live_entry(&self, key: &str) -> Option<Ref<'_, CompactStr, CacheEntry>> {
+        self.activate_due(key);
+        let entry = self.storage.get(key)?;
+        if !self.is_stale(key, &entry) {
+            return Some(entry);
+        }
+
+        drop(entry);
+        self.remove_stale(key);
+        None
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.storage.shards().len()
+    }
+
+    /// Rebuilds one storage shard's table into a fresh allocation sized for
+    /// the entries it holds, when a wave of deletes has left it mostly empty;
+    /// tables only grow otherwise. Returns the entries rehashed and the
+    /// bytes freed, (0, 0) when the shard was left alone. Writers to the
+    /// shard wait while it is rebuilt.
+    pub fn defrag_shard(&self, shard_index: usize) -> (usize, u64) {
+        let mut shard = self.storage.shards()[shard_index].write();
+        let (entries, buckets) = (shard.len(), shard.buckets());
+        if buckets < DEFRAG_MIN_BUCKETS || entries * DEFRAG_MAX_LOAD > buckets {
+            return (0, 0);
+        }
+        shard.shrink_to(entries, |(key, _)| self.storage.hash_usize(key) as u64);
+        (entries, (buckets.saturating_sub(shard.buckets()) * BUCKET_BYTES) as u64)
+    }
+
+    /// Drops the expired or invalidated entries of one shard and returns the
+    /// shard to sweep next, so repeated calls walk the whole map.
+    pub fn sweep_shard(&self, shard_index: usize) -> usize {
+        let shards = self.storage.shards();
+        let shard_index = shard_index % shards.len();
+        let stale: Vec<String> = {
+            let shard = shards[shard_index].read();
+            // SAFETY: the shard read lock is held for the whole iteration.
+            unsafe { shard.iter() }
+                .filter_map(|bucket| {
+                    let (key, entry) = unsafe { bucket.as_ref() };
+                    self.is_stale(key, entry.get()).then(|| key.to_string())
+                })
+                .collect()
+        };
+
+        for key in &stale {
+            self.remove_stale(key);
+        }
+        (shard_index + 1) % shards.len()
+    }
+
+    /// Drops leases past their TTL that no one came back to lock or unlock.
+    pub fn sweep_leases(&self) {
+        self.leases.retain(|_, lease| !lease.is_expired());
+    }
+
+    fn remove_stale(&self, key: &str) {
+        let removed = self.remove_entry_if(key, |key, entry| self.is_stale(key, entry));
+        if let Some((key, entry)) = removed
+            && entry.is_expired() {
+            self.expired_keys.fetch_add(1, Ordering::Relaxed);
+            webhooks::notify(KeyEvent::Expire, &key);
+        }
+    }
+
+    fn remove_entry(&self, key: &str) -> Option<(CompactStr, CacheEntry)> {
+        self.remove_entry_if(key, |_, _| true)
+    }
+
+    fn remove_entry_if(
+        &self,
+        key: &str,
+        predicate: impl FnOnce(&str, &CacheEntry) -> bool,
+    ) -> Option<(CompactStr, CacheEntry)> {
+        // The predicate runs under the shard lock, keeping the tag index in
+        // step with the removal.
+        let removed = self.storage.remove_if(key, |key, entry| {
+            let remove = predicate(key, entry);
+            if remove {
+                self.unindex_tags(key, &entry.tags);
+                self.mark_dirty(key);
+                self.drop_versions(key);
+            }
+            remove
+        });
+
+        if let Some((key, entry)) = &removed {
+            self.release(key, entry.memory_usage(key));
+            self.count_key(key, -1);
+        }
+
+        removed
+    }
+
+    // Swaps in a new entry for an existing key. The caller has already
+    // accounted for the new entry's memory; the previous entry's memory and
+    // tags are released here.
+    fn replace_occupied(&self, occupied: &mut OccupiedEntry<'_, CompactStr, CacheEntry>, entry: CacheEntry) {
+        let previous = occupied.insert(entry);
+        if previous.is_expired() {
+            self.expired_keys.fetch_add(1, Ordering::Relaxed);
+        }
+        self.release(occupied.key(), previous.memory_usage(occupied.key()));
+        self.unindex_tags(occupied.key(), &previous.tags);
+        self.index_tags(occupied.key(), &occupied.get().tags);
+    }
+
+    fn index_tags(&self, key: &str, tags: &[String]) {
+        for tag in tags {
+            self.tag_index.entry(tag.clone()).or_default().insert(key.to_string());
+        }
+    }
+
+    fn unindex_tags(&self, key: &str, tags: &[String]) {
+        for tag in tags {
+            if let Some(mut keys) = self.tag_index.get_mut(tag) {
+                keys.remove(key);
+            }
+            self.tag_index.remove_if(tag, |_, keys| keys.is_empty());
+        }
+    }
+
+    fn evict_if_needed(&self) {
+        if self.max_memory == 0 {
+            return;
+        }
+
+        // With the background executor evicting, writes only wake it, and
+        // fall back to evicting themselves once usage outruns it by more
+        // than an eighth of max_memory.
+        if background::evicts() {
+            let used = self.used_memory.load(Ordering::Relaxed);
+            if used > self.max_memory {
+                background::wake();
+            }
+            if used <= self.max_memory + self.max_memory / 8 {
+                return;
+            }
+        }
+
+        self.evict_to_limit();
+    }
+
+    // Refuses a write once usage reaches max_memory when the policy does
+    // not evict. It is checked before the key is looked at, so overwriting
+    // a value with a smaller one is refused as well; only deletes and
+    // expiry, which never call it, still go through.
+    fn ensure_room(&self, key: &str) -> Result<(), CacheError> {
+        if self.max_memory != 0
+            && !self.eviction_policy.evicts()
+            && self.used_memory.load(Ordering::Relaxed) >= self.max_memory {
+            return Err(CacheError::OutOfMemory(key.to_string()));
+        }
+        Ok(())
+    }
+
+    // Refuses an entry of `bytes` that could never fit under max_memory,
+    // which would otherwise evict every other key and then itself.
+    fn ensure_fits(&self, key: &str, bytes: u64) -> Result<(), CacheError> {
+        if self.max_memory != 0 && bytes > self.max_memory {
+            return Err(CacheError::OutOfMemory(key.to_string()));
+        }
+        Ok(())
+    }
+
+    // Refuses a value of `bytes` before it is built: one that could never
+    // fit, or, when the policy does not evict, one that would take usage
+    // past max_memory.
+    fn ensure_allocation(&self, key: &str, bytes: u64) -> Result<(), CacheError> {
+        self.ensure_fits(key, bytes)?;
+        if self.max_memory != 0
+            && !self.eviction_policy.evicts()
+            && self.used_memory.load(Ordering::Relaxed).saturating_add(bytes) > self.max_memory {
+            return Err(CacheError::OutOfMemory(key.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Evicts entries until usage is back under max_memory.
+    pub fn evict_to_limit(&self) {
+        if self.max_memory == 0 || !self.eviction_policy.evicts() {
+            return;
+        }
+
+        let mut empty_samples = 0;
+        while self.used_memory.load(Ordering::Relaxed) > self.max_memory {
+            if self.storage.is_empty() {
+                break;
+            }
+
+            // Usage charged to something other than entries, or a table too
+            // sparse to sample, must not keep the writer spinning.
+            let Some(victim) = with_rng(|rng| self.sample_eviction_candidate(rng)) else {
+                empty_samples += 1;
+                if empty_samples >= MAX_EMPTY_EVICTION_SAMPLES {
+                    break;
+                }
+                continue;
+            };
+            empty_samples = 0;
+
+            match self.remove_entry(&victim) {
+                Some((key, entry)) if entry.is_expired() => {
+                    self.expired_keys.fetch_add(1, Ordering::Relaxed);
+                    webhooks::notify(KeyEvent::Expire, &key);
+                }
+                Some((key, _)) => {
+                    self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                    webhooks::notify(KeyEvent::Evict, &key);
+                }
+                None => {}
+            }
+        }
+    }
+
+    // Probe a few random buckets and pick the entry the eviction policy
+    // ranks lowest among them, so no ordering structure has to be maintained
+    // on the hot path.
+    fn sample_eviction_candidate(&self, rng: &mut dyn RngCore) -> Option<String> {
+        let shards = self.storage.shards();
+        let mut lowest: Option<(String, u64)> = None;
+
+        for _ in 0..self.eviction_samples {
+            let shard = shards[rng.gen_range(0..shards.len())].read();
+            if shard.is_empty() {
+                continue;
+            }
+
+            let buckets = shard.buckets();
+            let start = rng.gen_range(0..buckets);
+            for offset in 0..buckets {
+                let index = (start + offset) % buckets;
+                // SAFETY: index is below the bucket count and the shard read
+                // lock is held, so a full bucket cannot be moved or freed.
+                let (key, entry) = unsafe {
+                    if !shard.is_bucket_full(index) {
+                        continue;
+                    }
+                    let (key, entry) = shard.bucket(index).as_ref();
+                    (key, entry.get())
+                };
+
+                // Expired or invalidated entries are dead weight, drop them first.
+                if self.is_stale(key, entry) {
+                    return Some(key.to_string());
+                }
+
+                let usage = EntryUsage {
+                    accessed_at: entry.accessed_at.load(Ordering::Relaxed),
+                    accesses: entry.accesses.load(Ordering::Relaxed),
+                };
+                let rank = self.eviction_policy.rank(&usage, rng);
+                if lowest.as_ref().is_none_or(|(_, lowest)| rank < *lowest) {
+                    lowest = Some((key.to_string(), rank));
+                }
+                break;
+            }
+        }
+
+        lowest.map(|(key, _)| key)
+    }
+}
+
+/// Lengthens `ttl` by a random amount up to `percent` of it, so keys written
+/// together with the same TTL do not all expire in the same moment. Applied
+/// once when a TTL is set; the log keeps the resulting expiry time.
+fn jittered_ttl(ttl: Duration, percent: u8) -> Duration {
+    if percent == 0 {
+        return ttl;
+    }
+    let spread = with_rng(|rng| rng.gen_range(0.0..=percent as f64 / 100.0));
+    // TTLs near the top of the range saturate rather than overflow.
+    Duration::try_from_secs_f64(ttl.as_secs_f64() * spread)
+        .ok()
+        .and_then(|extra| ttl.checked_add(extra))
+        .unwrap_or(Duration::MAX)
+}
+
+// Microseconds in `duration`, capped at what an expiry time can hold.
+fn saturating_micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+// Bytes a bitmap needs to hold the bit at `offset`.
+fn bitmap_len(offset: u64) -> u64 {
+    offset / 8 + 1
+}
+
+fn set_record(key: &str, entry: &CacheEntry) -> AofRecord {
+    AofRecord::Set {
+        key: key.to_string(),
+        value: entry.value.render().as_deref().unwrap_or_default().to_string(),
+        tags: entry.tags.clone(),
+        metadata: entry.metadata.clone(),
+        expires_at: entry.expires_at.load(Ordering::Relaxed),
+        sliding_ttl: entry.sliding_ttl,
+    }
+}
+
+fn schedule_record(key: &str, value: &str, options: &SetOptions) -> AofRecord {
+    AofRecord::Schedule {
+        key: key.to_string(),
+        value: value.to_string(),
+        tags: options.tags.clone(),
+        metadata: options.metadata.clone(),
+        ttl: options.ttl.map_or(0, saturating_micros),
+        sliding: options.sliding,
+        ttl_jitter: options.ttl_jitter,
+        visible_at: options.visible_at.unwrap_or_default(),
+    }
+}
+
+// Keeps `largest` sorted by size, descending, holding at most `limit` keys.
+fn push_largest(largest: &mut Vec<(String, u64)>, limit: usize, key: &str, size: u64) {
+    if limit == 0 || (largest.len() == limit && size <= largest[limit - 1].1) {
+        return;
+    }
+
+    let position = largest.partition_point(|(_, existing)| *existing >= size);
+    largest.insert(position, (key.to_string(), size));
+    largest.truncate(limit);
+}
+
+impl Default for Sodium {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static GLOBAL_CACHE: OnceLock<Arc<Sodium>> = OnceLock::new();
+
+pub fn initialize_cache(config: &SodiumConfig) {
+    if config.deterministic {
+        let _ = SEEDED_RNG.set(Mutex::new(StdRng::seed_from_u64(config.deterministic_seed)));
+    }
+    let _ = GLOBAL_CACHE.set(Arc::new(Sodium::with_config(config)));
+}
+
+pub fn get_cache() -> &'static Arc<Sodium> {
+    GLOBAL_CACHE.get().expect("Cache not initialized")
+}
+
+// Worker threads live outside the tokio runtime, so cache futures are
+// driven to completion here.
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle.block_on(future),
+        Err(_) => {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(future)
+        }
+    }
+}
+
+pub fn execute_get(key: &str) -> super::threading::TaskResult<Option<Arc<str>>> {
+    let cache = get_cache();
+    match block_on(cache.get(key)) {
+        Ok(value) => Ok(Some(value)),
+        Err(CacheError::KeyNotFound(_)) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+pub fn execute_get_early(key: &str, recompute: Duration) -> super::threading::TaskResult<Option<Arc<str>>> {
+    let cache = get_cache();
+    match block_on(cache.get_early(key, recompute)) {
+        Ok(value) => Ok(Some(value)),
+        Err(CacheError::KeyNotFound(_)) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+pub fn execute_set(key: String, value: String, options: SetOptions) -> super::threading::TaskResult<()> {
+    let cache = get_cache();
+    block_on(cache.set(key, value, options))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+/// execute_set for a set that queued sets of the same key were folded into;
+/// their values go into the key's history once it is stored.
+pub fn execute_coalesced_set(key: String, value: String, options: SetOptions, superseded: Vec<(String, Option<SocketAddr>)>) -> super::threading::TaskResult<()> {
+    let cache = get_cache();
+    block_on(cache.set(key.clone(), value, options))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    cache.record_superseded(&key, superseded);
+    Ok(())
+}
+
+pub fn execute_get_or_set(key: String, value: String, options: SetOptions) -> super::threading::TaskResult<Arc<str>> {
+    let cache = get_cache();
+    block_on(cache.get_or_set(key, value, options))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_set_bit(key: String, offset: u64, bit: bool) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.set_bit(key, offset, bit))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_get_bit(key: &str, offset: u64) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.get_bit(key, offset))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_get_set(key: String, value: String, writer: Option<SocketAddr>) -> super::threading::TaskResult<Option<Arc<str>>> {
+    let cache = get_cache();
+    block_on(cache.get_set(key, value, writer))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_get_del(key: &str) -> super::threading::TaskResult<Option<Arc<str>>> {
+    let cache = get_cache();
+    block_on(cache.get_del(key))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_mget(keys: &[String]) -> super::threading::TaskResult<Vec<Option<Arc<str>>>> {
+    Ok(block_on(get_cache().mget(keys)))
+}
+
+pub fn execute_mset(pairs: Vec<(String, String)>, writer: Option<SocketAddr>) -> super::threading::TaskResult<()> {
+    let cache = get_cache();
+    block_on(cache.mset(pairs, writer))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_incr_by(key: String, delta: i64, writer: Option<SocketAddr>) -> super::threading::TaskResult<i64> {
+    let cache = get_cache();
+    block_on(cache.incr_by(key, delta, writer))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_bit_count(key: &str) -> super::threading::TaskResult<u64> {
+    let cache = get_cache();
+    block_on(cache.bit_count(key))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_stream_add(key: String, value: String, max_len: u64) -> super::threading::TaskResult<u64> {
+    let cache = get_cache();
+    block_on(cache.stream_add(key, value, max_len))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_bloom_add(key: String, item: String, capacity: u64, error_rate: f64) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.bloom_add(key, item, capacity, error_rate))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_bloom_exists(key: &str, item: &str) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.bloom_exists(key, item))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_cuckoo_add(key: String, item: String, capacity: u64) -> super::threading::TaskResult<()> {
+    let cache = get_cache();
+    block_on(cache.cuckoo_add(key, item, capacity))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_cuckoo_exists(key: &str, item: &str) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.cuckoo_exists(key, item))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_cuckoo_delete(key: &str, item: &str) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.cuckoo_delete(key, item))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_topk_add(key: String, item: String, k: usize) -> super::threading::TaskResult<u64> {
+    let cache = get_cache();
+    block_on(cache.topk_add(key, item, k))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_topk_query(key: &str, item: &str) -> super::threading::TaskResult<u64> {
+    let cache = get_cache();
+    block_on(cache.topk_query(key, item))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_topk_list(key: &str) -> super::threading::TaskResult<Vec<(String, u64)>> {
+    let cache = get_cache();
+    block_on(cache.topk_list(key))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_stream_range(key: &str, start: u64, end: u64) -> super::threading::TaskResult<Vec<StreamEntry>> {
+    let cache = get_cache();
+    block_on(cache.stream_range(key, start, end))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_metadata(key: &str) -> super::threading::TaskResult<Option<Metadata>> {
+    let cache = get_cache();
+    match block_on(cache.metadata(key)) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(CacheError::KeyNotFound(_)) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+pub fn execute_delete(key: &str) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.delete(key))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_undelete(key: &str) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.undelete(key))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_history(key: &str) -> super::threading::TaskResult<Vec<Version>> {
+    let cache = get_cache();
+    block_on(cache.history(key))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_get_version(key: &str, n: usize) -> super::threading::TaskResult<Option<Arc<str>>> {
+    let cache = get_cache();
+    match block_on(cache.get_version(key, n)) {
+        Ok(value) => Ok(Some(value)),
+        Err(CacheError::KeyNotFound(_)) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+pub fn execute_keys(sort: Option<SortOrder>) -> super::threading::TaskResult<Vec<String>> {
+    let cache = get_cache();
+    let mut keys = block_on(cache.keys())
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    if let Some(order) = sort {
+        cache.sort_keys(&mut keys, order);
+    }
+    Ok(keys)
+}
+
+pub fn execute_tag(key: &str, tag: String) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.tag(key, tag))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_expire(key: &str, ttl: Duration) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.expire(key, ttl, None))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_ttl(key: &str) -> super::threading::TaskResult<Option<Option<Duration>>> {
+    let cache = get_cache();
+    block_on(cache.ttl(key))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_keys_by_tag(tag: &str) -> super::threading::TaskResult<Vec<String>> {
+    let cache = get_cache();
+    block_on(cache.keys_by_tag(tag))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_delete_by_tag(tag: &str, namespace: Option<&str>) -> super::threading::TaskResult<u64> {
+    let cache = get_cache();
+    block_on(cache.delete_by_tag(tag, namespace))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_invalidate(namespace: &str) -> super::threading::TaskResult<u64> {
+    let cache = get_cache();
+    block_on(cache.invalidate(namespace))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_lock(key: &str, ttl: Duration) -> super::threading::TaskResult<Option<u64>> {
+    let cache = get_cache();
+    block_on(cache.lock(key, ttl))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_unlock(key: &str, token: u64) -> super::threading::TaskResult<bool> {
+    let cache = get_cache();
+    block_on(cache.unlock(key, token))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_scan(cursor: ScanCursor, count: usize) -> super::threading::TaskResult<ScanPage> {
+    let cache = get_cache();
+    block_on(cache.scan(cursor, count))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_stats() -> super::threading::TaskResult<CacheStats> {
+    Ok(get_cache().stats())
+}
+
+pub fn execute_prefix_stats(namespace: &str) -> super::threading::TaskResult<Option<PrefixStats>> {
+    Ok(get_cache().prefix_stats(namespace))
+}
+
+pub fn execute_object_info(key: &str) -> super::threading::TaskResult<ObjectInfo> {
+    get_cache().object_info(key)
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_key_memory(key: &str) -> super::threading::TaskResult<u64> {
+    get_cache().key_memory(key)
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_set_access_time(key: &str, age: Duration) -> super::threading::TaskResult<()> {
+    get_cache().set_access_time(key, age)
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+}
+
+pub fn execute_memory_doctor() -> super::threading::TaskResult<MemoryReport> {
+    Ok(get_cache().memory_doctor())
+}
+
+pub fn execute_big_keys(count: usize) -> super::threading::TaskResult<BigKeysReport> {
+    Ok(get_cache().big_keys(count))
+}
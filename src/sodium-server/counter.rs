@@ -0,0 +1,51 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+static NEXT_THREAD_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Threads take slots round-robin as they first count something.
+    static THREAD_SLOT: usize = NEXT_THREAD_SLOT.fetch_add(1, Ordering::Relaxed);
+}
+
+// Padded to a cache line so neighbouring slots don't share one.
+#[repr(align(64))]
+#[derive(Default)]
+struct Slot(AtomicU64);
+
+/// A counter bumped on every operation. Each thread adds to its own slot
+/// instead of one shared atomic and reads add the slots up, so a read is a
+/// sum of relaxed loads rather than a single snapshot.
+pub struct ShardedCounter {
+    slots: Box<[Slot]>,
+}
+
+impl ShardedCounter {
+    pub fn new() -> Self {
+        let slots = num_cpus::get().next_power_of_two();
+        Self { slots: (0..slots).map(|_| Slot::default()).collect() }
+    }
+
+    pub fn increment(&self) {
+        let index = THREAD_SLOT.with(|slot| *slot) & (self.slots.len() - 1);
+        self.slots[index].0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.slots.iter().map(|slot| slot.0.load(Ordering::Relaxed)).sum()
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for ShardedCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ShardedCounter").field(&self.sum()).finish()
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use std::fs;
+use std::io;
+
+/// Detaches from the terminal with the classic double fork: the first child
+/// starts a new session, the second can never reacquire a controlling
+/// terminal. Standard streams are pointed at /dev/null. The working
+/// directory is kept so relative paths in sodium.toml still resolve.
+///
+/// Must run before the runtime or any other thread is started.
+#[cfg(unix)]
+pub fn daemonize() -> io::Result<()> {
+    // SAFETY: the process is still single-threaded, so forking cannot leave
+    // locks held by threads that do not exist in the child.
+    unsafe {
+        fork_and_exit_parent()?;
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        fork_and_exit_parent()?;
+
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        for fd in 0..=2 {
+            libc::dup2(null, fd);
+        }
+        if null > 2 {
+            libc::close(null);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+unsafe fn fork_and_exit_parent() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+#[cfg(not(unix))]
+pub fn daemonize() -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--daemon is only supported on Unix"))
+}
+
+/// Holds the server's pid in a file for as long as it is alive.
+pub struct PidFile {
+    path: String,
+}
+
+impl PidFile {
+    pub fn create(path: &str) -> io::Result<Self> {
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(Self { path: path.to_string() })
+    }
+}
+
+impl Drop for PidFile {
+    // Left alone once another process has written its own pid there, as a
+    // server taking over through a handoff does.
+    fn drop(&mut self) {
+        let ours = fs::read_to_string(&self.path).is_ok_and(|pid| pid.trim() == std::process::id().to_string());
+        if ours {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
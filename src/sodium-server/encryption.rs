@@ -0,0 +1,158 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Encryption at rest for the AOF and snapshot files. With encryption_key_id
+// set, every line's JSON document is sealed with AES-256-GCM under that key
+// and written as `enc:<key id>:<base64 of nonce and ciphertext>`, before the
+// line checksum is added. The key id is bound in as associated data, so a
+// line cannot be passed off as written under another key.
+//
+// Keys are rotated by adding a new entry to [encryption_keys] and pointing
+// encryption_key_id at it: new lines use the new key, and older lines are
+// still read with whichever listed key they name. A key can only be dropped
+// once no file holds lines written with it; snapshots are rewritten on their
+// own, and AOF rewrites re-encrypt the lines they keep with the current key.
+// Plain lines, from before encryption was turned on, are always read as they
+// are.
+//
+// [namespace_encryption_keys] gives an auth namespace a key of its own: lines
+// holding keys under "<namespace>:" are written with it instead of
+// encryption_key_id, so one tenant's data can be rotated or shredded apart
+// from the rest. Namespaces not listed use encryption_key_id.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rand::RngCore;
+use rand::rngs::OsRng;
+
+use crate::configuration::SodiumConfig;
+
+const PREFIX: &str = "enc:";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("Invalid encryption key {id}: {reason}")]
+    InvalidKey { id: String, reason: String },
+    #[error("encryption_key_id {0} is not listed in [encryption_keys]")]
+    MissingKey(String),
+    #[error("namespace_encryption_keys.{namespace} names key {id}, which is not listed in [encryption_keys]")]
+    MissingNamespaceKey { namespace: String, id: String },
+    #[error("line encrypted with unknown key {0}")]
+    UnknownKey(String),
+    #[error("line could not be decrypted: {0}")]
+    Decrypt(String),
+    #[error("line could not be encrypted: {0}")]
+    Encrypt(String),
+}
+
+struct Keyring {
+    // Id and cipher new lines are written with, if encryption is on.
+    current: Option<(String, Aes256Gcm)>,
+    // Namespaces whose lines are written with a key of their own.
+    namespaces: BTreeMap<String, (String, Aes256Gcm)>,
+    keys: BTreeMap<String, Aes256Gcm>,
+}
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// Loads the keys in [encryption_keys]. Called before anything is read from
+/// or written to disk; without it lines are written in the clear.
+pub fn initialize_encryption(config: &SodiumConfig) -> Result<(), EncryptionError> {
+    let mut keys = BTreeMap::new();
+    for (id, key) in &config.encryption_keys {
+        keys.insert(id.clone(), parse_key(id, key)?);
+    }
+    let current = match config.encryption_key_id.as_str() {
+        "" => None,
+        id => match keys.get(id) {
+            Some(cipher) => Some((id.to_string(), cipher.clone())),
+            None => return Err(EncryptionError::MissingKey(id.to_string())),
+        },
+    };
+    let mut namespaces = BTreeMap::new();
+    for (namespace, id) in &config.namespace_encryption_keys {
+        let Some(cipher) = keys.get(id) else {
+            return Err(EncryptionError::MissingNamespaceKey { namespace: namespace.clone(), id: id.clone() });
+        };
+        namespaces.insert(namespace.clone(), (id.clone(), cipher.clone()));
+    }
+    let _ = KEYRING.set(Keyring { current, namespaces, keys });
+    Ok(())
+}
+
+// A key is 64 hex digits, 256 bits. Ids are written into every line, so they
+// cannot hold the characters that delimit it.
+fn parse_key(id: &str, key: &str) -> Result<Aes256Gcm, EncryptionError> {
+    let invalid = |reason: &str| EncryptionError::InvalidKey { id: id.to_string(), reason: reason.to_string() };
+    if id.is_empty() || id.contains([':', '\t', '\n', '\r']) {
+        return Err(invalid("key ids must be non-empty and free of ':' and whitespace separators"));
+    }
+    if key.len() != KEY_LEN * 2 || !key.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid("expected 64 hex digits"));
+    }
+    let bytes: Vec<u8> = (0..KEY_LEN)
+        .map(|index| u8::from_str_radix(&key[index * 2..index * 2 + 2], 16).unwrap_or_default())
+        .collect();
+    Aes256Gcm::new_from_slice(&bytes).map_err(|e| invalid(&e.to_string()))
+}
+
+/// Encrypts `line`, a JSON document without its newline, with the key of
+/// `namespace`, the namespace of the key the line holds, or the current key.
+/// The line is left as it is when neither is set.
+pub fn encrypt(line: &mut Vec<u8>, namespace: Option<&str>) -> Result<(), EncryptionError> {
+    let Some((id, cipher)) = KEYRING.get().and_then(|keyring| {
+        namespace
+            .and_then(|namespace| keyring.namespaces.get(namespace))
+            .or(keyring.current.as_ref())
+    }) else {
+        return Ok(());
+    };
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    // Encryption with a valid key only fails past the 64 GiB message limit.
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: line, aad: id.as_bytes() })
+        .map_err(|e| EncryptionError::Encrypt(e.to_string()))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    let mut encoded = format!("{}{}:", PREFIX, id).into_bytes();
+    encoded.extend_from_slice(BASE64.encode(&sealed).as_bytes());
+    *line = encoded;
+    Ok(())
+}
+
+/// The JSON document of a line written by encrypt. Lines that were not
+/// encrypted are returned as they are.
+pub fn decrypt(line: &str) -> Result<Cow<'_, str>, EncryptionError> {
+    let Some(rest) = line.strip_prefix(PREFIX) else {
+        return Ok(Cow::Borrowed(line));
+    };
+    let (id, encoded) = rest
+        .split_once(':')
+        .ok_or_else(|| EncryptionError::Decrypt("missing key id".to_string()))?;
+    let cipher = KEYRING
+        .get()
+        .and_then(|keyring| keyring.keys.get(id))
+        .ok_or_else(|| EncryptionError::UnknownKey(id.to_string()))?;
+
+    let sealed = BASE64.decode(encoded).map_err(|e| EncryptionError::Decrypt(e.to_string()))?;
+    if sealed.len() < NONCE_LEN {
+        return Err(EncryptionError::Decrypt("truncated ciphertext".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let document = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: id.as_bytes() })
+        .map_err(|_| EncryptionError::Decrypt(format!("authentication failed under key {}", id)))?;
+    String::from_utf8(document)
+        .map(Cow::Owned)
+        .map_err(|_| EncryptionError::Decrypt("not valid UTF-8".to_string()))
+}
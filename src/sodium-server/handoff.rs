@@ -0,0 +1,351 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Warm restarts. A server started with `--handoff` connects to the running
+// one over handoff_socket and receives its listening sockets, then a full
+// snapshot of its keyspace streamed over the same connection. Once the new
+// process has loaded it and is ready it acknowledges, and the old one exits.
+// Clients connecting meanwhile wait in the shared listen backlog instead of
+// being refused, so an upgrade needs no cluster failover.
+//
+// On the wire: one byte giving the length of a space separated list of
+// socket names, sent with the sockets themselves as SCM_RIGHTS, then the
+// names, then the snapshot until the old process shuts its side down. The
+// new process answers with HANDOFF_ACK.
+
+use std::io;
+use std::net::TcpListener;
+
+use crate::configuration::SodiumConfig;
+use crate::snapshot::{SnapshotError, SnapshotLoad};
+
+#[cfg(unix)]
+use std::io::{BufReader, BufWriter, Read, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use tracing::{info, warn};
+
+#[cfg(unix)]
+use crate::api::{self, NetworkBackend};
+#[cfg(unix)]
+use crate::{aof, snapshot};
+
+#[cfg(unix)]
+const HANDOFF_ACK: &[u8] = b"ready\n";
+
+// Most sockets passed: the main, admin and metrics listeners.
+#[cfg(unix)]
+const MAX_SOCKETS: usize = 3;
+
+// How long clients get to finish the request in hand before their
+// connections are closed regardless.
+#[cfg(unix)]
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long the old process waits for the new one to load the keyspace.
+#[cfg(unix)]
+const ACK_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandoffError {
+    #[error("Handoff IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Handoff snapshot error: {0}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("Handoff protocol error: {0}")]
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Protocol(String),
+}
+
+/// Listening sockets passed from the old process to the new one.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    pub main: Option<TcpListener>,
+    pub admin: Option<TcpListener>,
+    pub metrics: Option<TcpListener>,
+}
+
+/// The new process's end of a handoff.
+pub struct Incoming {
+    pub listeners: Listeners,
+    #[cfg(unix)]
+    stream: UnixStream,
+}
+
+/// The old process's end, waiting for a new process to connect.
+pub struct HandoffListener {
+    path: String,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    listeners: Listeners,
+    #[cfg(unix)]
+    listener: tokio::net::UnixListener,
+}
+
+/// A new process that has connected to take over.
+pub struct Handoff<'a> {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    listeners: &'a Listeners,
+    #[cfg(unix)]
+    stream: UnixStream,
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "handoff is only supported on Unix")
+}
+
+/// Connects to the server running on `config.handoff_socket` and takes its
+/// listening sockets. Must run before the runtime starts, like
+/// systemd::take_listener.
+#[cfg(unix)]
+pub fn connect(config: &SodiumConfig) -> io::Result<Incoming> {
+    if config.handoff_socket.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--handoff needs handoff_socket to be set"));
+    }
+
+    let stream = UnixStream::connect(&config.handoff_socket)?;
+    let (length, fds) = receive_fds(&stream)?;
+    let mut names = vec![0u8; length as usize];
+    (&stream).read_exact(&mut names)?;
+    let names = String::from_utf8(names).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut listeners = Listeners::default();
+    let mut fds = fds.into_iter();
+    for name in names.split_whitespace() {
+        let fd = fds.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "fewer sockets than names"))?;
+        let listener = TcpListener::from(fd);
+        listener.set_nonblocking(true)?;
+        let listener = Some(listener);
+        match name {
+            "main" => listeners.main = listener,
+            "admin" => listeners.admin = listener,
+            "metrics" => listeners.metrics = listener,
+            other => warn!("Ignoring unknown handed off socket {}", other),
+        }
+    }
+    if listeners.main.is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the main listening socket was not handed off"));
+    }
+    Ok(Incoming { listeners, stream })
+}
+
+#[cfg(not(unix))]
+pub fn connect(_config: &SodiumConfig) -> io::Result<Incoming> {
+    Err(unsupported())
+}
+
+impl Incoming {
+    /// Loads the keyspace the old process streams.
+    #[cfg(unix)]
+    pub async fn load_state(&mut self) -> Result<SnapshotLoad, HandoffError> {
+        Ok(snapshot::load_stream(BufReader::new(&self.stream), "handoff stream").await?)
+    }
+
+    #[cfg(not(unix))]
+    pub async fn load_state(&mut self) -> Result<SnapshotLoad, HandoffError> {
+        Err(unsupported().into())
+    }
+
+    /// Tells the old process this one is serving, so it can exit.
+    #[cfg(unix)]
+    pub fn complete(self) -> Result<(), HandoffError> {
+        (&self.stream).write_all(HANDOFF_ACK)?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn complete(self) -> Result<(), HandoffError> {
+        Err(unsupported().into())
+    }
+}
+
+impl HandoffListener {
+    /// Listens on `config.handoff_socket` for a process to hand `listeners`
+    /// over to.
+    #[cfg(unix)]
+    pub fn bind(config: &SodiumConfig, listeners: Listeners) -> io::Result<Self> {
+        // io_uring threads keep accepting on their own handles, which a
+        // handoff cannot stop.
+        if NetworkBackend::parse(&config.network_backend) == Ok(NetworkBackend::IoUring) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "handoff needs the tokio network backend"));
+        }
+
+        // A file left behind by a process that did not exit cleanly.
+        let _ = std::fs::remove_file(&config.handoff_socket);
+        let listener = tokio::net::UnixListener::bind(&config.handoff_socket)?;
+        Ok(Self { path: config.handoff_socket.clone(), listeners, listener })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_config: &SodiumConfig, _listeners: Listeners) -> io::Result<Self> {
+        Err(unsupported())
+    }
+
+    /// Waits for a new process to connect.
+    #[cfg(unix)]
+    pub async fn accept(&self) -> io::Result<Handoff<'_>> {
+        let (stream, _) = self.listener.accept().await?;
+        let stream = stream.into_std()?;
+        stream.set_nonblocking(false)?;
+        Ok(Handoff { listeners: &self.listeners, stream })
+    }
+
+    #[cfg(not(unix))]
+    pub async fn accept(&self) -> io::Result<Handoff<'_>> {
+        std::future::pending().await
+    }
+}
+
+impl Drop for HandoffListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Handoff<'_> {
+    /// Passes the listening sockets, closes client connections and streams
+    /// the keyspace, then waits for the new process to take over. Returns
+    /// the number of keys sent. On failure client connections and the AOF
+    /// are resumed and this process carries on serving.
+    #[cfg(unix)]
+    pub async fn run(self) -> Result<usize, HandoffError> {
+        let Handoff { listeners, stream } = self;
+        let mut names = Vec::new();
+        let mut fds = Vec::new();
+        for (name, listener) in [("main", &listeners.main), ("admin", &listeners.admin), ("metrics", &listeners.metrics)] {
+            if let Some(listener) = listener {
+                names.push(name);
+                fds.push(listener.as_raw_fd());
+            }
+        }
+        let names = names.join(" ");
+        send_fds(&stream, names.len() as u8, &fds)?;
+        (&stream).write_all(names.as_bytes())?;
+
+        let dropped = api::drain_connections(DRAIN_TIMEOUT).await;
+        if dropped > 0 {
+            warn!("Closed {} client connections that did not finish their request in time", dropped);
+        }
+        aof::suspend().await;
+
+        let result = tokio::task::spawn_blocking(move || send_state(stream))
+            .await
+            .map_err(|e| HandoffError::Protocol(e.to_string()))
+            .and_then(|result| result);
+        if result.is_err() {
+            aof::resume();
+            api::resume_connections();
+        }
+        result
+    }
+
+    #[cfg(not(unix))]
+    pub async fn run(self) -> Result<usize, HandoffError> {
+        Err(unsupported().into())
+    }
+}
+
+#[cfg(unix)]
+fn send_state(stream: UnixStream) -> Result<usize, HandoffError> {
+    let mut writer = BufWriter::new(&stream);
+    let keys = snapshot::write_stream(&mut writer)?;
+    drop(writer);
+    stream.shutdown(std::net::Shutdown::Write)?;
+    info!("Sent {} keys, waiting for the new process to take over", keys);
+
+    stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+    let mut ack = Vec::new();
+    (&stream).take(HANDOFF_ACK.len() as u64).read_to_end(&mut ack)?;
+    if ack != HANDOFF_ACK {
+        return Err(HandoffError::Protocol("the new process closed the connection before taking over".to_string()));
+    }
+    Ok(keys)
+}
+
+// Sends `fds` attached to a single byte of data, `tag`.
+#[cfg(unix)]
+fn send_fds(stream: &UnixStream, tag: u8, fds: &[RawFd]) -> io::Result<()> {
+    let mut data = [tag];
+    let mut iov = libc::iovec { iov_base: data.as_mut_ptr().cast(), iov_len: data.len() };
+    let fds_len = std::mem::size_of_val(fds) as u32;
+    // SAFETY: CMSG_SPACE only computes a size.
+    let space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+    // u64 words keep the buffer aligned for cmsghdr.
+    let mut control = vec![0u64; space.div_ceil(8)];
+
+    // SAFETY: the header points at buffers that outlive the call, and the
+    // control buffer has room for one cmsghdr carrying `fds`.
+    unsafe {
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = space as _;
+
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(header).cast::<RawFd>(), fds.len());
+
+        if libc::sendmsg(stream.as_raw_fd(), &message, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Receives the byte sent by send_fds and the descriptors attached to it.
+#[cfg(unix)]
+fn receive_fds(stream: &UnixStream) -> io::Result<(u8, Vec<OwnedFd>)> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec { iov_base: data.as_mut_ptr().cast(), iov_len: data.len() };
+    // SAFETY: CMSG_SPACE only computes a size.
+    let space = unsafe { libc::CMSG_SPACE((MAX_SOCKETS * std::mem::size_of::<RawFd>()) as u32) } as usize;
+    let mut control = vec![0u64; space.div_ceil(8)];
+    #[cfg(target_os = "linux")]
+    let flags = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(not(target_os = "linux"))]
+    let flags = 0;
+
+    let mut fds = Vec::new();
+    // SAFETY: as in send_fds; every descriptor the kernel installed is taken
+    // into an OwnedFd exactly once.
+    unsafe {
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = space as _;
+
+        let received = libc::recvmsg(stream.as_raw_fd(), &mut message, flags);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if received == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let payload = (*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(header).cast::<RawFd>();
+                for index in 0..payload / std::mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(data.add(index).read_unaligned()));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+        if message.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "more sockets were sent than expected"));
+        }
+    }
+    Ok((data[0], fds))
+}
@@ -0,0 +1,143 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Replies to mutating commands sent with an idempotency token, so a client
+// retrying after a lost reply gets the original result instead of applying
+// the command twice.
+//
+// A token is claimed before its command runs, so a retry arriving while the
+// first attempt is still running waits for that attempt's reply rather than
+// running alongside it. Each token is tied to the command it was first sent
+// with; reusing it for different arguments is refused.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use tokio::sync::watch;
+
+use crate::protocol::Reply;
+
+// Expired tokens are swept once every this many claims.
+const SWEEP_INTERVAL: usize = 1024;
+
+// Keyed by the connection's namespace as well, so tenants cannot read each
+// other's replies by guessing tokens.
+type TokenKey = (Option<String>, String);
+
+struct Remembered {
+    // Hash of the command text the token was first sent with.
+    fingerprint: u64,
+    // Set once the reply is in; tokens still in flight do not expire.
+    expires_at: Option<Instant>,
+    // None while the first attempt runs, the reply once it finished.
+    reply: watch::Sender<Option<Reply>>,
+}
+
+static REPLIES: LazyLock<DashMap<TokenKey, Remembered>> = LazyLock::new(DashMap::new);
+static CLAIMS: AtomicUsize = AtomicUsize::new(0);
+
+/// What to do with a command sent with a token.
+pub enum Claim {
+    /// The token is new: run the command and hand the reply to the claim.
+    Run(Pending),
+    /// Reply of an earlier run of the same command.
+    Replay(Reply),
+    /// The token was first sent with a different command.
+    Mismatch,
+    /// `max_tokens` replies are already being kept.
+    Full,
+}
+
+/// A token whose command is running. Dropped without complete(), as when the
+/// command failed, the token is released so a retry gets to run.
+pub struct Pending {
+    key: TokenKey,
+    window: Duration,
+    completed: bool,
+}
+
+impl Pending {
+    /// Keeps `reply` for retries for the rest of the window and passes it to
+    /// retries already waiting.
+    pub fn complete(mut self, reply: Reply) {
+        if let Some(mut remembered) = REPLIES.get_mut(&self.key) {
+            remembered.expires_at = Some(Instant::now() + self.window);
+            remembered.reply.send_replace(Some(reply));
+        }
+        self.completed = true;
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if !self.completed {
+            REPLIES.remove_if(&self.key, |_, remembered| remembered.reply.borrow().is_none());
+        }
+    }
+}
+
+/// Claims `token` for `command`, or finds what an earlier use of it left:
+/// its reply, waited for while that attempt is still running.
+pub async fn claim(namespace: Option<&str>, token: &str, command: &str, window: Duration, max_tokens: usize) -> Claim {
+    let key = (namespace.map(str::to_string), token.to_string());
+    let fingerprint = fingerprint(command);
+    if CLAIMS.fetch_add(1, Ordering::Relaxed).is_multiple_of(SWEEP_INTERVAL) {
+        sweep();
+    }
+
+    loop {
+        // Checked before taking the entry, whose shard lock len() would wait on.
+        let mut full = REPLIES.len() >= max_tokens;
+        if full {
+            sweep();
+            full = REPLIES.len() >= max_tokens;
+        }
+        let now = Instant::now();
+        let mut receiver = match REPLIES.entry(key.clone()) {
+            Entry::Occupied(mut occupied) => {
+                let remembered = occupied.get();
+                if remembered.expires_at.is_some_and(|expires_at| expires_at <= now) {
+                    occupied.insert(in_flight(fingerprint));
+                    return Claim::Run(Pending { key, window, completed: false });
+                }
+                if remembered.fingerprint != fingerprint {
+                    return Claim::Mismatch;
+                }
+                if let Some(reply) = remembered.reply.borrow().clone() {
+                    return Claim::Replay(reply);
+                }
+                remembered.reply.subscribe()
+            }
+            Entry::Vacant(vacant) => {
+                if full {
+                    return Claim::Full;
+                }
+                vacant.insert(in_flight(fingerprint));
+                return Claim::Run(Pending { key, window, completed: false });
+            }
+        };
+
+        // Fails once the first attempt released the token, which is then
+        // claimed afresh.
+        let _ = receiver.wait_for(Option::is_some).await;
+    }
+}
+
+fn in_flight(fingerprint: u64) -> Remembered {
+    Remembered { fingerprint, expires_at: None, reply: watch::Sender::new(None) }
+}
+
+fn fingerprint(command: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    command.trim().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn sweep() {
+    let now = Instant::now();
+    REPLIES.retain(|_, remembered| remembered.expires_at.is_none_or(|expires_at| expires_at > now));
+}
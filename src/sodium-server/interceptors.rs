@@ -0,0 +1,226 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Hooks around every command a client sends. Interceptors run in
+// registration order before a request is parsed and before its command
+// executes, then in reverse order once there is a reply. A pre hook that
+// returns a reply answers the request in place of the command. Rate
+// limiting, authentication, the request log and request metrics are
+// interceptors themselves; more are added with register() before the first
+// request is served.
+
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::api::{error_response, Command, ErrorCode, Session};
+use crate::configuration::SodiumConfig;
+use crate::metrics;
+use crate::protocol::Reply;
+use crate::webhooks::pattern_matches;
+
+/// One command of a request line, as the client sent it.
+pub struct Request<'a> {
+    pub text: &'a str,
+    pub client_addr: SocketAddr,
+    // Arrived on the admin listener.
+    pub admin: bool,
+    pub config: &'a SodiumConfig,
+    /// Name of the parsed command, None until the request has parsed.
+    pub command: Option<&'static str>,
+    /// The single key the command operates on, if any.
+    pub key: Option<String>,
+}
+
+pub trait Interceptor: Send + Sync {
+    /// Runs on the request text before it is parsed.
+    fn pre_parse(&self, _session: &mut Session, _request: &Request) -> Option<Reply> {
+        None
+    }
+
+    /// Runs on the parsed command before it executes.
+    fn pre_execute(&self, _session: &mut Session, _request: &Request, _command: &Command) -> Option<Reply> {
+        None
+    }
+
+    /// Runs on every reply, including errors and replies given by a pre
+    /// hook, and may rewrite it.
+    fn post_execute(&self, _session: &mut Session, _request: &Request, _reply: &mut Reply, _elapsed: Duration) {}
+}
+
+static CHAIN: OnceLock<Vec<Box<dyn Interceptor>>> = OnceLock::new();
+// Interceptors registered so far, moved into CHAIN when it is built.
+static REGISTERED: Mutex<Vec<Box<dyn Interceptor>>> = Mutex::new(Vec::new());
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+static FAILED_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Adds an interceptor after the built-in ones and those registered before
+/// it. Returns false, registering nothing, once the chain has served a
+/// request.
+#[cfg_attr(not(feature = "example-plugins"), allow(dead_code))]
+pub fn register(interceptor: impl Interceptor + 'static) -> bool {
+    let mut registered = REGISTERED.lock().unwrap();
+    if CHAIN.get().is_some() {
+        return false;
+    }
+    registered.push(Box::new(interceptor));
+    true
+}
+
+// Built on first use rather than at startup so no request can ever be
+// served by a chain missing authentication.
+fn chain() -> &'static [Box<dyn Interceptor>] {
+    CHAIN.get_or_init(|| {
+        let mut chain: Vec<Box<dyn Interceptor>> = vec![
+            Box::new(RequestMetrics),
+            Box::new(RateLimit),
+            Box::new(Authorization),
+            Box::new(AuditLog),
+        ];
+        chain.extend(REGISTERED.lock().unwrap().drain(..));
+        chain
+    })
+}
+
+pub fn pre_parse(session: &mut Session, request: &Request) -> Option<Reply> {
+    chain().iter().find_map(|interceptor| interceptor.pre_parse(session, request))
+}
+
+pub fn pre_execute(session: &mut Session, request: &Request, command: &Command) -> Option<Reply> {
+    chain().iter().find_map(|interceptor| interceptor.pre_execute(session, request, command))
+}
+
+pub fn post_execute(session: &mut Session, request: &Request, reply: &mut Reply, elapsed: Duration) {
+    for interceptor in chain().iter().rev() {
+        interceptor.post_execute(session, request, reply, elapsed);
+    }
+}
+
+/// Commands answered, and how many of those replies were errors.
+pub fn request_counts() -> (u64, u64) {
+    (REQUESTS.load(Ordering::Relaxed), FAILED_REQUESTS.load(Ordering::Relaxed))
+}
+
+/// Per-connection token bucket behind rate_limit_per_sec.
+#[derive(Debug)]
+pub struct RateBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateBucket {
+    pub fn new(rate: u64) -> Self {
+        Self { tokens: rate as f64, refilled: Instant::now() }
+    }
+
+    fn take(&mut self, rate: u64) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() * rate as f64;
+        self.tokens = (self.tokens + refill).min(rate as f64);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+struct RequestMetrics;
+
+impl Interceptor for RequestMetrics {
+    fn post_execute(&self, _session: &mut Session, request: &Request, reply: &mut Reply, elapsed: Duration) {
+        REQUESTS.fetch_add(1, Ordering::Relaxed);
+        if reply.is_error() {
+            FAILED_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(command) = request.command {
+            let pattern = request.key.as_deref().and_then(|key| {
+                request.config.metrics_key_patterns.iter().find(|pattern| pattern_matches(pattern, key))
+            });
+            metrics::record_command(command, pattern.map(String::as_str), elapsed, reply.is_error());
+        }
+    }
+}
+
+struct RateLimit;
+
+impl Interceptor for RateLimit {
+    fn pre_parse(&self, session: &mut Session, request: &Request) -> Option<Reply> {
+        let rate = request.config.rate_limit_per_sec;
+        if rate == 0 || request.admin || session.rate_bucket.take(rate) {
+            return None;
+        }
+        Some(error_response(ErrorCode::Busy, "Rate limit exceeded, retry later"))
+    }
+}
+
+struct Authorization;
+
+impl Interceptor for Authorization {
+    fn pre_execute(&self, session: &mut Session, request: &Request, command: &Command) -> Option<Reply> {
+        // hello() is allowed before auth so a client can settle the framing
+        // of every reply it will read, including the auth() one.
+        if matches!(command, Command::Auth { .. } | Command::Hello { .. }) {
+            return None;
+        }
+        if !session.is_authenticated() {
+            return Some(error_response(ErrorCode::Auth, "Authentication required"));
+        }
+        if let Err(e) = session.authorize(command) {
+            warn!("Rejected {} from {}: {}", request.text, request.client_addr, e);
+            return Some(error_response(ErrorCode::NoPerm, e));
+        }
+        // shutdown() is never accepted on the public port; the other
+        // management commands only once an admin port exists to take them.
+        if !request.admin
+            && command.is_admin()
+            && (request.config.admin_port != 0 || matches!(command, Command::Shutdown))
+        {
+            warn!("Rejected {} from {}: admin command on the public port", request.text, request.client_addr);
+            return Some(error_response(ErrorCode::NoPerm, "Management commands are only accepted on the admin port"));
+        }
+        None
+    }
+}
+
+struct AuditLog;
+
+impl Interceptor for AuditLog {
+    fn pre_execute(&self, _session: &mut Session, request: &Request, command: &Command) -> Option<Reply> {
+        match command {
+            // Never log the token itself.
+            Command::Auth { .. } => info!("auth(...)"),
+            Command::Hello { .. } => {}
+            Command::Shutdown => warn!("Shutdown requested by {}", request.client_addr),
+            _ => info!("{}", request.text),
+        }
+        None
+    }
+}
+
+// Example interceptors, only built with the example-plugins feature.
+#[cfg(feature = "example-plugins")]
+pub mod examples {
+    use std::time::Duration;
+
+    use tracing::warn;
+
+    use super::{Interceptor, Request};
+    use crate::api::Session;
+    use crate::protocol::Reply;
+
+    /// Logs every command that takes longer than the given duration.
+    pub struct SlowCommands(pub Duration);
+
+    impl Interceptor for SlowCommands {
+        fn post_execute(&self, _session: &mut Session, request: &Request, _reply: &mut Reply, elapsed: Duration) {
+            if elapsed > self.0 {
+                warn!("Slow command from {} took {:?}: {}", request.client_addr, elapsed, request.text);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use crate::core::get_cache;
+use crate::threading::get_thread_pool;
+use std::fmt::{Display, Write};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use dashmap::DashMap;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::error;
+
+// Set once recovery has finished and the client listener is bound, cleared
+// again on shutdown.
+static READY: AtomicBool = AtomicBool::new(false);
+
+// Calls, errors and time spent per command name, and per configured key
+// pattern for commands on a single key.
+static COMMANDS: LazyLock<DashMap<&'static str, Counters>> = LazyLock::new(DashMap::new);
+static KEY_PATTERNS: LazyLock<DashMap<String, Counters>> = LazyLock::new(DashMap::new);
+
+#[derive(Debug, Default)]
+struct Counters {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    micros: AtomicU64,
+}
+
+impl Counters {
+    fn record(&self, elapsed: Duration, failed: bool) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Breakdown {
+        Breakdown {
+            calls: self.calls.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            micros: self.micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Totals for one command or key pattern.
+#[derive(Debug, Clone, Copy)]
+pub struct Breakdown {
+    pub calls: u64,
+    pub errors: u64,
+    /// Time spent answering, in microseconds.
+    pub micros: u64,
+}
+
+pub fn set_ready(ready: bool) {
+    READY.store(ready, Ordering::Relaxed);
+}
+
+/// Counts one answered command under its name and, when its key matched one
+/// of metrics_key_patterns, under that pattern.
+pub fn record_command(command: &'static str, pattern: Option<&str>, elapsed: Duration, failed: bool) {
+    match COMMANDS.get(command) {
+        Some(counters) => counters.record(elapsed, failed),
+        None => COMMANDS.entry(command).or_default().record(elapsed, failed),
+    }
+    if let Some(pattern) = pattern {
+        match KEY_PATTERNS.get(pattern) {
+            Some(counters) => counters.record(elapsed, failed),
+            None => KEY_PATTERNS.entry(pattern.to_string()).or_default().record(elapsed, failed),
+        }
+    }
+}
+
+/// Totals per command name, sorted by name.
+pub fn command_breakdown() -> Vec<(String, Breakdown)> {
+    let mut breakdown: Vec<_> = COMMANDS.iter()
+        .map(|entry| (entry.key().to_string(), entry.value().snapshot()))
+        .collect();
+    breakdown.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    breakdown
+}
+
+/// Totals per key pattern that has seen a command, sorted by pattern.
+pub fn key_pattern_breakdown() -> Vec<(String, Breakdown)> {
+    let mut breakdown: Vec<_> = KEY_PATTERNS.iter()
+        .map(|entry| (entry.key().clone(), entry.value().snapshot()))
+        .collect();
+    breakdown.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    breakdown
+}
+
+pub fn render_prometheus() -> String {
+    let stats = get_cache().stats();
+    let mut body = String::new();
+
+    write_metric(&mut body, "sodium_keys", "gauge", "Number of keys in the cache", &[("", stats.keys)]);
+    write_metric(&mut body, "sodium_used_memory_bytes", "gauge", "Approximate memory used by entries", &[("", stats.used_memory)]);
+    write_metric(&mut body, "sodium_max_memory_bytes", "gauge", "Configured memory limit, 0 when unlimited", &[("", stats.max_memory)]);
+    write_metric(&mut body, "sodium_commands_total", "counter", "Cache operations processed", &[("", stats.total_operations)]);
+    write_metric(&mut body, "sodium_keyspace_hits_total", "counter", "Lookups that found a key", &[("", stats.hits)]);
+    write_metric(&mut body, "sodium_keyspace_misses_total", "counter", "Lookups that missed", &[("", stats.misses)]);
+    let policy = format!("policy=\"{}\"", stats.eviction_policy);
+    write_metric(&mut body, "sodium_evicted_keys_total", "counter", "Keys evicted to stay under max_memory", &[(policy.as_str(), stats.evicted_keys)]);
+    write_metric(&mut body, "sodium_expired_keys_total", "counter", "Keys removed because their TTL elapsed", &[("", stats.expired_keys)]);
+    write_metric(&mut body, "sodium_scheduled_writes", "gauge", "Writes set with at() that are not visible yet", &[("", get_cache().scheduled_writes())]);
+
+    let (requests, failed_requests) = crate::interceptors::request_counts();
+    write_metric(&mut body, "sodium_requests_total", "counter", "Commands answered, including rejected ones", &[("", requests)]);
+    write_metric(&mut body, "sodium_request_errors_total", "counter", "Commands answered with an error", &[("", failed_requests)]);
+
+    let pool = get_thread_pool();
+    let depth = pool.queue_depth();
+    let capacity = pool.queue_capacity();
+    write_metric(&mut body, "sodium_queue_depth", "gauge", "Tasks waiting in the worker queues", &[("", depth)]);
+    write_metric(&mut body, "sodium_queue_capacity", "gauge", "Total capacity of the worker queues", &[("", capacity)]);
+    write_metric(&mut body, "sodium_queue_saturation", "gauge", "Fraction of worker queue capacity in use", &[("", depth as f64 / capacity.max(1) as f64)]);
+    let workers = pool.worker_queues();
+    let worker_labels: Vec<String> = (0..workers.len()).map(|worker| format!("worker=\"{}\"", worker)).collect();
+    let depths: Vec<(&str, usize)> = worker_labels.iter().map(String::as_str).zip(workers.iter().map(|(depth, _)| *depth)).collect();
+    let waits: Vec<(&str, f64)> = worker_labels.iter().map(String::as_str).zip(workers.iter().map(|(_, wait)| wait.as_secs_f64())).collect();
+    write_metric(&mut body, "sodium_worker_queue_depth", "gauge", "Tasks waiting in each worker's queue", &depths);
+    write_metric(&mut body, "sodium_worker_queue_wait_seconds", "gauge", "Recent average time tasks spent queued, by worker", &waits);
+    let (waited, dequeued) = pool.queue_wait();
+    write_metric(&mut body, "sodium_queue_wait_seconds_total", "counter", "Time tasks spent queued before a worker took them", &[("", waited.as_secs_f64())]);
+    write_metric(&mut body, "sodium_queue_dequeued_tasks_total", "counter", "Tasks taken off the worker queues", &[("", dequeued)]);
+    write_metric(&mut body, "sodium_task_run_seconds", "gauge", "Recent average time tasks took to run once taken", &[("", pool.average_task_time().as_secs_f64())]);
+    write_metric(&mut body, "sodium_busy_rejections_total", "counter", "Commands refused with BUSY because the queues were full", &[("", pool.rejected_tasks())]);
+    write_metric(&mut body, "sodium_worker_panics_total", "counter", "Panics caught in worker threads", &[("", pool.worker_panics())]);
+    write_metric(&mut body, "sodium_abandoned_tasks_total", "counter", "Queued reads skipped because their client had disconnected or timed out", &[("", pool.abandoned_tasks())]);
+    write_metric(&mut body, "sodium_webhook_dropped_events_total", "counter", "Key events dropped because a webhook or expiration listener queue was full", &[("", crate::webhooks::dropped_events())]);
+    write_metric(&mut body, "sodium_coalesced_writes_total", "counter", "Queued sets dropped in favour of a later set of the same key", &[("", pool.coalesced_writes())]);
+
+    write_breakdown(&mut body, "command", "command", &command_breakdown());
+    write_breakdown(&mut body, "key_pattern", "pattern", &key_pattern_breakdown());
+
+    body
+}
+
+fn write_breakdown(body: &mut String, name: &str, label: &str, breakdown: &[(String, Breakdown)]) {
+    if breakdown.is_empty() {
+        return;
+    }
+    let labels: Vec<String> = breakdown.iter()
+        .map(|(value, _)| format!("{}=\"{}\"", label, escape_label(value)))
+        .collect();
+    let samples = |field: fn(&Breakdown) -> f64| -> Vec<(&str, f64)> {
+        labels.iter().map(String::as_str).zip(breakdown.iter().map(|(_, totals)| field(totals))).collect()
+    };
+    write_metric(body, &format!("sodium_{}_calls_total", name), "counter", &format!("Commands answered, by {}", label), &samples(|totals| totals.calls as f64));
+    write_metric(body, &format!("sodium_{}_errors_total", name), "counter", &format!("Commands answered with an error, by {}", label), &samples(|totals| totals.errors as f64));
+    write_metric(body, &format!("sodium_{}_duration_seconds_total", name), "counter", &format!("Time spent answering commands, by {}", label), &samples(|totals| totals.micros as f64 / 1_000_000.0));
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_metric<T: Display>(body: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, T)]) {
+    let _ = writeln!(body, "# HELP {} {}", name, help);
+    let _ = writeln!(body, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(body, "{} {}", name, value);
+        } else {
+            let _ = writeln!(body, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+pub async fn serve(listener: std::net::TcpListener) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+
+    loop {
+        let (stream, client_addr) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream).await {
+                error!("Error serving metrics to {}: {}", client_addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut buffer = [0u8; 1024];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    // /livez only shows the process still answers; /readyz also requires
+    // startup recovery to be done, so traffic is not routed to a node that
+    // is still replaying its data.
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", render_prometheus()),
+        "/livez" => ("200 OK", "ok\n".to_string()),
+        "/readyz" if READY.load(Ordering::Relaxed) => ("200 OK", "ready\n".to_string()),
+        "/readyz" => ("503 Service Unavailable", "not ready\n".to_string()),
+        _ => ("404 Not Found", "Not Found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
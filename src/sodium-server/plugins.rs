@@ -0,0 +1,116 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Commands added without touching the parser or executor. A plugin names a
+// function, receives its arguments split the way built-in commands split
+// theirs and runs on a worker thread with the cache at hand. Plugins are
+// compiled in: implement CommandPlugin and pass it to register() before the
+// server starts.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use tracing::{info, warn};
+
+use crate::core::{get_cache, CacheError, Sodium};
+use crate::protocol::Reply;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PluginError {
+    // For plugins to reject their arguments with; none may be built in.
+    #[cfg_attr(not(feature = "example-plugins"), allow(dead_code))]
+    #[error("{0}")]
+    InvalidArguments(String),
+    #[error(transparent)]
+    Cache(#[from] CacheError),
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// A command served by a plugin. Built-in commands take precedence over a
+/// plugin of the same name.
+pub trait CommandPlugin: Send + Sync {
+    /// Function name clients call the command by, matched case-insensitively.
+    fn name(&self) -> &str;
+
+    /// Whether the command changes stored data, so its replies are
+    /// remembered for idempotency tokens and a queued run is never skipped.
+    fn is_mutating(&self) -> bool {
+        false
+    }
+
+    /// Runs the command on a worker thread. The cache's async methods are
+    /// driven with core::block_on.
+    fn execute(&self, cache: &Sodium, args: &[String]) -> Result<Reply, PluginError>;
+}
+
+static PLUGINS: OnceLock<HashMap<String, Arc<dyn CommandPlugin>>> = OnceLock::new();
+// Plugins registered so far, moved into PLUGINS by initialize_plugins().
+static REGISTERED: Mutex<Vec<Arc<dyn CommandPlugin>>> = Mutex::new(Vec::new());
+
+/// Adds a plugin command. Returns false, registering nothing, once
+/// initialize_plugins() has run.
+#[cfg_attr(not(feature = "example-plugins"), allow(dead_code))]
+pub fn register(plugin: impl CommandPlugin + 'static) -> bool {
+    let mut registered = REGISTERED.lock().unwrap();
+    if PLUGINS.get().is_some() {
+        return false;
+    }
+    registered.push(Arc::new(plugin));
+    true
+}
+
+/// Serves the plugins registered so far; later registrations are refused.
+pub fn initialize_plugins() {
+    let mut registered = REGISTERED.lock().unwrap();
+    let mut plugins = HashMap::new();
+    for plugin in registered.drain(..) {
+        let name = plugin.name().to_lowercase();
+        if plugins.insert(name.clone(), plugin).is_some() {
+            warn!("Plugin command {} registered twice, keeping the last", name);
+        }
+    }
+    if !plugins.is_empty() {
+        let mut names: Vec<&str> = plugins.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        info!("Plugin commands: {}", names.join(", "));
+    }
+    let _ = PLUGINS.set(plugins);
+}
+
+pub fn find(name: &str) -> Option<&'static Arc<dyn CommandPlugin>> {
+    PLUGINS.get()?.get(&name.to_lowercase())
+}
+
+pub fn execute_plugin(name: &str, args: &[String]) -> crate::threading::TaskResult<Reply> {
+    let plugin = find(name).ok_or_else(|| PluginError::Failed(format!("No plugin command {}", name)))?;
+    Ok(plugin.execute(get_cache(), args)?)
+}
+
+// Example plugins, only built with the example-plugins feature.
+#[cfg(feature = "example-plugins")]
+pub mod examples {
+    use super::{CommandPlugin, PluginError};
+    use crate::core::{block_on, CacheError, Sodium};
+    use crate::protocol::Reply;
+
+    /// strlen(key): byte length of a text value, 0 when the key is missing.
+    pub struct StrLen;
+
+    impl CommandPlugin for StrLen {
+        fn name(&self) -> &str {
+            "strlen"
+        }
+
+        fn execute(&self, cache: &Sodium, args: &[String]) -> Result<Reply, PluginError> {
+            let [key] = args else {
+                return Err(PluginError::InvalidArguments(format!("strlen() takes 1 argument, got {}", args.len())));
+            };
+            match block_on(cache.get(key)) {
+                Ok(value) => Ok(Reply::Integer(value.len() as i64)),
+                Err(CacheError::KeyNotFound(_)) => Ok(Reply::Integer(0)),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use std::sync::Arc;
+
+/// Protocol spoken by connections that never call hello(): every reply is a
+/// bare line, so a stored "NULL" reads the same as a miss.
+pub const DEFAULT_PROTOCOL: u8 = 1;
+/// Typed framing, opted into with hello(2).
+pub const TYPED_PROTOCOL: u8 = 2;
+
+/// A command result before it is framed for the connection's protocol.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Null,
+    Bulk(String),
+    // A stored value, still shared with the cache entry it was read from.
+    Value(Arc<str>),
+    Array(Vec<Reply>),
+    // An event sent unprompted between replies, never a command's reply.
+    Push(Vec<Reply>),
+    // Structured values that predate typed framing and are sent as JSON text.
+    Json(serde_json::Value),
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::Status("OK".to_string())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Reply::Error(_))
+    }
+
+    /// Frames the reply without its trailing newline.
+    ///
+    /// Version 2 prefixes every value with its type: `+` status, `-` error,
+    /// `:` integer, `_` null, `$<len>` followed by the bytes on the next line
+    /// for bulk strings, `*<count>` followed by one element per line for
+    /// arrays, and `><count>` laid out the same way for pushes, so a client
+    /// never takes an event for the reply it is waiting on.
+    pub fn encode(&self, protocol: u8) -> String {
+        let mut out = String::new();
+        if protocol >= TYPED_PROTOCOL {
+            self.encode_typed(&mut out);
+        } else {
+            self.encode_plain(&mut out);
+        }
+        out
+    }
+
+    fn encode_plain(&self, out: &mut String) {
+        match self {
+            Reply::Status(text) | Reply::Error(text) | Reply::Bulk(text) => out.push_str(text),
+            Reply::Value(text) => out.push_str(text),
+            Reply::Integer(value) => out.push_str(&value.to_string()),
+            Reply::Null => out.push_str("NULL"),
+            Reply::Array(items) if items.is_empty() => out.push_str("(empty)"),
+            Reply::Json(serde_json::Value::Array(items)) if items.is_empty() => out.push_str("(empty)"),
+            Reply::Json(value) => out.push_str(&value.to_string()),
+            Reply::Array(items) | Reply::Push(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        out.push(' ');
+                    }
+                    item.encode_plain(out);
+                }
+            }
+        }
+    }
+
+    fn encode_typed(&self, out: &mut String) {
+        match self {
+            Reply::Status(text) => {
+                out.push('+');
+                out.push_str(text);
+            }
+            Reply::Error(text) => {
+                out.push('-');
+                out.push_str(text);
+            }
+            Reply::Integer(value) => {
+                out.push(':');
+                out.push_str(&value.to_string());
+            }
+            Reply::Null => out.push('_'),
+            Reply::Bulk(text) => Self::encode_bulk(text, out),
+            Reply::Value(text) => Self::encode_bulk(text, out),
+            Reply::Json(value) => Self::encode_bulk(&value.to_string(), out),
+            Reply::Array(items) => Self::encode_items('*', items, out),
+            Reply::Push(items) => Self::encode_items('>', items, out),
+        }
+    }
+
+    fn encode_items(marker: char, items: &[Reply], out: &mut String) {
+        out.push_str(&format!("{}{}", marker, items.len()));
+        for item in items {
+            out.push('\n');
+            item.encode_typed(out);
+        }
+    }
+
+    fn encode_bulk(text: &str, out: &mut String) {
+        out.push_str(&format!("${}\n", text.len()));
+        out.push_str(text);
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use crate::aof::{self, AofError, AofReplay};
+use crate::configuration::SodiumConfig;
+use crate::handoff::{HandoffError, Incoming};
+use crate::snapshot::{self, SnapshotError, SnapshotLoad};
+
+use tracing::{info, warn};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecoveryError {
+    #[error("Snapshot recovery failed: {0}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("AOF recovery failed: {0}")]
+    Aof(#[from] AofError),
+    #[error("Handoff failed: {0}")]
+    Handoff(#[from] HandoffError),
+}
+
+#[derive(Debug)]
+pub struct RecoveryReport {
+    pub snapshot: Option<SnapshotLoad>,
+    pub aof: AofReplay,
+}
+
+/// Restores the cache on startup: the latest snapshot chain first, then the
+/// AOF records written after it. Records the snapshot already covers are
+/// skipped, and a break in the sequence of the rest aborts startup rather
+/// than silently serving a cache with writes missing from the middle.
+pub async fn recover(config: &SodiumConfig) -> Result<RecoveryReport, RecoveryError> {
+    let snapshot = snapshot::load_snapshot(config).await?;
+    let after_seq = snapshot.as_ref().map_or(0, |snapshot| snapshot.aof_seq);
+    let aof = aof::initialize_aof(config, after_seq).await?;
+
+    let report = RecoveryReport { snapshot, aof };
+    report.log(config);
+    Ok(report)
+}
+
+/// Restores the cache from the keyspace a server handing off to this one
+/// streams, then the AOF records it appended after taking it. The on-disk
+/// snapshot is not read: the stream is at least as recent.
+pub async fn recover_from_handoff(config: &SodiumConfig, incoming: &mut Incoming) -> Result<RecoveryReport, RecoveryError> {
+    let snapshot = incoming.load_state().await?;
+    info!("Received {} keys from the previous process, covering AOF through seq {}", snapshot.keys, snapshot.aof_seq);
+    let aof = aof::initialize_aof(config, snapshot.aof_seq).await?;
+
+    let report = RecoveryReport { snapshot: Some(snapshot), aof };
+    report.log_aof(config);
+    Ok(report)
+}
+
+impl RecoveryReport {
+    fn log(&self, config: &SodiumConfig) {
+        match &self.snapshot {
+            Some(snapshot) => info!(
+                "Recovered {} keys from snapshot {} and {} deltas, covering AOF through seq {}",
+                snapshot.keys, config.snapshot_path, snapshot.deltas, snapshot.aof_seq
+            ),
+            None => info!("No snapshot to recover from"),
+        }
+        self.log_aof(config);
+    }
+
+    fn log_aof(&self, config: &SodiumConfig) {
+        if !config.aof_enabled {
+            return;
+        }
+        info!(
+            "Replayed {} AOF records from {} ({} already in the snapshot), now at seq {}",
+            self.aof.applied, config.aof_path, self.aof.skipped, self.aof.last_seq
+        );
+        if self.aof.truncated_tail {
+            warn!("The last AOF record was incomplete and has been dropped");
+        }
+    }
+}
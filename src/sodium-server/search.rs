@@ -1,7 +1,10 @@
 // Copyright (c) 2025, TheByteSlayer, Sodium
 // A scalable and optimized Key Value Caching System, written in Rust.
 
-use crate::core::{CacheError, get_cache};
+use crate::core::{CacheError, SortOrder, block_on, get_cache};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::warn;
 
 #[derive(Debug, Clone)]
 pub enum SearchType {
@@ -23,18 +26,39 @@ impl SearchType {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct SearchResult {
+    pub keys: Vec<String>,
+    pub truncated: bool,
+}
+
 pub struct SearchEngine;
 
 impl SearchEngine {
-    pub async fn search_multiple(search_type: SearchType, queries: &[String]) -> Result<Vec<String>, CacheError> {
+    pub async fn search_multiple(
+        search_type: SearchType,
+        queries: &[String],
+        deadline: Option<Instant>,
+        cancelled: &AtomicBool,
+    ) -> Result<SearchResult, CacheError> {
         let cache = get_cache();
         let queries_lower: Vec<String> = queries.iter().map(|q| q.to_lowercase()).collect();
         
         // Get all key-value pairs from cache
         let all_keys = cache.keys().await?;
+        let total_keys = all_keys.len();
         let mut matching_keys = Vec::new();
         
-        for key in all_keys {
+        for (scanned, key) in all_keys.into_iter().enumerate() {
+            if cancelled.load(Ordering::Relaxed) {
+                return Ok(SearchResult { keys: matching_keys, truncated: true });
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                warn!("Search ran out of time after scanning {} of {} keys", scanned, total_keys);
+                return Ok(SearchResult { keys: matching_keys, truncated: true });
+            }
+
             let should_include = match &search_type {
                 SearchType::Key => {
                     Self::key_contains_all(&key, &queries_lower)
@@ -71,7 +95,7 @@ impl SearchEngine {
             }
         }
         
-        Ok(matching_keys)
+        Ok(SearchResult { keys: matching_keys, truncated: false })
     }
 
 
@@ -91,20 +115,17 @@ impl SearchEngine {
 
 
 
-pub fn execute_search_multiple(search_type: SearchType, queries: Vec<String>) -> super::threading::TaskResult<Vec<String>> {
-    match tokio::runtime::Handle::try_current() {
-        Ok(handle) => {
-            handle.block_on(async {
-                SearchEngine::search_multiple(search_type, &queries).await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
-        }
-        Err(_) => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(async {
-                SearchEngine::search_multiple(search_type, &queries).await
-                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            })
-        }
+pub fn execute_search_multiple(
+    search_type: SearchType,
+    queries: Vec<String>,
+    sort: Option<SortOrder>,
+    deadline: Option<Instant>,
+    cancelled: &AtomicBool,
+) -> super::threading::TaskResult<SearchResult> {
+    let mut result = block_on(SearchEngine::search_multiple(search_type, &queries, deadline, cancelled))
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    if let Some(order) = sort {
+        get_cache().sort_keys(&mut result.keys, order);
     }
-} 
\ No newline at end of file
+    Ok(result)
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Secrets kept out of sodium.toml, so the file can be checked in. Settings
+// holding credentials have a `*_file` variant naming a file to read them
+// from, such as a mounted secret, and may refer to environment variables as
+// `${NAME}`. Both are resolved on the loaded config only; the file on disk
+// keeps the references.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::configuration::SodiumConfig;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error("Failed to read {setting} from {path}: {source}")]
+    Read { setting: String, path: String, source: std::io::Error },
+    #[error("Failed to parse {setting} from {path}: {reason}")]
+    Parse { setting: String, path: String, reason: String },
+    #[error("Set either {0} or {0}_file, not both")]
+    Conflict(String),
+    #[error("{setting} refers to ${{{name}}}, which is not set in the environment")]
+    MissingVariable { setting: String, name: String },
+    #[error("{0} has an unterminated ${{")]
+    Unterminated(String),
+}
+
+/// Reads the `*_file` settings into the settings they stand for, then
+/// replaces `${NAME}` references in every setting that may hold a secret.
+pub fn resolve(config: &mut SodiumConfig) -> Result<(), SecretError> {
+    if !config.backing_store_url_file.is_empty() {
+        if !config.backing_store_url.is_empty() {
+            return Err(SecretError::Conflict("backing_store_url".to_string()));
+        }
+        config.backing_store_url = read_secret("backing_store_url_file", &config.backing_store_url_file)?;
+    }
+    merge_table("auth_tokens_file", &config.auth_tokens_file, &mut config.auth_tokens)?;
+    merge_table("encryption_keys_file", &config.encryption_keys_file, &mut config.encryption_keys)?;
+
+    config.backing_store_url = expand("backing_store_url", &config.backing_store_url)?;
+    let mut auth_tokens = BTreeMap::new();
+    for (token, namespace) in &config.auth_tokens {
+        auth_tokens.insert(expand("auth_tokens", token)?, namespace.clone());
+    }
+    config.auth_tokens = auth_tokens;
+    for (id, key) in config.encryption_keys.iter_mut() {
+        *key = expand(&format!("encryption_keys.{}", id), key)?;
+    }
+    for (pattern, loader) in config.loaders.iter_mut() {
+        loader.url = expand(&format!("loaders.{}", pattern), &loader.url)?;
+    }
+    // A pipe command is left to its shell, which expands variables itself.
+    for (pattern, target) in config.webhooks.iter_mut() {
+        if !target.starts_with("pipe:") {
+            *target = expand(&format!("webhooks.{}", pattern), target)?;
+        }
+    }
+    Ok(())
+}
+
+// The file's content without the trailing newline editors add.
+fn read_secret(setting: &str, path: &str) -> Result<String, SecretError> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.trim_end_matches(['\n', '\r']).to_string()),
+        Err(source) => Err(SecretError::Read { setting: setting.to_string(), path: path.to_string(), source }),
+    }
+}
+
+// Adds the string pairs of the TOML file at `path` to `table`, keeping the
+// entries it already has.
+fn merge_table(setting: &str, path: &str, table: &mut BTreeMap<String, String>) -> Result<(), SecretError> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    let content = read_secret(setting, path)?;
+    let parsed: toml::Table = toml::from_str(&content)
+        .map_err(|e| SecretError::Parse { setting: setting.to_string(), path: path.to_string(), reason: e.to_string() })?;
+    for (key, value) in parsed {
+        if let toml::Value::String(value) = value {
+            table.entry(key).or_insert(value);
+        }
+    }
+    Ok(())
+}
+
+// `value` with each `${NAME}` replaced by the environment variable NAME.
+fn expand(setting: &str, value: &str) -> Result<String, SecretError> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let Some(end) = reference.find('}') else {
+            return Err(SecretError::Unterminated(setting.to_string()));
+        };
+        let name = &reference[..end];
+        match std::env::var(name) {
+            Ok(variable) => expanded.push_str(&variable),
+            Err(_) => return Err(SecretError::MissingVariable { setting: setting.to_string(), name: name.to_string() }),
+        }
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
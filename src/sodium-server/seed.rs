@@ -0,0 +1,95 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Seed keys: defaults declared in sodium.toml, or a file it points to, that
+// are written at startup unless the key already holds a value. Recovery runs
+// first, so a value changed at runtime survives restarts instead of being
+// reset to its default.
+
+use std::collections::BTreeMap;
+
+use crate::backing;
+use crate::configuration::SodiumConfig;
+use crate::core::{get_cache, CacheError, SetOptions};
+
+use tracing::info;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SeedError {
+    #[error("Failed to read seed file {path}: {source}")]
+    Read { path: String, source: std::io::Error },
+    #[error("Failed to parse seed file {path}: {source}")]
+    Parse { path: String, source: toml::de::Error },
+    #[error("Failed to seed {key}: {source}")]
+    Cache { key: String, source: CacheError },
+    #[error("Failed to seed {key}: {source}")]
+    Backing { key: String, source: Box<dyn std::error::Error + Send + Sync> },
+}
+
+/// A seed value as text. Numbers and booleans are accepted too, so flags
+/// can be written as `dark_mode = false`.
+pub fn seed_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Writes every seed key missing from the cache and returns how many were
+/// written. With a backing store, a key it already holds counts as present.
+pub async fn seed(config: &SodiumConfig) -> Result<usize, SeedError> {
+    let seeds = load_seeds(config)?;
+    let cache = get_cache();
+
+    let mut written = 0;
+    for (key, value) in seeds {
+        if cache.get(&key).await.is_ok() {
+            continue;
+        }
+        if backing::is_enabled() {
+            match backing::load_on_miss(&key).await {
+                Ok(Some(_)) => continue,
+                Ok(None) => {}
+                Err(source) => return Err(SeedError::Backing { key, source }),
+            }
+            if let Err(source) = backing::write(&key, &value).await {
+                return Err(SeedError::Backing { key, source });
+            }
+        }
+        if let Err(source) = cache.set(key.clone(), value, SetOptions::default()).await {
+            return Err(SeedError::Cache { key, source });
+        }
+        written += 1;
+    }
+
+    if written > 0 && !config.silent {
+        info!("Seeded {} missing keys", written);
+    }
+    Ok(written)
+}
+
+// The seed file's keys, overridden by those in sodium.toml.
+fn load_seeds(config: &SodiumConfig) -> Result<BTreeMap<String, String>, SeedError> {
+    let mut seeds = BTreeMap::new();
+    if !config.seed_file.is_empty() {
+        let path = config.seed_file.clone();
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(source) => return Err(SeedError::Read { path, source }),
+        };
+        let table: toml::Table = match toml::from_str(&content) {
+            Ok(table) => table,
+            Err(source) => return Err(SeedError::Parse { path, source }),
+        };
+        for (key, value) in &table {
+            if let Some(value) = seed_value(value) {
+                seeds.insert(key.clone(), value);
+            }
+        }
+    }
+    seeds.extend(config.seed.iter().map(|(key, value)| (key.clone(), value.clone())));
+    Ok(seeds)
+}
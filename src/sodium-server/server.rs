@@ -2,53 +2,338 @@
 // A scalable and optimized Key Value Caching System, written in Rust.
 
 mod api;
+mod aof;
+mod background;
+mod backing;
+mod chaos;
+mod checksum;
+mod check;
 mod core;
 mod cluster;
+mod compact;
+mod counter;
 mod configuration;
+mod daemon;
+mod encryption;
+mod handoff;
+mod idempotency;
+mod interceptors;
+mod metrics;
+mod plugins;
+mod protocol;
+mod recovery;
 mod search;
+mod secrets;
+mod seed;
+mod service;
+mod sketches;
+mod snapshot;
+mod systemd;
 mod threading;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod verify;
+mod webhooks;
 
 use api::TcpApiServer;
 use configuration::SodiumConfig;
+use handoff::{HandoffListener, Incoming, Listeners};
 
+use tokio::sync::Notify;
 use tracing::{info, error};
-use tracing_subscriber;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = SodiumConfig::load_or_create()?;
-    
+// Signalled by shutdown() on the admin port.
+static SHUTDOWN_REQUESTED: Notify = Notify::const_new();
+
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.notify_one();
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--check") {
+        return check::run();
+    }
+    let profile = argument_value(&args, "--profile").or_else(|| std::env::var("SODIUM_PROFILE").ok());
+    if let Some(path) = argument_value(&args, "--verify-dump") {
+        verify::initialize_keys(profile.as_deref())?;
+        return verify::verify_dump(&path);
+    }
+    if let Some(path) = argument_value(&args, "--repair-aof") {
+        verify::initialize_keys(profile.as_deref())?;
+        return verify::repair_aof(&path);
+    }
+    let service_mode = args.iter().any(|arg| arg == "--service");
+    if service_mode {
+        service::enter_service_directory()?;
+    }
+
+    let config = SodiumConfig::load_or_create(profile.as_deref())?;
+
+    // Forking is only safe while the process is single-threaded, so this
+    // happens before the runtime starts.
+    if args.iter().any(|arg| arg == "--daemon") {
+        daemon::daemonize()?;
+    }
+    let _pidfile = match config.pidfile.as_str() {
+        "" => None,
+        path => Some(daemon::PidFile::create(path)?),
+    };
+
+    if service_mode {
+        service::run(config, |config| start(config, None, None))?;
+        return Ok(());
+    }
+
+    let activated_listener = systemd::take_listener()?;
+    // Like socket activation, taking over the sockets of a running server
+    // happens before any other thread starts.
+    let incoming = if args.iter().any(|arg| arg == "--handoff") {
+        Some(handoff::connect(&config)?)
+    } else {
+        None
+    };
+    start(config, activated_listener, incoming)
+}
+
+// `<flag> <value>` or `<flag>=<value>`, as in `--profile <name>`.
+fn argument_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().enumerate().find_map(|(index, arg)| match arg.strip_prefix(flag) {
+        Some("") => args.get(index + 1).cloned(),
+        Some(value) => value.strip_prefix('=').map(str::to_string),
+        None => None,
+    })
+}
+
+fn start(config: SodiumConfig, activated_listener: Option<std::net::TcpListener>, incoming: Option<Incoming>) -> Result<(), Box<dyn std::error::Error>> {
+    // Deterministic mode polls connections on one thread so their commands
+    // reach the worker in a repeatable order.
+    let mut builder = if config.deterministic {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    builder
+        .enable_all()
+        .build()?
+        .block_on(run(config, activated_listener, incoming))
+}
+
+async fn run(
+    config: SodiumConfig,
+    activated_listener: Option<std::net::TcpListener>,
+    mut incoming: Option<Incoming>,
+) -> Result<(), Box<dyn std::error::Error>> {
     if !config.silent {
         tracing_subscriber::fmt()
             .with_target(false)
             .with_thread_ids(true)
             .with_level(true)
+            .with_max_level(config.log_level.parse().unwrap_or(tracing::Level::INFO))
             .init();
     }
 
-    threading::initialize_threading();
-    core::initialize_cache();
-    
-    let bind_addr = config.bind_address();
+    threading::initialize_threading(&config);
+    core::initialize_cache(&config);
+    encryption::initialize_encryption(&config)?;
+
+    let mut inherited = incoming.as_mut().map(|incoming| std::mem::take(&mut incoming.listeners)).unwrap_or_default();
+    // Handles to every listening socket, kept to pass on in a handoff.
+    let mut listeners = Listeners::default();
+
+    // Started before recovery so probes can report the node as live but not
+    // yet ready while a snapshot or AOF is still being replayed.
+    if config.metrics_port != 0 {
+        let bound = match inherited.metrics.take() {
+            Some(listener) => Ok(listener),
+            None => std::net::TcpListener::bind(config.metrics_address()),
+        };
+        match bound {
+            Ok(listener) => {
+                listeners.metrics = listener.try_clone().ok();
+                let silent = config.silent;
+                tokio::spawn(async move {
+                    if let Err(e) = metrics::serve(listener).await
+                        && !silent {
+                        error!("Metrics endpoint stopped: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                if !config.silent {
+                    error!("Metrics endpoint stopped: {}", e);
+                }
+            }
+        }
+    }
+
+    match incoming.as_mut() {
+        Some(incoming) => recovery::recover_from_handoff(&config, incoming).await?,
+        None => recovery::recover(&config).await?,
+    };
+    background::start(&config)?;
+    backing::initialize_backing_store(&config)?;
+    backing::initialize_loaders(&config)?;
+    seed::seed(&config).await?;
+    webhooks::initialize_webhooks(&config)?;
+    #[cfg(feature = "example-plugins")]
+    {
+        plugins::register(plugins::examples::StrLen);
+        interceptors::register(interceptors::examples::SlowCommands(std::time::Duration::from_millis(100)));
+    }
+    plugins::initialize_plugins();
     
-    let server = TcpApiServer::new(&bind_addr).await?;
+    let server = match activated_listener.or(inherited.main.take()) {
+        Some(listener) => TcpApiServer::from_listener(listener, &config)?,
+        None => TcpApiServer::new(&config.bind_address(), &config).await?,
+    };
+    listeners.main = Some(server.duplicate_listener()?);
+    if config.admin_port != 0 {
+        let admin = match inherited.admin.take() {
+            Some(listener) => TcpApiServer::from_admin_listener(listener, &config)?,
+            None => TcpApiServer::new_admin(&config).await?,
+        };
+        listeners.admin = Some(admin.duplicate_listener()?);
+        info!("Admin commands accepted on {}", admin.local_addr()?);
+        tokio::spawn(async move {
+            if let Err(e) = admin.run().await {
+                error!("Admin listener stopped: {}", e);
+            }
+        });
+    }
+    let handoff_listener = match config.handoff_socket.as_str() {
+        "" => None,
+        _ => Some(HandoffListener::bind(&config, listeners)?),
+    };
+
+    metrics::set_ready(true);
+    systemd::notify("READY=1");
+    service::report_running();
     
     if !config.silent {
+        if config.banner {
+            print_banner(&config, server.local_addr()?);
+        }
         info!("Sodium running on {}", server.local_addr()?);
         info!("Sodium listening on {}", config.public_bind_address());
     }
     
-    tokio::select! {
-        result = server.run() => {
-            if let Err(e) = result {
-                if !config.silent {
+    if let Some(incoming) = incoming {
+        match incoming.complete() {
+            Ok(()) => info!("Took over from the previous process"),
+            Err(e) => error!("Failed to tell the previous process to exit: {}", e),
+        }
+    }
+
+    loop {
+        tokio::select! {
+            result = server.run() => {
+                if let Err(e) = result
+                    && !config.silent {
                     error!("Error accepting TCP connection: {}", e);
                 }
+                break;
+            }
+            _ = shutdown_signal() => {
+                metrics::set_ready(false);
+                systemd::notify("STOPPING=1");
+                break;
+            }
+            handoff = next_handoff(handoff_listener.as_ref()) => {
+                let handoff = match handoff {
+                    Ok(handoff) => handoff,
+                    Err(e) => {
+                        error!("Error accepting handoff connection: {}", e);
+                        continue;
+                    }
+                };
+                // Accepting stops here: connections arriving from now on
+                // queue up for the new process.
+                info!("Handing off to a new process");
+                metrics::set_ready(false);
+                match handoff.run().await {
+                    Ok(keys) => {
+                        info!("Handed off {} keys, exiting", keys);
+                        break;
+                    }
+                    Err(e) => {
+                        error!("Handoff failed, resuming service: {}", e);
+                        metrics::set_ready(true);
+                    }
+                }
             }
-        }
-        _ = tokio::signal::ctrl_c() => {
         }
     }
 
+    aof::flush().await;
     Ok(())
+}
+
+// The next process taking over, never resolving when handoff is off.
+async fn next_handoff(listener: Option<&HandoffListener>) -> std::io::Result<handoff::Handoff<'_>> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn print_banner(config: &SodiumConfig, local_addr: std::net::SocketAddr) {
+    let mode = if config.cluster_enabled { "cluster" } else { "standalone" };
+    let mut persistence = Vec::new();
+    if config.aof_enabled {
+        persistence.push(format!("aof (fsync {})", config.fsync));
+    }
+    if config.snapshot_interval_secs > 0 {
+        persistence.push(format!("snapshots every {}s", config.snapshot_interval_secs));
+    }
+    let persistence = if persistence.is_empty() { "none".to_string() } else { persistence.join(", ") };
+
+    println!("Sodium {} ({} mode)", env!("CARGO_PKG_VERSION"), mode);
+    println!("  pid          {}", std::process::id());
+    println!("  listening    {}", local_addr);
+    println!("  persistence  {}", persistence);
+    println!("  workers      {}", num_cpus::get());
+}
+
+async fn shutdown_signal() {
+    tokio::select! {
+        _ = os_shutdown_signal() => {}
+        _ = SHUTDOWN_REQUESTED.notified() => {}
+    }
+}
+
+// Ctrl-C, SIGTERM from a service manager or `kill`, and a stop or shutdown
+// control from the Windows service control manager all end the server the
+// same way.
+async fn os_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = service::stop_requested() => {}
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,215 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use crate::configuration::SodiumConfig;
+use std::io;
+
+pub type ServiceEntry = fn(SodiumConfig) -> Result<(), Box<dyn std::error::Error>>;
+
+// Signalled when the service control manager asks the service to stop.
+#[cfg(windows)]
+static STOP: tokio::sync::Notify = tokio::sync::Notify::const_new();
+
+/// Resolves once the service control manager has asked the service to stop;
+/// never resolves outside service mode.
+#[cfg(windows)]
+pub async fn stop_requested() {
+    STOP.notified().await;
+}
+
+/// Services start in the system directory, so relative paths such as
+/// sodium.toml are resolved next to the executable instead.
+#[cfg(windows)]
+pub fn enter_service_directory() -> io::Result<()> {
+    let executable = std::env::current_exe()?;
+    match executable.parent() {
+        Some(directory) => std::env::set_current_dir(directory),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn enter_service_directory() -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(windows)]
+mod scm {
+    use super::{ServiceEntry, STOP};
+    use crate::configuration::SodiumConfig;
+    use std::ffi::c_void;
+    use std::io;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+    const SERVICE_STOPPED: u32 = 1;
+    const SERVICE_START_PENDING: u32 = 2;
+    const SERVICE_STOP_PENDING: u32 = 3;
+    const SERVICE_RUNNING: u32 = 4;
+    const SERVICE_ACCEPT_STOP: u32 = 0x1;
+    const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x4;
+    const SERVICE_CONTROL_STOP: u32 = 1;
+    const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+    const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+    const NO_ERROR: u32 = 0;
+    const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+    const ERROR_SERVICE_SPECIFIC_ERROR: u32 = 1066;
+    // Generous, since startup includes replaying the snapshot and AOF.
+    const START_WAIT_HINT_MS: u32 = 60_000;
+    const STOP_WAIT_HINT_MS: u32 = 10_000;
+
+    #[repr(C)]
+    struct ServiceTableEntry {
+        service_name: *mut u16,
+        service_proc: Option<unsafe extern "system" fn(u32, *mut *mut u16)>,
+    }
+
+    #[repr(C)]
+    struct ServiceStatus {
+        service_type: u32,
+        current_state: u32,
+        controls_accepted: u32,
+        win32_exit_code: u32,
+        service_specific_exit_code: u32,
+        check_point: u32,
+        wait_hint: u32,
+    }
+
+    type HandlerEx = unsafe extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32;
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn StartServiceCtrlDispatcherW(service_table: *const ServiceTableEntry) -> i32;
+        fn RegisterServiceCtrlHandlerExW(service_name: *const u16, handler: Option<HandlerEx>, context: *mut c_void) -> *mut c_void;
+        fn SetServiceStatus(status_handle: *mut c_void, status: *const ServiceStatus) -> i32;
+    }
+
+    static STATUS_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+    static PENDING_START: Mutex<Option<(SodiumConfig, ServiceEntry)>> = Mutex::new(None);
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    pub fn run(config: SodiumConfig, entry: ServiceEntry) -> io::Result<()> {
+        *PENDING_START.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((config, entry));
+
+        // The name is ignored for SERVICE_WIN32_OWN_PROCESS services.
+        let mut name = wide("sodium");
+        let table = [
+            ServiceTableEntry { service_name: name.as_mut_ptr(), service_proc: Some(service_main) },
+            ServiceTableEntry { service_name: std::ptr::null_mut(), service_proc: None },
+        ];
+
+        // SAFETY: the table is null-terminated and outlives the call, which
+        // blocks until the service has stopped.
+        if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    unsafe extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+        let name = wide("sodium");
+        // SAFETY: the name is a valid null-terminated string and the handler
+        // is a static function.
+        let handle = unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), std::ptr::null_mut()) };
+        if handle.is_null() {
+            return;
+        }
+        STATUS_HANDLE.store(handle, Ordering::Release);
+        set_status(SERVICE_START_PENDING, NO_ERROR, START_WAIT_HINT_MS);
+
+        let pending = PENDING_START.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        let exit_code = match pending {
+            Some((config, entry)) => match entry(config) {
+                Ok(()) => NO_ERROR,
+                Err(e) => {
+                    tracing::error!("Sodium service stopped with an error: {}", e);
+                    ERROR_SERVICE_SPECIFIC_ERROR
+                }
+            },
+            None => ERROR_SERVICE_SPECIFIC_ERROR,
+        };
+        set_status(SERVICE_STOPPED, exit_code, 0);
+    }
+
+    unsafe extern "system" fn control_handler(control: u32, _event_type: u32, _event_data: *mut c_void, _context: *mut c_void) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_status(SERVICE_STOP_PENDING, NO_ERROR, STOP_WAIT_HINT_MS);
+                STOP.notify_one();
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    pub fn set_status(state: u32, exit_code: u32, wait_hint: u32) {
+        let handle = STATUS_HANDLE.load(Ordering::Acquire);
+        if handle.is_null() {
+            return;
+        }
+
+        let status = ServiceStatus {
+            service_type: SERVICE_WIN32_OWN_PROCESS,
+            current_state: state,
+            controls_accepted: if state == SERVICE_RUNNING { SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN } else { 0 },
+            win32_exit_code: exit_code,
+            service_specific_exit_code: if exit_code == ERROR_SERVICE_SPECIFIC_ERROR { 1 } else { 0 },
+            check_point: 0,
+            wait_hint,
+        };
+        // SAFETY: the handle came from RegisterServiceCtrlHandlerExW and the
+        // status is a fully initialised SERVICE_STATUS.
+        unsafe {
+            SetServiceStatus(handle, &status);
+        }
+    }
+
+    pub fn report_running() {
+        set_status(SERVICE_RUNNING, NO_ERROR, 0);
+    }
+}
+
+/// Hands control to the Windows service control manager, which calls back
+/// into `entry` with `config` and translates stop and shutdown controls into
+/// the server's graceful shutdown.
+#[cfg(windows)]
+pub fn run(config: SodiumConfig, entry: ServiceEntry) -> io::Result<()> {
+    scm::run(config, entry)
+}
+
+#[cfg(not(windows))]
+pub fn run(_config: SodiumConfig, _entry: ServiceEntry) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "--service is only supported on Windows")
+}
+
+/// Tells the service control manager the server is ready; does nothing
+/// outside service mode.
+#[cfg(windows)]
+pub fn report_running() {
+    scm::report_running();
+}
+
+#[cfg(not(windows))]
+pub fn report_running() {}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_mode_is_refused_off_windows() {
+        let error = run(SodiumConfig::default(), |_| Ok(())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(enter_service_directory().unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,385 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use crate::aof;
+use crate::checksum;
+use crate::encryption::{self, EncryptionError};
+use crate::background::{Job, Throttled};
+use crate::configuration::SodiumConfig;
+use crate::core::{get_cache, key_namespace, ScheduledEntry, SnapshotEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::{error, info, warn};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Snapshot IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Snapshot encoding error: {0}")]
+    Encode(#[from] serde_json::Error),
+    #[error("Snapshot encryption error: {0}")]
+    Encrypt(#[from] EncryptionError),
+    #[error("Corrupt snapshot {path}: {reason}")]
+    Corrupt { path: String, reason: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SnapshotKind {
+    Full,
+    Delta,
+}
+
+/// First line of every snapshot file. Deltas name the full snapshot they
+/// were taken against, so a delta left over from an older chain is never
+/// applied on top of a newer full snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotHeader {
+    kind: SnapshotKind,
+    base: u64,
+    // Last AOF record whose effect the file is guaranteed to include;
+    // recovery replays the log from the record after it.
+    #[serde(default)]
+    aof_seq: u64,
+    generations: BTreeMap<String, u64>,
+}
+
+/// What verify() found in a snapshot file.
+#[derive(Debug, Clone)]
+pub struct SnapshotCheck {
+    pub kind: &'static str,
+    pub entries: usize,
+    // Delta lines recording that a key was removed.
+    pub removals: usize,
+    pub aof_seq: u64,
+}
+
+/// What loading the snapshot chain restored.
+#[derive(Debug, Clone)]
+pub struct SnapshotLoad {
+    pub keys: usize,
+    pub deltas: u32,
+    pub aof_seq: u64,
+}
+
+// One key per line; a missing entry records that the key was removed. The
+// write scheduled for the key, if any, rides along on the same line.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotLine {
+    key: String,
+    entry: Option<SnapshotEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scheduled: Option<ScheduledEntry>,
+}
+
+struct Snapshotter {
+    path: String,
+    full_every: u32,
+    // Id of the current full snapshot, 0 until one has been written.
+    base: u64,
+    deltas: u32,
+    // Set when a delta failed after taking the dirty keys, which only a full
+    // snapshot captures again.
+    needs_full: bool,
+    // Namespace generations as of the last file written.
+    generations: BTreeMap<String, u64>,
+    // Write rate cap, 0 for none.
+    io_bytes_per_sec: u64,
+}
+
+impl Snapshotter {
+    // Full snapshots are written on the first run and after every
+    // `full_every - 1` deltas; in between only dirty keys are written.
+    fn run(&mut self) -> Result<(), SnapshotError> {
+        let started = Instant::now();
+        if self.base == 0 || self.needs_full || self.deltas + 1 >= self.full_every {
+            let written = self.write_full()?;
+            info!("Wrote full snapshot of {} keys to {} in {:?}", written, self.path, started.elapsed());
+        } else if let Some(written) = self.write_delta()? {
+            info!("Wrote snapshot delta of {} keys to {} in {:?}", written, delta_path(&self.path, self.deltas), started.elapsed());
+        }
+        Ok(())
+    }
+
+    fn write_full(&mut self) -> Result<usize, SnapshotError> {
+        let cache = get_cache();
+        // Cleared first: a key changed while the snapshot is written is
+        // marked again and lands in the next delta.
+        cache.clear_dirty_keys();
+
+        let base = now_micros().max(self.base + 1);
+        let aof_seq = aof::last_seq();
+        let generations = cache.generations();
+        let written = write_file(&self.path, SnapshotKind::Full, base, aof_seq, &generations, cache.stored_keys(), true, self.io_bytes_per_sec)?;
+
+        for index in 1..=self.deltas {
+            let _ = fs::remove_file(delta_path(&self.path, index));
+        }
+        self.base = base;
+        self.deltas = 0;
+        self.needs_full = false;
+        self.generations = generations;
+        Ok(written)
+    }
+
+    // Returns None without writing anything when nothing changed.
+    fn write_delta(&mut self) -> Result<Option<usize>, SnapshotError> {
+        let cache = get_cache();
+        let aof_seq = aof::last_seq();
+        let keys = cache.take_dirty_keys();
+        let generations = cache.generations();
+        if keys.is_empty() && generations == self.generations {
+            return Ok(None);
+        }
+
+        let index = self.deltas + 1;
+        let written = write_file(&delta_path(&self.path, index), SnapshotKind::Delta, self.base, aof_seq, &generations, keys, false, self.io_bytes_per_sec)
+            .inspect_err(|_| self.needs_full = true)?;
+        self.deltas = index;
+        self.generations = generations;
+        Ok(Some(written))
+    }
+}
+
+fn delta_path(path: &str, index: u32) -> String {
+    format!("{}.{}", path, index)
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64
+}
+
+// Written to a temporary file and renamed into place, so a crash mid-write
+// leaves the previous file intact.
+#[allow(clippy::too_many_arguments)]
+fn write_file(
+    path: &str,
+    kind: SnapshotKind,
+    base: u64,
+    aof_seq: u64,
+    generations: &BTreeMap<String, u64>,
+    keys: Vec<String>,
+    live_only: bool,
+    io_bytes_per_sec: u64,
+) -> Result<usize, SnapshotError> {
+    let temporary = format!("{}.tmp", path);
+    let mut writer = BufWriter::new(Throttled::new(File::create(&temporary)?, io_bytes_per_sec));
+    let header = SnapshotHeader { kind, base, aof_seq, generations: generations.clone() };
+    let written = write_records(&mut writer, &header, keys, live_only)?;
+
+    let file = writer.into_inner().map_err(|e| e.into_error())?.into_inner();
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(written)
+}
+
+// `header.aof_seq` must be read before any entry, so every logged change up
+// to it is already visible to the reads below.
+fn write_records(writer: &mut impl Write, header: &SnapshotHeader, keys: Vec<String>, live_only: bool) -> Result<usize, SnapshotError> {
+    let cache = get_cache();
+    write_line(writer, header, None)?;
+
+    let mut written = 0;
+    for key in keys {
+        let entry = cache.snapshot_entry(&key);
+        let scheduled = cache.scheduled_entry(&key);
+        if live_only && entry.is_none() && scheduled.is_none() {
+            continue;
+        }
+        let line = SnapshotLine { key, entry, scheduled };
+        write_line(writer, &line, key_namespace(&line.key))?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+// `namespace` picks the encryption key; the header belongs to none.
+fn write_line(writer: &mut impl Write, value: &impl Serialize, namespace: Option<&str>) -> Result<(), SnapshotError> {
+    let mut line = serde_json::to_vec(value)?;
+    encryption::encrypt(&mut line, namespace)?;
+    checksum::seal(&mut line);
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    Ok(())
+}
+
+/// Background job writing a snapshot every `snapshot_interval_secs`, or
+/// None when snapshots are disabled.
+pub fn snapshot_job(config: &SodiumConfig) -> Option<Job> {
+    if config.snapshot_interval_secs == 0 {
+        return None;
+    }
+
+    let mut snapshotter = Snapshotter {
+        path: config.snapshot_path.clone(),
+        full_every: config.snapshot_full_every.max(1),
+        base: 0,
+        deltas: 0,
+        needs_full: false,
+        generations: BTreeMap::new(),
+        io_bytes_per_sec: config.background_io_bytes_per_sec,
+    };
+    Some(Job::new("snapshot", Duration::from_secs(config.snapshot_interval_secs), move || {
+        if let Err(e) = snapshotter.run() {
+            error!("Failed to write snapshot: {}", e);
+        }
+    }))
+}
+
+/// Writes a full snapshot right away, outside the periodic schedule.
+pub fn write_full_snapshot(config: &SodiumConfig) -> Result<usize, SnapshotError> {
+    let mut snapshotter = Snapshotter {
+        path: config.snapshot_path.clone(),
+        full_every: 1,
+        base: 0,
+        deltas: 0,
+        needs_full: false,
+        generations: BTreeMap::new(),
+        io_bytes_per_sec: 0,
+    };
+    snapshotter.write_full()
+}
+
+/// Writes a full snapshot of the cache to `writer` instead of a file, for
+/// handing the keyspace to another process.
+#[cfg(unix)]
+pub fn write_stream(writer: &mut impl Write) -> Result<usize, SnapshotError> {
+    let cache = get_cache();
+    let header = SnapshotHeader { kind: SnapshotKind::Full, base: now_micros(), aof_seq: aof::last_seq(), generations: cache.generations() };
+    write_records(writer, &header, cache.stored_keys(), true)
+}
+
+/// Loads a full snapshot written by write_stream. `source` names the stream
+/// in errors.
+#[cfg(unix)]
+pub async fn load_stream(reader: impl BufRead, source: &str) -> Result<SnapshotLoad, SnapshotError> {
+    let loaded = load_records(reader, source, SnapshotKind::Full, None).await?;
+    Ok(SnapshotLoad { keys: loaded.keys, deltas: 0, aof_seq: loaded.aof_seq })
+}
+
+/// Loads the full snapshot and the deltas taken against it, in order.
+/// Returns None when there is no snapshot to load.
+pub async fn load_snapshot(config: &SodiumConfig) -> Result<Option<SnapshotLoad>, SnapshotError> {
+    if config.snapshot_interval_secs == 0 || !Path::new(&config.snapshot_path).exists() {
+        return Ok(None);
+    }
+
+    let full = load_file(&config.snapshot_path, SnapshotKind::Full, None).await?;
+    let mut load = SnapshotLoad { keys: full.keys, deltas: 0, aof_seq: full.aof_seq };
+    let mut index = 1;
+    while Path::new(&delta_path(&config.snapshot_path, index)).exists() {
+        let path = delta_path(&config.snapshot_path, index);
+        match load_file(&path, SnapshotKind::Delta, Some(full.base)).await {
+            Ok(delta) => {
+                load.keys += delta.keys;
+                load.deltas += 1;
+                load.aof_seq = delta.aof_seq;
+            }
+            Err(e) => {
+                // Later deltas build on this one, so they cannot be used either.
+                warn!("Stopping snapshot load at {}: {}", path, e);
+                break;
+            }
+        }
+        index += 1;
+    }
+
+    Ok(Some(load))
+}
+
+struct LoadedFile {
+    base: u64,
+    aof_seq: u64,
+    keys: usize,
+}
+
+async fn load_file(path: &str, kind: SnapshotKind, base: Option<u64>) -> Result<LoadedFile, SnapshotError> {
+    load_records(BufReader::new(File::open(path)?), path, kind, base).await
+}
+
+/// Reads a snapshot file, full or delta, without loading it, checking that
+/// the header and every line decode and match their checksums.
+pub fn verify(path: &str) -> Result<SnapshotCheck, SnapshotError> {
+    let (header, records) = read_records(BufReader::new(File::open(path)?), path)?;
+    let removals = records.iter().filter(|record| record.entry.is_none() && record.scheduled.is_none()).count();
+    Ok(SnapshotCheck {
+        kind: match header.kind {
+            SnapshotKind::Full => "full",
+            SnapshotKind::Delta => "delta",
+        },
+        entries: records.len() - removals,
+        removals,
+        aof_seq: header.aof_seq,
+    })
+}
+
+/// Sequence number of the last AOF record the full snapshot at `path`
+/// holds, read from its header alone.
+pub fn covered_seq(path: &str) -> Result<u64, SnapshotError> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = read_header(lines.next(), path)?;
+    Ok(header.aof_seq)
+}
+
+async fn load_records(reader: impl BufRead, source: &str, kind: SnapshotKind, base: Option<u64>) -> Result<LoadedFile, SnapshotError> {
+    let corrupt = |reason: String| SnapshotError::Corrupt { path: source.to_string(), reason };
+    // Parsed in full before anything is applied, so a bad line cannot leave
+    // a half-applied delta behind.
+    let (header, records) = read_records(reader, source)?;
+    if header.kind != kind {
+        return Err(corrupt(format!("expected a {:?} snapshot", kind)));
+    }
+    if base.is_some_and(|base| base != header.base) {
+        return Err(corrupt("taken against a different full snapshot".to_string()));
+    }
+
+    let cache = get_cache();
+    cache.restore_generations(header.generations);
+    let keys = records.len();
+    for record in records {
+        match record.entry {
+            Some(entry) => cache.restore_entry(record.key.clone(), entry),
+            None => {
+                let _ = cache.delete(&record.key).await;
+            }
+        }
+        cache.restore_scheduled(record.key, record.scheduled);
+    }
+    Ok(LoadedFile { base: header.base, aof_seq: header.aof_seq, keys })
+}
+
+fn read_records(reader: impl BufRead, source: &str) -> Result<(SnapshotHeader, Vec<SnapshotLine>), SnapshotError> {
+    let corrupt = |line: usize, reason: String| SnapshotError::Corrupt { path: source.to_string(), reason: format!("line {}: {}", line, reason) };
+    let mut lines = reader.lines();
+    let header = read_header(lines.next(), source)?;
+
+    let mut records = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let document = checksum::unseal(&line).map_err(|reason| corrupt(index + 2, reason))?;
+        let document = encryption::decrypt(document).map_err(|e| corrupt(index + 2, e.to_string()))?;
+        let record: SnapshotLine = serde_json::from_str(&document).map_err(|e| corrupt(index + 2, e.to_string()))?;
+        records.push(record);
+    }
+    Ok((header, records))
+}
+
+fn read_header(line: Option<std::io::Result<String>>, source: &str) -> Result<SnapshotHeader, SnapshotError> {
+    let corrupt = |reason: String| SnapshotError::Corrupt { path: source.to_string(), reason: format!("line 1: {}", reason) };
+    let header = line.ok_or_else(|| corrupt("missing header".to_string()))??;
+    let header = checksum::unseal(&header).map_err(corrupt)?;
+    let header = encryption::decrypt(header).map_err(|e| corrupt(e.to_string()))?;
+    serde_json::from_str(&header).map_err(|e| corrupt(e.to_string()))
+}
@@ -0,0 +1,127 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+use std::io;
+
+#[cfg(unix)]
+use tracing::warn;
+
+// First descriptor systemd passes to socket-activated services.
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes the listening socket handed over by systemd socket activation, or
+/// None when the server was not started that way. The activation variables
+/// are removed so they are not inherited by anything spawned later.
+///
+/// Must run before any other thread is started.
+#[cfg(unix)]
+pub fn take_listener() -> io::Result<Option<std::net::TcpListener>> {
+    use std::os::fd::FromRawFd;
+
+    let count = passed_sockets(std::env::var("LISTEN_PID").ok().as_deref(), std::env::var("LISTEN_FDS").ok().as_deref());
+
+    // SAFETY: the process is still single-threaded, so nothing can be
+    // reading the environment concurrently.
+    unsafe {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+
+    if count < 1 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!("systemd passed {} sockets, only the first is used", count);
+    }
+
+    // SAFETY: with LISTEN_PID matching this process, systemd guarantees the
+    // descriptor is an open socket owned by us and not used elsewhere.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+// Sockets systemd passed to this process, given LISTEN_PID and LISTEN_FDS;
+// 0 when they were meant for another one.
+#[cfg(unix)]
+fn passed_sockets(listen_pid: Option<&str>, listen_fds: Option<&str>) -> i32 {
+    let for_us = listen_pid
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    if !for_us {
+        return 0;
+    }
+    listen_fds.and_then(|count| count.parse::<i32>().ok()).unwrap_or(0)
+}
+
+#[cfg(not(unix))]
+pub fn take_listener() -> io::Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
+
+/// Sends a state update such as "READY=1" to systemd. Does nothing when the
+/// server is not running under a Type=notify unit.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_state(&path, state) {
+        warn!("Failed to notify systemd of {}: {}", state, e);
+    }
+}
+
+// Sends `state` to the socket NOTIFY_SOCKET names, `@` marking an abstract
+// one.
+#[cfg(unix)]
+fn send_state(path: &str, state: &str) -> io::Result<usize> {
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let address = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name)?
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets need Linux")),
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    // The environment is read by the callers of these, never here: tests
+    // run in parallel, and changing it while another thread reads it is
+    // undefined behaviour.
+    #[test]
+    fn notify_sends_state_to_notify_socket() {
+        let path = std::env::temp_dir().join(format!("sodium-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        send_state(path.to_str().unwrap(), "READY=1").unwrap();
+
+        let mut buffer = [0u8; 64];
+        let read = socket.recv(&mut buffer).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(&buffer[..read], b"READY=1");
+    }
+
+    #[test]
+    fn sockets_passed_to_another_process_are_not_taken() {
+        let other = (std::process::id() + 1).to_string();
+        assert_eq!(passed_sockets(Some(&other), Some("1")), 0);
+        assert_eq!(passed_sockets(None, Some("1")), 0);
+        assert_eq!(passed_sockets(Some(&std::process::id().to_string()), Some("1")), 1);
+    }
+}
@@ -1,69 +1,437 @@
 // Copyright (c) 2025, TheByteSlayer, Sodium
 // A scalable and optimized Key Value Caching System, written in Rust.
 
-use std::sync::{Arc, Mutex, OnceLock};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::collections::VecDeque;
+use std::net::SocketAddr;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
+use tracing::error;
+
+use crate::core::SetOptions;
 
 pub type TaskResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+// How many queued tasks past the one being taken are checked for sets of the
+// same key, bounding the scan done under the queue lock.
+const COALESCE_WINDOW: usize = 64;
+
+/// Returned instead of queueing when every work queue is full.
+#[derive(Debug, thiserror::Error)]
+#[error("Work queues are saturated, retry after {retry_after_ms}ms")]
+pub struct BusyError {
+    pub retry_after_ms: u64,
+}
+
+#[allow(clippy::enum_variant_names)]
 pub enum Task {
     CacheGet {
         key: String,
-        sender: oneshot::Sender<TaskResult<Option<String>>>,
+        sender: oneshot::Sender<TaskResult<Option<Arc<str>>>>,
+    },
+    CacheGetEarly {
+        key: String,
+        recompute: Duration,
+        sender: oneshot::Sender<TaskResult<Option<Arc<str>>>>,
     },
     CacheSet {
         key: String,
         value: String,
+        options: crate::core::SetOptions,
+        sender: oneshot::Sender<TaskResult<()>>,
+        // Queued sets of the same key this one overwrote before they ran,
+        // newest first; they get this set's outcome.
+        coalesced: Vec<Superseded>,
+    },
+    CacheGetOrSet {
+        key: String,
+        value: String,
+        options: crate::core::SetOptions,
+        sender: oneshot::Sender<TaskResult<Arc<str>>>,
+    },
+    CacheGetSet {
+        key: String,
+        value: String,
+        writer: Option<std::net::SocketAddr>,
+        sender: oneshot::Sender<TaskResult<Option<Arc<str>>>>,
+    },
+    CacheGetDel {
+        key: String,
+        sender: oneshot::Sender<TaskResult<Option<Arc<str>>>>,
+    },
+    CacheMGet {
+        keys: Vec<String>,
+        sender: oneshot::Sender<TaskResult<Vec<Option<Arc<str>>>>>,
+    },
+    CacheMSet {
+        pairs: Vec<(String, String)>,
+        writer: Option<std::net::SocketAddr>,
+        sender: oneshot::Sender<TaskResult<()>>,
+    },
+    CacheIncrBy {
+        key: String,
+        delta: i64,
+        writer: Option<std::net::SocketAddr>,
+        sender: oneshot::Sender<TaskResult<i64>>,
+    },
+    CacheSetBit {
+        key: String,
+        offset: u64,
+        bit: bool,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheGetBit {
+        key: String,
+        offset: u64,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheBitCount {
+        key: String,
+        sender: oneshot::Sender<TaskResult<u64>>,
+    },
+    CacheStreamAdd {
+        key: String,
+        value: String,
+        max_len: u64,
+        sender: oneshot::Sender<TaskResult<u64>>,
+    },
+    CacheBloomAdd {
+        key: String,
+        item: String,
+        capacity: u64,
+        error_rate: f64,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheBloomExists {
+        key: String,
+        item: String,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheCuckooAdd {
+        key: String,
+        item: String,
+        capacity: u64,
         sender: oneshot::Sender<TaskResult<()>>,
     },
+    CacheCuckooExists {
+        key: String,
+        item: String,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheCuckooDelete {
+        key: String,
+        item: String,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheTopKAdd {
+        key: String,
+        item: String,
+        k: usize,
+        sender: oneshot::Sender<TaskResult<u64>>,
+    },
+    CacheTopKQuery {
+        key: String,
+        item: String,
+        sender: oneshot::Sender<TaskResult<u64>>,
+    },
+    CacheTopKList {
+        key: String,
+        sender: oneshot::Sender<TaskResult<Vec<(String, u64)>>>,
+    },
+    CacheStreamRange {
+        key: String,
+        start: u64,
+        end: u64,
+        sender: oneshot::Sender<TaskResult<Vec<crate::core::StreamEntry>>>,
+    },
+    CacheMetadata {
+        key: String,
+        sender: oneshot::Sender<TaskResult<Option<crate::core::Metadata>>>,
+    },
     CacheDelete {
         key: String,
         sender: oneshot::Sender<TaskResult<bool>>,
     },
+    CacheHistory {
+        key: String,
+        sender: oneshot::Sender<TaskResult<Vec<crate::core::Version>>>,
+    },
+    CacheGetVersion {
+        key: String,
+        n: usize,
+        sender: oneshot::Sender<TaskResult<Option<Arc<str>>>>,
+    },
+    CacheUndelete {
+        key: String,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheKeyMemory {
+        key: String,
+        sender: oneshot::Sender<TaskResult<u64>>,
+    },
+    CacheObjectInfo {
+        key: String,
+        sender: oneshot::Sender<TaskResult<crate::core::ObjectInfo>>,
+    },
+    CacheSetAccessTime {
+        key: String,
+        age: Duration,
+        sender: oneshot::Sender<TaskResult<()>>,
+    },
     CacheKeys {
+        sort: Option<crate::core::SortOrder>,
+        sender: oneshot::Sender<TaskResult<Vec<String>>>,
+    },
+    CacheTag {
+        key: String,
+        tag: String,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheExpire {
+        key: String,
+        ttl: Duration,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheTtl {
+        key: String,
+        sender: oneshot::Sender<TaskResult<Option<Option<Duration>>>>,
+    },
+    CacheKeysByTag {
+        tag: String,
         sender: oneshot::Sender<TaskResult<Vec<String>>>,
     },
+    CacheDeleteByTag {
+        tag: String,
+        namespace: Option<String>,
+        sender: oneshot::Sender<TaskResult<u64>>,
+    },
+    CacheInvalidate {
+        namespace: String,
+        sender: oneshot::Sender<TaskResult<u64>>,
+    },
+    CacheLock {
+        key: String,
+        ttl: Duration,
+        sender: oneshot::Sender<TaskResult<Option<u64>>>,
+    },
+    CacheUnlock {
+        key: String,
+        token: u64,
+        sender: oneshot::Sender<TaskResult<bool>>,
+    },
+    CacheScan {
+        cursor: crate::core::ScanCursor,
+        count: usize,
+        sender: oneshot::Sender<TaskResult<crate::core::ScanPage>>,
+    },
+    CacheStats {
+        sender: oneshot::Sender<TaskResult<crate::core::CacheStats>>,
+    },
+    CachePrefixStats {
+        namespace: String,
+        sender: oneshot::Sender<TaskResult<Option<crate::core::PrefixStats>>>,
+    },
+    CacheMemoryDoctor {
+        sender: oneshot::Sender<TaskResult<crate::core::MemoryReport>>,
+    },
+    CacheBigKeys {
+        count: usize,
+        sender: oneshot::Sender<TaskResult<crate::core::BigKeysReport>>,
+    },
 
     CacheSearchMultiple {
         search_type: crate::search::SearchType,
         queries: Vec<String>,
-        sender: oneshot::Sender<TaskResult<Vec<String>>>,
+        sort: Option<crate::core::SortOrder>,
+        deadline: Option<Instant>,
+        cancelled: Arc<AtomicBool>,
+        sender: oneshot::Sender<TaskResult<crate::search::SearchResult>>,
     },
+    Plugin {
+        name: String,
+        args: Vec<String>,
+        sender: oneshot::Sender<TaskResult<crate::protocol::Reply>>,
+    },
+    // A background expiry sweep pass, run here in deterministic mode.
+    ExpirySweep {
+        shard: usize,
+        sender: oneshot::Sender<usize>,
+    },
+}
+
+impl Task {
+    // A read whose caller stopped waiting, on disconnect or timeout, has
+    // nobody to reply to. Writes still run: their client may have given up,
+    // but the command was received.
+    fn is_abandoned(&self) -> bool {
+        match self {
+            Task::CacheGet { sender, .. } | Task::CacheGetEarly { sender, .. } => sender.is_closed(),
+            Task::CacheMGet { sender, .. } => sender.is_closed(),
+            Task::CacheGetBit { sender, .. } => sender.is_closed(),
+            Task::CacheBitCount { sender, .. } => sender.is_closed(),
+            Task::CacheStreamRange { sender, .. } => sender.is_closed(),
+            Task::CacheBloomExists { sender, .. } => sender.is_closed(),
+            Task::CacheTopKQuery { sender, .. } => sender.is_closed(),
+            Task::CacheCuckooExists { sender, .. } => sender.is_closed(),
+            Task::CacheTopKList { sender, .. } => sender.is_closed(),
+            Task::CacheMetadata { sender, .. } => sender.is_closed(),
+            Task::CacheObjectInfo { sender, .. } => sender.is_closed(),
+            Task::CacheKeyMemory { sender, .. } => sender.is_closed(),
+            Task::CacheTtl { sender, .. } => sender.is_closed(),
+            Task::CacheHistory { sender, .. } => sender.is_closed(),
+            Task::CacheGetVersion { sender, .. } => sender.is_closed(),
+            Task::CacheKeys { sender, .. } | Task::CacheKeysByTag { sender, .. } => sender.is_closed(),
+            Task::CacheScan { sender, .. } => sender.is_closed(),
+            Task::CacheStats { sender } => sender.is_closed(),
+            Task::CachePrefixStats { sender, .. } => sender.is_closed(),
+            Task::CacheMemoryDoctor { sender } => sender.is_closed(),
+            Task::CacheBigKeys { sender, .. } => sender.is_closed(),
+            Task::CacheSearchMultiple { sender, .. } => sender.is_closed(),
+            Task::Plugin { name, sender, .. } => {
+                sender.is_closed() && !crate::plugins::find(name).is_some_and(|plugin| plugin.is_mutating())
+            }
+            Task::CacheSet { .. }
+            | Task::CacheGetOrSet { .. }
+            | Task::CacheGetSet { .. }
+            | Task::CacheGetDel { .. }
+            | Task::CacheMSet { .. }
+            | Task::CacheIncrBy { .. }
+            | Task::CacheSetBit { .. }
+            | Task::CacheStreamAdd { .. }
+            | Task::CacheBloomAdd { .. }
+            | Task::CacheTopKAdd { .. }
+            | Task::CacheCuckooAdd { .. }
+            | Task::CacheCuckooDelete { .. }
+            | Task::CacheDelete { .. }
+            | Task::CacheUndelete { .. }
+            | Task::CacheSetAccessTime { .. }
+            | Task::CacheTag { .. }
+            | Task::CacheExpire { .. }
+            | Task::CacheDeleteByTag { .. }
+            | Task::CacheInvalidate { .. }
+            | Task::CacheLock { .. }
+            | Task::CacheUnlock { .. }
+            | Task::ExpirySweep { .. } => false,
+        }
+    }
+
+    // The command a task runs, with its key or tag when it has one, for logs.
+    fn describe(&self) -> String {
+        let (name, subject) = match self {
+            Task::CacheGet { key, .. } => ("get", Some(key)),
+            Task::CacheGetEarly { key, .. } => ("get", Some(key)),
+            Task::CacheSet { key, .. } => ("set", Some(key)),
+            Task::CacheGetOrSet { key, .. } => ("getorset", Some(key)),
+            Task::CacheGetSet { key, .. } => ("getset", Some(key)),
+            Task::CacheGetDel { key, .. } => ("getdel", Some(key)),
+            Task::CacheMGet { keys, .. } => ("mget", keys.first()),
+            Task::CacheMSet { pairs, .. } => ("mset", pairs.first().map(|(key, _)| key)),
+            Task::CacheIncrBy { key, .. } => ("incrby", Some(key)),
+            Task::CacheSetBit { key, .. } => ("setbit", Some(key)),
+            Task::CacheGetBit { key, .. } => ("getbit", Some(key)),
+            Task::CacheBitCount { key, .. } => ("bitcount", Some(key)),
+            Task::CacheStreamAdd { key, .. } => ("xadd", Some(key)),
+            Task::CacheBloomAdd { key, .. } => ("bfadd", Some(key)),
+            Task::CacheBloomExists { key, .. } => ("bfexists", Some(key)),
+            Task::CacheTopKAdd { key, .. } => ("topk_add", Some(key)),
+            Task::CacheCuckooAdd { key, .. } => ("cfadd", Some(key)),
+            Task::CacheCuckooExists { key, .. } => ("cfexists", Some(key)),
+            Task::CacheCuckooDelete { key, .. } => ("cfdel", Some(key)),
+            Task::CacheTopKQuery { key, .. } => ("topk_query", Some(key)),
+            Task::CacheTopKList { key, .. } => ("topk_list", Some(key)),
+            Task::CacheStreamRange { key, .. } => ("xrange", Some(key)),
+            Task::CacheMetadata { key, .. } => ("meta", Some(key)),
+            Task::CacheDelete { key, .. } => ("delete", Some(key)),
+            Task::CacheUndelete { key, .. } => ("undelete", Some(key)),
+            Task::CacheHistory { key, .. } => ("history", Some(key)),
+            Task::CacheGetVersion { key, .. } => ("getversion", Some(key)),
+            Task::CacheObjectInfo { key, .. } => ("debug object", Some(key)),
+            Task::CacheKeyMemory { key, .. } => ("memory", Some(key)),
+            Task::CacheSetAccessTime { key, .. } => ("debug set-access-time", Some(key)),
+            Task::CacheKeys { .. } => ("keys", None),
+            Task::CacheTag { key, .. } => ("tag", Some(key)),
+            Task::CacheExpire { key, .. } => ("expire", Some(key)),
+            Task::CacheTtl { key, .. } => ("ttl", Some(key)),
+            Task::CacheKeysByTag { tag, .. } => ("keysbytag", Some(tag)),
+            Task::CacheDeleteByTag { tag, .. } => ("deletebytag", Some(tag)),
+            Task::CacheInvalidate { namespace, .. } => ("invalidate", Some(namespace)),
+            Task::CacheLock { key, .. } => ("lock", Some(key)),
+            Task::CacheUnlock { key, .. } => ("unlock", Some(key)),
+            Task::CacheScan { .. } => ("scan", None),
+            Task::CacheStats { .. } => ("stats", None),
+            Task::CachePrefixStats { namespace, .. } => ("stats prefix", Some(namespace)),
+            Task::CacheMemoryDoctor { .. } => ("memory", None),
+            Task::CacheBigKeys { .. } => ("bigkeys", None),
+            Task::CacheSearchMultiple { .. } => ("search", None),
+            Task::Plugin { name, .. } => (name.as_str(), None),
+            Task::ExpirySweep { .. } => ("expiry sweep", None),
+        };
+        match subject {
+            Some(subject) => format!("{}({})", name, subject),
+            None => name.to_string(),
+        }
+    }
 }
 
 struct WorkQueue {
-    queue: Mutex<VecDeque<Task>>,
+    // Each task with the time it was queued.
+    queue: Mutex<VecDeque<(Instant, Task)>>,
     is_shutdown: AtomicBool,
+    capacity: usize,
+    // Mirrors the queue length so gauges can read it without the lock.
+    depth: AtomicUsize,
+    coalesced: AtomicU64,
+    // Time tasks spent queued before a worker took them: a total, a count
+    // and a moving average like the pool's task run time.
+    waited_micros: AtomicU64,
+    dequeued: AtomicU64,
+    average_wait_micros: AtomicU64,
 }
 
 impl WorkQueue {
-    fn new() -> Self {
+    fn new(capacity: usize) -> Self {
         Self {
             queue: Mutex::new(VecDeque::new()),
             is_shutdown: AtomicBool::new(false),
+            capacity,
+            depth: AtomicUsize::new(0),
+            coalesced: AtomicU64::new(0),
+            waited_micros: AtomicU64::new(0),
+            dequeued: AtomicU64::new(0),
+            average_wait_micros: AtomicU64::new(0),
         }
     }
 
-    fn push(&self, task: Task) -> bool {
+    // Hands the task back when the queue is shut down or full so the caller
+    // can try another queue. Waits out a worker holding the lock rather than
+    // counting a momentarily contended queue as full.
+    #[allow(clippy::result_large_err)]
+    fn push(&self, task: Task) -> Result<(), Task> {
         if self.is_shutdown.load(Ordering::Relaxed) {
-            return false;
+            return Err(task);
         }
-        
-        if let Ok(mut queue) = self.queue.try_lock() {
-            queue.push_back(task);
-            true
-        } else {
-            false
+
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        if queue.len() >= self.capacity {
+            return Err(task);
         }
+        queue.push_back((Instant::now(), task));
+        self.depth.store(queue.len(), Ordering::Relaxed);
+        Ok(())
     }
 
     fn pop(&self) -> Option<Task> {
         if let Ok(mut queue) = self.queue.try_lock() {
-            queue.pop_front()
+            let task = queue.pop_front().map(|(queued_at, task)| {
+                self.record_wait(queued_at);
+                self.coalesce(&mut queue, task, true)
+            });
+            self.depth.store(queue.len(), Ordering::Relaxed);
+            task
         } else {
             None
         }
@@ -71,42 +439,130 @@ impl WorkQueue {
 
     fn steal(&self) -> Option<Task> {
         if let Ok(mut queue) = self.queue.try_lock() {
-            queue.pop_back()
+            let task = queue.pop_back().map(|(queued_at, task)| {
+                self.record_wait(queued_at);
+                self.coalesce(&mut queue, task, false)
+            });
+            self.depth.store(queue.len(), Ordering::Relaxed);
+            task
         } else {
             None
         }
     }
 
+    fn record_wait(&self, queued_at: Instant) {
+        let sample = queued_at.elapsed().as_micros() as u64;
+        self.waited_micros.fetch_add(sample, Ordering::Relaxed);
+        self.dequeued.fetch_add(1, Ordering::Relaxed);
+        let average = self.average_wait_micros.load(Ordering::Relaxed);
+        self.average_wait_micros.store(average - average / 8 + sample / 8, Ordering::Relaxed);
+    }
+
+    // Folds queued sets of the same key as `task` into a single write: the
+    // most recently queued value wins and the others only get its reply.
+    // Every queued task is in flight concurrently, so any of them may take
+    // effect first. `from_front` says which end of the queue `task` came from
+    // and so which way is newer. Only plain sets with identical options,
+    // apart from the writer, are folded: a TTL or a delayed write makes the
+    // superseded value matter.
+    fn coalesce(&self, queue: &mut VecDeque<(Instant, Task)>, task: Task, from_front: bool) -> Task {
+        let Task::CacheSet { key, options, .. } = &task else {
+            return task;
+        };
+        if options.ttl.is_some() || options.visible_at.is_some() {
+            return task;
+        }
+
+        let window = queue.len().min(COALESCE_WINDOW);
+        let range = if from_front { 0..window } else { queue.len() - window..queue.len() };
+        let matches: Vec<usize> = range
+            .filter(|&index| {
+                matches!(&queue[index].1, Task::CacheSet { key: queued, options: queued_options, .. }
+                    if queued == key && SetOptions { writer: options.writer, ..queued_options.clone() } == *options)
+            })
+            .collect();
+        if matches.is_empty() {
+            return task;
+        }
+
+        // Removing back to front keeps the remaining indexes valid, and
+        // yields the queued sets newest first.
+        let mut queued: Vec<Task> = matches.iter().rev().filter_map(|&index| queue.remove(index)).map(|(_, task)| task).collect();
+        self.coalesced.fetch_add(queued.len() as u64, Ordering::Relaxed);
+        let (mut winner, superseded) = if from_front {
+            let newest = queued.remove(0);
+            queued.push(task);
+            (newest, queued)
+        } else {
+            (task, queued)
+        };
+
+        if let Task::CacheSet { coalesced, .. } = &mut winner {
+            for task in superseded {
+                if let Task::CacheSet { value, options, sender, coalesced: earlier, .. } = task {
+                    coalesced.push(Superseded { value, writer: options.writer, sender });
+                    coalesced.extend(earlier);
+                }
+            }
+        }
+        winner
+    }
+
     fn shutdown(&self) {
         self.is_shutdown.store(true, Ordering::Relaxed);
     }
 }
 
+// A queued set folded into a later one. Its value and writer are kept for
+// the key's version history.
+pub struct Superseded {
+    value: String,
+    writer: Option<SocketAddr>,
+    sender: oneshot::Sender<TaskResult<()>>,
+}
+
 pub struct ThreadPool {
     workers: Vec<thread::JoinHandle<()>>,
     queues: Vec<Arc<WorkQueue>>,
     next_queue: AtomicUsize,
     shutdown: Arc<AtomicBool>,
+    rejected: AtomicU64,
+    // Moving average of task run time, used to size retry-after hints.
+    average_task_micros: Arc<AtomicU64>,
+    panics: Arc<AtomicU64>,
+    abandoned: Arc<AtomicU64>,
 }
 
 impl ThreadPool {
-    pub fn new() -> Self {
-        let num_threads = num_cpus::get();
+    pub fn new(queue_capacity: usize, num_threads: usize) -> Self {
         let mut workers = Vec::with_capacity(num_threads);
         let mut queues = Vec::with_capacity(num_threads);
         let shutdown = Arc::new(AtomicBool::new(false));
+        let average_task_micros = Arc::new(AtomicU64::new(0));
+        let panics = Arc::new(AtomicU64::new(0));
+        let abandoned = Arc::new(AtomicU64::new(0));
 
         for _ in 0..num_threads {
-            queues.push(Arc::new(WorkQueue::new()));
+            queues.push(Arc::new(WorkQueue::new(queue_capacity)));
         }
 
         for i in 0..num_threads {
             let worker_queues = queues.clone();
             let worker_shutdown = shutdown.clone();
+            let worker_average = average_task_micros.clone();
+            let worker_panics = panics.clone();
+            let worker_abandoned = abandoned.clone();
             let worker_id = i;
 
+            // Panics in a task are caught around the task; this restarts
+            // the loop itself should anything else in it panic.
             let handle = thread::spawn(move || {
-                Self::worker_loop(worker_id, worker_queues, worker_shutdown);
+                while let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| {
+                    Self::worker_loop(worker_id, &worker_queues, &worker_shutdown, &worker_average, &worker_panics, &worker_abandoned);
+                })) {
+                    worker_panics.fetch_add(1, Ordering::Relaxed);
+                    error!("Worker {} panicked: {}, restarting it", worker_id, panic_message(&*payload));
+                }
             });
 
             workers.push(handle);
@@ -117,6 +573,10 @@ impl ThreadPool {
             queues,
             next_queue: AtomicUsize::new(0),
             shutdown,
+            rejected: AtomicU64::new(0),
+            average_task_micros,
+            panics,
+            abandoned,
         }
     }
 
@@ -125,36 +585,130 @@ impl ThreadPool {
             return false;
         }
 
-        let queue_index = self.next_queue.fetch_add(1, Ordering::Relaxed) % self.queues.len();
-        let queue = &self.queues[queue_index];
-        
-        queue.push(task)
+        // Start round-robin, then fall over to the other queues before
+        // declaring the pool saturated.
+        let start = self.next_queue.fetch_add(1, Ordering::Relaxed);
+        let mut task = task;
+        for offset in 0..self.queues.len() {
+            let queue = &self.queues[(start + offset) % self.queues.len()];
+            match queue.push(task) {
+                Ok(()) => return true,
+                Err(rejected) => task = rejected,
+            }
+        }
+
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    /// Error for a task execute() refused, with a retry hint based on how
+    /// long the current backlog should take to drain.
+    pub fn busy(&self) -> Box<dyn std::error::Error + Send + Sync> {
+        let backlog_per_worker = (self.queue_depth() / self.queues.len().max(1)) as u64;
+        let drain_micros = backlog_per_worker * self.average_task_micros.load(Ordering::Relaxed);
+        Box::new(BusyError {
+            retry_after_ms: (drain_micros / 1000).max(1),
+        })
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queues.iter().map(|queue| queue.depth.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Tasks waiting in each worker's queue and the recent time its tasks
+    /// spent queued, by worker.
+    pub fn worker_queues(&self) -> Vec<(usize, Duration)> {
+        self.queues
+            .iter()
+            .map(|queue| {
+                let wait = Duration::from_micros(queue.average_wait_micros.load(Ordering::Relaxed));
+                (queue.depth.load(Ordering::Relaxed), wait)
+            })
+            .collect()
+    }
+
+    /// Total time tasks spent queued before a worker took them, and how many
+    /// tasks that covers.
+    pub fn queue_wait(&self) -> (Duration, u64) {
+        let micros = self.queues.iter().map(|queue| queue.waited_micros.load(Ordering::Relaxed)).sum();
+        let tasks = self.queues.iter().map(|queue| queue.dequeued.load(Ordering::Relaxed)).sum();
+        (Duration::from_micros(micros), tasks)
+    }
+
+    /// Recent time tasks take to run once a worker has them.
+    pub fn average_task_time(&self) -> Duration {
+        Duration::from_micros(self.average_task_micros.load(Ordering::Relaxed))
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.queues.iter().map(|queue| queue.capacity).sum()
+    }
+
+    /// Sets dropped from the queues because a later set of the same key
+    /// was already waiting.
+    pub fn coalesced_writes(&self) -> u64 {
+        self.queues.iter().map(|queue| queue.coalesced.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn rejected_tasks(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Panics caught in worker threads since startup.
+    pub fn worker_panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Queued reads skipped because their caller had stopped waiting.
+    pub fn abandoned_tasks(&self) -> u64 {
+        self.abandoned.load(Ordering::Relaxed)
+    }
+
+    // A panicking task drops its reply sender while unwinding, so the waiting
+    // client gets an internal error and the worker carries on.
+    fn run_task(task: Task, average_task_micros: &AtomicU64, panics: &AtomicU64, abandoned: &AtomicU64) {
+        if task.is_abandoned() {
+            abandoned.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let started = Instant::now();
+        let description = task.describe();
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| Self::execute_task(task))) {
+            panics.fetch_add(1, Ordering::Relaxed);
+            error!("Task {} panicked: {}", description, panic_message(&*payload));
+        }
+        let sample = started.elapsed().as_micros() as u64;
+        let average = average_task_micros.load(Ordering::Relaxed);
+        average_task_micros.store(average - average / 8 + sample / 8, Ordering::Relaxed);
     }
 
     fn worker_loop(
         worker_id: usize,
-        queues: Vec<Arc<WorkQueue>>,
-        shutdown: Arc<AtomicBool>,
+        queues: &[Arc<WorkQueue>],
+        shutdown: &AtomicBool,
+        average_task_micros: &AtomicU64,
+        panics: &AtomicU64,
+        abandoned: &AtomicU64,
     ) {
         let my_queue = &queues[worker_id];
         let mut idle_count = 0u32;
         
         while !shutdown.load(Ordering::Relaxed) {
             if let Some(task) = my_queue.pop() {
-                Self::execute_task(task);
+                Self::run_task(task, average_task_micros, panics, abandoned);
                 idle_count = 0;
                 continue;
             }
 
             let mut found_work = false;
             for (i, queue) in queues.iter().enumerate() {
-                if i != worker_id {
-                    if let Some(task) = queue.steal() {
-                        Self::execute_task(task);
-                        found_work = true;
-                        idle_count = 0;
-                        break;
-                    }
+                if i != worker_id
+                    && let Some(task) = queue.steal() {
+                    Self::run_task(task, average_task_micros, panics, abandoned);
+                    found_work = true;
+                    idle_count = 0;
+                    break;
                 }
             }
 
@@ -168,25 +722,209 @@ impl ThreadPool {
 
     fn execute_task(task: Task) {
         match task {
+            Task::ExpirySweep { shard, sender } => {
+                let _ = sender.send(crate::background::sweep_expired(shard));
+            }
             Task::CacheGet { key, sender } => {
                 let result = crate::core::execute_get(&key);
                 let _ = sender.send(result);
             }
-            Task::CacheSet { key, value, sender } => {
-                let result = crate::core::execute_set(key, value);
+            Task::CacheGetEarly { key, recompute, sender } => {
+                let result = crate::core::execute_get_early(&key, recompute);
+                let _ = sender.send(result);
+            }
+            Task::CacheSet { key, value, options, sender, coalesced } if coalesced.is_empty() => {
+                let result = crate::core::execute_set(key, value, options);
+                let _ = sender.send(result);
+            }
+            Task::CacheSet { key, value, options, sender, coalesced } => {
+                let mut senders = Vec::with_capacity(coalesced.len());
+                let mut superseded = Vec::with_capacity(coalesced.len());
+                for Superseded { value, writer, sender } in coalesced.into_iter().rev() {
+                    superseded.push((value, writer));
+                    senders.push(sender);
+                }
+                let result = crate::core::execute_coalesced_set(key, value, options, superseded);
+                for superseded in senders {
+                    let outcome = match &result {
+                        Ok(()) => Ok(()),
+                        Err(e) => Err(e.to_string().into()),
+                    };
+                    let _ = superseded.send(outcome);
+                }
+                let _ = sender.send(result);
+            }
+            Task::CacheGetOrSet { key, value, options, sender } => {
+                let result = crate::core::execute_get_or_set(key, value, options);
+                let _ = sender.send(result);
+            }
+            Task::CacheGetSet { key, value, writer, sender } => {
+                let result = crate::core::execute_get_set(key, value, writer);
+                let _ = sender.send(result);
+            }
+            Task::CacheGetDel { key, sender } => {
+                let result = crate::core::execute_get_del(&key);
+                let _ = sender.send(result);
+            }
+            Task::CacheMGet { keys, sender } => {
+                let result = crate::core::execute_mget(&keys);
+                let _ = sender.send(result);
+            }
+            Task::CacheMSet { pairs, writer, sender } => {
+                let result = crate::core::execute_mset(pairs, writer);
+                let _ = sender.send(result);
+            }
+            Task::CacheIncrBy { key, delta, writer, sender } => {
+                let result = crate::core::execute_incr_by(key, delta, writer);
+                let _ = sender.send(result);
+            }
+            Task::CacheSetBit { key, offset, bit, sender } => {
+                let result = crate::core::execute_set_bit(key, offset, bit);
+                let _ = sender.send(result);
+            }
+            Task::CacheGetBit { key, offset, sender } => {
+                let result = crate::core::execute_get_bit(&key, offset);
+                let _ = sender.send(result);
+            }
+            Task::CacheBitCount { key, sender } => {
+                let result = crate::core::execute_bit_count(&key);
+                let _ = sender.send(result);
+            }
+            Task::CacheStreamAdd { key, value, max_len, sender } => {
+                let result = crate::core::execute_stream_add(key, value, max_len);
+                let _ = sender.send(result);
+            }
+            Task::CacheBloomAdd { key, item, capacity, error_rate, sender } => {
+                let result = crate::core::execute_bloom_add(key, item, capacity, error_rate);
+                let _ = sender.send(result);
+            }
+            Task::CacheBloomExists { key, item, sender } => {
+                let result = crate::core::execute_bloom_exists(&key, &item);
+                let _ = sender.send(result);
+            }
+            Task::CacheCuckooAdd { key, item, capacity, sender } => {
+                let result = crate::core::execute_cuckoo_add(key, item, capacity);
+                let _ = sender.send(result);
+            }
+            Task::CacheCuckooExists { key, item, sender } => {
+                let result = crate::core::execute_cuckoo_exists(&key, &item);
+                let _ = sender.send(result);
+            }
+            Task::CacheCuckooDelete { key, item, sender } => {
+                let result = crate::core::execute_cuckoo_delete(&key, &item);
+                let _ = sender.send(result);
+            }
+            Task::CacheTopKAdd { key, item, k, sender } => {
+                let result = crate::core::execute_topk_add(key, item, k);
+                let _ = sender.send(result);
+            }
+            Task::CacheTopKQuery { key, item, sender } => {
+                let result = crate::core::execute_topk_query(&key, &item);
+                let _ = sender.send(result);
+            }
+            Task::CacheTopKList { key, sender } => {
+                let result = crate::core::execute_topk_list(&key);
+                let _ = sender.send(result);
+            }
+            Task::CacheStreamRange { key, start, end, sender } => {
+                let result = crate::core::execute_stream_range(&key, start, end);
+                let _ = sender.send(result);
+            }
+            Task::CacheMetadata { key, sender } => {
+                let result = crate::core::execute_metadata(&key);
                 let _ = sender.send(result);
             }
             Task::CacheDelete { key, sender } => {
                 let result = crate::core::execute_delete(&key);
                 let _ = sender.send(result);
             }
-            Task::CacheKeys { sender } => {
-                let result = crate::core::execute_keys();
+            Task::CacheHistory { key, sender } => {
+                let result = crate::core::execute_history(&key);
+                let _ = sender.send(result);
+            }
+            Task::CacheGetVersion { key, n, sender } => {
+                let result = crate::core::execute_get_version(&key, n);
+                let _ = sender.send(result);
+            }
+            Task::CacheUndelete { key, sender } => {
+                let result = crate::core::execute_undelete(&key);
+                let _ = sender.send(result);
+            }
+            Task::Plugin { name, args, sender } => {
+                let result = crate::plugins::execute_plugin(&name, &args);
+                let _ = sender.send(result);
+            }
+            Task::CacheKeyMemory { key, sender } => {
+                let result = crate::core::execute_key_memory(&key);
+                let _ = sender.send(result);
+            }
+            Task::CacheObjectInfo { key, sender } => {
+                let result = crate::core::execute_object_info(&key);
+                let _ = sender.send(result);
+            }
+            Task::CacheSetAccessTime { key, age, sender } => {
+                let result = crate::core::execute_set_access_time(&key, age);
+                let _ = sender.send(result);
+            }
+            Task::CacheKeys { sort, sender } => {
+                let result = crate::core::execute_keys(sort);
+                let _ = sender.send(result);
+            }
+            Task::CacheTag { key, tag, sender } => {
+                let result = crate::core::execute_tag(&key, tag);
+                let _ = sender.send(result);
+            }
+            Task::CacheExpire { key, ttl, sender } => {
+                let result = crate::core::execute_expire(&key, ttl);
+                let _ = sender.send(result);
+            }
+            Task::CacheTtl { key, sender } => {
+                let result = crate::core::execute_ttl(&key);
+                let _ = sender.send(result);
+            }
+            Task::CacheKeysByTag { tag, sender } => {
+                let result = crate::core::execute_keys_by_tag(&tag);
+                let _ = sender.send(result);
+            }
+            Task::CacheDeleteByTag { tag, namespace, sender } => {
+                let result = crate::core::execute_delete_by_tag(&tag, namespace.as_deref());
+                let _ = sender.send(result);
+            }
+            Task::CacheInvalidate { namespace, sender } => {
+                let result = crate::core::execute_invalidate(&namespace);
+                let _ = sender.send(result);
+            }
+            Task::CacheLock { key, ttl, sender } => {
+                let result = crate::core::execute_lock(&key, ttl);
+                let _ = sender.send(result);
+            }
+            Task::CacheUnlock { key, token, sender } => {
+                let result = crate::core::execute_unlock(&key, token);
+                let _ = sender.send(result);
+            }
+            Task::CacheScan { cursor, count, sender } => {
+                let result = crate::core::execute_scan(cursor, count);
+                let _ = sender.send(result);
+            }
+            Task::CachePrefixStats { namespace, sender } => {
+                let result = crate::core::execute_prefix_stats(&namespace);
+                let _ = sender.send(result);
+            }
+            Task::CacheStats { sender } => {
+                let result = crate::core::execute_stats();
+                let _ = sender.send(result);
+            }
+            Task::CacheMemoryDoctor { sender } => {
+                let result = crate::core::execute_memory_doctor();
+                let _ = sender.send(result);
+            }
+            Task::CacheBigKeys { count, sender } => {
+                let result = crate::core::execute_big_keys(count);
                 let _ = sender.send(result);
             }
 
-            Task::CacheSearchMultiple { search_type, queries, sender } => {
-                let result = crate::search::execute_search_multiple(search_type, queries);
+            Task::CacheSearchMultiple { search_type, queries, sort, deadline, cancelled, sender } => {
+                let result = crate::search::execute_search_multiple(search_type, queries, sort, deadline, &cancelled);
                 let _ = sender.send(result);
             }
         }
@@ -211,69 +949,544 @@ impl Drop for ThreadPool {
     }
 }
 
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause")
+}
+
 static THREAD_POOL: OnceLock<ThreadPool> = OnceLock::new();
 
-pub fn initialize_threading() {
-    let _ = THREAD_POOL.set(ThreadPool::new());
+pub fn initialize_threading(config: &crate::configuration::SodiumConfig) {
+    // A single worker runs tasks in the order they were submitted.
+    let num_threads = if config.deterministic { 1 } else { num_cpus::get() };
+    let _ = THREAD_POOL.set(ThreadPool::new(config.queue_capacity, num_threads));
 }
 
 pub fn get_thread_pool() -> &'static ThreadPool {
     THREAD_POOL.get().expect("Thread pool not initialized")
 }
 
-pub async fn execute_cache_get(key: String) -> TaskResult<Option<String>> {
+/// Runs an expiry sweep pass on a worker, after the tasks already queued,
+/// and returns the shard to sweep next; `shard` again when the pool is
+/// saturated. Blocks, so it is only called from the background thread.
+pub fn execute_expiry_sweep(shard: usize) -> usize {
+    let (sender, receiver) = oneshot::channel();
+    if get_thread_pool().execute(Task::ExpirySweep { shard, sender }) {
+        receiver.blocking_recv().unwrap_or(shard)
+    } else {
+        shard
+    }
+}
+
+pub async fn execute_cache_get(key: String) -> TaskResult<Option<Arc<str>>> {
     let (sender, receiver) = oneshot::channel();
     let task = Task::CacheGet { key, sender };
     
     if get_thread_pool().execute(task) {
         receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
     } else {
-        Err("Failed to queue task".into())
+        Err(get_thread_pool().busy())
     }
 }
 
-pub async fn execute_cache_set(key: String, value: String) -> TaskResult<()> {
+pub async fn execute_cache_get_early(key: String, recompute: Duration) -> TaskResult<Option<Arc<str>>> {
     let (sender, receiver) = oneshot::channel();
-    let task = Task::CacheSet { key, value, sender };
+    let task = Task::CacheGetEarly { key, recompute, sender };
     
     if get_thread_pool().execute(task) {
         receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
     } else {
-        Err("Failed to queue task".into())
+        Err(get_thread_pool().busy())
     }
 }
 
-pub async fn execute_cache_delete(key: String) -> TaskResult<bool> {
+pub async fn execute_cache_set(key: String, value: String, options: crate::core::SetOptions) -> TaskResult<()> {
     let (sender, receiver) = oneshot::channel();
-    let task = Task::CacheDelete { key, sender };
+    let task = Task::CacheSet { key, value, options, sender, coalesced: Vec::new() };
     
     if get_thread_pool().execute(task) {
         receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
     } else {
-        Err("Failed to queue task".into())
+        Err(get_thread_pool().busy())
     }
 }
 
-pub async fn execute_cache_keys() -> TaskResult<Vec<String>> {
+pub async fn execute_cache_get_or_set(key: String, value: String, options: crate::core::SetOptions) -> TaskResult<Arc<str>> {
     let (sender, receiver) = oneshot::channel();
-    let task = Task::CacheKeys { sender };
+    let task = Task::CacheGetOrSet { key, value, options, sender };
     
     if get_thread_pool().execute(task) {
         receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
     } else {
-        Err("Failed to queue task".into())
+        Err(get_thread_pool().busy())
     }
 }
 
+pub async fn execute_cache_get_set(key: String, value: String, writer: Option<std::net::SocketAddr>) -> TaskResult<Option<Arc<str>>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheGetSet { key, value, writer, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_get_del(key: String) -> TaskResult<Option<Arc<str>>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheGetDel { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_mget(keys: Vec<String>) -> TaskResult<Vec<Option<Arc<str>>>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheMGet { keys, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_mset(pairs: Vec<(String, String)>, writer: Option<std::net::SocketAddr>) -> TaskResult<()> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheMSet { pairs, writer, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_incr_by(key: String, delta: i64, writer: Option<std::net::SocketAddr>) -> TaskResult<i64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheIncrBy { key, delta, writer, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_set_bit(key: String, offset: u64, bit: bool) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheSetBit { key, offset, bit, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_get_bit(key: String, offset: u64) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheGetBit { key, offset, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_bit_count(key: String) -> TaskResult<u64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheBitCount { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_stream_add(key: String, value: String, max_len: u64) -> TaskResult<u64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheStreamAdd { key, value, max_len, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_bloom_add(key: String, item: String, capacity: u64, error_rate: f64) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheBloomAdd { key, item, capacity, error_rate, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_bloom_exists(key: String, item: String) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheBloomExists { key, item, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_cuckoo_add(key: String, item: String, capacity: u64) -> TaskResult<()> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheCuckooAdd { key, item, capacity, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_cuckoo_exists(key: String, item: String) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheCuckooExists { key, item, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_cuckoo_delete(key: String, item: String) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheCuckooDelete { key, item, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_topk_add(key: String, item: String, k: usize) -> TaskResult<u64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheTopKAdd { key, item, k, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_topk_query(key: String, item: String) -> TaskResult<u64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheTopKQuery { key, item, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_topk_list(key: String) -> TaskResult<Vec<(String, u64)>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheTopKList { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_stream_range(key: String, start: u64, end: u64) -> TaskResult<Vec<crate::core::StreamEntry>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheStreamRange { key, start, end, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_metadata(key: String) -> TaskResult<Option<crate::core::Metadata>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheMetadata { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_key_memory(key: String) -> TaskResult<u64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheKeyMemory { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_object_info(key: String) -> TaskResult<crate::core::ObjectInfo> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheObjectInfo { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_set_access_time(key: String, age: Duration) -> TaskResult<()> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheSetAccessTime { key, age, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_delete(key: String) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheDelete { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_history(key: String) -> TaskResult<Vec<crate::core::Version>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheHistory { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_get_version(key: String, n: usize) -> TaskResult<Option<Arc<str>>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheGetVersion { key, n, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_undelete(key: String) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheUndelete { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_plugin(name: String, args: Vec<String>) -> TaskResult<crate::protocol::Reply> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::Plugin { name, args, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_keys(sort: Option<crate::core::SortOrder>) -> TaskResult<Vec<String>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheKeys { sort, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_expire(key: String, ttl: Duration) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheExpire { key, ttl, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_ttl(key: String) -> TaskResult<Option<Option<Duration>>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheTtl { key, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_tag(key: String, tag: String) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheTag { key, tag, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_keys_by_tag(tag: String) -> TaskResult<Vec<String>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheKeysByTag { tag, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_delete_by_tag(tag: String, namespace: Option<String>) -> TaskResult<u64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheDeleteByTag { tag, namespace, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_invalidate(namespace: String) -> TaskResult<u64> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheInvalidate { namespace, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_lock(key: String, ttl: Duration) -> TaskResult<Option<u64>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheLock { key, ttl, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_unlock(key: String, token: u64) -> TaskResult<bool> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheUnlock { key, token, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_scan(cursor: crate::core::ScanCursor, count: usize) -> TaskResult<crate::core::ScanPage> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheScan { cursor, count, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_prefix_stats(namespace: String) -> TaskResult<Option<crate::core::PrefixStats>> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CachePrefixStats { namespace, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_stats() -> TaskResult<crate::core::CacheStats> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheStats { sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_memory_doctor() -> TaskResult<crate::core::MemoryReport> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheMemoryDoctor { sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
+
+pub async fn execute_cache_big_keys(count: usize) -> TaskResult<crate::core::BigKeysReport> {
+    let (sender, receiver) = oneshot::channel();
+    let task = Task::CacheBigKeys { count, sender };
+    
+    if get_thread_pool().execute(task) {
+        receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
+    } else {
+        Err(get_thread_pool().busy())
+    }
+}
 
-
-pub async fn execute_cache_search_multiple(search_type: crate::search::SearchType, queries: Vec<String>) -> TaskResult<Vec<String>> {
+pub async fn execute_cache_search_multiple(
+    search_type: crate::search::SearchType,
+    queries: Vec<String>,
+    sort: Option<crate::core::SortOrder>,
+    deadline: Option<Instant>,
+    cancelled: Arc<AtomicBool>,
+) -> TaskResult<crate::search::SearchResult> {
     let (sender, receiver) = oneshot::channel();
-    let task = Task::CacheSearchMultiple { search_type, queries, sender };
+    let task = Task::CacheSearchMultiple { search_type, queries, sort, deadline, cancelled, sender };
     
     if get_thread_pool().execute(task) {
         receiver.await.unwrap_or_else(|_| Err("Task execution failed".into()))
     } else {
-        Err("Failed to queue task".into())
+        Err(get_thread_pool().busy())
     }
 } 
\ No newline at end of file
@@ -0,0 +1,140 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// io_uring network backend for the main port, selected with
+// network_backend = "io_uring". Each thread runs its own tokio-uring runtime
+// and accepts from the shared listening socket; requests go through the same
+// handling as the tokio backend, so only the socket calls differ.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio_uring::net::{TcpListener, TcpStream};
+use tracing::{error, warn};
+
+use crate::api::{self, ClientConnection, LineOutcome, OpenConnection, Session, TcpApiServer, MAX_REQUEST_BYTES};
+use crate::configuration::SodiumConfig;
+
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+// Reads complete into buffers owned by the kernel, so a socket cannot be
+// polled for a hangup while one of its commands runs. A disconnect is noticed
+// on the next read instead and command_timeout_ms still bounds the work.
+struct UringConnection;
+
+impl ClientConnection for UringConnection {
+    async fn closed(&mut self) {
+        std::future::pending::<()>().await
+    }
+
+    // Nothing reads the socket between requests, so events would only go
+    // out behind the client's next reply.
+    fn can_push(&self) -> bool {
+        false
+    }
+}
+
+pub fn spawn_workers(listener: std::net::TcpListener, config: Arc<SodiumConfig>) -> std::io::Result<()> {
+    let threads = if config.deterministic { 1 } else { num_cpus::get().max(1) };
+    for index in 0..threads {
+        let listener = listener.try_clone()?;
+        let config = config.clone();
+        std::thread::Builder::new()
+            .name(format!("sodium-uring-{}", index))
+            .spawn(move || tokio_uring::start(accept_loop(listener, config)))?;
+    }
+    Ok(())
+}
+
+async fn accept_loop(listener: std::net::TcpListener, config: Arc<SodiumConfig>) {
+    let listener = TcpListener::from_std(listener);
+    loop {
+        match listener.accept().await {
+            Ok((stream, client_addr)) => {
+                tokio_uring::spawn(handle_client(stream, client_addr, config.clone()));
+            }
+            Err(e) => {
+                error!("Error accepting TCP connection: {}", e);
+            }
+        }
+    }
+}
+
+async fn handle_client(stream: TcpStream, client_addr: SocketAddr, config: Arc<SodiumConfig>) {
+    let mut session = Session::new(&config);
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+    let mut pending = Vec::new();
+    let _open = OpenConnection::new();
+
+    // Dropped mid-request, its reply unsent, once draining for a handoff
+    // times out.
+    let serve = async {
+        loop {
+            // Dropping the read cancels it; tokio-uring keeps the buffer alive
+            // until the kernel lets go of it.
+            let read = tokio::select! {
+                biased;
+                _ = api::draining() => break,
+                (result, returned) = stream.read(buffer) => {
+                    buffer = returned;
+                    result
+                }
+            };
+            let eof = match read {
+                Ok(0) => true,
+                Ok(read) => {
+                    pending.extend_from_slice(&buffer[..read]);
+                    false
+                }
+                Err(e) => {
+                    error!("Error reading from TCP stream {}: {}", client_addr, e);
+                    break;
+                }
+            };
+            // A last request sent without its newline is still answered, as on
+            // the tokio backend.
+            if eof && !pending.is_empty() {
+                pending.push(b'\n');
+            }
+
+            // Every complete request in what has arrived is answered before the
+            // replies go out in one write, as pipelined requests are on tokio.
+            let mut responses = Vec::new();
+            let mut consumed = 0;
+            let mut close = eof;
+            while let Some(end) = pending[consumed..].iter().position(|&byte| byte == b'\n') {
+                let line = &pending[consumed..consumed + end + 1];
+                consumed += end + 1;
+                match TcpApiServer::handle_line(line, &mut session, &config, client_addr, false, &mut UringConnection).await {
+                    LineOutcome::Reply(reply) => responses.extend_from_slice(reply.as_bytes()),
+                    LineOutcome::Empty => {}
+                    LineOutcome::Close => {
+                        close = true;
+                        break;
+                    }
+                }
+            }
+            pending.drain(..consumed);
+
+            if !responses.is_empty() {
+                let (result, _) = stream.write_all(responses).await;
+                if let Err(e) = result {
+                    error!("Failed to send response to {}: {}", client_addr, e);
+                    break;
+                }
+            }
+            if close {
+                break;
+            }
+            if pending.len() >= MAX_REQUEST_BYTES {
+                error!("Request from {} exceeds {} bytes, closing the connection", client_addr, MAX_REQUEST_BYTES);
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        biased;
+        _ = api::closing() => warn!("Closing connection from {} mid-request for a handoff", client_addr),
+        _ = serve => {}
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// `sodium-server --verify-dump <file>` and `--repair-aof <file>`: offline
+// checks of persistence files, run against the files of a stopped server.
+// Both exit non-zero when the file cannot be used as it is.
+
+use std::path::Path;
+
+use crate::configuration::{self, SodiumConfig};
+use crate::{aof, encryption, snapshot};
+
+type VerifyResult = Result<(), Box<dyn std::error::Error>>;
+
+/// Loads the encryption keys of sodium.toml in the working directory, if
+/// there is one, so encrypted files can be read.
+pub fn initialize_keys(profile: Option<&str>) -> VerifyResult {
+    if Path::new(configuration::CONFIG_PATH).exists() {
+        let config = SodiumConfig::load_or_create(profile)?;
+        encryption::initialize_encryption(&config)?;
+    }
+    Ok(())
+}
+
+/// Checks a snapshot file, full or delta, line by line.
+pub fn verify_dump(path: &str) -> VerifyResult {
+    match snapshot::verify(path) {
+        Ok(check) => {
+            println!(
+                "{}: ok, {} snapshot with {} entries and {} removals, covering AOF records up to {}",
+                path, check.kind, check.entries, check.removals, check.aof_seq
+            );
+            Ok(())
+        }
+        Err(e) => {
+            println!("{}: {}", path, e);
+            Err(e.into())
+        }
+    }
+}
+
+/// Checks an AOF and cuts off a torn tail. Damage elsewhere is reported and
+/// left for the operator, since dropping it would lose acknowledged writes.
+pub fn repair_aof(path: &str) -> VerifyResult {
+    match aof::repair(path) {
+        Ok(repair) => {
+            match &repair.saved_to {
+                Some(saved_to) => println!(
+                    "{}: truncated a torn tail of {} bytes, saved to {}; {} records remain",
+                    path, repair.truncated_bytes, saved_to, repair.records
+                ),
+                None => println!("{}: ok, {} records", path, repair.records),
+            }
+            Ok(())
+        }
+        Err(e) => {
+            println!("{}: {}, not repaired", path, e);
+            Err(e.into())
+        }
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Key event notifications. Each [webhooks] entry maps a key pattern to a
+// target: an http:// URL that gets one POST per event, or "pipe:<command>",
+// a process started once and fed one JSON line per event on its stdin.
+// Events are queued per target and delivered by a task of its own, so a slow
+// receiver never holds up a write; once a target's queue is full its events
+// are dropped and counted.
+//
+// Connections can also ask for expirations with onexpire(pattern); each gets
+// an ExpirationFeed of the keys the TTL sweep or a lookup expired, which the
+// connection pushes to its client as `expired <key>` lines.
+
+use std::sync::{LazyLock, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::{Child, ChildStdin, Command};
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::configuration::SodiumConfig;
+
+const QUEUE_CAPACITY: usize = 10_000;
+// Expirations held for a connection whose client is not reading them.
+const FEED_CAPACITY: usize = 1_024;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+// Wait before restarting a pipe command that exited or stopped reading.
+const PIPE_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Invalid webhook target for {pattern}: {target} (expected http://host[:port]/path or pipe:<command>)")]
+    InvalidTarget { pattern: String, target: String },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum KeyEvent {
+    Set,
+    SetBit,
+    Incr,
+    StreamAdd,
+    BloomAdd,
+    TopKAdd,
+    CuckooAdd,
+    CuckooDelete,
+    Tag,
+    Delete,
+    Expire,
+    Evict,
+}
+
+impl KeyEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            KeyEvent::Set => "set",
+            KeyEvent::SetBit => "setbit",
+            KeyEvent::Incr => "incr",
+            KeyEvent::StreamAdd => "xadd",
+            KeyEvent::BloomAdd => "bfadd",
+            KeyEvent::TopKAdd => "topk_add",
+            KeyEvent::CuckooAdd => "cfadd",
+            KeyEvent::CuckooDelete => "cfdel",
+            KeyEvent::Tag => "tag",
+            KeyEvent::Delete => "delete",
+            KeyEvent::Expire => "expire",
+            KeyEvent::Evict => "evict",
+        }
+    }
+}
+
+enum Target {
+    Http { address: String, path: String },
+    Pipe(String),
+}
+
+impl Target {
+    fn parse(target: &str) -> Option<Self> {
+        if let Some(command) = target.strip_prefix("pipe:") {
+            return (!command.trim().is_empty()).then(|| Target::Pipe(command.to_string()));
+        }
+
+        let rest = target.strip_prefix("http://")?;
+        let (address, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if address.is_empty() {
+            return None;
+        }
+        let address = if address.contains(':') { address.to_string() } else { format!("{}:80", address) };
+        Some(Target::Http { address, path: path.to_string() })
+    }
+}
+
+struct Hook {
+    pattern: String,
+    // Encoded events waiting for delivery.
+    queue: mpsc::Sender<String>,
+}
+
+static HOOKS: OnceLock<Vec<Hook>> = OnceLock::new();
+static DROPPED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+struct ExpirationListener {
+    patterns: Vec<String>,
+    queue: mpsc::Sender<String>,
+}
+
+static LISTENERS: LazyLock<DashMap<u64, ExpirationListener>> = LazyLock::new(DashMap::new);
+static NEXT_LISTENER: AtomicU64 = AtomicU64::new(0);
+
+/// Keys expired under the patterns one connection registered. Dropping the
+/// feed unregisters it.
+#[derive(Debug)]
+pub struct ExpirationFeed {
+    id: u64,
+    events: mpsc::Receiver<String>,
+}
+
+impl ExpirationFeed {
+    pub fn new() -> Self {
+        let id = NEXT_LISTENER.fetch_add(1, Ordering::Relaxed);
+        let (queue, events) = mpsc::channel(FEED_CAPACITY);
+        LISTENERS.insert(id, ExpirationListener { patterns: Vec::new(), queue });
+        Self { id, events }
+    }
+
+    /// Adds a pattern and returns how many the feed now listens to.
+    pub fn listen(&self, pattern: String) -> usize {
+        let Some(mut listener) = LISTENERS.get_mut(&self.id) else {
+            return 0;
+        };
+        if !listener.patterns.contains(&pattern) {
+            listener.patterns.push(pattern);
+        }
+        listener.patterns.len()
+    }
+
+    /// Waits for the next expired key.
+    pub async fn next(&mut self) -> String {
+        match self.events.recv().await {
+            Some(key) => key,
+            // The sender lives in LISTENERS until this feed is dropped.
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for ExpirationFeed {
+    fn drop(&mut self) {
+        LISTENERS.remove(&self.id);
+    }
+}
+
+/// Starts a delivery task for every configured webhook. Must be called from
+/// within the runtime.
+pub fn initialize_webhooks(config: &SodiumConfig) -> Result<(), WebhookError> {
+    if config.webhooks.is_empty() {
+        return Ok(());
+    }
+
+    let mut hooks = Vec::new();
+    for (pattern, target) in &config.webhooks {
+        let parsed = Target::parse(target)
+            .ok_or_else(|| WebhookError::InvalidTarget { pattern: pattern.clone(), target: target.clone() })?;
+        let (queue, events) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(deliver(parsed, events));
+        hooks.push(Hook { pattern: pattern.clone(), queue });
+    }
+    let _ = HOOKS.set(hooks);
+    Ok(())
+}
+
+/// Queues `event` for every webhook whose pattern matches `key`. Never
+/// blocks, so it is safe to call with an entry lock held.
+pub fn notify(event: KeyEvent, key: &str) {
+    if matches!(event, KeyEvent::Expire) {
+        notify_listeners(key);
+    }
+    let Some(hooks) = HOOKS.get() else {
+        return;
+    };
+
+    let mut encoded = None;
+    for hook in hooks.iter().filter(|hook| pattern_matches(&hook.pattern, key)) {
+        let line = encoded.get_or_insert_with(|| {
+            let at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+            serde_json::json!({ "event": event.as_str(), "key": key, "at": at }).to_string()
+        });
+        if hook.queue.try_send(line.clone()).is_err() {
+            DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn notify_listeners(key: &str) {
+    for listener in LISTENERS.iter() {
+        if listener.patterns.iter().any(|pattern| pattern_matches(pattern, key))
+            && listener.queue.try_send(key.to_string()).is_err() {
+            DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Events dropped because a webhook's or a connection's queue was full.
+pub fn dropped_events() -> u64 {
+    DROPPED_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Glob match where `*` stands for any run of characters.
+pub fn pattern_matches(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = key.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+async fn deliver(target: Target, mut events: mpsc::Receiver<String>) {
+    match target {
+        Target::Http { address, path } => {
+            while let Some(event) = events.recv().await {
+                let result = tokio::time::timeout(HTTP_TIMEOUT, post(&address, &path, &event)).await;
+                match result {
+                    Ok(Ok(status)) if (200..300).contains(&status) => {}
+                    Ok(Ok(status)) => warn!("Webhook {}{} returned status {}", address, path, status),
+                    Ok(Err(e)) => warn!("Webhook {}{} failed: {}", address, path, e),
+                    Err(_) => warn!("Webhook {}{} timed out", address, path),
+                }
+            }
+        }
+        Target::Pipe(command) => {
+            let mut pipe: Option<(Child, ChildStdin)> = None;
+            while let Some(mut event) = events.recv().await {
+                event.push('\n');
+                if pipe.is_none() {
+                    pipe = spawn_pipe(&command).await;
+                }
+                let Some((_, stdin)) = &mut pipe else {
+                    continue;
+                };
+                if let Err(e) = stdin.write_all(event.as_bytes()).await {
+                    warn!("Webhook command {} stopped reading events: {}", command, e);
+                    if let Some((mut child, _)) = pipe.take() {
+                        let _ = child.kill().await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Events arriving while the command cannot be started are dropped.
+async fn spawn_pipe(command: &str) -> Option<(Child, ChildStdin)> {
+    #[cfg(windows)]
+    let mut process = Command::new("cmd");
+    #[cfg(windows)]
+    process.arg("/C");
+    #[cfg(not(windows))]
+    let mut process = Command::new("sh");
+    #[cfg(not(windows))]
+    process.arg("-c");
+
+    match process.arg(command).stdin(std::process::Stdio::piped()).kill_on_drop(true).spawn() {
+        Ok(mut child) => {
+            let stdin = child.stdin.take()?;
+            Some((child, stdin))
+        }
+        Err(e) => {
+            warn!("Failed to start webhook command {}: {}", command, e);
+            tokio::time::sleep(PIPE_RESTART_DELAY).await;
+            None
+        }
+    }
+}
+
+async fn post(address: &str, path: &str, body: &str) -> std::io::Result<u16> {
+    let mut stream = TcpStream::connect(address).await?;
+    let request = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        path,
+        address,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let status = String::from_utf8_lossy(&response)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok());
+    status.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "missing status code"))
+}
@@ -0,0 +1,202 @@
+// Copyright (c) 2025, TheByteSlayer, Sodium
+// A scalable and optimized Key Value Caching System, written in Rust.
+
+// Behavioural tests that run the real server binary, each in its own
+// directory and on its own port, and talk to it over the wire.
+
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use sodium_testkit::{Connection, Frame};
+
+struct Server {
+    directory: PathBuf,
+    settings: String,
+    port: u16,
+    child: Option<Child>,
+}
+
+impl Server {
+    /// Starts a server in a fresh directory with `settings` added to a
+    /// minimal sodium.toml.
+    fn start(name: &str, settings: &str) -> Self {
+        let directory = std::env::temp_dir().join(format!("sodium-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut server = Self { directory, settings: settings.to_string(), port, child: None };
+        server.restart();
+        server
+    }
+
+    /// Kills the server without a clean shutdown, if it is running, and
+    /// starts it again on the same directory.
+    fn restart(&mut self) {
+        self.kill();
+        let config = format!(
+            "bind-ip = \"127.0.0.1\"\nbind-port = {}\nbanner = false\nsilent = true\nnetwork_backend = \"tokio\"\n{}\n",
+            self.port, self.settings
+        );
+        fs::write(self.directory.join("sodium.toml"), config).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_sodium-server"))
+            .current_dir(&self.directory)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        self.child = Some(child);
+    }
+
+    fn kill(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    fn connect(&self) -> Client {
+        let address = format!("127.0.0.1:{}", self.port);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match Connection::connect(&address, Duration::from_secs(5)) {
+                Ok(connection) => return Client(connection),
+                Err(e) if Instant::now() > deadline => panic!("server on {} never came up: {}", address, e),
+                Err(_) => thread::sleep(Duration::from_millis(50)),
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.kill();
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+struct Client(Connection);
+
+impl Client {
+    fn send(&mut self, request: &str) -> String {
+        match self.0.request(request).unwrap() {
+            Frame::Line(line) => line,
+            other => panic!("{} replied {}", request, other),
+        }
+    }
+
+    fn stat(&mut self, name: &str) -> u64 {
+        let stats = self.send("stats()");
+        stats
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(|| panic!("no {} in {}", name, stats))
+    }
+}
+
+#[test]
+fn aof_replays_writes_after_a_crash() {
+    let mut server = Server::start("aof", "aof_enabled = true\nfsync = \"always\"\nsnapshot_interval_secs = 0");
+    let mut client = server.connect();
+    assert_eq!(client.send("set(kept, value)"), "OK");
+    assert_eq!(client.send("set(dropped, value)"), "OK");
+    assert_eq!(client.send("del(dropped)"), "1");
+    assert_eq!(client.send("incrby(counter, 40)"), "40");
+    assert_eq!(client.send("incr(counter)"), "41");
+    assert_eq!(client.send("setex(expiring, 600, value)"), "OK");
+    assert_eq!(client.send("bfadd(bloom, x)"), "1");
+    assert_eq!(client.send("cfadd(cuckoo, x)"), "1");
+
+    server.restart();
+    let mut client = server.connect();
+    assert_eq!(client.send("get(kept)"), "value");
+    assert_eq!(client.send("get(dropped)"), "NULL");
+    assert_eq!(client.send("incr(counter)"), "42");
+    let ttl: u64 = client.send("ttl(expiring)").parse().unwrap();
+    assert!(ttl > 590 && ttl <= 600, "ttl {} after replay", ttl);
+    assert_eq!(client.send("bfexists(bloom, x)"), "1");
+    assert_eq!(client.send("cfexists(cuckoo, x)"), "1");
+}
+
+#[test]
+fn snapshots_restore_full_and_delta_writes() {
+    let mut server = Server::start("snapshot", "aof_enabled = false\nsnapshot_interval_secs = 1");
+    let mut client = server.connect();
+    assert_eq!(client.send("set(first, one)"), "OK");
+    assert_eq!(client.send("set(doomed, value)"), "OK");
+    assert_eq!(client.send("topk_add(topk, a)"), "1");
+    // Lets the first, full snapshot be written before the next writes.
+    thread::sleep(Duration::from_millis(2500));
+    assert_eq!(client.send("set(second, two)"), "OK");
+    assert_eq!(client.send("del(doomed)"), "1");
+    assert_eq!(client.send("topk_add(topk, a)"), "2");
+    thread::sleep(Duration::from_millis(2500));
+
+    server.restart();
+    let mut client = server.connect();
+    assert_eq!(client.send("get(first)"), "one");
+    assert_eq!(client.send("get(second)"), "two");
+    assert_eq!(client.send("get(doomed)"), "NULL");
+    assert_eq!(client.send("topk_query(topk, a)"), "2");
+}
+
+#[test]
+fn scheduled_writes_survive_a_restart() {
+    let mut server = Server::start("scheduled", "aof_enabled = true\nfsync = \"always\"\nsnapshot_interval_secs = 0");
+    let mut client = server.connect();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    assert_eq!(client.send(&format!("set(later, value, at({}))", now + 3)), "OK");
+    assert_eq!(client.send("get(later)"), "NULL");
+
+    server.restart();
+    let mut client = server.connect();
+    assert_eq!(client.send("get(later)"), "NULL");
+    thread::sleep(Duration::from_secs(4));
+    assert_eq!(client.send("get(later)"), "value");
+}
+
+#[test]
+fn expired_keys_are_removed() {
+    let server = Server::start("ttl", "expiry_sweep_interval_ms = 50");
+    let mut client = server.connect();
+    assert_eq!(client.send("setex(short, 1, value)"), "OK");
+    assert_eq!(client.send("set(long, value)"), "OK");
+    assert_eq!(client.send("expire(long, 600)"), "1");
+    thread::sleep(Duration::from_millis(1500));
+    // The sweep removes the key without it being read first.
+    assert!(client.stat("expired_keys") >= 1);
+    assert_eq!(client.send("get(short)"), "NULL");
+    assert_eq!(client.send("get(long)"), "value");
+}
+
+#[test]
+fn eviction_keeps_memory_under_the_limit() {
+    let server = Server::start("evict", "max_memory = 65536\neviction_policy = \"lru\"");
+    let mut client = server.connect();
+    let value = "x".repeat(200);
+    for index in 0..1000 {
+        assert_eq!(client.send(&format!("set(key{}, {})", index, value)), "OK");
+    }
+    assert!(client.stat("evicted_keys") > 0);
+    assert!(client.stat("used_memory") <= 65536);
+    assert_eq!(client.send("get(key999)"), value);
+}
+
+#[test]
+fn noeviction_refuses_writes_past_the_limit() {
+    let server = Server::start("noevict", "max_memory = 65536\neviction_policy = \"noeviction\"");
+    let mut client = server.connect();
+    let value = "x".repeat(200);
+    let refused = (0..1000)
+        .map(|index| client.send(&format!("set(key{}, {})", index, value)))
+        .find(|reply| reply != "OK")
+        .expect("every write was accepted");
+    assert!(refused.starts_with("ERR_OOM"), "{}", refused);
+    assert_eq!(client.stat("evicted_keys"), 0);
+    assert_eq!(client.send("get(key0)"), value);
+    assert!(client.send("incr(counter)").starts_with("ERR_OOM"));
+}